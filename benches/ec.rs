@@ -0,0 +1,37 @@
+// Verifying the signatures of a few keys one by one, and as a batch
+// (the multiples of G and of each key found once).
+//
+// cargo bench --bench ec
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use doing_some_blockchain::ec::{verify_batch, SecretKey};
+
+const KEYS: u32 = 4;
+const SIGNATURES: u32 = 64;
+
+fn bench(c: &mut Criterion) {
+    let keys =
+        (1..=KEYS).map(SecretKey::new).collect::<Vec<_>>();
+    let items = (0..SIGNATURES)
+        .map(|msg| {
+            let key = &keys[(msg % KEYS) as usize];
+            (msg, key.sign(&msg), key.public_key())
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("verify");
+    group.bench_function("one_by_one", |b| {
+        b.iter(|| {
+            assert!(items
+                .iter()
+                .all(|(msg, sig, key)| key.is_valid(msg, sig)))
+        })
+    });
+    group.bench_function("batch", |b| {
+        b.iter(|| assert!(verify_batch(&items)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
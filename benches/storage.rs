@@ -0,0 +1,72 @@
+// Reads and writes of different keys from a few threads at once:
+// the storage behind a single lock, and split into shards.
+//
+// cargo bench --bench storage
+
+use std::{sync::Mutex, thread};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use doing_some_blockchain::storage::{Shards, Storage, DB};
+
+const THREADS: u64 = 8;
+const KEYS: u64 = 1024; // per thread
+const SHARDS: usize = 16;
+
+fn filled() -> DB<u64, u32> {
+    let mut db = DB::new();
+    for key in 0..THREADS * KEYS {
+        db.set(key, key as u32);
+    }
+    db
+}
+
+// each thread reads its own keys, and patches every 8th one
+fn load(op: impl Fn(u64, bool) + Sync) {
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let op = &op;
+            s.spawn(move || {
+                for key in t * KEYS..(t + 1) * KEYS {
+                    op(key, key % 8 == 0);
+                }
+            });
+        }
+    });
+}
+
+fn access(db: &mut DB<u64, u32>, key: u64, write: bool) {
+    if write {
+        db.update(key, |x| x.wrapping_add(1));
+    } else {
+        assert!(db.get(key).is_some());
+    }
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("storage");
+
+    let db = Mutex::new(filled());
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            load(|key, write| {
+                access(&mut db.lock().unwrap(), key, write)
+            })
+        })
+    });
+
+    let db =
+        Shards::new((0..SHARDS).map(|_| DB::new()).collect());
+    db.restore(&filled().snapshot()).unwrap();
+    group.bench_function("shards", |b| {
+        b.iter(|| {
+            load(|key, write| {
+                access(&mut db.lock(key), key, write)
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -1,4 +1,6 @@
-use std::{thread, time::Duration};
+use std::time::Duration;
+
+use crate::ec::{Encoding, PublicKey, SecretKey, Signature};
 
 #[derive(Debug)]
 pub enum Error {
@@ -28,29 +30,45 @@ pub trait Sender<T: 'static>: Sized {
 pub trait Receiver<T: 'static>: Sized {
     fn recv(&self) -> Result<Option<T>>;
 
-    fn recv_timeout(&self, timeout: Duration) -> Result<T> {
-        if let Some(received) = self.recv()? {
-            return Ok(received);
-        }
-        thread::sleep(timeout / 2);
-        if let Some(received) = self.recv()? {
-            return Ok(received);
-        }
-        thread::sleep(timeout / 2);
-        match self.recv()? {
-            Some(received) => Ok(received),
-            None => {
-                let kind = std::io::ErrorKind::TimedOut;
-                let e = std::io::Error::new(kind, "timeout");
-                Err(Error::IO(e))
-            }
-        }
-    }
+    // Blocks for at most `timeout` (e.g. with the socket read
+    // timeout), transports do not poll
+    fn recv_timeout(&self, timeout: Duration) -> Result<T>;
+}
+
+pub(crate) fn timeout() -> Error {
+    let kind = std::io::ErrorKind::TimedOut;
+    Error::IO(std::io::Error::new(kind, "timeout"))
+}
+
+pub(crate) fn closed() -> Error {
+    let kind = std::io::ErrorKind::UnexpectedEof;
+    Error::IO(std::io::Error::new(kind, "connection closed"))
 }
 
 pub const TAG_SECRET_SHARE: u32 = 1;
 pub const TAG_PUBLIC_KEY: u32 = 2;
 pub const TAG_REFRESH: u32 = 3;
+pub const TAG_DELETE: u32 = 4;
+pub const TAG_LIST: u32 = 5;
+pub const TAG_PING: u32 = 6;
+pub const TAG_PONG: u32 = 7;
+pub const TAG_BATCH: u32 = 8;
+pub const TAG_CLOSE: u32 = 9;
+pub const TAG_STATUS: u32 = 10;
+pub const TAG_SNAPSHOT: u32 = 11;
+pub const TAG_AUDIT: u32 = 12;
+pub const TAG_SIGN_COMMIT: u32 = 13;
+pub const TAG_SIGN_SHARE: u32 = 14;
+pub const TAG_REKEY: u32 = 15;
+pub const TAG_GOSSIP: u32 = 16;
+pub const TAG_JOIN: u32 = 17;
+pub const TAG_LEAVE: u32 = 18;
+pub const TAG_TRANSFER: u32 = 19;
+pub const TAG_DIGEST: u32 = 20;
+pub const TAG_REPAIR: u32 = 21;
+pub const TAG_GET_BLOCKS: u32 = 22;
+pub const TAG_BLOCKS: u32 = 23;
+pub const TAG_LEDGER: u32 = 24;
 
 pub const TAG_HELLO: u32 = 255;
 
@@ -60,21 +78,41 @@ pub const TAG_SERVER_ERROR: u32 = 500;
 
 pub const ERR_NOT_FOUND: u32 = 32001;
 pub const ERR_EXPIRED: u32 = 32002;
+pub const ERR_BAD_CHECKSUM: u32 = 32003;
+pub const ERR_BAD_SIGNATURE: u32 = 32004;
+pub const ERR_RATE_LIMITED: u32 = 32005;
+pub const ERR_BAD_SHARE: u32 = 32006;
+pub const ERR_STORAGE: u32 = 32007;
+pub const ERR_DELETED: u32 = 32008;
+pub const ERR_FORBIDDEN: u32 = 32009;
+pub const ERR_STALE: u32 = 32010;
+
+pub const MAX_PAYLOAD_LEN: usize = 64 * 1024;
+pub const MAX_FRAME_LEN: usize = 4 * 10 + MAX_PAYLOAD_LEN; // bytes
+pub const MAX_BATCH_SIZE: usize = 64;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct Frame {
     pub idx: u32,
     pub tag: u32,
     pub msg: u32,
     pub key: u32,
-    pub sig: u64, // signature over `idx || tag || msg`
+    pub sig: u64, // signature over `digest()`
     pub ext: u32,
-    pub sum: u32, // crc32
+    // namespace (tenant) of the key, zero for the default one
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ns: u32,
+    pub sum: u32,      // crc32
+    pub data: Vec<u8>, // length-prefixed payload
 }
 
 impl Frame {
-    pub fn words(&self) -> [u32; 8] {
-        let mut ret = [0u32; 8];
+    pub fn words(&self) -> [u32; 9] {
+        let mut ret = [0u32; 9];
         ret[0] = self.idx;
         ret[1] = self.tag;
         ret[2] = self.msg;
@@ -83,11 +121,12 @@ impl Frame {
         ret[4] = hi;
         ret[5] = lo;
         ret[6] = self.ext;
-        ret[7] = self.sum;
+        ret[7] = self.ns;
+        ret[8] = self.sum;
         ret
     }
 
-    pub fn from(words: [u32; 8]) -> Self {
+    pub fn from(words: [u32; 9]) -> Self {
         Self {
             idx: words[0],
             tag: words[1],
@@ -95,7 +134,133 @@ impl Frame {
             key: words[3],
             sig: crate::util::merge(words[4], words[5]),
             ext: words[6],
-            sum: words[7],
+            ns: words[7],
+            sum: words[8],
+            data: Vec::new(),
+        }
+    }
+
+    // All the words: header followed by length-prefixed payload
+    pub fn encode(&self) -> Vec<u32> {
+        self.words().into_iter().chain(self.payload()).collect()
+    }
+
+    pub fn decode(words: &[u32]) -> Result<Self> {
+        if words.len() < 10 {
+            return Err(Error::App(
+                "frame too short".to_string(),
+            ));
+        }
+        let len = words[9] as usize;
+        if len > MAX_PAYLOAD_LEN
+            || words.len() != 10 + len.div_ceil(4)
+        {
+            return Err(Error::App(format!(
+                "invalid payload length: {len} bytes"
+            )));
+        }
+        let mut header = [0u32; 9];
+        header.copy_from_slice(&words[..9]);
+        let mut frame = Frame::from(header);
+        frame.data = crate::util::unpack(&words[10..], len);
+        Ok(frame)
+    }
+
+    pub fn payload(&self) -> Vec<u32> {
+        let mut ret =
+            Vec::with_capacity(1 + self.data.len() / 4);
+        ret.push(self.data.len() as u32);
+        ret.extend(crate::util::pack(&self.data));
+        ret
+    }
+
+    // SHA-256 (`util::hash32`) over the signed words: all except
+    // `sig` and `sum`
+    pub fn digest(&self) -> u32 {
+        let words = self.words();
+        let bytes = words[..4]
+            .iter()
+            .chain(words[6..8].iter())
+            .chain(self.payload().iter())
+            .flat_map(|w| w.to_be_bytes())
+            .collect::<Vec<_>>();
+        crate::util::hash32(&bytes)
+    }
+
+    pub fn sign(&mut self, secret_key: &SecretKey) {
+        let sig = secret_key.sign(&self.digest());
+        self.sig = u64::from(&sig);
+    }
+
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        let sig = Signature::from(self.sig);
+        public_key.is_valid(&self.digest(), &sig)
+    }
+
+    // The keys the frame's signature can be of, see `ec::recover`
+    pub fn recover(&self) -> Vec<PublicKey> {
+        let sig = Signature::from(self.sig);
+        crate::ec::recover(&self.digest(), &sig)
+    }
+
+    // The public key a client's frame is signed with may lead the
+    // payload, if the first 8 bytes are a point on the curve (what
+    // else could be there is not, but for a chance of about 2^-31)
+    // with the fingerprint in `key`: any other key is not the one
+    // the frame is for. Without it, the key is recovered from the
+    // signature, see `signer`.
+    pub fn carried(&self) -> Option<PublicKey> {
+        PublicKey::from_bytes(self.data.get(..8)?)
+            .ok()
+            .filter(|key| key.fingerprint() == self.key)
+    }
+
+    // The public key a client's frame is signed with: the one
+    // carried in the payload, or else the one of the keys the
+    // signature can be of with the fingerprint in `key`
+    pub fn signer(&self) -> Option<PublicKey> {
+        let key = self.carried().or_else(|| {
+            self.recover()
+                .into_iter()
+                .find(|key| key.fingerprint() == self.key)
+        })?;
+        self.verify(&key).then_some(key)
+    }
+
+    // crc32 over all the words except `sum` itself
+    pub fn checksum(&self) -> u32 {
+        let words = self.words();
+        let bytes = words[..8]
+            .iter()
+            .chain(self.payload().iter())
+            .flat_map(|w| w.to_be_bytes())
+            .collect::<Vec<_>>();
+        crate::util::crc32(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::arbitrary;
+
+    proptest! {
+        #[test]
+        fn test_any_words(frame in arbitrary::frame()) {
+            let header = Frame::from(frame.words());
+            prop_assert_eq!(header.words(), frame.words());
+            prop_assert!(header.data.is_empty());
+            let words = frame.encode();
+            let decoded = Frame::decode(&words).unwrap();
+            prop_assert_eq!(decoded, frame.clone());
+            // cut short, or with a word too many
+            let shorter = &words[..words.len() - 1];
+            prop_assert!(Frame::decode(shorter).is_err());
+            let mut longer = words.clone();
+            longer.push(0);
+            prop_assert!(Frame::decode(&longer).is_err());
         }
     }
 }
@@ -0,0 +1,92 @@
+use std::cell::Cell;
+
+use proptest::{
+    arbitrary::Arbitrary, collection::vec, prelude::*,
+    strategy::BoxedStrategy,
+};
+
+use crate::{
+    api::Frame,
+    ec::{curve, Scheme, SecretKey},
+    xor,
+};
+
+// Payloads of the frames generated: up to so many bytes (a frame
+// takes up to MAX_PAYLOAD_LEN, it would only make the cases slow)
+pub const MAX_DATA_LEN: usize = 256;
+
+// Any header, any payload, and the checksum that goes with them:
+// the frames a peer takes in as they are
+impl Arbitrary for Frame {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (any::<[u32; 9]>(), vec(any::<u8>(), 0..=MAX_DATA_LEN))
+            .prop_map(|(words, data)| {
+                let mut frame = Frame::from(words);
+                frame.data = data;
+                frame.sum = frame.checksum();
+                frame
+            })
+            .boxed()
+    }
+}
+
+pub fn frame() -> impl Strategy<Value = Frame> {
+    any::<Frame>()
+}
+
+// In [1, N), of either scheme
+pub fn secret_key() -> impl Strategy<Value = SecretKey> {
+    (
+        1..curve::N as u32,
+        prop_oneof![Just(Scheme::Ecdsa), Just(Scheme::Schnorr)],
+    )
+        .prop_map(|(secret, scheme)| {
+            SecretKey::with_scheme(secret, scheme)
+        })
+}
+
+// A secret and its XOR shares (`xor::split`), 1 to `max` of them
+pub fn shares(
+    max: usize,
+) -> impl Strategy<Value = (u32, Vec<u32>)> {
+    (any::<u32>(), vec(any::<u32>(), 1..=max)).prop_map(
+        |(secret, words)| {
+            let shares =
+                xor::split(secret, words.len(), next(&words));
+            (secret, shares)
+        },
+    )
+}
+
+// Same, of a secret of bytes (`xor::split_bytes`)
+pub fn byte_shares(
+    max: usize,
+) -> impl Strategy<Value = (Vec<u8>, Vec<Vec<u8>>)> {
+    (vec(any::<u8>(), 0..=MAX_DATA_LEN), 1..=max)
+        .prop_flat_map(|(secret, n)| {
+            // a word for 4 bytes of every share
+            let words =
+                vec(any::<u32>(), n * secret.len().div_ceil(4));
+            (Just(secret), Just(n), words)
+        })
+        .prop_map(|(secret, n, words)| {
+            let shares =
+                xor::split_bytes(&secret, n, next(&words));
+            (secret, shares)
+        })
+}
+
+// The words generated, one after the other, for the functions that
+// take a source of randomness
+fn next(words: &[u32]) -> impl Fn() -> u32 + '_ {
+    let i = Cell::new(0);
+    move || {
+        let word =
+            words.get(i.get()).copied().unwrap_or_default();
+        i.set(i.get() + 1);
+        word
+    }
+}
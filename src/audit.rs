@@ -0,0 +1,308 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::api::{Error, Result};
+
+// Entries kept in memory for `recent`
+pub const RECENT: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Op {
+    Get,
+    Set,
+    Patch,
+    Delete,
+    Sign,
+}
+
+impl Op {
+    const ALL: [Op; 5] =
+        [Op::Get, Op::Set, Op::Patch, Op::Delete, Op::Sign];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Get => "get",
+            Op::Set => "set",
+            Op::Patch => "patch",
+            Op::Delete => "delete",
+            Op::Sign => "sign",
+        }
+    }
+}
+
+// An access to a key and what came of it: zero if it went through,
+// the error code (`ERR_*`) otherwise
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub at: u32, // unix seconds
+    pub op: Op,
+    pub ns: u32,
+    pub key: u32,
+    pub peer: IpAddr,
+    pub outcome: u32,
+}
+
+// A line of the audit file:
+// `time=<secs> op=<op> ns=<ns> key=<hex> peer=<ip> outcome=<ok or code>`
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "time={} op={} ns={} key={:0x} peer={} outcome=",
+            self.at,
+            self.op.name(),
+            self.ns,
+            self.key,
+            self.peer
+        )?;
+        match self.outcome {
+            0 => write!(f, "ok"),
+            code => write!(f, "{code}"),
+        }
+    }
+}
+
+impl FromStr for Entry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let invalid = || {
+            Error::App(format!("invalid audit entry: {line}"))
+        };
+        let mut fields = line.split(' ').map(|field| {
+            field.split_once('=').map(|(_, value)| value)
+        });
+        let mut next =
+            || fields.next().flatten().ok_or_else(invalid);
+        let at = next()?.parse().map_err(|_| invalid())?;
+        let op = next()?;
+        let op = Op::ALL
+            .into_iter()
+            .find(|o| o.name() == op)
+            .ok_or_else(invalid)?;
+        let ns = next()?.parse().map_err(|_| invalid())?;
+        let key = u32::from_str_radix(next()?, 16)
+            .map_err(|_| invalid())?;
+        let peer = next()?.parse().map_err(|_| invalid())?;
+        let outcome = match next()? {
+            "ok" => 0,
+            code => code.parse().map_err(|_| invalid())?,
+        };
+        Ok(Self {
+            at,
+            op,
+            ns,
+            key,
+            peer,
+            outcome,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    file: Option<File>,
+    recent: VecDeque<Entry>,
+}
+
+// Append-only record of the accesses to the keys: a line per entry
+// in the file (if any), the latest `capacity` entries in memory to
+// be queried. The file is only appended to (not synced, the shares
+// are), a line torn by a crash is skipped when the file is opened.
+#[derive(Debug)]
+pub struct Audit {
+    state: Mutex<State>,
+    capacity: usize,
+}
+
+impl Audit {
+    // in memory only
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                file: None,
+                recent: VecDeque::new(),
+            }),
+            capacity,
+        }
+    }
+
+    // appended to, and the latest entries already there are recent
+    pub fn open(path: &Path, capacity: usize) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                String::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut recent = text
+            .lines()
+            .rev()
+            .filter_map(|line| line.parse().ok())
+            .take(capacity)
+            .collect::<VecDeque<_>>();
+        recent.make_contiguous().reverse();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            file.write_all(b"\n")?; // after the torn line
+        }
+        Ok(Self {
+            state: Mutex::new(State {
+                file: Some(file),
+                recent,
+            }),
+            capacity,
+        })
+    }
+
+    // kept in memory even if it could not be written down
+    pub fn record(&self, entry: Entry) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let written = match state.file.as_mut() {
+            Some(file) => {
+                file.write_all(format!("{entry}\n").as_bytes())
+            }
+            None => Ok(()),
+        };
+        state.recent.push_back(entry);
+        if state.recent.len() > self.capacity {
+            state.recent.pop_front();
+        }
+        Ok(written?)
+    }
+
+    // the latest `n` entries (of the `key` if there is one), oldest
+    // first
+    pub fn recent(
+        &self,
+        n: usize,
+        key: Option<u32>,
+    ) -> Vec<Entry> {
+        let state = self.state.lock().unwrap();
+        let mut entries = state
+            .recent
+            .iter()
+            .rev()
+            .filter(|e| key.is_none_or(|key| e.key == key))
+            .take(n)
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.reverse();
+        entries
+    }
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self::new(RECENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::random;
+
+    use super::*;
+
+    fn entry(at: u32, op: Op, key: u32, outcome: u32) -> Entry {
+        Entry {
+            at,
+            op,
+            ns: 0,
+            key,
+            peer: [127, 0, 0, 1].into(),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_entry() -> Result<()> {
+        let e = entry(1700000000, Op::Get, 0x58a2edb2, 0);
+        let line = e.to_string();
+        assert_eq!(
+            line,
+            "time=1700000000 op=get ns=0 key=58a2edb2 \
+             peer=127.0.0.1 outcome=ok"
+        );
+        assert_eq!(line.parse::<Entry>()?, e);
+        let e = Entry {
+            ns: 7,
+            peer: "::1".parse().unwrap(),
+            ..entry(1, Op::Delete, 1, 32001)
+        };
+        assert_eq!(e.to_string().parse::<Entry>()?, e);
+        assert!("time=1 op=get key=1".parse::<Entry>().is_err());
+        assert!(
+            "time=1 op=put ns=0 key=1 peer=127.0.0.1 outcome=ok"
+                .parse::<Entry>()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recent() -> Result<()> {
+        let audit = Audit::new(3);
+        for at in 1..=4 {
+            audit.record(entry(at, Op::Set, at % 2, 0))?;
+        }
+        let at = |entries: Vec<Entry>| {
+            entries.iter().map(|e| e.at).collect::<Vec<_>>()
+        };
+        assert_eq!(at(audit.recent(10, None)), vec![2, 3, 4]);
+        assert_eq!(at(audit.recent(2, None)), vec![3, 4]);
+        assert_eq!(at(audit.recent(10, Some(1))), vec![3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}.audit",
+            random()
+        ));
+        {
+            let audit = Audit::open(&path, 2)?;
+            audit.record(entry(1, Op::Set, 1, 0))?;
+            audit.record(entry(2, Op::Get, 1, 0))?;
+            audit.record(entry(3, Op::Patch, 1, 0))?;
+        }
+        // torn by a crash
+        let mut file =
+            OpenOptions::new().append(true).open(&path)?;
+        file.write_all(b"time=4 op=del")?;
+
+        let audit = Audit::open(&path, 2)?;
+        assert_eq!(
+            audit.recent(10, None),
+            vec![
+                entry(2, Op::Get, 1, 0),
+                entry(3, Op::Patch, 1, 0)
+            ]
+        );
+        audit.record(entry(5, Op::Delete, 1, 32001))?;
+        let text = fs::read_to_string(&path)?;
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[3], "time=4 op=del");
+        assert_eq!(
+            lines[4],
+            entry(5, Op::Delete, 1, 32001).to_string()
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,161 +1,792 @@
 use std::{
-    env::args,
-    net::{SocketAddr, TcpStream},
+    collections::HashMap,
+    fs,
+    io::IsTerminal,
+    net::SocketAddr,
+    num::ParseIntError,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
+use clap::{Args, Parser, Subcommand};
+
 use doing_some_blockchain::{
-    api::{
-        Error, Frame, Receiver, Result, Sender, TAG_OK,
-        TAG_PUBLIC_KEY, TAG_SECRET_SHARE,
-    },
-    dhke::dhke_handshake,
-    tcp::Tcp,
-    util::{merge, random, time},
-    xor,
+    api::{Error, Result, TAG_OK, TAG_SECRET_SHARE},
+    client::{self, Client, Config, Quorum, Scheme, Secret},
+    dhke::Group,
+    ec::{Encoding, PublicKey, SecretKey},
+    retry::Retry,
+    tcp::Rekey,
+    util::{from_hex, load_env, to_hex},
 };
 
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+// The servers' addresses, the key and the command come from the
+// args, how to reach the servers from the environment (`config`)
+fn config() -> Config {
+    let default = Config::default();
+    Config {
+        timeout: millis("TIMEOUT").unwrap_or(default.timeout),
+        connect_timeout: millis("CONNECT_TIMEOUT")
+            .unwrap_or(default.connect_timeout),
+        retry: retry_config(),
+        namespace: namespace(),
+        exchange: key_exchange(),
+        server_keys: server_keys(),
+        known_hosts: std::env::var_os("KNOWN_HOSTS")
+            .map(|path| known_hosts(path.as_ref()))
+            .unwrap_or_default(),
+        psk: psk(),
+        rekey: rekey(),
+        quorum: quorum(None, None, None),
+        #[cfg(feature = "tls")]
+        tls: tls_config(),
+        #[cfg(feature = "quic")]
+        quic: quic_config(),
+        #[cfg(feature = "noise")]
+        noise: noise_config(),
+        #[cfg(feature = "testkit")]
+        network: None,
+    }
+}
 
-fn client(addr: &SocketAddr, frame: &Frame) -> Result<Frame> {
-    let frame = frame.clone();
-    let socket = TcpStream::connect(addr)?;
-    let mut tx = Tcp::from(socket);
-    let a = random();
-    let key = dhke_handshake(&tx, DEFAULT_TIMEOUT, a)?;
-    tx.set_key(key);
-    tx.send(&frame)?;
-    println!("debug: send: {frame:?}");
-    let frame: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
-    println!("debug: recv: {frame:?}");
-    Ok(frame)
+// TIMEOUT (of a response and of the handshake) and CONNECT_TIMEOUT,
+// in milliseconds, 2 seconds each by default
+fn millis(name: &str) -> Option<Duration> {
+    let millis = std::env::var(name).ok()?;
+    let millis = millis
+        .parse()
+        .unwrap_or_else(|_| panic!("invalid {name}"));
+    Some(Duration::from_millis(millis))
 }
 
-const USAGE: &str =
-    "Usage: <pubkey> <host:port> <get/set> [<secret>]";
+// REPLICAS (default 1): servers keeping each share, consecutive
+// ones in the list; WRITE_QUORUM of them take a share for a write
+// to count (a majority by default) and READ_QUORUM answer a read
+// (by default the fewest for W + R to be over N); the flags over
+// them
+fn quorum(
+    n: Option<usize>,
+    w: Option<usize>,
+    r: Option<usize>,
+) -> Quorum {
+    let var = |name: &str| {
+        std::env::var(name).ok().map(|s| {
+            s.parse()
+                .unwrap_or_else(|_| panic!("invalid {name}"))
+        })
+    };
+    let n = n.or_else(|| var("REPLICAS")).unwrap_or(1);
+    let w = w
+        .or_else(|| var("WRITE_QUORUM"))
+        .unwrap_or(Quorum::majority(n).w);
+    let r = r
+        .or_else(|| var("READ_QUORUM"))
+        .unwrap_or((n + 1).saturating_sub(w));
+    Quorum { n, w, r }
+}
 
-fn main() -> Result<()> {
-    let args = args().skip(1).collect::<Vec<_>>();
-    if args.len() < 4 {
-        eprintln!("{USAGE}");
-        return Err(Error::App("invalid args".to_string()));
+// RETRIES (default 2) after the first failed attempt, the first of
+// them after RETRY_BACKOFF milliseconds (default 100), doubling up
+// to RETRY_MAX_BACKOFF milliseconds (default 2000)
+fn retry_config() -> Retry {
+    let mut retry = Retry::default();
+    if let Ok(retries) = std::env::var("RETRIES") {
+        retry.retries =
+            retries.parse().expect("invalid RETRIES");
     }
+    if let Some(backoff) = millis("RETRY_BACKOFF") {
+        retry.backoff = backoff;
+    }
+    if let Some(max) = millis("RETRY_MAX_BACKOFF") {
+        retry.max_backoff = max;
+    }
+    retry
+}
 
-    let (((key, addr1), addr2), cmd) = args
-        .get(0)
-        .zip(args.get(1))
-        .zip(args.get(2))
-        .zip(args.get(3))
-        .expect(USAGE);
-    let key =
-        u32::from_str_radix(key, 16).expect("invalid key hex");
-    let addr1: SocketAddr =
-        addr1.parse().expect("invalid peer address provided");
-    let addr2: SocketAddr =
-        addr2.parse().expect("invalid peer address provided");
-    let peers = [addr1, addr2];
+// KEY_EXCHANGE (comma-separated: `x25519`, `dhke`) offered in the
+// handshake, all of them by default
+fn key_exchange() -> Vec<Group> {
+    match std::env::var("KEY_EXCHANGE") {
+        Ok(groups) => groups
+            .split(',')
+            .map(|group| group.trim().parse())
+            .collect::<Result<_>>()
+            .expect("invalid KEY_EXCHANGE"),
+        Err(_) => Group::ALL.to_vec(),
+    }
+}
 
-    match (cmd.as_ref(), args.get(4)) {
-        ("get", _) => {
-            let secret = get_secret(key, &peers)?;
-            println!("{secret:0x}");
-        }
-        ("set", Some(secret)) => {
-            let secret = u32::from_str_radix(secret, 16)
-                .expect("invalid secret hex");
-            set_secret(key, &peers, secret)?;
+// PSK (hex): the key shared with the servers out of band, instead
+// of the key exchange
+fn psk() -> Option<Vec<u8>> {
+    let hex = std::env::var("PSK").ok()?;
+    Some(from_hex(hex.trim()).expect("invalid PSK"))
+}
+
+// REKEY_FRAMES and/or REKEY_INTERVAL (seconds): new session keys
+// after so many frames sent or so much time, see `tcp::Rekey`
+fn rekey() -> Option<Rekey> {
+    let frames = std::env::var("REKEY_FRAMES")
+        .ok()
+        .map(|s| s.parse().expect("invalid REKEY_FRAMES"));
+    let every = std::env::var("REKEY_INTERVAL").ok().map(|s| {
+        Duration::from_secs(
+            s.parse().expect("invalid REKEY_INTERVAL"),
+        )
+    });
+    (frames.is_some() || every.is_some()).then(|| Rekey {
+        frames: frames.unwrap_or(u64::MAX),
+        every: every.unwrap_or(Duration::MAX),
+    })
+}
+
+// SERVER_KEYS (comma-separated, hex, as logged by the servers on
+// startup): the handshake is authenticated, a server must sign it
+// with one of them
+fn server_keys() -> Vec<PublicKey> {
+    std::env::var("SERVER_KEYS")
+        .map(|keys| {
+            keys.split(',')
+                .map(|key| PublicKey::from_hex(key.trim()))
+                .collect::<Result<_>>()
+                .expect("invalid SERVER_KEYS")
+        })
+        .unwrap_or_default()
+}
+
+// KNOWN_HOSTS or `--known-hosts` (file of `<address> <public key>`
+// lines): each server must sign the handshake with its own key
+fn known_hosts(path: &Path) -> HashMap<SocketAddr, PublicKey> {
+    fs::read_to_string(path)
+        .map_err(Error::from)
+        .and_then(|text| client::known_hosts(&text))
+        .unwrap_or_else(|e| panic!("{}: {e:?}", path.display()))
+}
+
+// TLS_CA (PEM file) to connect over TLS instead of DHKE+XOR
+#[cfg(feature = "tls")]
+fn tls_config() -> Option<std::sync::Arc<rustls::ClientConfig>> {
+    let path = std::env::var("TLS_CA").ok()?;
+    let ca = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("{path}: {e}"));
+    Some(
+        doing_some_blockchain::tls::client_config(&ca)
+            .expect("invalid TLS_CA"),
+    )
+}
+
+// QUIC (any value) to connect over QUIC, verified with TLS_CA
+#[cfg(feature = "quic")]
+fn quic_config() -> bool {
+    let quic = std::env::var("QUIC").is_ok();
+    if quic && std::env::var("TLS_CA").is_err() {
+        panic!("TLS_CA is not set");
+    }
+    quic
+}
+
+// NOISE_PEERS (comma-separated hex) are trusted static public keys
+// of the servers, the client's own static key is ephemeral
+#[cfg(feature = "noise")]
+fn noise_config() -> Option<(Vec<u8>, Vec<Vec<u8>>)> {
+    use doing_some_blockchain::noise::generate_keypair;
+    let peers = std::env::var("NOISE_PEERS").ok()?;
+    let trusted = peers
+        .split(',')
+        .map(|hex| {
+            from_hex(hex.trim())
+                .expect("invalid NOISE_PEERS hex")
+        })
+        .collect();
+    let (key, _) = generate_keypair().expect("noise keypair");
+    Some((key, trusted))
+}
+
+// NAMESPACE (default 0) the secret is kept in, the servers only let
+// the keys they list for a namespace other than zero use it
+fn namespace() -> u32 {
+    std::env::var("NAMESPACE")
+        .map_or(0, |ns| ns.parse().expect("invalid NAMESPACE"))
+}
+
+// Same as the server's: RUST_LOG for the level (`warn` unless set,
+// stdout is for the results), LOG_FORMAT=json for JSON lines
+fn init_tracing() {
+    use tracing_subscriber::EnvFilter;
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("warn"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if std::env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+// `<key> <host:port>... <command>`, e.g. `12345678 127.0.0.1:10001
+// 127.0.0.1:10002 get`
+#[derive(Parser)]
+#[command(subcommand_precedence_over_arg = true)]
+struct Cli {
+    /// The owner's secret key (hex)
+    #[arg(value_parser = hex)]
+    key: u32,
+    /// The servers, a share of the secret per server (per
+    /// `--replicas` of them)
+    #[arg(required = true)]
+    peers: Vec<SocketAddr>,
+    /// Of a response and of the handshake, in milliseconds
+    /// (TIMEOUT otherwise)
+    #[arg(long, global = true, value_name = "MS", value_parser = positive)]
+    timeout: Option<u64>,
+    /// Of a connection to a server, in milliseconds
+    /// (CONNECT_TIMEOUT otherwise)
+    #[arg(long, global = true, value_name = "MS", value_parser = positive)]
+    connect_timeout: Option<u64>,
+    /// Attempts after the first one on connection errors and
+    /// timeouts (RETRIES otherwise)
+    #[arg(long, global = true, value_name = "N")]
+    retries: Option<u32>,
+    /// Before the first retry, in milliseconds, doubling for each
+    /// next one (RETRY_BACKOFF otherwise)
+    #[arg(long, global = true, value_name = "MS")]
+    retry_backoff: Option<u64>,
+    /// Longest wait between retries, in milliseconds
+    /// (RETRY_MAX_BACKOFF otherwise)
+    #[arg(long, global = true, value_name = "MS")]
+    max_backoff: Option<u64>,
+    /// Servers keeping each share, consecutive ones in the list
+    /// (REPLICAS otherwise, 1 by default)
+    #[arg(long, global = true, value_name = "N")]
+    replicas: Option<usize>,
+    /// Replicas to take a share for a write to count, a majority
+    /// by default (WRITE_QUORUM otherwise)
+    #[arg(long, global = true, value_name = "W")]
+    write_quorum: Option<usize>,
+    /// Replicas to answer a read, W + R must be over N
+    /// (READ_QUORUM otherwise)
+    #[arg(long, global = true, value_name = "R")]
+    read_quorum: Option<usize>,
+    /// `NAME=value` lines, for the env variables not set
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// The servers are the live members of the cluster, as the
+    /// first of the ones given to answer sees it (gossip)
+    #[arg(long, global = true)]
+    discover: bool,
+    /// `<address> <public key>` lines: the servers' keys, pinned
+    /// (KNOWN_HOSTS otherwise)
+    #[arg(long, global = true, value_name = "FILE")]
+    known_hosts: Option<PathBuf>,
+    /// Print the result of `get`, `set` and `rotate` as JSON, along
+    /// with how each server responded (status, error code, time)
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Print the secret
+    Get,
+    /// Store the secret, a share per server
+    Set {
+        #[command(flatten)]
+        shares: Shares,
+        /// The secret (hex), or SECRET if not given: an argument
+        /// is seen in `ps` and kept in the shell's history
+        secret: Option<String>,
+        /// Read the secret (hex) from stdin instead
+        #[arg(long, conflicts_with = "secret")]
+        secret_stdin: bool,
+    },
+    /// Replace the secret (it must be there) with a new one, the
+    /// old one is stored again if any server fails to take its
+    /// share of the new one
+    Rotate {
+        #[command(flatten)]
+        shares: Shares,
+        /// The new secret (hex), a random one of the same kind if
+        /// not given
+        secret: Option<String>,
+        /// Read the new secret (hex) from stdin
+        #[arg(long, conflicts_with = "secret")]
+        secret_stdin: bool,
+    },
+    /// Delete the secret from all the servers
+    Delete,
+    /// The keys each server keeps a secret of
+    List,
+    /// Round-trip time to each server (handshake included)
+    Ping,
+    /// The cluster as each server sees it (gossip), a member and
+    /// whether it is alive or suspect a line
+    Members,
+    /// The server's metrics (needs ADMIN_KEY)
+    Status,
+    /// Add a running server to the cluster (needs ADMIN_KEY): the
+    /// servers take it as a peer, the first one hands the XOR
+    /// shares over to it
+    Join { addr: SocketAddr },
+    /// Take a server out of the cluster (needs ADMIN_KEY): it
+    /// hands its XOR shares over to its peers, then the servers
+    /// drop it
+    Leave { addr: SocketAddr },
+    /// The server's snapshot to a file, one server at a time
+    Snapshot { path: PathBuf },
+    /// The server's audit log (needs ADMIN_KEY), of a single key
+    /// (fingerprint, hex) if given
+    Audit {
+        #[arg(value_parser = hex)]
+        of: Option<u32>,
+    },
+    /// The head of the server's ledger of changes: how many there
+    /// were, the hash of them all and the server's signature
+    Ledger,
+    /// Commands from stdin, a line each (e.g. `set CAFEBABE`), on
+    /// the same sessions, until `quit`
+    Repl,
+    /// Threshold signature of the message (hex) with the shared
+    /// signing key, see `set --signing`
+    Sign {
+        #[arg(value_parser = hex)]
+        msg: u32,
+    },
+}
+
+// How `set` and `rotate` share the secret
+#[derive(Args)]
+struct Shares {
+    /// k-of-n Shamir shares instead of XOR ones (`get` tells the
+    /// scheme from the responses)
+    #[arg(long, value_name = "K")]
+    threshold: Option<usize>,
+    /// Feldman shares: each server checks its share against the
+    /// commitments, and so does `get` (n-of-n by default)
+    #[arg(long, conflicts_with = "signing")]
+    verifiable: bool,
+    /// The secret is a signing key, for the servers to sign with
+    /// (see `sign`) without putting it together
+    #[arg(long)]
+    signing: bool,
+    /// The secret is hex bytes of any length, XOR-shared
+    #[arg(
+        long,
+        conflicts_with_all = ["threshold", "verifiable", "signing"]
+    )]
+    bytes: bool,
+    /// The servers drop the secret that many seconds later
+    #[arg(long, default_value_t = 0, value_name = "SECONDS")]
+    ttl: u32,
+}
+
+impl Shares {
+    // n: the number of servers
+    fn scheme(&self, n: usize) -> Scheme {
+        match (self.threshold, self.verifiable, self.signing) {
+            (k, _, true) => Scheme::Frost(k.unwrap_or(n)),
+            (k, true, _) => Scheme::Feldman(k.unwrap_or(n)),
+            (Some(k), false, _) => Scheme::Shamir(k),
+            (None, false, _) => Scheme::Xor,
         }
-        _ => {
-            return Err(Error::App("invalid cmd".to_string()));
+    }
+
+    fn parse(&self, secret: &str) -> Result<Secret> {
+        if self.bytes {
+            from_hex(secret).map(Secret::Bytes).ok_or_else(
+                || Error::App("invalid secret hex".to_string()),
+            )
+        } else {
+            hex(secret).map(Secret::Word).map_err(|e| {
+                Error::App(format!("invalid secret hex: {e}"))
+            })
         }
     }
+}
 
-    Ok(())
+// A zero timeout would be no timeout at all for a socket
+fn positive(s: &str) -> std::result::Result<u64, String> {
+    match s.parse() {
+        Ok(0) => Err("must be positive".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{e}")),
+    }
 }
 
-fn get_secret(key: u32, peers: &[SocketAddr]) -> Result<u32> {
-    println!("debug: get secret from {peers:?} [key={key:0x}]");
+fn hex(s: &str) -> std::result::Result<u32, ParseIntError> {
+    u32::from_str_radix(s, 16)
+}
 
-    let frame = Frame {
-        idx: time(),
-        tag: TAG_PUBLIC_KEY,
-        msg: 0,
-        key,
-        sig: merge(key, key),
-        ext: 0,
-        sum: 0xFACE,
+// The argument if given, the first line of stdin with
+// `--secret-stdin`, SECRET otherwise
+fn read_secret(
+    arg: Option<String>,
+    stdin: bool,
+) -> Result<String> {
+    let secret = if let Some(secret) = arg {
+        secret
+    } else if stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line
+    } else {
+        std::env::var("SECRET").map_err(|_| {
+            Error::App(
+                "no secret: not given, no --secret-stdin, no SECRET"
+                    .to_string(),
+            )
+        })?
+    };
+    let secret = secret.trim().to_string();
+    if secret.is_empty() {
+        return Err(Error::App("empty secret".to_string()));
+    }
+    Ok(secret)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(path) = &cli.config {
+        load_env(path)?;
+    }
+    init_tracing();
+    let mut config = config();
+    if let Some(millis) = cli.timeout {
+        config.timeout = Duration::from_millis(millis);
+    }
+    if let Some(millis) = cli.connect_timeout {
+        config.connect_timeout = Duration::from_millis(millis);
+    }
+    if let Some(path) = &cli.known_hosts {
+        config.known_hosts = known_hosts(path);
+    }
+    if let Some(retries) = cli.retries {
+        config.retry.retries = retries;
+    }
+    if let Some(millis) = cli.retry_backoff {
+        config.retry.backoff = Duration::from_millis(millis);
+    }
+    if let Some(millis) = cli.max_backoff {
+        config.retry.max_backoff = Duration::from_millis(millis);
+    }
+    if cli.replicas.is_some()
+        || cli.write_quorum.is_some()
+        || cli.read_quorum.is_some()
+    {
+        config.quorum = quorum(
+            cli.replicas,
+            cli.write_quorum,
+            cli.read_quorum,
+        );
+    }
+    config.quorum.check()?;
+    let peers = if cli.discover {
+        let key = SecretKey::new(cli.key);
+        Client::new(key, cli.peers, config.clone()).discover()?
+    } else {
+        cli.peers
     };
+    let client =
+        Client::new(SecretKey::new(cli.key), peers, config);
+    match cli.cmd {
+        Cmd::Repl => repl(&client, cli.json),
+        cmd => run(&client, cmd, cli.json),
+    }
+}
 
-    let mut secret: u32 = 0;
+// A line of `repl`: a command and its args, same as after the
+// servers' addresses
+#[derive(Parser)]
+#[command(name = "", no_binary_name = true)]
+struct Line {
+    /// Same as `--json` of the client, for this command
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    cmd: Cmd,
+}
 
-    let mut errors = Vec::with_capacity(peers.len());
-    for addr in peers {
-        let response = match client(addr, &frame) {
-            Ok(frame) => frame,
+// Commands from stdin, a line each, all of them on the same client
+// (so on the same sessions, handshakes done once), until `quit` or
+// EOF; a command failing does not end it
+fn repl(client: &Client, json: bool) -> Result<()> {
+    let stdin = std::io::stdin();
+    let prompt = stdin.is_terminal();
+    loop {
+        if prompt {
+            eprint!("> ");
+        }
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.first() {
+            None => continue,
+            Some(&"quit" | &"exit") => return Ok(()),
+            Some(_) => (),
+        }
+        match Line::try_parse_from(words) {
+            Ok(Line { cmd: Cmd::Repl, .. }) => {
+                eprintln!("Error: already there");
+            }
+            Ok(Line { cmd, json: once }) => {
+                if let Err(e) = run(client, cmd, json || once) {
+                    eprintln!("Error: {e:?}");
+                }
+            }
+            // usage errors and `help` alike
             Err(e) => {
-                let message =
-                    format!("error: peer={addr} err={e:?}");
-                errors.push(message);
-                continue;
+                let _ = e.print();
             }
-        };
-
-        if response.tag != TAG_OK {
-            let message = format!(
-                "error: peer={addr} tag={} ext={}",
-                response.tag, response.ext
-            );
-            errors.push(message);
-            continue;
         }
-        secret ^= response.msg;
     }
+}
 
-    if !errors.is_empty() {
-        return Err(Error::App(errors.join("; ")));
+fn run(client: &Client, cmd: Cmd, json: bool) -> Result<()> {
+    let peers = &client.peers;
+    let replicas = client.config.quorum.n;
+    match cmd {
+        Cmd::Repl => unreachable!("see `repl`"),
+        Cmd::Get => {
+            let secret = client.get_secret();
+            if replicas > 1 && !json {
+                acks(client);
+            }
+            output(client, json, secret.map(Some))?;
+        }
+        Cmd::Set {
+            shares,
+            secret,
+            secret_stdin,
+        } => {
+            let secret = shares
+                .parse(&read_secret(secret, secret_stdin)?)?;
+            let scheme = shares.scheme(peers.len() / replicas);
+            let stored = client.set(&secret, scheme, shares.ttl);
+            // enough of them have it, the rest are to be repaired
+            // (the secret set again) when they are back
+            for addr in stored.iter().flatten() {
+                eprintln!("warning: no share on {addr}");
+            }
+            output(client, json, stored.map(|_| None))?;
+        }
+        Cmd::Rotate {
+            shares,
+            secret,
+            secret_stdin,
+        } => {
+            let new = if secret.is_some() || secret_stdin {
+                let secret = read_secret(secret, secret_stdin)?;
+                Some(shares.parse(&secret)?)
+            } else {
+                None
+            };
+            let scheme = shares.scheme(peers.len() / replicas);
+            let rotated = client.rotate(new, scheme, shares.ttl);
+            if !json {
+                // how each server took its share, of the new secret
+                // (of the old one too, if it had to be restored)
+                for o in client
+                    .outcomes()
+                    .iter()
+                    .filter(|o| o.tag == TAG_SECRET_SHARE)
+                {
+                    match (o.status, &o.error) {
+                        (Some(TAG_OK), _) => {
+                            println!("{}: ok", o.peer)
+                        }
+                        (Some(tag), _) => println!(
+                            "{}: tag={tag} ext={}",
+                            o.peer, o.code
+                        ),
+                        (None, e) => println!(
+                            "{}: {}",
+                            o.peer,
+                            e.as_deref().unwrap_or_default()
+                        ),
+                    }
+                }
+            }
+            output(client, json, rotated.map(Some))?;
+        }
+        Cmd::Delete => {
+            client.delete_secret()?;
+        }
+        Cmd::Sign { msg } => {
+            let (group_key, sig) = client.sign(msg)?;
+            println!("{} {}", group_key.to_hex(), sig.to_hex());
+        }
+        Cmd::Ping => {
+            for addr in peers {
+                match client.ping(addr) {
+                    Ok(rtt) => println!("{addr}: {rtt:?}"),
+                    Err(e) => println!("{addr}: {e:?}"),
+                }
+            }
+        }
+        Cmd::Members => {
+            for addr in peers {
+                match client.members(addr) {
+                    Ok(members) => {
+                        for (member, state) in members {
+                            println!("{addr}: {member} {state}");
+                        }
+                    }
+                    Err(e) => println!("{addr}: {e:?}"),
+                }
+            }
+        }
+        Cmd::List => {
+            for addr in peers {
+                let keys = client.list_keys(addr)?;
+                let keys = keys
+                    .iter()
+                    .map(|key| format!("{key:0x}"))
+                    .collect::<Vec<_>>();
+                println!("{addr}: {}", keys.join(" "));
+            }
+        }
+        Cmd::Status => {
+            for addr in peers {
+                let lines = client.status(addr)?;
+                println!("{addr}: {}", lines.join(" "));
+            }
+        }
+        Cmd::Join { addr: new } => {
+            for (i, addr) in peers.iter().enumerate() {
+                let lines = client.join(addr, new, i == 0)?;
+                println!("{addr}: {}", lines.join(" "));
+            }
+        }
+        Cmd::Leave { addr: leaving } => {
+            // all its shares handed over before anyone drops it
+            let lines = client.leave(&leaving, leaving)?;
+            println!("{leaving}: {}", lines.join(" "));
+            for addr in peers.iter().filter(|a| **a != leaving) {
+                let lines = client.leave(addr, leaving)?;
+                println!("{addr}: {}", lines.join(" "));
+            }
+        }
+        Cmd::Audit { of } => {
+            for addr in peers {
+                for line in client.audit(addr, of)? {
+                    println!("{addr}: {line}");
+                }
+            }
+        }
+        Cmd::Ledger => {
+            for addr in peers {
+                let head = client.ledger(addr)?;
+                println!(
+                    "{addr}: len={} head={} sig={:016x}",
+                    head.len,
+                    to_hex(&head.hash),
+                    head.sig
+                );
+            }
+        }
+        Cmd::Snapshot { path } => {
+            // a file per server, the shares are not the same
+            let [addr] = peers.as_slice() else {
+                return Err(Error::App(
+                    "snapshot: one server at a time".to_string(),
+                ));
+            };
+            let snapshot = client.snapshot(addr)?;
+            fs::write(path, &snapshot)?;
+            println!("{addr}: {} bytes", snapshot.len());
+        }
     }
 
-    Ok(secret)
+    Ok(())
 }
 
-fn set_secret(
-    key: u32,
-    peers: &[SocketAddr],
-    secret: u32,
+// The secret if there is one, as JSON with `--json`: `{"ok":true,
+// "secret":"cafebabe","peers":[{"peer":"127.0.0.1:10001","tag":2,
+// "status":200,"code":0,"ms":1.234,"error":null},...]}` (an element
+// per request, `tag` of the request), the error instead of the
+// secret if it failed (and on stderr, same as without), neither for
+// `set`
+fn output(
+    client: &Client,
+    json: bool,
+    result: Result<Option<Secret>>,
 ) -> Result<()> {
-    println!(
-        "debug: set secret '{secret}' to {peers:?} [key={key:0x}]"
-    );
-
-    let shares = xor::split(secret, peers.len(), random);
-    assert_eq!(xor::merge(&shares), secret); // better safe than sorry!
-
-    let mut errors = Vec::with_capacity(peers.len());
-    for (addr, msg) in peers.iter().zip(shares.iter()) {
-        let frame = Frame {
-            idx: time(),
-            tag: TAG_SECRET_SHARE,
-            msg: *msg,
-            key,
-            sig: merge(key, key),
-            ext: 0,
-            sum: 0xFACE,
+    if json {
+        let peers = client
+            .outcomes()
+            .iter()
+            .map(|o| {
+                let status = o
+                    .status
+                    .map_or("null".to_string(), |s| s.to_string());
+                let error = o
+                    .error
+                    .as_deref()
+                    .map_or("null".to_string(), quoted);
+                let ms = o.elapsed.as_secs_f64() * 1000.0;
+                format!(
+                    concat!(
+                        "{{\"peer\":\"{}\",\"tag\":{},",
+                        "\"status\":{},\"code\":{},\"ms\":{:.3},",
+                        "\"error\":{}}}",
+                    ),
+                    o.peer, o.tag, status, o.code, ms, error
+                )
+            })
+            .collect::<Vec<_>>();
+        let field = match &result {
+            Ok(Some(secret)) => {
+                format!(
+                    "\"secret\":{},",
+                    quoted(&secret.to_string())
+                )
+            }
+            Ok(None) => String::new(),
+            Err(e) => format!(
+                "\"error\":{},",
+                quoted(&format!("{e:?}"))
+            ),
         };
-        let response = client(addr, &frame)?;
-
-        if response.tag != TAG_OK {
-            let message = format!(
-                "error: peer={addr} tag={} ext={}",
-                response.tag, response.ext
-            );
-            errors.push(message);
-            continue;
-        }
+        println!(
+            "{{\"ok\":{},{field}\"peers\":[{}]}}",
+            result.is_ok(),
+            peers.join(",")
+        );
+    } else if let Ok(Some(secret)) = &result {
+        println!("{secret}");
     }
+    result.map(|_| ())
+}
 
-    if !errors.is_empty() {
-        return Err(Error::App(errors.join("; ")));
+// Which replicas of each share answered the read, and which failed
+fn acks(client: &Client) {
+    for acks in client.acks() {
+        eprintln!(
+            "share {}: acked by {:?}, failed {:?}",
+            acks.share, acks.acked, acks.failed
+        );
     }
+}
 
-    Ok(())
+// A JSON string
+fn quoted(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
@@ -0,0 +1,20 @@
+// A fresh key pair, for the client (`secret_key`, the `<key>` of
+// its command line) and the servers (`fingerprint`, as in
+// ADMIN_KEY or NAMESPACES), as `name=value` lines of hex:
+//
+// cargo run --bin keygen
+use doing_some_blockchain::{
+    ec::{Encoding, SecretKey},
+    util::to_hex,
+};
+
+fn main() {
+    let secret_key = SecretKey::generate();
+    let public_key = secret_key.public_key();
+    println!("secret_key={}", secret_key.to_hex());
+    println!("public_key={}", public_key.to_hex());
+    println!(
+        "fingerprint={}",
+        to_hex(&public_key.fingerprint().to_be_bytes())
+    );
+}
@@ -0,0 +1,75 @@
+// Mines a block for each payload given on top of the chain in the
+// file (created if missing, validated first), and writes the chain
+// back: a line per block with its height, hash, nonce, how many
+// hashes it took and how long, to see what the proof of work costs
+// as the difficulty goes up:
+//
+// cargo run --release --bin miner -- chain.bin hello world --difficulty 20
+use std::{path::PathBuf, time::Instant};
+
+use clap::Parser;
+
+use doing_some_blockchain::{
+    api::{Error, Result},
+    block::Chain,
+    util::{time, to_hex},
+};
+
+#[derive(Parser)]
+struct Cli {
+    /// Blocks one after the other, as `Chain::encode` makes them
+    chain: PathBuf,
+    /// A block each (the bytes of the string)
+    #[arg(required = true)]
+    payloads: Vec<String>,
+    /// Leading zero bits of the hash of every block, the ones in
+    /// the file included (DIFFICULTY otherwise, 16 by default)
+    #[arg(long, value_name = "BITS")]
+    difficulty: Option<u32>,
+}
+
+fn difficulty(arg: Option<u32>) -> u32 {
+    arg.or_else(|| {
+        std::env::var("DIFFICULTY")
+            .ok()
+            .map(|s| s.parse().expect("invalid DIFFICULTY"))
+    })
+    .unwrap_or(16)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let difficulty = difficulty(cli.difficulty);
+    if difficulty > 256 {
+        return Err(Error::App(format!(
+            "difficulty {difficulty} is over 256 bits"
+        )));
+    }
+    let bytes = match std::fs::read(&cli.chain) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            vec![]
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut chain = Chain::decode(&bytes, difficulty)?;
+
+    for payload in cli.payloads {
+        let mut block = chain.next(time(), payload.into_bytes());
+        let started = Instant::now();
+        let tries = block.mine(difficulty);
+        let elapsed = started.elapsed();
+        println!(
+            "{} {} nonce={} tries={} {}ms",
+            chain.len(),
+            to_hex(&block.hash()),
+            block.nonce,
+            tries,
+            elapsed.as_millis()
+        );
+        chain.append(block)?;
+        // a block mined is not lost to a later one interrupted
+        std::fs::write(&cli.chain, chain.encode())?;
+    }
+    Ok(())
+}
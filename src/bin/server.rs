@@ -1,331 +1,7014 @@
 use std::{
-    collections::HashMap,
-    env::args,
-    net::{SocketAddr, TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    collections::{BTreeSet, HashMap},
+    fs,
+    net::{
+        IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream,
+    },
+    num::ParseIntError,
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex, RwLock},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use clap::Parser;
 use doing_some_blockchain::{
     api::{
-        Frame, Receiver, Result, Sender, ERR_NOT_FOUND,
-        TAG_BAD_REQUEST, TAG_OK, TAG_PUBLIC_KEY, TAG_REFRESH,
-        TAG_SECRET_SHARE,
+        Error, Frame, Receiver, Result, Sender,
+        ERR_BAD_CHECKSUM, ERR_BAD_SHARE, ERR_BAD_SIGNATURE,
+        ERR_DELETED, ERR_EXPIRED, ERR_FORBIDDEN, ERR_NOT_FOUND,
+        ERR_RATE_LIMITED, ERR_STALE, ERR_STORAGE,
+        MAX_BATCH_SIZE, MAX_PAYLOAD_LEN, TAG_AUDIT,
+        TAG_BAD_REQUEST, TAG_BATCH, TAG_BLOCKS, TAG_CLOSE,
+        TAG_DELETE, TAG_DIGEST, TAG_GET_BLOCKS, TAG_GOSSIP,
+        TAG_JOIN, TAG_LEAVE, TAG_LEDGER, TAG_LIST, TAG_OK,
+        TAG_PING, TAG_PONG, TAG_PUBLIC_KEY, TAG_REFRESH,
+        TAG_REPAIR, TAG_SECRET_SHARE, TAG_SERVER_ERROR,
+        TAG_SIGN_COMMIT, TAG_SIGN_SHARE, TAG_SNAPSHOT,
+        TAG_STATUS, TAG_TRANSFER,
+    },
+    audit::{self, Audit, Entry},
+    block::{self, Block, Chain},
+    dhke::{self, Auth, Group, Keys},
+    digest::{self, Digest},
+    ec::{curve, Encoding, PublicKey, Scheme, SecretKey},
+    frost::{self, Commitment, SIGNING},
+    gossip::Members,
+    ledger::{Ledger, Ledgered},
+    mempool::{self, Mempool},
+    metrics::{self, Counter, Counters, Histogram, Text},
+    nonce::Nonces,
+    pool::Pool,
+    shamir,
+    storage::{FileDB, Reads, Shards, Storage, DB},
+    tcp::{Rekey, Tcp},
+    util::{
+        crc32, from_hex, load_env, merge, pack, pack64, split,
+        time, unpack, unpack64, Os, Rng,
     },
-    dhke::dhke_handshake,
-    tcp::Tcp,
-    util::{merge, random, time},
+    vss::{self, VERIFIABLE},
+    workers::Workers,
+    xor::{self, pad, BYTES},
 };
+use tracing::{debug, info, info_span, warn};
+
+#[cfg(feature = "noise")]
+use doing_some_blockchain::noise::Noise;
+#[cfg(feature = "quic")]
+use doing_some_blockchain::quic::{self, QuicClient};
+#[cfg(test)]
+use doing_some_blockchain::testkit::{Network, Probe};
+#[cfg(feature = "tls")]
+use doing_some_blockchain::tls::Tls;
+#[cfg(feature = "ws")]
+use doing_some_blockchain::ws::Ws;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_WINDOW: u32 = 30;
+const DEFAULT_GENESIS_SEED: &str = "doing-some-blockchain";
+const LIST_PAGE_SIZE: usize = 256;
+// audit entries per TAG_AUDIT response, a line is under 100 bytes
+const AUDIT_PAGE_SIZE: usize = 512;
+const MAX_IDLE: usize = 4; // pooled connections to each peer
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_RATE: f64 = 50.0; // per second, per remote address
+const DEFAULT_BURST: f64 = 100.0;
+const MAX_BUCKETS: usize = 10_000; // before forgetting full ones
+const DEFAULT_MAX_CONNECTIONS: usize = 64; // worker threads
+const DEFAULT_SHARDS: usize = 16;
+const REFRESH_JITTER: f64 = 0.2; // of the refresh interval
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
+// rounds without a member's heartbeat going up, see `gossip`
+const SUSPECT_ROUNDS: u32 = 3;
+const DEAD_ROUNDS: u32 = 10;
+// blocks asked of a peer at a time (TAG_GET_BLOCKS), as many as
+// there are up to MAX_SYNC_LEN bytes of them, one at least
+const MAX_SYNC_BLOCKS: usize = 64;
+const MAX_SYNC_LEN: usize = 16 * 1024 * 1024;
+// blocks of its own a server rolls back at most for a peer's branch
+const MAX_REORG_DEPTH: usize = 1024;
+// long past any frame sent before the deletion (see `Nonces`)
+const TOMBSTONE_TTL: u32 = 24 * 60 * 60; // seconds
+
+#[derive(Clone, Debug)]
+struct Config {
+    key: u32,
+    peers: Arc<Peers>,     // the other servers
+    members: Arc<Members>, // of the cluster, heard of by gossip
+    sync: bool,
+    // the shares are kept by more than one server each (see
+    // `Quorum` of the client): the epoch of a share is the stamp
+    // (`idx`) of the write, the same on every replica, and the
+    // shares are neither refreshed nor handed over, as the replicas
+    // of a share would each get a mask of their own
+    replicated: bool,
+    window: u32, // freshness window for `idx`, seconds
+    // of the frames seen within `window`: shared by all the
+    // connections, a frame taken on one is a replay on any other
+    nonces: Arc<Mutex<Nonces>>,
+    idle: Duration, // idle timeout of TCP sessions
+    timeout: Duration, // of a response, and of the handshake
+    connect_timeout: Duration, // to a peer
+    json: bool, // plain-text JSON frames, no handshake (debug only)
+    limiter: Option<Arc<Limiter>>, // shared by all connections
+    max_conns: usize, // handled concurrently, TCP and WebSocket
+    reject: bool, // over `max_conns`: close right away, or queue
+    drain: Arc<Drain>, // shared by all connections
+    metrics: Arc<Metrics>,
+    audit: Arc<Audit>,
+    ledger: Arc<Ledger>,
+    // of everything random the server does: refresh masks and
+    // polynomials, nonces, peers picked (seeded in tests only)
+    rng: Arc<dyn Rng>,
+    // the operations taken, until they go in a block on top of
+    // `chain` (BLOCK_INTERVAL, none if not set)
+    mempool: Option<Arc<Mempool>>,
+    chain: Arc<Mutex<Chain>>,
+    reads: Reads,
+    admin: Option<u32>, // fingerprint of the admin's key
+    // fingerprints of the keys allowed in each namespace but zero
+    namespaces: HashMap<u32, Vec<u32>>,
+    started: Instant,
+    pending: Arc<Pending>, // shared by all connections
+    exchange: Vec<Group>,  // offered in the handshake's hello
+    peer_keys: Vec<PublicKey>, // of the peers, if authenticated
+    rekey: Option<Rekey>,  // of raw TCP sessions, both ways
+    psk: Option<Vec<u8>>, // pre-shared key, no key exchange then
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    #[cfg(feature = "noise")]
+    noise: Option<NoiseConfig>,
+    #[cfg(feature = "ws")]
+    ws: Option<SocketAddr>, // WebSocket listener, next to raw TCP
+    #[cfg(feature = "quic")]
+    quic: bool, // QUIC (UDP, same port) instead of TCP, needs `tls`
+    // the peers' addresses are names on this in-memory network
+    // instead (see `tests::Cluster`)
+    #[cfg(test)]
+    network: Option<Network>,
+}
+
+// FROST nonces handed out (TAG_SIGN_COMMIT) and not used yet (see
+// TAG_SIGN_SHARE), by the key and the nonce's commitment D, with the
+// time they were handed out
+type Pending =
+    Mutex<HashMap<(u64, curve::Point), (frost::Nonce, u32)>>;
+
+// Server side for accepted connections, client side for the peer
+#[cfg(feature = "tls")]
+type TlsConfig =
+    (Arc<rustls::ServerConfig>, Arc<rustls::ClientConfig>);
+
+// Own static private key, trusted static public keys of the peers
+#[cfg(feature = "noise")]
+type NoiseConfig = (Vec<u8>, Vec<Vec<u8>>);
 
 trait Transport<K>:
     Sender<u32> + Receiver<u32> + Sender<Frame> + Receiver<Frame>
 {
     fn set_session_key(&mut self, key: K);
+
+    // false if the transport is already encrypted (no DHKE)
+    fn needs_handshake(&self) -> bool {
+        true
+    }
+}
+
+impl Transport<Keys> for Tcp {
+    fn set_session_key(&mut self, keys: Keys) {
+        self.set_keys(keys.seal, keys.open);
+    }
 }
 
-impl Transport<u32> for Tcp {
-    fn set_session_key(&mut self, key: u32) {
-        self.set_key(key);
+#[cfg(feature = "tls")]
+impl Transport<Keys> for Tls {
+    fn set_session_key(&mut self, _keys: Keys) {}
+
+    fn needs_handshake(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "ws")]
+impl Transport<Keys> for Ws {
+    fn set_session_key(&mut self, keys: Keys) {
+        self.set_keys(keys.send, keys.recv);
+        self.set_mac(keys.mac);
+    }
+}
+
+#[cfg(test)]
+impl Transport<Keys> for Probe {
+    fn set_session_key(&mut self, keys: Keys) {
+        self.set_keys(keys.seal, keys.open);
     }
 }
 
-trait Storage<K, S, M>: Send {
-    fn set(&mut self, key: K, secret: S);
-    fn get(&mut self, key: K) -> Option<S>;
-    fn patch(&mut self, key: K, mask: M);
+#[cfg(feature = "noise")]
+impl Transport<Keys> for Noise {
+    fn set_session_key(&mut self, _keys: Keys) {}
+
+    fn needs_handshake(&self) -> bool {
+        false
+    }
+}
+
+// Exported in Prometheus text format on METRICS_PORT
+#[derive(Debug, Default)]
+struct Metrics {
+    received: Counters<u32>, // frames by tag
+    sent: Counters<u32>,
+    handshake_failures: Counter, // DHKE, TLS or Noise
+    hits: Counter,               // shares found in the storage
+    misses: Counter,
+    refreshes: Counter, // triggered by this server, by peer
+    refresh_failures: Counter,
+    patches: Counter, // applied on a peer's request
+    repairs: Counter, // keys rolled back by anti-entropy
+    blocks: Counter,  // of operations, on top of the chain
+    synced: Counter,  // blocks of the peers' appended
+    reorgs: Counter,  // to a peer's branch, blocks rolled back
+    latency: Histogram, // from request frame to response frame
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut text = Text::default();
+        text.counters(
+            "frames_received_total",
+            "Frames received, by tag.",
+            "tag",
+            &self.received,
+        );
+        text.counters(
+            "frames_sent_total",
+            "Frames sent in response, by tag.",
+            "tag",
+            &self.sent,
+        );
+        text.counter(
+            "handshake_failures_total",
+            "Failed DHKE, TLS or Noise handshakes.",
+            &self.handshake_failures,
+        );
+        text.counter(
+            "storage_hits_total",
+            "Secret shares found in the storage.",
+            &self.hits,
+        );
+        text.counter(
+            "storage_misses_total",
+            "Secret shares not found in the storage.",
+            &self.misses,
+        );
+        text.counter(
+            "refreshes_total",
+            "Refreshes triggered and applied by a peer.",
+            &self.refreshes,
+        );
+        text.counter(
+            "refresh_failures_total",
+            "Refreshes that did not go through.",
+            &self.refresh_failures,
+        );
+        text.counter(
+            "patches_total",
+            "Refreshes applied on a peer's request.",
+            &self.patches,
+        );
+        text.counter(
+            "repairs_total",
+            "Keys brought back in line with the peers' by anti-entropy.",
+            &self.repairs,
+        );
+        text.counter(
+            "blocks_total",
+            "Blocks of operations appended to the chain.",
+            &self.blocks,
+        );
+        text.counter(
+            "blocks_synced_total",
+            "Blocks of the peers appended to the chain.",
+            &self.synced,
+        );
+        text.counter(
+            "reorgs_total",
+            "Blocks rolled back for a branch of a peer's with more work.",
+            &self.reorgs,
+        );
+        text.histogram(
+            "request_duration_seconds",
+            "Time from a request frame to its response.",
+            &self.latency,
+        );
+        text.finish()
+    }
 }
 
-struct DB {
-    data: HashMap<u32, Vec<u32>>,
-    hits: HashMap<u32, usize>,
+// Token bucket per remote address: `burst` tokens at most, refilled
+// at `rate` tokens per second. Each connection (handshake) and each
+// request frame takes a token.
+#[derive(Debug)]
+struct Limiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
 }
 
-impl DB {
-    fn new() -> Self {
+impl Limiter {
+    fn new(rate: f64, burst: f64) -> Self {
         Self {
-            data: HashMap::new(),
-            hits: HashMap::new(),
+            rate,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
         }
     }
-}
 
-impl Storage<u32, u32, u32> for DB {
-    fn set(&mut self, key: u32, secret: u32) {
-        self.data.insert(key, vec![secret]);
-        self.hits.insert(key, 0);
+    // false if the remote is over the limit (no token taken)
+    fn allow(&self, remote: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            // a full bucket is the same as no bucket
+            buckets.retain(|_, (tokens, last)| {
+                self.refill(*tokens, *last, now) < self.burst
+            });
+        }
+        let (tokens, last) =
+            buckets.entry(remote).or_insert((self.burst, now));
+        *tokens = self.refill(*tokens, *last, now);
+        *last = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    fn refill(
+        &self,
+        tokens: f64,
+        last: Instant,
+        now: Instant,
+    ) -> f64 {
+        let elapsed = now.saturating_duration_since(last);
+        (tokens + elapsed.as_secs_f64() * self.rate)
+            .min(self.burst)
     }
+}
+
+// Connections being handled, closed for reading on shutdown: the
+// request in flight (if any) still gets its response and the refresh
+// it triggers is done, then the session ends as if on EOF.
+#[derive(Debug, Default)]
+struct Drain {
+    open: Mutex<Open>,
+    stopped: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Open {
+    stopping: bool,
+    sockets: HashMap<(SocketAddr, SocketAddr), TcpStream>,
+}
+
+// Registered connection, unregistered when dropped
+struct Opened {
+    drain: Arc<Drain>,
+    key: (SocketAddr, SocketAddr),
+}
 
-    fn get(&mut self, key: u32) -> Option<u32> {
-        let idx = self.hits.get(&key).cloned()?;
-        *self.hits.get_mut(&key).unwrap() += 1;
-        self.data.get(&key).and_then(|vec| vec.get(idx)).cloned()
+impl Drain {
+    // None if shutting down: the connection is to be dropped
+    fn open(
+        self: &Arc<Self>,
+        socket: &TcpStream,
+    ) -> Result<Option<Opened>> {
+        let key = (socket.local_addr()?, socket.peer_addr()?);
+        let mut open = self.open.lock().unwrap();
+        if open.stopping {
+            return Ok(None);
+        }
+        open.sockets.insert(key, socket.try_clone()?);
+        Ok(Some(Opened {
+            drain: self.clone(),
+            key,
+        }))
     }
 
-    fn patch(&mut self, key: u32, mask: u32) {
-        if let Some(next) = self
-            .data
-            .get(&key)
-            .and_then(|vec| vec.last().cloned())
-            .map(|last| last ^ mask)
-        {
-            self.data.entry(key).or_default().push(next);
+    fn stop(&self) {
+        let mut open = self.open.lock().unwrap();
+        open.stopping = true;
+        for socket in open.sockets.values() {
+            let _ = socket.shutdown(Shutdown::Read);
         }
+        self.stopped.notify_all();
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.open.lock().unwrap().stopping
+    }
+
+    // Sleep for `timeout` or until shutting down, whichever comes
+    // first: true if shutting down
+    fn sleep(&self, timeout: Duration) -> bool {
+        let (open, _) = self
+            .stopped
+            .wait_timeout_while(
+                self.open.lock().unwrap(),
+                timeout,
+                |open| !open.stopping,
+            )
+            .unwrap();
+        open.stopping
+    }
+
+    #[cfg(feature = "quic")]
+    fn wait(&self) {
+        let _open = self
+            .stopped
+            .wait_while(self.open.lock().unwrap(), |open| {
+                !open.stopping
+            })
+            .unwrap();
     }
 }
 
-fn handle<T: Transport<u32>, S: Storage<u32, u32, u32>>(
-    tx: &mut T,
-    key: u32,
-    db: Arc<Mutex<S>>,
-    peer: SocketAddr,
-    sync: bool,
-) -> Result<()> {
-    {
-        let a = random();
-        let key = dhke_handshake(tx, DEFAULT_TIMEOUT, a)?;
-        tx.set_session_key(key);
+impl Drop for Opened {
+    fn drop(&mut self) {
+        let mut open = self.drain.open.lock().unwrap();
+        open.sockets.remove(&self.key);
     }
+}
 
-    let frame: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
-    println!("debug: recv: {frame:?}");
+// The other servers, as they join (TAG_JOIN) and leave (TAG_LEAVE)
+// the running cluster: kept in `<data dir>/peers` (an address a
+// line) if there is a data dir, and read from there on restart
+// instead of the ones given
+#[derive(Debug, Default)]
+struct Peers {
+    list: RwLock<Vec<SocketAddr>>,
+    path: Option<PathBuf>,
+}
 
-    let mut trigger_refresh = false;
-    let response = match frame.tag {
-        TAG_SECRET_SHARE => {
-            // skipping: validate checksum & signature
-            {
-                let mut db = db.lock().unwrap();
-                db.set(frame.key, frame.msg);
-            }
-            Frame {
-                idx: time(),
-                tag: TAG_OK,
-                msg: 200,
-                key,
-                sig: merge(key, key),
-                ext: 0,
-                sum: 42,
-            }
-        }
-        TAG_PUBLIC_KEY => {
-            // skipping: validate checksum & signature
-            if let Some(msg) = {
-                let mut db = db.lock().unwrap();
-                db.get(frame.key)
-            } {
-                trigger_refresh = sync;
-                Frame {
-                    idx: time(),
-                    tag: TAG_OK,
-                    msg,
-                    key,
-                    sig: merge(key, key),
-                    ext: 0,
-                    sum: 42,
-                }
-            } else {
-                Frame {
-                    idx: time(),
-                    tag: TAG_BAD_REQUEST,
-                    msg: 0,
-                    key,
-                    sig: merge(key, key),
-                    ext: ERR_NOT_FOUND,
-                    sum: 42,
-                }
-            }
+impl Peers {
+    fn new(list: Vec<SocketAddr>) -> Self {
+        Self {
+            list: RwLock::new(list),
+            path: None,
         }
-        TAG_REFRESH => {
+    }
+
+    fn open(
+        path: PathBuf,
+        given: Vec<SocketAddr>,
+    ) -> Result<Self> {
+        let list = match fs::read_to_string(&path) {
+            Ok(text) => text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| {
+                    line.parse().map_err(|_| {
+                        Error::App(format!(
+                            "invalid peer: {line}"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound =>
             {
-                let mut db = db.lock().unwrap();
-                db.patch(frame.ext, frame.msg);
-                println!(
-                    "debug: patch: key={:0x} mask={:0x}",
-                    frame.ext, frame.msg
-                );
-            }
-            Frame {
-                idx: time(),
-                tag: TAG_OK,
-                msg: 0,
-                key,
-                sig: merge(key, key),
-                ext: 0,
-                sum: 0,
+                given
             }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            list: RwLock::new(list),
+            path: Some(path),
+        })
+    }
+
+    fn list(&self) -> Vec<SocketAddr> {
+        self.list.read().unwrap().clone()
+    }
+
+    // false if it is a peer already
+    fn add(&self, peer: SocketAddr) -> Result<bool> {
+        let mut list = self.list.write().unwrap();
+        if list.contains(&peer) {
+            return Ok(false);
         }
-        tag => Frame {
-            idx: time(),
-            tag: TAG_BAD_REQUEST,
-            msg: 0,
-            key,
-            sig: merge(key, key),
-            ext: tag,
-            sum: 42,
-        },
-    };
+        let next = [list.as_slice(), &[peer]].concat();
+        self.save(&next)?;
+        *list = next;
+        Ok(true)
+    }
 
-    println!("debug: send: {response:?}");
-    tx.send(&response)?;
+    // false if it is not a peer
+    fn remove(&self, peer: SocketAddr) -> Result<bool> {
+        let mut list = self.list.write().unwrap();
+        if !list.contains(&peer) {
+            return Ok(false);
+        }
+        let next = list
+            .iter()
+            .filter(|p| **p != peer)
+            .cloned()
+            .collect::<Vec<_>>();
+        self.save(&next)?;
+        *list = next;
+        Ok(true)
+    }
 
-    if trigger_refresh {
-        refresh(key, db.clone(), peer, frame.key)?;
+    // to a temporary file first, as `FileDB` does
+    fn save(&self, list: &[SocketAddr]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = list
+            .iter()
+            .map(|peer| format!("{peer}\n"))
+            .collect::<String>();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
     }
+}
 
-    Ok(())
+// Stop accepting connections (the listeners are woken up with a
+// connection of their own) and drain the ones being handled. The
+// server's thread returns once they are all done with.
+fn shutdown(cfg: &Config, addr: SocketAddr) {
+    cfg.drain.stop();
+    let _ = TcpStream::connect(addr);
+    #[cfg(feature = "ws")]
+    if let Some(addr) = cfg.ws {
+        let _ = TcpStream::connect(addr);
+    }
 }
 
-fn server(
-    addr: SocketAddr,
-    key: u32,
-    peer: SocketAddr,
-    db: Arc<Mutex<DB>>,
-    sync: bool,
-) -> JoinHandle<Result<()>> {
-    let h = thread::spawn(move || {
-        let listener = TcpListener::bind(addr)?;
-        while let Ok((socket, _remote)) = listener.accept() {
-            let db = db.clone();
-            thread::spawn(move || {
-                // Thread-per-request: gross simplification
-                // but "enough for the demo LOL" (c)
-                let mut tx = Tcp::from(socket);
-                handle(&mut tx, key, db, peer, sync)
-            });
+// Hand the connection over to a free worker: when queueing, wait
+// for one (nothing gets accepted meanwhile, so new connections wait
+// in the listener's backlog), otherwise reject the connection.
+fn dispatch(
+    workers: &Workers,
+    cfg: &Config,
+    remote: SocketAddr,
+    job: impl FnOnce() + Send + 'static,
+) -> Result<()> {
+    if cfg.reject {
+        if !workers.try_run(job) {
+            // dropping the job closes the socket
+            warn!(%remote, max = cfg.max_conns, "rejected");
         }
-        Ok(())
-    });
-    thread::sleep(Duration::from_millis(100));
-    h
+        return Ok(());
+    }
+    workers.run(job)
 }
 
-fn refresh<S: Storage<u32, u32, u32>>(
-    key: u32,
-    db: Arc<Mutex<S>>,
-    peer: SocketAddr,
-    owner: u32,
-) -> Result<()> {
-    let mask = random();
-    let refresh = Frame {
+fn allowed(cfg: &Config, remote: IpAddr) -> bool {
+    cfg.limiter.as_ref().is_none_or(|limiter| {
+        limiter.allow(remote, Instant::now())
+    })
+}
+
+fn rate_limited(key: u32) -> Frame {
+    server_error(key, ERR_RATE_LIMITED)
+}
+
+fn server_error(key: u32, code: u32) -> Frame {
+    Frame {
         idx: time(),
-        tag: TAG_REFRESH,
-        msg: mask,
+        tag: TAG_SERVER_ERROR,
+        msg: 0,
         key,
         sig: merge(key, key),
-        ext: owner,
-        sum: 42,
-    };
-
-    let mut tx = Tcp::from(TcpStream::connect(peer)?);
-    {
-        let a = random();
-        let key = dhke_handshake(&tx, DEFAULT_TIMEOUT, a)?;
-        tx.set_key(key);
+        ext: code,
+        ns: 0,
+        sum: 0,
+        data: vec![],
     }
+}
 
-    tx.send(&refresh)?;
-    println!("debug: send: {refresh:?}");
-    let refresh: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
-    println!("debug: recv: {refresh:?}");
-    if refresh.tag == TAG_OK {
-        let mut db = db.lock().unwrap();
-        db.patch(owner, mask);
-        println!(
-            "debug: patch: key={:0x} mask={:0x}",
-            owner, mask
-        );
+// What follows the public key in the payload, if it is there (see
+// `Frame::carried`)
+fn payload(frame: &Frame) -> &[u8] {
+    match frame.carried() {
+        Some(_) => &frame.data[8..],
+        None => &frame.data,
     }
-    Ok(())
 }
 
-const USAGE: &str = "Usage: <key> <port> <peer> [sync]";
+// Seconds the secret is to be kept for (zero: until deleted), first
+// in a share frame's payload
+fn ttl(frame: &Frame) -> u32 {
+    payload(frame)
+        .get(..4)
+        .map_or(0, |b| u32::from_be_bytes(b.try_into().unwrap()))
+}
 
-fn main() {
-    let args = args().skip(1).collect::<Vec<_>>();
-
-    let ((key, port), peer) = args
-        .get(0)
-        .zip(args.get(1))
-        .zip(args.get(2))
-        .expect(USAGE);
-    let key =
-        u32::from_str_radix(key, 16).expect("invalid key hex");
-    let port: u16 = port.parse().expect("invalid port provided");
-    let peer: SocketAddr =
-        peer.parse().expect("invalid peer address provided");
-
-    let sync =
-        args.get(3).map(|arg| arg == "sync").unwrap_or_default();
-
-    println!("debug: key={key:0x} port={port}, peer={peer:?} sync={sync}");
-    let addr: SocketAddr = ([127, 0, 0, 1], port).into();
-    let db = Arc::new(Mutex::new(DB::new()));
-    let jh = server(addr, key, peer, db, sync);
-    let _ = jh.join().expect("server process failed");
+// Whatever follows the TTL in a share frame: the commitments of a
+// Feldman share, the share of a byte secret
+fn attachment(frame: &Frame) -> &[u8] {
+    payload(frame).get(4..).unwrap_or_default()
 }
 
-#[cfg(test)]
-mod tests {
-    use std::net::TcpStream;
+// Storage key of the share a frame is about: the namespace in the
+// high 32 bits, the key (in `ext` for a refresh or a hand-over) in
+// the low ones
+fn scoped(frame: &Frame) -> u64 {
+    let key = match frame.tag {
+        TAG_REFRESH | TAG_TRANSFER | TAG_REPAIR => frame.ext,
+        _ => frame.key,
+    };
+    merge(frame.ns, key)
+}
 
-    use super::*;
+// A refresh (or transfer) of a stored key, at an epoch other than
+// the one after the latest (the epoch goes first in `data`)
+fn is_stale<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    key: u64,
+    frame: &Frame,
+) -> bool {
+    let Some(epoch) = frame.data.get(..4).map(|e| pack(e)[0])
+    else {
+        return false;
+    };
+    let mut db = db.lock(key);
+    db.epochs(key).last().is_some_and(|e| e + 1 != epoch)
+}
 
-    fn client(addr: SocketAddr, frame: &Frame) -> Result<Frame> {
-        let frame = frame.clone();
-        let socket = TcpStream::connect(addr)?;
-        let mut tx = Tcp::from(socket);
-        let a = random();
-        let key = dhke_handshake(&tx, DEFAULT_TIMEOUT, a)?;
-        tx.set_key(key);
-        tx.send(&frame)?;
-        let frame: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
-        Ok(frame)
+fn is_expired<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    key: u64,
+) -> bool {
+    let mut db = db.lock(key);
+    db.expiry(key).is_some_and(|at| at <= time())
+}
+
+// Sent before the key was deleted (`idx` is the sender's time), so
+// it must not bring the key back
+fn is_deleted<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    key: u64,
+    idx: u32,
+) -> bool {
+    let mut db = db.lock(key);
+    db.deleted(key).is_some_and(|at| idx < at)
+}
+
+// A Feldman share must match the commitments it comes with (one per
+// coefficient), a share of a signing key must be of one (below N)
+// and come with the group key, other shares can not be checked
+fn is_consistent(frame: &Frame) -> bool {
+    if frame.ext & SIGNING != 0 {
+        return (frame.msg as curve::Int) < curve::N
+            && PublicKey::from_bytes(attachment(frame)).is_ok();
     }
+    if frame.ext & VERIFIABLE == 0 {
+        return true;
+    }
+    let threshold = (frame.ext >> 16) & 0x1FFF;
+    let x = frame.ext & 0xFFFF;
+    let commitments = pack64(attachment(frame));
+    commitments.len() == threshold as usize
+        && vss::verify((x, frame.msg), &commitments)
+}
 
-    fn server(addr: SocketAddr) -> JoinHandle<Result<()>> {
-        let h = thread::spawn(move || {
-            let listener = TcpListener::bind(addr)?;
-            if let Ok((socket, _remote)) = listener.accept() {
-                let mut tx = Tcp::from(socket);
-                {
-                    let a = random();
-                    let key =
-                        dhke_handshake(&tx, DEFAULT_TIMEOUT, a)?;
-                    tx.set_session_key(key);
-                }
+// Namespace zero is open to any key, any other one only to the keys
+// listed for it
+fn is_member(cfg: &Config, ns: u32, owner: &PublicKey) -> bool {
+    let fingerprint = owner.fingerprint();
+    ns == 0
+        || cfg
+            .namespaces
+            .get(&ns)
+            .is_some_and(|keys| keys.contains(&fingerprint))
+}
 
-                let frame: Frame =
-                    tx.recv_timeout(DEFAULT_TIMEOUT)?;
-                tx.send(&frame)?;
-            }
-            Ok(())
-        });
-        thread::sleep(Duration::from_millis(100));
-        h
+// Keys of a namespace other than zero are only listed to its
+// members, a TAG_LIST frame is signed as any other client's frame
+fn may_list(frame: &Frame, cfg: &Config) -> bool {
+    if frame.ns == 0 {
+        return true;
     }
+    frame.signer().is_some_and(|public_key| {
+        is_member(cfg, frame.ns, &public_key)
+    })
+}
 
-    #[test]
-    fn test_echo() -> Result<()> {
-        let port: u16 = 32456;
-        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
-        let server = server(addr);
+// Signed by one of the other servers (PEER_KEYS), none if not set:
+// with the key itself, whatever the frame's `key` (a hand-over is of
+// a client's key)
+fn is_peer(frame: &Frame, cfg: &Config) -> bool {
+    cfg.peer_keys.iter().any(|key| frame.verify(key))
+}
 
-        let frame: Frame = Frame {
-            idx: 0x01020304,
-            tag: 0x05060708,
-            msg: 0x090A0B0C,
-            key: 0xCAFEBABE,
-            sig: 0x0102030405060708,
-            ext: 0x090A0B0C,
-            sum: 0x0D0E0F00,
+// TAG_STATUS, TAG_SNAPSHOT, TAG_AUDIT, TAG_JOIN and TAG_LEAVE are
+// signed with the admin's key, which (as for any client) is
+// fingerprinted in `key`
+fn is_admin(frame: &Frame, cfg: &Config) -> bool {
+    let Some(admin) = cfg.admin else {
+        return false;
+    };
+    frame.key == admin
+        && frame.signer().is_some_and(|public_key| {
+            public_key.fingerprint() == admin
+        })
+}
+
+// `name=value` lines for TAG_STATUS: the peers are pinged to tell
+// if they are reachable (`peer=<addr>,up,rtt=<rtt>` or
+// `peer=<addr>,down`)
+fn status<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+) -> String {
+    let keys = db.keys().len();
+    let m = &cfg.metrics;
+    let mut lines = vec![
+        format!("uptime={}s", cfg.started.elapsed().as_secs()),
+        format!("keys={keys}"),
+        format!("refreshes={}", m.refreshes.get()),
+        format!("refresh_failures={}", m.refresh_failures.get()),
+        format!("patches={}", m.patches.get()),
+    ];
+
+    for peer in &cfg.peers.list() {
+        let nonce = cfg.rng.next_u32();
+        let mut ping = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: nonce,
+            key: cfg.key,
+            sig: merge(cfg.key, cfg.key),
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
         };
-        let rcvd = client(addr, &frame)?;
-        server.join()??;
+        ping.sum = ping.checksum();
+        let now = Instant::now();
+        let line = match call_peer(*peer, &ping, cfg) {
+            Ok(pong)
+                if pong.tag == TAG_PONG && pong.msg == nonce =>
+            {
+                format!("peer={peer},up,rtt={:?}", now.elapsed())
+            }
+            _ => format!("peer={peer},down"),
+        };
+        lines.push(line);
+    }
+    for (member, state) in cfg.members.members() {
+        lines.push(format!("member={member},{state}"));
+    }
+    lines.join("\n")
+}
 
-        assert_eq!(rcvd, frame);
+// Public key of the frame's owner, if the signature checks out:
+// the registered one, or the signer's (see `Frame::signer`) for a new key.
+fn authenticate<S: Storage<u64, u32, u32>>(
+    frame: &Frame,
+    db: &Arc<Shards<S>>,
+) -> Option<PublicKey> {
+    let id = scoped(frame);
+    let registered = db.lock(id).owner(id);
+    let owner = registered.or_else(|| frame.signer())?;
+    (owner.is_on_curve() && frame.verify(&owner))
+        .then_some(owner)
+}
+
+// The share of a signing key, its x and the threshold (see
+// `set_secret` of the client), and the group key
+fn signing_share<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    id: u64,
+) -> std::result::Result<((u32, u32), usize, PublicKey), u32> {
+    let mut db = db.lock(id);
+    let y = db.get(id).ok_or(ERR_NOT_FOUND)?;
+    let scheme = db.scheme(id);
+    if scheme & SIGNING == 0 {
+        return Err(ERR_BAD_SHARE);
+    }
+    let version = db.version(id);
+    let group_key =
+        PublicKey::from_bytes(&db.attachment(id, version))
+            .map_err(|_| ERR_BAD_SHARE)?
+            .with_scheme(Scheme::Schnorr);
+    let threshold = (scheme >> 16) & 0x1FFF;
+    Ok(((scheme & 0xFFFF, y), threshold as usize, group_key))
+}
+
+// TAG_SIGN_COMMIT: fresh nonces for a signature with the share, kept
+// until they are used (for up to the freshness window); `msg` is the
+// share's x, `data` the commitment to the nonces, the public
+// counterpart of the share and the group key
+fn commit<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+    id: u64,
+) -> std::result::Result<(u32, Vec<u8>), u32> {
+    let ((x, y), _, group_key) = signing_share(db, id)?;
+    let nonce = frost::Nonce::random(|| cfg.rng.next_u32());
+    let commitment = nonce.commitment(x);
+    {
+        let now = time();
+        let mut pending = cfg.pending.lock().unwrap();
+        pending.retain(|_, (_, at)| {
+            now.saturating_sub(*at) <= cfg.window
+        });
+        pending.insert((id, commitment.d), (nonce, now));
+    }
+    let data = [
+        commitment.to_bytes(),
+        frost::key_share(y).to_bytes(),
+        group_key.to_bytes(),
+    ]
+    .concat();
+    Ok((x, data))
+}
+
+// TAG_SIGN_SHARE: the share of the signature of `msg`, the payload
+// has the commitments of all the signers (after the public key, if
+// it is there); the
+// nonces committed to are gone once asked for, whatever comes of it
+fn sign_share<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+    id: u64,
+    frame: &Frame,
+) -> std::result::Result<(u32, Vec<u8>), u32> {
+    let commitments = Commitment::parse(payload(frame))
+        .map_err(|_| ERR_BAD_SHARE)?;
+    let (share, threshold, group_key) = signing_share(db, id)?;
+    let own = commitments
+        .iter()
+        .find(|c| c.x == share.0)
+        .ok_or(ERR_BAD_SHARE)?;
+    let (nonce, _) = cfg
+        .pending
+        .lock()
+        .unwrap()
+        .remove(&(id, own.d))
+        .ok_or(ERR_EXPIRED)?;
+    if commitments.len() < threshold {
+        return Err(ERR_BAD_SHARE);
+    }
+    let z = frost::sign(
+        share,
+        nonce,
+        frame.msg,
+        &commitments,
+        &group_key,
+    )
+    .ok_or(ERR_BAD_SHARE)?;
+    Ok((z, vec![]))
+}
+
+// TAG_TRANSFER: a share handed over by a peer (see `hand_over`),
+// there already or not: created as a zero share if not (on a server
+// that joined), then masked with `msg` (or with the mask for a byte
+// secret) at the round's epoch. The payload has the epoch, the
+// scheme (XOR only), the expiry (zero: none) and the owner's public
+// key (zero: none), then the mask for a byte secret.
+fn transfer<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    id: u64,
+    frame: &Frame,
+) -> std::result::Result<(), u32> {
+    let (head, mask) =
+        frame.data.split_at_checked(20).ok_or(ERR_BAD_SHARE)?;
+    let words = pack(&head[..12]);
+    let (epoch, scheme, expiry) = (words[0], words[1], words[2]);
+    if scheme != 0 && scheme != BYTES {
+        return Err(ERR_BAD_SHARE);
+    }
+    let owner = match &head[12..] {
+        [0, 0, 0, 0, 0, 0, 0, 0] => None,
+        key => Some(
+            PublicKey::from_bytes(key)
+                .map_err(|_| ERR_BAD_SHARE)?,
+        ),
+    };
+    let mut db = db.lock(id);
+    if db.get(id).is_none() {
+        db.set(id, 0);
+        db.set_scheme(id, scheme);
+        db.attach(id, |_| vec![0; mask.len()]);
+        if let Some(owner) = owner {
+            db.register(id, owner);
+        }
+        if expiry > 0 {
+            db.set_expiry(id, expiry);
+        }
+    } else if db.scheme(id) != scheme {
+        return Err(ERR_BAD_SHARE);
+    }
+    patch(&mut *db, id, Some(epoch), frame.msg, mask);
+    Ok(())
+}
+
+// TAG_JOIN: the server at the address in the payload is a peer from
+// now on, and with `msg` set this server hands its shares over to
+// it (and to the other peers, see `hand_over`): one server does,
+// the others only take it as a peer. TAG_LEAVE: the server at the
+// address is not a peer any more, or, if it is this one, its shares
+// are handed over to the peers. How many keys failed to be handed
+// over, and `name=value` lines of what came of it.
+fn membership<S: Storage<u64, u32, u32>>(
+    frame: &Frame,
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+) -> Result<(u32, String)> {
+    let text = String::from_utf8_lossy(payload(frame));
+    let addr: SocketAddr =
+        text.trim().parse().map_err(|_| {
+            Error::App(format!("invalid address: {text}"))
+        })?;
+    let me = cfg.members.me();
+    let leaving = frame.tag == TAG_LEAVE && addr == me;
+    if frame.tag == TAG_JOIN {
+        if addr == me {
+            return Err(Error::App(format!(
+                "joining itself: {addr}"
+            )));
+        }
+        if cfg.peers.add(addr)? {
+            info!(%addr, "joined");
+        }
+        cfg.members.add(addr);
+    } else if !leaving && cfg.peers.remove(addr)? {
+        info!(%addr, "left");
+    }
+
+    let mut lines = cfg
+        .peers
+        .list()
+        .iter()
+        .map(|peer| format!("peer={peer}"))
+        .collect::<Vec<_>>();
+    let mut failed = 0;
+    if leaving || (frame.tag == TAG_JOIN && frame.msg != 0) {
+        let (mut handed, mut skipped) = (0, 0);
+        for owner in db.keys() {
+            match hand_over(db, cfg, owner, leaving) {
+                Ok(true) => handed += 1,
+                Ok(false) => skipped += 1,
+                Err(e) => {
+                    warn!(
+                        key = %format_args!("{owner:0x}"),
+                        ?e,
+                        "hand-over failed"
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        info!(handed, skipped, failed, leaving, "handed over");
+        lines.push(format!("handed={handed}"));
+        lines.push(format!("skipped={skipped}"));
+        lines.push(format!("failed={failed}"));
+    }
+    Ok((failed, lines.join("\n")))
+}
+
+fn handle<T: Transport<Keys>, S: Storage<u64, u32, u32>>(
+    tx: &mut T,
+    db: Arc<Shards<S>>,
+    cfg: &Config,
+    remote: IpAddr,
+) -> Result<()> {
+    // over the limit: the first frame gets ERR_RATE_LIMITED (the
+    // client needs the handshake to read it), then the connection
+    // is closed
+    let limited = !allowed(cfg, remote);
+    if !cfg.json && tx.needs_handshake() {
+        // authenticated if the other side asks (a client or a
+        // peer knowing this server's public key)
+        let key = SecretKey::new(cfg.key);
+        let auth = Auth {
+            key: &key,
+            trusted: &[],
+            required: false,
+        };
+        let keys = match &cfg.psk {
+            Some(psk) => dhke::psk(tx, cfg.timeout, psk),
+            None => dhke::authenticated(
+                tx,
+                cfg.timeout,
+                &cfg.exchange,
+                &auth,
+            )
+            .map(|(shared, _)| shared.keys()),
+        }
+        .inspect_err(|_| cfg.metrics.handshake_failures.inc())?;
+        tx.set_session_key(keys);
+    }
+
+    // Session loop: until EOF or TAG_CLOSE
+    while let Some(frame) = Receiver::<Frame>::recv(tx)? {
+        debug!(?frame, "recv");
+        let started = Instant::now();
+        cfg.metrics.received.inc(frame.tag);
+        let valid = frame.sum == frame.checksum();
+
+        // TAG_BATCH: `msg` is the number of frames that follow,
+        // nothing to do but to keep reading them one by one
+        if valid
+            && frame.tag == TAG_BATCH
+            && frame.msg as usize <= MAX_BATCH_SIZE
+        {
+            continue;
+        }
+
+        let (mut response, trigger_refresh) =
+            if limited || !allowed(cfg, remote) {
+                (rate_limited(cfg.key), false)
+            } else {
+                respond(&frame, &db, cfg)
+            };
+        response.idx = frame.idx; // correlation ID
+        response.sum = response.checksum();
+        audit(cfg, &frame, &response, remote);
+        pool(cfg, &frame, &response);
+        debug!(?response, "send");
+        tx.send(&response)?;
+        cfg.metrics.sent.inc(response.tag);
+        cfg.metrics.latency.observe(started.elapsed());
+
+        if limited || (valid && frame.tag == TAG_CLOSE) {
+            break;
+        }
+        if trigger_refresh {
+            if let Err(e) =
+                refresh(db.clone(), cfg, scoped(&frame))
+            {
+                warn!(?e, "refresh failed");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Reads and changes of the shares (whoever asked), and what came of
+// them
+fn audit(
+    cfg: &Config,
+    frame: &Frame,
+    response: &Frame,
+    peer: IpAddr,
+) {
+    let (op, key) = match frame.tag {
+        TAG_PUBLIC_KEY => (audit::Op::Get, frame.key),
+        TAG_SECRET_SHARE => (audit::Op::Set, frame.key),
+        TAG_REFRESH | TAG_TRANSFER | TAG_REPAIR => {
+            (audit::Op::Patch, frame.ext)
+        }
+        TAG_DELETE => (audit::Op::Delete, frame.key),
+        TAG_SIGN_SHARE => (audit::Op::Sign, frame.key),
+        _ => return,
+    };
+    let entry = Entry {
+        at: time(),
+        op,
+        ns: frame.ns,
+        key,
+        peer,
+        outcome: match response.tag {
+            TAG_OK => 0,
+            _ => response.ext,
+        },
+    };
+    if let Err(e) = cfg.audit.record(entry) {
+        warn!(?e, "audit failed");
+    }
+}
+
+// The operations taken go in the mempool, for the next block
+fn pool(cfg: &Config, frame: &Frame, response: &Frame) {
+    let Some(mempool) = &cfg.mempool else {
+        return;
+    };
+    if response.tag != TAG_OK || !mempool::is_op(frame.tag) {
+        return;
+    }
+    if !mempool.push(frame.clone()) {
+        warn!(
+            tag = frame.tag,
+            idx = frame.idx,
+            "mempool is full"
+        );
+    }
+}
+
+// Response to a single request frame, and whether the refresh
+// of the frame's key needs to be triggered
+fn respond<S: Storage<u64, u32, u32>>(
+    frame: &Frame,
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+) -> (Frame, bool) {
+    let key = cfg.key;
+    let id = scoped(frame);
+    let owner = match frame.tag {
+        TAG_SECRET_SHARE | TAG_PUBLIC_KEY | TAG_DELETE
+        | TAG_SIGN_COMMIT | TAG_SIGN_SHARE => {
+            authenticate(frame, db)
+        }
+        _ => None,
+    };
+
+    let mut trigger_refresh = false;
+    let response = match frame.tag {
+        _ if frame.sum != frame.checksum() => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: ERR_BAD_CHECKSUM,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        _ if !cfg.nonces.lock().unwrap().check(
+            frame.idx,
+            merge(frame.ns, frame.key),
+            frame.digest(),
+            time(),
+        ) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_EXPIRED,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_SECRET_SHARE | TAG_PUBLIC_KEY | TAG_DELETE
+        | TAG_SIGN_COMMIT | TAG_SIGN_SHARE
+            if owner.is_none() =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_BAD_SIGNATURE,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_SECRET_SHARE | TAG_PUBLIC_KEY | TAG_DELETE
+        | TAG_SIGN_COMMIT | TAG_SIGN_SHARE
+            if owner.as_ref().is_some_and(|owner| {
+                !is_member(cfg, frame.ns, owner)
+            }) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_FORBIDDEN,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_SECRET_SHARE if is_deleted(db, id, frame.idx) => {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_DELETED,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_SECRET_SHARE if !is_consistent(frame) => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: ERR_BAD_SHARE,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        TAG_SECRET_SHARE => {
+            {
+                let mut db = db.lock(id);
+                if let Some(owner) = owner {
+                    db.register(id, owner);
+                }
+                db.set(id, frame.msg);
+                if cfg.replicated {
+                    db.set_epoch(id, frame.idx);
+                }
+                db.set_scheme(id, frame.ext);
+                db.attach(id, |_| attachment(frame).to_vec());
+                let ttl = ttl(frame);
+                if ttl > 0 {
+                    db.set_expiry(
+                        id,
+                        time().saturating_add(ttl),
+                    );
+                }
+            }
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: 200,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_PUBLIC_KEY | TAG_SIGN_COMMIT | TAG_SIGN_SHARE
+            if is_expired(db, id) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_EXPIRED,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_PUBLIC_KEY => {
+            if let Some((msg, scheme, epoch, attachment)) = {
+                let mut db = db.lock(id);
+                let epochs = db.epochs(id);
+                // non-zero `ext`: the epoch of an earlier version
+                // (plus one), for shares of a refresh that did not
+                // reach every server yet
+                let version = match frame.ext {
+                    0 => Some(db.version(id)),
+                    ext => epochs
+                        .iter()
+                        .rposition(|e| *e == ext - 1),
+                };
+                version
+                    .and_then(|version| {
+                        let msg = db.get_version(id, version)?;
+                        let scheme = db.scheme(id);
+                        Some((
+                            msg,
+                            scheme,
+                            epochs
+                                .get(version)
+                                .cloned()
+                                .unwrap_or_default(),
+                            db.attachment(id, version),
+                        ))
+                    })
+                    .inspect(|_| {
+                        if cfg.reads == Reads::Once {
+                            db.delete(id, time());
+                        }
+                    })
+            } {
+                cfg.metrics.hits.inc();
+                // nothing left to refresh after a burning read,
+                // nor after a read of an earlier version
+                trigger_refresh = cfg.sync
+                    && cfg.reads == Reads::Latest
+                    && frame.ext == 0;
+                Frame {
+                    idx: time(),
+                    tag: TAG_OK,
+                    msg,
+                    key,
+                    sig: merge(key, key),
+                    ext: scheme,
+                    ns: 0,
+                    sum: 0,
+                    // shares of different epochs do not match
+                    data: epoch
+                        .to_be_bytes()
+                        .into_iter()
+                        .chain(attachment)
+                        .collect(),
+                }
+            } else {
+                cfg.metrics.misses.inc();
+                Frame {
+                    idx: time(),
+                    tag: TAG_BAD_REQUEST,
+                    msg: 0,
+                    key,
+                    sig: merge(key, key),
+                    ext: ERR_NOT_FOUND,
+                    ns: 0,
+                    sum: 0,
+                    data: vec![],
+                }
+            }
+        }
+        TAG_SIGN_COMMIT | TAG_SIGN_SHARE => {
+            let signed = if frame.tag == TAG_SIGN_COMMIT {
+                commit(db, cfg, id)
+            } else {
+                sign_share(db, cfg, id, frame)
+            };
+            match signed {
+                Ok((msg, data)) => Frame {
+                    idx: time(),
+                    tag: TAG_OK,
+                    msg,
+                    key,
+                    sig: merge(key, key),
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                Err(code) => Frame {
+                    idx: time(),
+                    tag: TAG_BAD_REQUEST,
+                    msg: 0,
+                    key,
+                    sig: merge(key, key),
+                    ext: code,
+                    ns: 0,
+                    sum: 0,
+                    data: vec![],
+                },
+            }
+        }
+        TAG_DELETE => {
+            let deleted = {
+                let mut db = db.lock(id);
+                db.delete(id, time())
+            };
+            Frame {
+                idx: time(),
+                tag: if deleted {
+                    TAG_OK
+                } else {
+                    TAG_BAD_REQUEST
+                },
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: if deleted { 0 } else { ERR_NOT_FOUND },
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_CLOSE => Frame {
+            idx: time(),
+            tag: TAG_OK,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        TAG_PING => Frame {
+            idx: time(),
+            tag: TAG_PONG,
+            msg: frame.msg,
+            key,
+            sig: merge(key, key),
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        // a peer's view, taken in only signed with a key of
+        // PEER_KEYS (a client's is empty), this server's view in
+        // response
+        TAG_GOSSIP => {
+            let merged = if is_peer(frame, cfg) {
+                cfg.members.merge(&frame.data)
+            } else {
+                Ok(())
+            };
+            match merged {
+                Ok(()) => Frame {
+                    idx: time(),
+                    tag: TAG_OK,
+                    msg: 0,
+                    key,
+                    sig: merge(key, key),
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data: cfg.members.encode(),
+                },
+                Err(e) => {
+                    debug!(?e, "invalid gossip");
+                    Frame {
+                        idx: time(),
+                        tag: TAG_BAD_REQUEST,
+                        msg: 0,
+                        key,
+                        sig: merge(key, key),
+                        ext: 0,
+                        ns: 0,
+                        sum: 0,
+                        data: vec![],
+                    }
+                }
+            }
+        }
+        TAG_LIST if !may_list(frame, cfg) => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: ERR_FORBIDDEN,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        TAG_LIST => {
+            // `msg` is the offset of the page, `ext` is the total,
+            // of the keys in the frame's namespace
+            let keys = db
+                .keys()
+                .into_iter()
+                .map(split)
+                .filter(|(ns, _)| *ns == frame.ns)
+                .map(|(_, key)| key)
+                .collect::<Vec<_>>();
+            let page = keys
+                .iter()
+                .skip(frame.msg as usize)
+                .take(LIST_PAGE_SIZE)
+                .flat_map(|key| key.to_be_bytes())
+                .collect::<Vec<_>>();
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: frame.msg,
+                key,
+                sig: merge(key, key),
+                ext: keys.len() as u32,
+                ns: 0,
+                sum: 0,
+                data: page,
+            }
+        }
+        // a share masked, a share and its owner, created if not
+        // there, or a share back to an old version: from the other
+        // servers only
+        TAG_REFRESH | TAG_TRANSFER | TAG_REPAIR
+            if !is_peer(frame, cfg) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_FORBIDDEN,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        // the refresh of a deleted key (whenever it was sent): the
+        // peer is to delete it too
+        TAG_REFRESH | TAG_TRANSFER | TAG_REPAIR
+            if is_deleted(db, id, 0) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_DELETED,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        // not there (yet, on a server that joined): the peer must
+        // not count the mask as applied
+        TAG_REFRESH if db.lock(id).get(id).is_none() => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: ERR_NOT_FOUND,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        // a round missed since (or this one is): the share is left
+        // behind, for anti-entropy to bring it back in line, rather
+        // than patched out of step with the peers'
+        TAG_REFRESH | TAG_TRANSFER
+            if is_stale(db, id, frame) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_STALE,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_REFRESH => {
+            {
+                // the epoch of the refresh round goes first
+                let epoch =
+                    frame.data.get(..4).map(|e| pack(e)[0]);
+                let data =
+                    frame.data.get(4..).unwrap_or_default();
+                let mut db = db.lock(id);
+                patch(&mut *db, id, epoch, frame.msg, data);
+                cfg.metrics.patches.inc();
+            }
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_TRANSFER => match transfer(db, id, frame) {
+            Ok(()) => {
+                cfg.metrics.patches.inc();
+                Frame {
+                    idx: time(),
+                    tag: TAG_OK,
+                    msg: 0,
+                    key,
+                    sig: merge(key, key),
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data: vec![],
+                }
+            }
+            Err(code) => Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: code,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            },
+        },
+        // back to the version of an epoch, at a new one: the epoch
+        // to go back to and the new one
+        TAG_REPAIR => {
+            let result = match pack(&frame.data)[..] {
+                [epoch, to] if frame.data.len() == 8 => {
+                    let mut db = db.lock(id);
+                    rollback(&mut *db, id, epoch, to)
+                }
+                _ => Err(0),
+            };
+            match result {
+                Ok(()) => {
+                    cfg.metrics.patches.inc();
+                    Frame {
+                        idx: time(),
+                        tag: TAG_OK,
+                        msg: 0,
+                        key,
+                        sig: merge(key, key),
+                        ext: 0,
+                        ns: 0,
+                        sum: 0,
+                        data: vec![],
+                    }
+                }
+                Err(code) => Frame {
+                    idx: time(),
+                    tag: TAG_BAD_REQUEST,
+                    msg: 0,
+                    key,
+                    sig: merge(key, key),
+                    ext: code,
+                    ns: 0,
+                    sum: 0,
+                    data: vec![],
+                },
+            }
+        }
+        // the keys of all the namespaces: for the other servers
+        // only
+        TAG_DIGEST if !is_peer(frame, cfg) => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: ERR_FORBIDDEN,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        // the digest of each bucket of keys (`msg` zero), or the
+        // keys of a bucket and their epochs (`msg` the bucket plus
+        // one), see `anti_entropy`
+        TAG_DIGEST if frame.msg as usize <= digest::BUCKETS => {
+            let digest = digest(db);
+            let data = match frame.msg as usize {
+                0 => digest
+                    .roots()
+                    .into_iter()
+                    .flat_map(u32::to_be_bytes)
+                    .collect(),
+                bucket => digest.encode(bucket - 1),
+            };
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data,
+            }
+        }
+        // the blocks carry the operations, shares included: for the
+        // other servers only
+        TAG_GET_BLOCKS if !is_peer(frame, cfg) => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: ERR_FORBIDDEN,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+        // the blocks of the range (see `MAX_SYNC_BLOCKS`), from the
+        // offset in their bytes on; `msg` is the height of the chain,
+        // `ext` the length of the blocks of the range
+        TAG_GET_BLOCKS => match range(frame) {
+            Some((from, to, offset)) => {
+                let chain = cfg.chain.lock().unwrap();
+                let bytes = blocks(&chain, from, to);
+                let offset = offset.min(bytes.len());
+                let end =
+                    bytes.len().min(offset + MAX_PAYLOAD_LEN);
+                Frame {
+                    idx: time(),
+                    tag: TAG_BLOCKS,
+                    msg: chain.len() as u32,
+                    key,
+                    sig: merge(key, key),
+                    ext: bytes.len() as u32,
+                    ns: 0,
+                    sum: 0,
+                    data: bytes[offset..end].to_vec(),
+                }
+            }
+            None => Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            },
+        },
+        TAG_STATUS | TAG_SNAPSHOT | TAG_AUDIT | TAG_JOIN
+        | TAG_LEAVE
+            if !is_admin(frame, cfg) =>
+        {
+            Frame {
+                idx: time(),
+                tag: TAG_BAD_REQUEST,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: ERR_BAD_SIGNATURE,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            }
+        }
+        TAG_STATUS => Frame {
+            idx: time(),
+            tag: TAG_OK,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: status(db, cfg).into_bytes(),
+        },
+        // `msg` is the number of keys that failed to be handed over
+        TAG_JOIN | TAG_LEAVE => match membership(frame, db, cfg)
+        {
+            Ok((failed, lines)) => Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: failed,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: lines.into_bytes(),
+            },
+            Err(e) => {
+                warn!(?e, tag = frame.tag, "membership failed");
+                Frame {
+                    idx: time(),
+                    tag: TAG_BAD_REQUEST,
+                    msg: 0,
+                    key,
+                    sig: merge(key, key),
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data: vec![],
+                }
+            }
+        },
+        // a page of the snapshot from offset `msg`, along with its
+        // length and crc32, so that the pages can be told to be of
+        // the same snapshot
+        TAG_SNAPSHOT => {
+            let snapshot = match db.snapshot() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    warn!(?e, "snapshot failed");
+                    return (
+                        server_error(key, ERR_STORAGE),
+                        false,
+                    );
+                }
+            };
+            let offset =
+                (frame.msg as usize).min(snapshot.len());
+            let end =
+                snapshot.len().min(offset + MAX_PAYLOAD_LEN);
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: snapshot.len() as u32,
+                key,
+                sig: merge(key, key),
+                ext: crc32(&snapshot),
+                ns: 0,
+                sum: 0,
+                data: snapshot[offset..end].to_vec(),
+            }
+        }
+        // the ledger's head, signed with the server's key: anyone
+        // may ask, it tells nothing of the shares
+        TAG_LEDGER => {
+            let mut head = cfg.ledger.head();
+            head.sign(&SecretKey::new(cfg.key));
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: head.encode(),
+            }
+        }
+        // the latest `msg` entries (as many as fit), of the key in
+        // `ext` unless it is zero, a line each
+        TAG_AUDIT => {
+            let n = (frame.msg as usize).min(AUDIT_PAGE_SIZE);
+            let key_filter =
+                (frame.ext != 0).then_some(frame.ext);
+            let lines = cfg
+                .audit
+                .recent(n, key_filter)
+                .iter()
+                .map(|entry| format!("{entry}\n"))
+                .collect::<String>();
+            Frame {
+                idx: time(),
+                tag: TAG_OK,
+                msg: 0,
+                key,
+                sig: merge(key, key),
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: lines.into_bytes(),
+            }
+        }
+        tag => Frame {
+            idx: time(),
+            tag: TAG_BAD_REQUEST,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: tag,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        },
+    };
+
+    // stored before it is acknowledged
+    let flushed = {
+        let mut db = db.lock(id);
+        db.flush()
+    };
+    if let Err(e) = flushed {
+        warn!(?e, "flush failed");
+        return (server_error(key, ERR_STORAGE), false);
+    }
+    (response, trigger_refresh)
+}
+
+fn server<S: Storage<u64, u32, u32> + 'static>(
+    addr: SocketAddr,
+    db: Arc<Shards<S>>,
+    cfg: Config,
+) -> JoinHandle<Result<()>> {
+    // a worker per connection being handled, no queue
+    let workers = Arc::new(Workers::new(cfg.max_conns, 0));
+    #[cfg(feature = "ws")]
+    if let Some(addr) = cfg.ws {
+        ws_server(
+            addr,
+            db.clone(),
+            cfg.clone(),
+            workers.clone(),
+        );
+    }
+    #[cfg(feature = "quic")]
+    if cfg.quic {
+        let h = quic_server(addr, db, cfg);
+        thread::sleep(Duration::from_millis(100));
+        return h;
+    }
+    let h = thread::spawn(move || {
+        let listener = TcpListener::bind(addr)?;
+        while let Ok((socket, remote)) = listener.accept() {
+            if cfg.drain.is_stopping() {
+                break;
+            }
+            let job = {
+                let db = db.clone();
+                let cfg = cfg.clone();
+                move || {
+                    let _span =
+                        info_span!("conn", %remote).entered();
+                    debug!("accepted");
+                    let ret = connection(
+                        socket,
+                        db,
+                        &cfg,
+                        remote.ip(),
+                    );
+                    if let Err(e) = ret {
+                        debug!(?e, "closed");
+                    }
+                }
+            };
+            dispatch(&workers, &cfg, remote, job)?;
+        }
+        drop(listener);
+        workers.join();
+        Ok(())
+    });
+    thread::sleep(Duration::from_millis(100));
+    h
+}
+
+fn connection<S: Storage<u64, u32, u32>>(
+    socket: TcpStream,
+    db: Arc<Shards<S>>,
+    cfg: &Config,
+    remote: IpAddr,
+) -> Result<()> {
+    let Some(_opened) = cfg.drain.open(&socket)? else {
+        return Ok(());
+    };
+    #[cfg(feature = "tls")]
+    if let Some((tls, _)) = &cfg.tls {
+        let mut tx = Tls::server(socket, tls.clone())
+            .inspect_err(|_| {
+                cfg.metrics.handshake_failures.inc()
+            })?;
+        return handle(&mut tx, db, cfg, remote);
+    }
+    // any client: clients are identified by signatures
+    #[cfg(feature = "noise")]
+    if let Some((key, _)) = &cfg.noise {
+        let mut tx = Noise::responder(socket, key, &[])
+            .inspect_err(|_| {
+                cfg.metrics.handshake_failures.inc()
+            })?;
+        return handle(&mut tx, db, cfg, remote);
+    }
+    let mut tx = transport(socket, cfg.json);
+    tx.set_idle_timeout(Some(cfg.idle))?;
+    tx.set_rekey(cfg.rekey);
+    handle(&mut tx, db, cfg, remote)
+}
+
+// Same protocol over WebSocket (binary messages), for browsers
+#[cfg(feature = "ws")]
+fn ws_server<S: Storage<u64, u32, u32> + 'static>(
+    addr: SocketAddr,
+    db: Arc<Shards<S>>,
+    cfg: Config,
+    workers: Arc<Workers>,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        let listener = TcpListener::bind(addr)?;
+        while let Ok((socket, remote)) = listener.accept() {
+            if cfg.drain.is_stopping() {
+                break;
+            }
+            let job = {
+                let db = db.clone();
+                let cfg = cfg.clone();
+                move || {
+                    let _span =
+                        info_span!("ws", %remote).entered();
+                    let Ok(Some(_opened)) =
+                        cfg.drain.open(&socket)
+                    else {
+                        return;
+                    };
+                    let _ =
+                        Ws::accept(socket).and_then(|mut tx| {
+                            handle(
+                                &mut tx,
+                                db,
+                                &cfg,
+                                remote.ip(),
+                            )
+                        });
+                }
+            };
+            dispatch(&workers, &cfg, remote, job)?;
+        }
+        Ok(())
+    })
+}
+
+// Stream per request: no session, no TAG_BATCH or TAG_CLOSE
+#[cfg(feature = "quic")]
+fn quic_server<S: Storage<u64, u32, u32> + 'static>(
+    addr: SocketAddr,
+    db: Arc<Shards<S>>,
+    cfg: Config,
+) -> JoinHandle<Result<()>> {
+    let (tls, _) = cfg.tls.clone().expect("QUIC requires TLS");
+    thread::spawn(move || {
+        let drain = cfg.drain.clone();
+        let until = move || drain.wait();
+        quic::serve(addr, tls, until, move |remote, frame| {
+            let _span = info_span!("quic", %remote).entered();
+            debug!(?frame, "recv");
+            let started = Instant::now();
+            cfg.metrics.received.inc(frame.tag);
+            let (mut response, trigger_refresh) =
+                if !allowed(&cfg, remote.ip()) {
+                    (rate_limited(cfg.key), false)
+                } else {
+                    respond(&frame, &db, &cfg)
+                };
+            response.idx = frame.idx; // correlation ID
+            response.sum = response.checksum();
+            audit(&cfg, &frame, &response, remote.ip());
+            pool(&cfg, &frame, &response);
+            if trigger_refresh {
+                if let Err(e) =
+                    refresh(db.clone(), &cfg, scoped(&frame))
+                {
+                    warn!(?e, "refresh failed");
+                }
+            }
+            debug!(?response, "send");
+            cfg.metrics.sent.inc(response.tag);
+            cfg.metrics.latency.observe(started.elapsed());
+            response
+        })
+    })
+}
+
+fn transport(socket: TcpStream, json: bool) -> Tcp {
+    #[cfg(feature = "json")]
+    if json {
+        use doing_some_blockchain::codec::Json;
+        return Tcp::with_codec(socket, Box::new(Json));
+    }
+    assert!(!json, "`json` feature is not enabled");
+    Tcp::from(socket)
+}
+
+// The masks are those of `xor::refresh` over this server's share and
+// the peers' ones: each peer masks its share with its own, and this
+// server's share is masked with the XOR of the masks the peers did
+// apply (its own mask of `xor::refresh` if they all did): the shares
+// still XOR to the secret, for any number of peers and whichever of
+// them fail to refresh. Shamir shares all
+// get the same random polynomial with zero constant term added
+// instead (each server evaluates it at its own x), mod q for
+// Feldman shares, with the commitments updated to match. Byte
+// secrets are refreshed as XOR ones, each mask as long as the share.
+fn refresh<S: Storage<u64, u32, u32>>(
+    db: Arc<Shards<S>>,
+    cfg: &Config,
+    owner: u64,
+) -> Result<()> {
+    if cfg.replicated {
+        return Ok(());
+    }
+    let key = cfg.key;
+    let (ns, owner_key) = split(owner);
+    let (scheme, len, epoch) = {
+        let mut db = db.lock(owner);
+        let epoch = db.epochs(owner).last().map_or(0, |e| e + 1);
+        // the same in every version
+        (db.scheme(owner), db.attachment(owner, 0).len(), epoch)
+    };
+    let delta = (scheme & !BYTES != 0).then(|| {
+        let threshold = (scheme >> 16) & 0x1FFF;
+        let modulo = if scheme & VERIFIABLE != 0 {
+            vss::Q as u32
+        } else if scheme & SIGNING != 0 {
+            curve::N as u32
+        } else {
+            u32::MAX
+        };
+        std::iter::once(0)
+            .chain(
+                (1..threshold)
+                    .map(|_| cfg.rng.next_u32() % modulo),
+            )
+            .collect::<Vec<_>>()
+    });
+    // a polynomial is applied whatever the peers do
+    let mut own = delta.as_ref().map(|_| 0);
+    let mut own_bytes = vec![0u8; len];
+    let mut failed = None;
+    let peers = cfg.peers.list();
+    // this server's share first, then the peers' ones
+    let mut masks = vec![0; peers.len() + 1];
+    xor::refresh(&mut masks, || cfg.rng.next_u32());
+    for (peer, &mask) in peers.iter().zip(&masks[1..]) {
+        let data = match &delta {
+            Some(delta) => unpack(delta, 4 * delta.len()),
+            None if scheme & BYTES != 0 => {
+                pad(len, || cfg.rng.next_u32())
+            }
+            None => vec![],
+        };
+        let mut refresh = Frame {
+            idx: time(),
+            tag: TAG_REFRESH,
+            msg: mask,
+            key,
+            sig: merge(key, key),
+            ext: owner_key,
+            ns,
+            sum: 0,
+            data: epoch
+                .to_be_bytes()
+                .into_iter()
+                .chain(data)
+                .collect(),
+        };
+        refresh.sign(&SecretKey::new(cfg.key));
+        refresh.sum = refresh.checksum();
+        match call_peer(*peer, &refresh, cfg) {
+            Ok(response) if response.tag == TAG_OK => {
+                cfg.metrics.refreshes.inc();
+                if delta.is_none() {
+                    own = Some(own.unwrap_or_default() ^ mask);
+                    xor::mask(
+                        &mut own_bytes,
+                        &refresh.data[4..],
+                    );
+                }
+            }
+            Ok(response) if response.ext == ERR_DELETED => {
+                // the owner deleted the secret while this server
+                // was not there to hear about it
+                info!(
+                    key = %format_args!("{owner:0x}"),
+                    %peer,
+                    "deleted by peer"
+                );
+                let mut db = db.lock(owner);
+                db.delete(owner, time());
+                db.flush()?;
+                return Ok(());
+            }
+            Ok(response) => {
+                cfg.metrics.refresh_failures.inc();
+                failed = Some(Error::App(format!(
+                    "peer={peer} tag={} ext={}",
+                    response.tag, response.ext
+                )));
+            }
+            Err(e) => {
+                cfg.metrics.refresh_failures.inc();
+                failed = Some(e);
+            }
+        }
+    }
+
+    if let Some(mask) = own {
+        let data = match delta {
+            Some(delta) => unpack(&delta, 4 * delta.len()),
+            None => own_bytes,
+        };
+        let mut db = db.lock(owner);
+        patch(&mut *db, owner, Some(epoch), mask, &data);
+        db.flush()?;
+    }
+    failed.map_or(Ok(()), Err)
+}
+
+// A round of its own for the owner's share, with every peer in it
+// (a server that joined too, which gets a zero share to begin with,
+// see `transfer`): each peer's share is masked with a random mask
+// and this server's with the XOR of the masks the peers did apply,
+// as in `refresh`, all at the same epoch. Leaving, the masks XOR to
+// this server's share: it is zero once they are all applied, and
+// the peers' shares alone XOR to the secret. A threshold share (x
+// and polynomial of its own) can not be handed over that way, it is
+// left as it is (false).
+fn hand_over<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+    owner: u64,
+    leaving: bool,
+) -> Result<bool> {
+    if cfg.replicated {
+        return Ok(false);
+    }
+    let key = cfg.key;
+    let (ns, owner_key) = split(owner);
+    let peers = cfg.peers.list();
+    let (share, bytes, scheme, epoch, expiry, owner_pk) = {
+        let mut db = db.lock(owner);
+        let Some(share) = db.get(owner) else {
+            return Ok(false); // deleted meanwhile
+        };
+        let version = db.version(owner);
+        (
+            share,
+            db.attachment(owner, version),
+            db.scheme(owner),
+            db.epochs(owner).last().map_or(0, |e| e + 1),
+            db.expiry(owner).unwrap_or_default(),
+            db.owner(owner),
+        )
+    };
+    if scheme != 0 && scheme != BYTES {
+        return Ok(false);
+    }
+    if peers.is_empty() {
+        return Err(Error::App("no peers".to_string()));
+    }
+    let rng = || cfg.rng.next_u32();
+    let (masks, pads) = if leaving {
+        (
+            xor::split(share, peers.len(), rng),
+            xor::split_bytes(&bytes, peers.len(), rng),
+        )
+    } else {
+        (
+            peers.iter().map(|_| rng()).collect(),
+            peers
+                .iter()
+                .map(|_| pad(bytes.len(), rng))
+                .collect(),
+        )
+    };
+    let head = [epoch, scheme, expiry]
+        .into_iter()
+        .flat_map(u32::to_be_bytes)
+        .chain(owner_pk.map_or(vec![0; 8], |pk| pk.to_bytes()))
+        .collect::<Vec<_>>();
+
+    let mut own = 0;
+    let mut own_bytes = vec![0u8; bytes.len()];
+    let mut failed = None;
+    for ((peer, mask), data) in peers.iter().zip(masks).zip(pads)
+    {
+        let mut transfer = Frame {
+            idx: time(),
+            tag: TAG_TRANSFER,
+            msg: mask,
+            key,
+            sig: merge(key, key),
+            ext: owner_key,
+            ns,
+            sum: 0,
+            data: [head.as_slice(), &data].concat(),
+        };
+        transfer.sign(&SecretKey::new(cfg.key));
+        transfer.sum = transfer.checksum();
+        match call_peer(*peer, &transfer, cfg) {
+            Ok(response) if response.tag == TAG_OK => {
+                own ^= mask;
+                xor::mask(&mut own_bytes, &data);
+            }
+            Ok(response) if response.ext == ERR_DELETED => {
+                // as in `refresh`
+                let mut db = db.lock(owner);
+                db.delete(owner, time());
+                db.flush()?;
+                return Ok(true);
+            }
+            Ok(response) => {
+                failed = Some(Error::App(format!(
+                    "peer={peer} tag={} ext={}",
+                    response.tag, response.ext
+                )));
+            }
+            Err(e) => failed = Some(e),
+        }
+    }
+
+    let mut db = db.lock(owner);
+    patch(&mut *db, owner, Some(epoch), own, &own_bytes);
+    db.flush()?;
+    failed.map_or(Ok(true), Err)
+}
+
+// Next version of the owner's share: XOR-ed with `mask` (or with
+// the mask in `data` for a byte secret), or with the polynomial in
+// `data` (zero constant term) added at the share's x, along with
+// the commitments of a Feldman share (over Z_N for a share of a
+// signing key), at `epoch` (the one after the
+// latest if none is given)
+fn patch<S: Storage<u64, u32, u32>>(
+    db: &mut S,
+    owner: u64,
+    epoch: Option<u32>,
+    mask: u32,
+    data: &[u8],
+) {
+    let scheme = db.scheme(owner);
+    let x = scheme & 0xFFFF;
+    let delta = pack(data);
+    if scheme & VERIFIABLE != 0 {
+        let d = vss::eval(&delta, x);
+        db.update(owner, |y| vss::add(y, d));
+        db.attach(owner, |c| {
+            unpack64(&vss::refresh(&pack64(c), &delta))
+        });
+    } else if scheme & BYTES != 0 {
+        db.patch(owner, 0);
+        db.attach(owner, |share| {
+            let mut share = share.to_vec();
+            xor::mask(&mut share, data);
+            share
+        });
+    } else if scheme & SIGNING != 0 {
+        let d = frost::eval(&delta, x);
+        db.update(owner, |y| frost::add(y, d));
+        // the group key stays, a version of it for each version
+        db.attach(owner, |group_key| group_key.to_vec());
+    } else if scheme != 0 {
+        db.patch(owner, shamir::eval(&delta, x));
+    } else {
+        db.patch(owner, mask);
+    }
+    if let Some(epoch) = epoch {
+        db.set_epoch(owner, epoch);
+    }
+    debug!(
+        key = %format_args!("{owner:0x}"),
+        mask = %format_args!("{mask:0x}"),
+        ?epoch,
+        "patch"
+    );
+}
+
+// The owner's share back to its version of `epoch` (the latest
+// one, with what is attached to it), as the next version, at `to`:
+// the servers that went different ways since are in line again, as
+// long as they all had that version
+fn rollback<S: Storage<u64, u32, u32>>(
+    db: &mut S,
+    owner: u64,
+    epoch: u32,
+    to: u32,
+) -> std::result::Result<(), u32> {
+    let version = db
+        .epochs(owner)
+        .iter()
+        .rposition(|e| *e == epoch)
+        .ok_or(ERR_NOT_FOUND)?;
+    let share =
+        db.get_version(owner, version).ok_or(ERR_NOT_FOUND)?;
+    if db.scheme(owner) & (VERIFIABLE | BYTES | SIGNING) != 0 {
+        let attachment = db.attachment(owner, version);
+        db.attach(owner, |_| attachment);
+    }
+    db.update(owner, |_| share);
+    db.set_epoch(owner, to);
+    debug!(
+        key = %format_args!("{owner:0x}"),
+        epoch,
+        to,
+        "rollback"
+    );
+    Ok(())
+}
+
+// Refresh all the stored keys every `interval`, until shutdown. Each
+// wait is shortened by a random part of up to REFRESH_JITTER of it,
+// so that servers refreshing on their own do not do it in lockstep.
+fn schedule<S: Storage<u64, u32, u32>>(
+    db: Arc<Shards<S>>,
+    cfg: &Config,
+    interval: Duration,
+) {
+    loop {
+        let jitter = REFRESH_JITTER
+            * (cfg.rng.next_u32() as f64 / u32::MAX as f64);
+        if cfg.drain.sleep(interval.mul_f64(1.0 - jitter)) {
+            return;
+        }
+        let keys = db.keys();
+        debug!(keys = keys.len(), "scheduled refresh");
+        for owner in keys {
+            if let Err(e) = refresh(db.clone(), cfg, owner) {
+                warn!(
+                    key = %format_args!("{owner:0x}"),
+                    ?e,
+                    "scheduled refresh failed"
+                );
+            }
+        }
+    }
+}
+
+// Delete the secrets that expired by `now`, and forget the old
+// tombstones: how many of both there were
+fn purge<S: Storage<u64, u32, u32>>(
+    db: &mut S,
+    now: u32,
+) -> usize {
+    let expired = db
+        .keys()
+        .into_iter()
+        .filter(|key| {
+            db.expiry(*key).is_some_and(|at| at <= now)
+        })
+        .collect::<Vec<_>>();
+    for key in &expired {
+        db.delete(*key, now);
+    }
+    expired.len() + db.forget(now.saturating_sub(TOMBSTONE_TTL))
+}
+
+fn janitor<S: Storage<u64, u32, u32>>(
+    db: Arc<Shards<S>>,
+    cfg: &Config,
+) {
+    while !cfg.drain.sleep(JANITOR_INTERVAL) {
+        let mut purged = 0;
+        for mut shard in db.iter() {
+            let n = purge(&mut *shard, time());
+            if n > 0 {
+                if let Err(e) = shard.flush() {
+                    warn!(?e, "flush failed");
+                }
+                purged += n;
+            }
+        }
+        if purged > 0 {
+            debug!(
+                purged,
+                "expired secrets and tombstones purged"
+            );
+        }
+    }
+}
+
+// A round every `interval`: the membership view goes to a random
+// member (one of the peers to begin with), which answers with its
+// own, both sides take in what they did not know
+fn gossip(cfg: &Config, interval: Duration) {
+    while !cfg.drain.sleep(interval) {
+        cfg.members.tick();
+        let Some(peer) = cfg.members.pick(cfg.rng.next_u32())
+        else {
+            continue;
+        };
+        let key = cfg.key;
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_GOSSIP,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: cfg.members.encode(),
+        };
+        frame.sign(&SecretKey::new(cfg.key));
+        frame.sum = frame.checksum();
+        match call_peer(peer, &frame, cfg) {
+            Ok(response) if response.tag == TAG_OK => {
+                if let Err(e) = cfg.members.merge(&response.data)
+                {
+                    warn!(%peer, ?e, "invalid gossip");
+                }
+            }
+            Ok(response) => {
+                debug!(%peer, tag = response.tag, "gossip rejected")
+            }
+            Err(e) => debug!(%peer, ?e, "gossip failed"),
+        }
+    }
+}
+
+// All the keys stored and their epochs
+fn digest<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+) -> Digest {
+    Digest::new(db.keys().into_iter().map(|key| {
+        let epochs = db.lock(key).epochs(key);
+        (key, epochs)
+    }))
+}
+
+// A block of the operations in the mempool every `interval` (and a
+// last one at shutdown), appended to the chain
+fn package(cfg: &Config, interval: Duration) {
+    let Some(mempool) = &cfg.mempool else {
+        return;
+    };
+    loop {
+        // the peers' blocks first, to go on top of them
+        match catch_up(cfg) {
+            Ok(0) => (),
+            Ok(synced) => info!(synced, "blocks synced"),
+            Err(e) => warn!(?e, "block sync failed"),
+        }
+        let stopped = cfg.drain.sleep(interval);
+        while !mempool.is_empty() {
+            let mut chain = cfg.chain.lock().unwrap();
+            match mempool.package(&mut chain, time()) {
+                Ok(Some(block)) => {
+                    cfg.metrics.blocks.inc();
+                    let ops = mempool::ops(&block.payload)
+                        .map_or(0, |ops| ops.len());
+                    info!(
+                        height = chain.len() - 1,
+                        ops, "block"
+                    );
+                }
+                Ok(None) => (),
+                Err(e) => warn!(?e, "packaging failed"),
+            }
+        }
+        if stopped {
+            break;
+        }
+    }
+}
+
+// From, to and the offset of a TAG_GET_BLOCKS, after the public key
+fn range(frame: &Frame) -> Option<(usize, usize, usize)> {
+    let data = payload(frame);
+    if data.len() != 12 {
+        return None;
+    }
+    let word = |i: usize| {
+        u32::from_be_bytes(data[i..i + 4].try_into().unwrap())
+            as usize
+    };
+    Some((word(0), word(4), word(8)))
+}
+
+// The blocks from `from` up to `to` (MAX_SYNC_BLOCKS and
+// MAX_SYNC_LEN at most, one at least), encoded
+fn blocks(chain: &Chain, from: usize, to: usize) -> Vec<u8> {
+    let blocks = chain.blocks();
+    let from = from.min(blocks.len());
+    let to = to.min(blocks.len()).min(from + MAX_SYNC_BLOCKS);
+    let mut bytes = vec![];
+    for block in blocks[from..to.max(from)].iter() {
+        let block = block.encode();
+        if !bytes.is_empty()
+            && bytes.len() + block.len() > MAX_SYNC_LEN
+        {
+            break;
+        }
+        bytes.extend(block);
+    }
+    bytes
+}
+
+// The blocks the peers have on top of this server's chain (a server
+// that restarted, or was left behind), or in place of its latest
+// ones (a branch with more work done, see `Chain::reorg`), taken
+// once checked, each peer in turn. The operations of the blocks
+// rolled back that are not in the new ones go back to the mempool,
+// to be applied again in a block to come. How many blocks were taken.
+fn catch_up(cfg: &Config) -> Result<usize> {
+    let mut taken = 0;
+    for peer in cfg.peers.list() {
+        let branch = match branch(peer, cfg) {
+            Ok(branch) => branch,
+            Err(e) => {
+                debug!(%peer, ?e, "get blocks failed");
+                continue;
+            }
+        };
+        let mut chain = cfg.chain.lock().unwrap();
+        let reorg = match chain.reorg(branch) {
+            Ok(Some(reorg)) => reorg,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!(%peer, %e, "blocks not taken");
+                continue;
+            }
+        };
+        drop(chain);
+        if !reorg.undone.is_empty() {
+            cfg.metrics.reorgs.inc();
+            info!(
+                %peer,
+                height = reorg.height,
+                undone = reorg.undone.len(),
+                done = reorg.done.len(),
+                "reorg"
+            );
+            requeue(cfg, &reorg);
+        }
+        for _ in &reorg.done {
+            cfg.metrics.synced.inc();
+        }
+        taken += reorg.done.len();
+    }
+    Ok(taken)
+}
+
+// The peer's blocks above the highest one of this server's chain the
+// peer has too: the ones on top of the chain, down from its tip
+// (twice as far each time, MAX_REORG_DEPTH at most) until the lowest
+// one is on top of a block of the chain, then up to the peer's
+// height. None if the peer is not as high as this server.
+fn branch(peer: SocketAddr, cfg: &Config) -> Result<Vec<Block>> {
+    let height = cfg.chain.lock().unwrap().len();
+    let lowest = height.saturating_sub(MAX_REORG_DEPTH);
+    let (mut from, mut depth) = (height, 1);
+    let (top, mut branch) = loop {
+        let (top, blocks) = get_blocks(peer, from, cfg)?;
+        let Some(first) = blocks.first() else {
+            return Ok(vec![]);
+        };
+        let above = cfg.chain.lock().unwrap().above(&first.prev);
+        if above == Some(from) {
+            break (top, blocks);
+        }
+        if from == lowest {
+            return Err(Error::App(format!(
+                "peer={peer} no block in common"
+            )));
+        }
+        from = from.saturating_sub(depth).max(lowest);
+        depth *= 2;
+    };
+    while from + branch.len() < top {
+        let (_, blocks) =
+            get_blocks(peer, from + branch.len(), cfg)?;
+        if blocks.is_empty() {
+            break;
+        }
+        branch.extend(blocks);
+    }
+    Ok(branch)
+}
+
+// The operations of the blocks rolled back that the new ones do not
+// have, back to the mempool (first, in the order they were in)
+fn requeue(cfg: &Config, reorg: &block::Reorg) {
+    let Some(mempool) = &cfg.mempool else {
+        return;
+    };
+    let ops = |blocks: &[Block]| {
+        blocks
+            .iter()
+            .filter_map(|block| {
+                mempool::ops(&block.payload).ok()
+            })
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+    let done = ops(&reorg.done);
+    let lost = ops(&reorg.undone)
+        .into_iter()
+        .filter(|op| !done.contains(op))
+        .collect::<Vec<_>>();
+    if !lost.is_empty() {
+        info!(ops = lost.len(), "back to the mempool");
+        mempool.restore(lost);
+    }
+}
+
+// The peer's height and its blocks from `from` on (up to
+// MAX_SYNC_BLOCKS), a piece of their bytes at a time
+fn get_blocks(
+    peer: SocketAddr,
+    from: usize,
+    cfg: &Config,
+) -> Result<(usize, Vec<Block>)> {
+    let secret_key = SecretKey::new(cfg.key);
+    let public_key = secret_key.public_key();
+    let mut bytes = vec![];
+    loop {
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_GET_BLOCKS,
+            msg: 0,
+            key: public_key.fingerprint(),
+            sig: 0,
+            ext: cfg.rng.next_u32(), // not a replay of the one before
+            ns: 0,
+            sum: 0,
+            data: [
+                u64::from(&public_key),
+                merge(
+                    from as u32,
+                    (from + MAX_SYNC_BLOCKS) as u32,
+                ),
+            ]
+            .into_iter()
+            .flat_map(u64::to_be_bytes)
+            .chain((bytes.len() as u32).to_be_bytes())
+            .collect(),
+        };
+        frame.sign(&secret_key);
+        frame.sum = frame.checksum();
+        let response = call_peer(peer, &frame, cfg)?;
+        if response.tag != TAG_BLOCKS {
+            return Err(Error::App(format!(
+                "peer={peer} tag={} ext={}",
+                response.tag, response.ext
+            )));
+        }
+        let len = response.ext as usize;
+        if len > MAX_SYNC_LEN + MAX_PAYLOAD_LEN
+            || bytes.len() + response.data.len() > len
+            || (response.data.is_empty() && bytes.len() < len)
+        {
+            return Err(Error::App(format!(
+                "peer={peer} invalid blocks: {len} bytes"
+            )));
+        }
+        bytes.extend(response.data);
+        if bytes.len() == len {
+            return Ok((
+                response.msg as usize,
+                block::decode(&bytes)?,
+            ));
+        }
+    }
+}
+
+// A round every `interval` (see `anti_entropy`), until shutdown
+fn reconcile<S: Storage<u64, u32, u32>>(
+    db: Arc<Shards<S>>,
+    cfg: &Config,
+    interval: Duration,
+) {
+    let mut diverged = HashMap::new();
+    while !cfg.drain.sleep(interval) {
+        match anti_entropy(&db, cfg, &mut diverged) {
+            Ok(0) => (),
+            Ok(repaired) => info!(repaired, "anti-entropy"),
+            Err(e) => warn!(?e, "anti-entropy failed"),
+        }
+    }
+}
+
+// The keys whose latest epoch on some peer is not this server's (a
+// refresh round the peer missed, or this server did): the digests
+// of the buckets of keys (TAG_DIGEST) are compared first, then the
+// keys of the buckets that differ. A key that differs the same way
+// as in the round before (not a refresh on its way then) is brought
+// back in line on all the servers (TAG_REPAIR, see `rollback` and
+// `digest::repair`), `diverged` keeps the ones seen from one round
+// to the next. How many keys were repaired. Nothing to do for
+// replicated shares, which are not refreshed.
+fn anti_entropy<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+    diverged: &mut HashMap<u64, u32>,
+) -> Result<usize> {
+    if cfg.replicated {
+        return Ok(0);
+    }
+    let own = digest(db);
+    let roots = own.roots();
+    // the buckets that differ on any of the peers, then the keys of
+    // those on all of them (but the ones that do not answer)
+    let mut peers = vec![];
+    let mut buckets = BTreeSet::new();
+    for peer in cfg.peers.list() {
+        match call_digest(peer, 0, cfg) {
+            Ok(response) => {
+                let theirs = pack(&response.data);
+                buckets.extend((0..roots.len()).filter(|b| {
+                    theirs.get(*b) != Some(&roots[*b])
+                }));
+                peers.push(peer);
+            }
+            Err(e) => debug!(%peer, ?e, "digest failed"),
+        }
+    }
+    // the epochs of each key on the peers that have it
+    let mut theirs: HashMap<u64, Vec<Vec<u32>>> = HashMap::new();
+    for peer in &peers {
+        for bucket in &buckets {
+            let response =
+                call_digest(*peer, *bucket as u32 + 1, cfg)?;
+            for (key, epochs) in digest::decode(&response.data)?
+            {
+                theirs.entry(key).or_default().push(epochs);
+            }
+        }
+    }
+
+    let mut repaired = 0;
+    let mut seen = HashMap::with_capacity(theirs.len());
+    for (owner, theirs) in theirs {
+        let own = own.keys(digest::bucket(owner));
+        let Some((_, own)) =
+            own.iter().find(|(k, _)| *k == owner)
+        else {
+            continue; // not here
+        };
+        if theirs
+            .iter()
+            .all(|epochs| epochs.last() == own.last())
+        {
+            continue;
+        }
+        let view = crc32(
+            &std::iter::once(own)
+                .chain(&theirs)
+                .flatten()
+                .flat_map(|e| e.to_be_bytes())
+                .collect::<Vec<_>>(),
+        );
+        seen.insert(owner, view);
+        if diverged.get(&owner) != Some(&view) {
+            continue;
+        }
+        let Some((epoch, to)) = digest::repair(own, &theirs)
+        else {
+            warn!(
+                key = %format_args!("{owner:0x}"),
+                "no epoch in common with the peers"
+            );
+            continue;
+        };
+        repair(db, cfg, owner, epoch, to)?;
+        cfg.metrics.repairs.inc();
+        repaired += 1;
+    }
+    *diverged = seen;
+    Ok(repaired)
+}
+
+// The owner's share back to its version of `epoch`, at `to`, on
+// every peer, then on this server
+fn repair<S: Storage<u64, u32, u32>>(
+    db: &Arc<Shards<S>>,
+    cfg: &Config,
+    owner: u64,
+    epoch: u32,
+    to: u32,
+) -> Result<()> {
+    let key = cfg.key;
+    let (ns, owner_key) = split(owner);
+    for peer in &cfg.peers.list() {
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_REPAIR,
+            msg: 0,
+            key,
+            sig: merge(key, key),
+            ext: owner_key,
+            ns,
+            sum: 0,
+            data: unpack(&[epoch, to], 8),
+        };
+        frame.sign(&SecretKey::new(cfg.key));
+        frame.sum = frame.checksum();
+        match call_peer(*peer, &frame, cfg) {
+            Ok(response) if response.tag == TAG_OK => (),
+            // a peer that does not have it, or missed it (it is
+            // for the next round then)
+            Ok(response) => debug!(
+                %peer,
+                tag = response.tag,
+                ext = response.ext,
+                "repair rejected"
+            ),
+            Err(e) => debug!(%peer, ?e, "repair failed"),
+        }
+    }
+    let mut db = db.lock(owner);
+    if let Err(code) = rollback(&mut *db, owner, epoch, to) {
+        return Err(Error::App(format!("rollback: ext={code}")));
+    }
+    db.flush()
+}
+
+fn call_digest(
+    peer: SocketAddr,
+    bucket: u32,
+    cfg: &Config,
+) -> Result<Frame> {
+    let key = cfg.key;
+    let mut frame = Frame {
+        idx: time(),
+        tag: TAG_DIGEST,
+        msg: bucket,
+        key,
+        sig: merge(key, key),
+        ext: cfg.rng.next_u32(), // not a replay of the one before
+        ns: 0,
+        sum: 0,
+        data: vec![],
+    };
+    frame.sign(&SecretKey::new(cfg.key));
+    frame.sum = frame.checksum();
+    let response = call_peer(peer, &frame, cfg)?;
+    if response.tag != TAG_OK {
+        return Err(Error::App(format!(
+            "peer={peer} tag={} ext={}",
+            response.tag, response.ext
+        )));
+    }
+    Ok(response)
+}
+
+// Call a peer over the configured transport, reusing sessions
+fn call_peer(
+    peer: SocketAddr,
+    frame: &Frame,
+    cfg: &Config,
+) -> Result<Frame> {
+    // a session per call
+    #[cfg(test)]
+    if let Some(net) = &cfg.network {
+        let from = cfg.members.me().to_string();
+        let mut tx = net.connect(&from, &peer.to_string())?;
+        handshake(&mut tx, cfg)?;
+        send(&mut tx, frame)?;
+        return recv(&mut tx, cfg.timeout);
+    }
+    #[cfg(feature = "quic")]
+    if let (true, Some((_, tls))) = (cfg.quic, &cfg.tls) {
+        static QUIC_PEERS: Pool<QuicClient> =
+            Pool::new(MAX_IDLE);
+        let connect = || QuicClient::connect(peer, tls.clone());
+        let frame = QUIC_PEERS.with(
+            peer,
+            connect,
+            |tx| {
+                debug!(?frame, "send");
+                tx.send(frame)
+            },
+            |tx, stream| tx.recv(stream),
+        )?;
+        debug!(?frame, "recv");
+        if frame.sum != frame.checksum() {
+            return Err(Error::App(
+                "invalid checksum".to_string(),
+            ));
+        }
+        return Ok(frame);
+    }
+    #[cfg(feature = "tls")]
+    if let Some((_, tls)) = &cfg.tls {
+        static TLS_PEERS: Pool<Tls> = Pool::new(MAX_IDLE);
+        let connect = || {
+            let socket = connect(peer, cfg)?;
+            Tls::client(socket, tls.clone(), peer.ip())
+        };
+        return TLS_PEERS.with(
+            peer,
+            connect,
+            |tx| send(tx, frame),
+            |tx, _| recv(tx, cfg.timeout),
+        );
+    }
+    #[cfg(feature = "noise")]
+    if let Some((key, trusted)) = &cfg.noise {
+        static NOISE_PEERS: Pool<Noise> = Pool::new(MAX_IDLE);
+        let connect = || {
+            let socket = connect(peer, cfg)?;
+            Noise::initiator(socket, key, trusted)
+        };
+        return NOISE_PEERS.with(
+            peer,
+            connect,
+            |tx| send(tx, frame),
+            |tx, _| recv(tx, cfg.timeout),
+        );
+    }
+    static PEERS: Pool<Tcp> = Pool::new(MAX_IDLE);
+    let connect = || {
+        let mut tx = transport(connect(peer, cfg)?, cfg.json);
+        handshake(&mut tx, cfg)?;
+        tx.set_rekey(cfg.rekey);
+        // well within the peer's idle timeout (if the same)
+        tx.keep_alive(cfg.idle / 3);
+        Ok(tx)
+    };
+    PEERS.with(
+        peer,
+        connect,
+        |tx| send(tx, frame),
+        |tx, _| recv(tx, cfg.timeout),
+    )
+}
+
+fn connect(peer: SocketAddr, cfg: &Config) -> Result<TcpStream> {
+    Ok(TcpStream::connect_timeout(&peer, cfg.connect_timeout)?)
+}
+
+fn handshake<T: Transport<Keys>>(
+    tx: &mut T,
+    cfg: &Config,
+) -> Result<()> {
+    if !cfg.json && tx.needs_handshake() {
+        // the peer must be one of PEER_KEYS, if set
+        let key = SecretKey::new(cfg.key);
+        let auth = Auth {
+            key: &key,
+            trusted: &cfg.peer_keys,
+            required: !cfg.peer_keys.is_empty(),
+        };
+        let keys = match &cfg.psk {
+            Some(psk) => dhke::psk(tx, cfg.timeout, psk)?,
+            None => dhke::authenticated(
+                tx,
+                cfg.timeout,
+                &cfg.exchange,
+                &auth,
+            )?
+            .0
+            .keys(),
+        };
+        tx.set_session_key(keys);
+    }
+    Ok(())
+}
+
+fn send<T: Transport<Keys>>(
+    tx: &mut T,
+    frame: &Frame,
+) -> Result<()> {
+    tx.send(frame)?;
+    debug!(?frame, "send");
+    Ok(())
+}
+
+fn recv<T: Transport<Keys>>(
+    tx: &mut T,
+    timeout: Duration,
+) -> Result<Frame> {
+    let frame: Frame = tx.recv_timeout(timeout)?;
+    debug!(?frame, "recv");
+    if frame.sum != frame.checksum() {
+        return Err(Error::App("invalid checksum".to_string()));
+    }
+    Ok(frame)
+}
+
+// `<key> <port> <peer>[,<peer>...]`, e.g. `AAAAAAAA 10001
+// 127.0.0.1:10002 --sync`
+#[derive(Parser)]
+struct Cli {
+    /// The server's secret key (hex)
+    #[arg(value_parser = hex)]
+    key: u32,
+    /// To listen on (127.0.0.1)
+    port: u16,
+    /// The other servers, comma-separated
+    #[arg(value_delimiter = ',', required = true)]
+    peers: Vec<SocketAddr>,
+    /// This one starts the refreshes (exactly one server should)
+    #[arg(long)]
+    sync: bool,
+    /// Plain-text JSON frames, no handshake (debug only)
+    #[arg(long)]
+    json: bool,
+    /// Keep the shares in a file there, in memory only if not set
+    #[arg(long, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+    /// Start with the shares from a snapshot (taken with the
+    /// client's `snapshot`), only if there are none yet
+    #[arg(long, value_name = "SNAPSHOT")]
+    restore: Option<PathBuf>,
+    /// Of TCP sessions, in seconds (IDLE_TIMEOUT otherwise)
+    #[arg(long, value_name = "SECONDS")]
+    idle_timeout: Option<u64>,
+    /// Of a response from a peer and of the handshake, in
+    /// milliseconds (TIMEOUT otherwise)
+    #[arg(long, value_name = "MS", value_parser = positive)]
+    timeout: Option<u64>,
+    /// Of a connection to a peer, in milliseconds
+    /// (CONNECT_TIMEOUT otherwise)
+    #[arg(long, value_name = "MS", value_parser = positive)]
+    connect_timeout: Option<u64>,
+    /// `NAME=value` lines, for the env variables not set
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
+// A zero timeout would be no timeout at all for a socket
+fn positive(s: &str) -> std::result::Result<u64, String> {
+    match s.parse() {
+        Ok(0) => Err("must be positive".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("{e}")),
+    }
+}
+
+fn hex(s: &str) -> std::result::Result<u32, ParseIntError> {
+    u32::from_str_radix(s, 16)
+}
+
+fn main() {
+    let Cli {
+        key,
+        port,
+        peers,
+        sync,
+        json,
+        data_dir,
+        restore: snapshot,
+        idle_timeout,
+        timeout,
+        connect_timeout,
+        config,
+    } = Cli::parse();
+    if let Some(path) = config {
+        load_env(&path).unwrap_or_else(|e| {
+            panic!("{}: {e:?}", path.display())
+        });
+    }
+    let snapshot = snapshot.map(|path| {
+        fs::read(&path).unwrap_or_else(|e| {
+            panic!("{}: {e}", path.display())
+        })
+    });
+
+    let window = std::env::var("FRESHNESS_WINDOW")
+        .map(|w| w.parse().expect("invalid freshness window"))
+        .unwrap_or(DEFAULT_WINDOW);
+    let idle = idle_timeout
+        .map(Duration::from_secs)
+        .or_else(|| {
+            std::env::var("IDLE_TIMEOUT").ok().map(|s| {
+                let secs =
+                    s.parse().expect("invalid idle timeout");
+                Duration::from_secs(secs)
+            })
+        })
+        .unwrap_or(DEFAULT_IDLE_TIMEOUT);
+    // milliseconds, the flag or the env variable
+    let millis = |flag: Option<u64>, name: &str| {
+        flag.or_else(|| {
+            std::env::var(name).ok().map(|s| {
+                s.parse()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .unwrap_or_else(|| panic!("invalid {name}"))
+            })
+        })
+        .map_or(DEFAULT_TIMEOUT, Duration::from_millis)
+    };
+    let timeout = millis(timeout, "TIMEOUT");
+    let connect_timeout =
+        millis(connect_timeout, "CONNECT_TIMEOUT");
+    let rate = std::env::var("RATE_LIMIT")
+        .map(|s| s.parse().expect("invalid rate limit"))
+        .unwrap_or(DEFAULT_RATE);
+    let burst = std::env::var("RATE_BURST")
+        .map(|s| s.parse().expect("invalid rate burst"))
+        .unwrap_or(DEFAULT_BURST);
+    let limiter = (rate > 0.0)
+        .then(|| Arc::new(Limiter::new(rate, burst)));
+    let max_conns = std::env::var("MAX_CONNECTIONS")
+        .map(|s| s.parse().expect("invalid max connections"))
+        .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+    // of the in-memory storage (the one on disk is a single shard)
+    let shards = std::env::var("SHARDS")
+        .map(|s| s.parse().expect("invalid shards"))
+        .unwrap_or(DEFAULT_SHARDS);
+    assert!(shards > 0, "invalid shards: zero");
+    let reject = match std::env::var("OVERLOAD").as_deref() {
+        Ok("reject") => true,
+        Ok("queue") | Err(_) => false,
+        Ok(other) => panic!("invalid OVERLOAD: {other}"),
+    };
+
+    let metrics_port =
+        std::env::var("METRICS_PORT").ok().map(|port| {
+            port.parse::<u16>().expect("invalid METRICS_PORT")
+        });
+
+    // servers keeping each share, same as the clients' REPLICAS
+    let replicas: usize = std::env::var("REPLICAS")
+        .map(|s| s.parse().expect("invalid REPLICAS"))
+        .unwrap_or(1);
+    assert!(replicas > 0, "invalid REPLICAS: zero");
+
+    // milliseconds between rounds of gossip (see `gossip`)
+    let gossip_interval = std::env::var("GOSSIP_INTERVAL")
+        .map(|ms| {
+            let ms =
+                ms.parse().expect("invalid GOSSIP_INTERVAL");
+            assert!(ms > 0, "invalid GOSSIP_INTERVAL: zero");
+            Duration::from_millis(ms)
+        })
+        .unwrap_or(DEFAULT_GOSSIP_INTERVAL);
+
+    // seconds between refreshes of all the keys, none if not set
+    let refresh_interval =
+        std::env::var("REFRESH_INTERVAL").ok().map(|secs| {
+            let secs =
+                secs.parse().expect("invalid REFRESH_INTERVAL");
+            Duration::from_secs(secs)
+        });
+
+    // seconds between rounds of anti-entropy, none if not set
+    let anti_entropy_interval = std::env::var(
+        "ANTI_ENTROPY_INTERVAL",
+    )
+    .ok()
+    .map(|secs| {
+        let secs =
+            secs.parse().expect("invalid ANTI_ENTROPY_INTERVAL");
+        Duration::from_secs(secs)
+    });
+
+    // seconds between blocks of the operations taken (set, patch,
+    // delete, see `mempool`), none if not set; DIFFICULTY leading
+    // zero bits of their hashes (proof of work), 0 by default
+    let block_interval =
+        std::env::var("BLOCK_INTERVAL").ok().map(|secs| {
+            let secs =
+                secs.parse().expect("invalid BLOCK_INTERVAL");
+            Duration::from_secs(secs)
+        });
+    let difficulty = std::env::var("DIFFICULTY")
+        .map_or(0, |d| d.parse().expect("invalid DIFFICULTY"));
+    assert!(difficulty <= 256, "DIFFICULTY is over 256 bits");
+    // of the genesis block, the same on all the servers
+    let seed = std::env::var("GENESIS_SEED")
+        .unwrap_or_else(|_| DEFAULT_GENESIS_SEED.to_string());
+
+    // fingerprint (hex) of the public key allowed TAG_STATUS and
+    // TAG_SNAPSHOT
+    let admin = std::env::var("ADMIN_KEY").ok().map(|key| {
+        u32::from_str_radix(&key, 16)
+            .expect("invalid ADMIN_KEY hex")
+    });
+
+    // `once`: a secret is deleted by each server as soon as it has
+    // read its share
+    let reads = match std::env::var("READS").as_deref() {
+        Ok("latest") | Err(_) => Reads::Latest,
+        Ok("once") => Reads::Once,
+        Ok(other) => panic!("invalid READS: {other}"),
+    };
+
+    // key exchanges offered in the handshake (comma-separated:
+    // `x25519`, `dhke`), the first of `Group::ALL` both sides offer
+    // is run; all of them by default
+    let exchange = match std::env::var("KEY_EXCHANGE") {
+        Ok(groups) => groups
+            .split(',')
+            .map(|group| group.trim().parse())
+            .collect::<Result<Vec<Group>>>()
+            .expect("invalid KEY_EXCHANGE"),
+        Err(_) => Group::ALL.to_vec(),
+    };
+
+    // public keys (hex) of the other servers, comma-separated: the
+    // handshakes with them are authenticated, see `dhke::Auth`
+    let peer_keys = std::env::var("PEER_KEYS")
+        .map(|keys| {
+            keys.split(',')
+                .map(|key| PublicKey::from_hex(key.trim()))
+                .collect::<Result<Vec<_>>>()
+                .expect("invalid PEER_KEYS")
+        })
+        .unwrap_or_default();
+
+    // REKEY_FRAMES and/or REKEY_INTERVAL (seconds): new session keys
+    // after so many frames sent or so much time, see `tcp::Rekey`
+    let frames = std::env::var("REKEY_FRAMES")
+        .ok()
+        .map(|s| s.parse().expect("invalid REKEY_FRAMES"));
+    let every = std::env::var("REKEY_INTERVAL").ok().map(|s| {
+        Duration::from_secs(
+            s.parse().expect("invalid REKEY_INTERVAL"),
+        )
+    });
+    let rekey =
+        (frames.is_some() || every.is_some()).then(|| Rekey {
+            frames: frames.unwrap_or(u64::MAX),
+            every: every.unwrap_or(Duration::MAX),
+        });
+    // PSK (hex): a key given to the clients and the servers out of
+    // band, the handshake is no key exchange then (see `dhke::psk`)
+    let psk = std::env::var("PSK")
+        .ok()
+        .map(|hex| from_hex(hex.trim()).expect("invalid PSK"));
+    // accesses to the keys are appended to the file, if set
+    let audit = match std::env::var("AUDIT_LOG") {
+        Ok(path) => Audit::open(path.as_ref(), audit::RECENT)
+            .expect("failed to open AUDIT_LOG"),
+        Err(_) => Audit::default(),
+    };
+    // the changes of the storage are chained in the file, if set
+    // (checked first), see `ledger::Ledger`
+    let ledger = Arc::new(match std::env::var("LEDGER_LOG") {
+        Ok(path) => Ledger::open(path.as_ref())
+            .expect("failed to open LEDGER_LOG"),
+        Err(_) => Ledger::new(),
+    });
+
+    #[cfg(feature = "tls")]
+    let tls = tls_config();
+    #[cfg(feature = "noise")]
+    let noise = noise_config();
+    #[cfg(feature = "quic")]
+    let quic = std::env::var("QUIC").is_ok();
+    #[cfg(feature = "ws")]
+    let ws = std::env::var("WS_PORT").ok().map(|port| {
+        let port: u16 = port.parse().expect("invalid WS_PORT");
+        SocketAddr::from(([127, 0, 0, 1], port))
+    });
+
+    init_tracing();
+    // the ones that joined and left since the start, if kept
+    let peers = match &data_dir {
+        Some(dir) => Peers::open(dir.join("peers"), peers)
+            .expect("failed to read the peers"),
+        None => Peers::new(peers),
+    };
+    let public_key = SecretKey::new(key).public_key().to_hex();
+    info!(key = %format_args!("{key:0x}"), %public_key, port, peers = ?peers.list(), sync, window, json, "starting");
+    let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+    let members = Members::new(
+        addr,
+        gossip_interval * SUSPECT_ROUNDS,
+        gossip_interval * DEAD_ROUNDS,
+    );
+    for peer in peers.list() {
+        members.add(peer);
+    }
+    let cfg = Config {
+        key,
+        peers: Arc::new(peers),
+        members: Arc::new(members),
+        sync,
+        replicated: replicas > 1,
+        window,
+        nonces: Arc::new(Mutex::new(Nonces::new(window))),
+        idle,
+        timeout,
+        connect_timeout,
+        json,
+        limiter,
+        max_conns,
+        reject,
+        drain: Arc::default(),
+        metrics: Arc::default(),
+        audit: Arc::new(audit),
+        ledger: ledger.clone(),
+        rng: Arc::new(Os),
+        mempool: block_interval.map(|_| Arc::default()),
+        chain: Arc::new(Mutex::new(
+            Chain::genesis(seed.as_bytes(), difficulty)
+                .with_check(mempool::check),
+        )),
+        reads,
+        admin,
+        namespaces: namespaces(),
+        started: Instant::now(),
+        pending: Arc::default(),
+        exchange,
+        peer_keys,
+        rekey,
+        psk,
+        #[cfg(feature = "tls")]
+        tls,
+        #[cfg(feature = "noise")]
+        noise,
+        #[cfg(feature = "ws")]
+        ws,
+        #[cfg(feature = "quic")]
+        quic,
+        #[cfg(test)]
+        network: None,
+    };
+    if let Some(port) = metrics_port {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .expect("failed to bind METRICS_PORT");
+        let m = cfg.metrics.clone();
+        thread::spawn(move || {
+            metrics::serve(listener, move || m.render())
+        });
+    }
+    match data_dir {
+        Some(dir) => {
+            info!(?dir, "loading");
+            #[cfg(feature = "encrypt")]
+            let db = match std::env::var("STORAGE_PASSPHRASE") {
+                Ok(passphrase) => FileDB::open_sealed(
+                    &dir,
+                    passphrase.as_bytes(),
+                ),
+                Err(_) => FileDB::open(&dir),
+            };
+            #[cfg(not(feature = "encrypt"))]
+            let db = FileDB::open(&dir);
+            let mut db = db.expect("failed to open --data-dir");
+            info!(keys = db.keys().len(), "loaded");
+            // a single shard: one log and one checkpoint
+            let db = Shards::from(Ledgered::new(db, ledger));
+            restore(&db, snapshot);
+            run(
+                db,
+                addr,
+                cfg,
+                refresh_interval,
+                anti_entropy_interval,
+                block_interval,
+                gossip_interval,
+            );
+        }
+        None => {
+            let db = Shards::new(
+                (0..shards)
+                    .map(|_| {
+                        Ledgered::new(DB::new(), ledger.clone())
+                    })
+                    .collect(),
+            );
+            restore(&db, snapshot);
+            run(
+                db,
+                addr,
+                cfg,
+                refresh_interval,
+                anti_entropy_interval,
+                block_interval,
+                gossip_interval,
+            )
+        }
+    }
+}
+
+fn restore<S: Storage<u64, u32, u32>>(
+    db: &Shards<S>,
+    snapshot: Option<Vec<u8>>,
+) {
+    let Some(snapshot) = snapshot else {
+        return;
+    };
+    if !db.keys().is_empty() {
+        panic!("--restore: the storage is not empty");
+    }
+    db.restore(&snapshot).expect("failed to restore");
+    db.flush().expect("failed to flush storage");
+    info!(keys = db.keys().len(), "restored");
+}
+
+fn run<S: Storage<u64, u32, u32> + 'static>(
+    db: Shards<S>,
+    addr: SocketAddr,
+    cfg: Config,
+    refresh_interval: Option<Duration>,
+    anti_entropy_interval: Option<Duration>,
+    block_interval: Option<Duration>,
+    gossip_interval: Duration,
+) {
+    let db = Arc::new(db);
+    let scheduler = refresh_interval.map(|interval| {
+        let db = db.clone();
+        let cfg = cfg.clone();
+        thread::spawn(move || schedule(db, &cfg, interval))
+    });
+    let reconciler = anti_entropy_interval.map(|interval| {
+        let db = db.clone();
+        let cfg = cfg.clone();
+        thread::spawn(move || reconcile(db, &cfg, interval))
+    });
+    let packager = block_interval.map(|interval| {
+        let cfg = cfg.clone();
+        thread::spawn(move || package(&cfg, interval))
+    });
+    let janitor = {
+        let db = db.clone();
+        let cfg = cfg.clone();
+        thread::spawn(move || janitor(db, &cfg))
+    };
+    let gossip = {
+        let cfg = cfg.clone();
+        thread::spawn(move || gossip(&cfg, gossip_interval))
+    };
+    let jh = server(addr, db.clone(), cfg.clone());
+    // SIGINT/SIGTERM
+    ctrlc::set_handler(move || {
+        info!("shutting down");
+        shutdown(&cfg, addr);
+    })
+    .expect("failed to set signal handler");
+    let _ = jh.join().expect("server process failed");
+    if let Some(scheduler) = scheduler {
+        let _ = scheduler.join();
+    }
+    if let Some(reconciler) = reconciler {
+        let _ = reconciler.join();
+    }
+    if let Some(packager) = packager {
+        let _ = packager.join();
+    }
+    let _ = janitor.join();
+    let _ = gossip.join();
+
+    db.flush().expect("failed to flush storage");
+    info!("shut down");
+}
+
+// RUST_LOG sets the level (and per-module filters), e.g. `debug`
+// or `info,doing_some_blockchain=trace`; LOG_FORMAT=json for one
+// JSON object per line. Logs go to stderr.
+fn init_tracing() {
+    use tracing_subscriber::EnvFilter;
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if std::env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+// TLS_CERT and TLS_KEY (PEM files) to accept TLS connections,
+// TLS_CA (PEM file) to verify the peer
+#[cfg(feature = "tls")]
+fn tls_config() -> Option<TlsConfig> {
+    use doing_some_blockchain::tls::{
+        client_config, server_config,
+    };
+    std::env::var("TLS_CERT").ok()?;
+    let read = |var: &str| {
+        let path = std::env::var(var)
+            .unwrap_or_else(|_| panic!("{var} is not set"));
+        std::fs::read(&path)
+            .unwrap_or_else(|e| panic!("{path}: {e}"))
+    };
+    let server =
+        server_config(&read("TLS_CERT"), &read("TLS_KEY"))
+            .expect("invalid TLS_CERT/TLS_KEY");
+    let client =
+        client_config(&read("TLS_CA")).expect("invalid TLS_CA");
+    Some((server, client))
+}
+
+// NAMESPACES is `<ns>=<fingerprint>,...;...`: the keys (fingerprints,
+// hex) allowed in each namespace (u32) other than zero, the default
+// one, which is open to any key
+fn namespaces() -> HashMap<u32, Vec<u32>> {
+    let Ok(var) = std::env::var("NAMESPACES") else {
+        return HashMap::new();
+    };
+    var.split(';')
+        .map(|entry| {
+            let (ns, keys) = entry
+                .split_once('=')
+                .expect("invalid NAMESPACES");
+            let ns =
+                ns.trim().parse().expect("invalid namespace");
+            let keys = keys
+                .split(',')
+                .map(|key| {
+                    u32::from_str_radix(key.trim(), 16)
+                        .expect("invalid NAMESPACES hex")
+                })
+                .collect();
+            (ns, keys)
+        })
+        .collect()
+}
+
+// NOISE_KEY (hex) is own static private key, NOISE_PEER (hex,
+// comma-separated) are the static public keys of the peers
+#[cfg(feature = "noise")]
+fn noise_config() -> Option<NoiseConfig> {
+    use doing_some_blockchain::{
+        noise::public_key, util::to_hex,
+    };
+    let key = std::env::var("NOISE_KEY").ok()?;
+    let key = from_hex(&key).expect("invalid NOISE_KEY hex");
+    let public = public_key(&key).expect("invalid NOISE_KEY");
+    info!(public = to_hex(&public), "noise");
+    let peer = std::env::var("NOISE_PEER")
+        .expect("NOISE_PEER is not set");
+    let peers = peer
+        .split(',')
+        .map(|peer| {
+            from_hex(peer.trim())
+                .expect("invalid NOISE_PEER hex")
+        })
+        .collect();
+    Some((key, peers))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+
+    use doing_some_blockchain::{
+        client::{
+            Client, Config as ClientConfig, Quorum, Scheme,
+            Secret,
+        },
+        ec::SecretKey,
+        frost,
+        gossip::{self, State},
+        ledger::Head,
+        nonce::next_idx,
+        testkit::network,
+        util::pack,
+        util::{random, Seeded},
+        vss, xor,
+    };
+
+    use super::*;
+
+    // a few shards, for the keys to be spread over
+    fn sharded() -> Shards<DB<u64, u32>> {
+        Shards::new((0..4).map(|_| DB::new()).collect())
+    }
+
+    fn client(addr: SocketAddr, frame: &Frame) -> Result<Frame> {
+        let frame = frame.clone();
+        let socket = TcpStream::connect(addr)?;
+        let mut tx = Tcp::from(socket);
+        let keys =
+            dhke::handshake(&tx, DEFAULT_TIMEOUT, &Group::ALL)?
+                .keys();
+        tx.set_keys(keys.seal, keys.open);
+        tx.send(&frame)?;
+        let frame: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        Ok(frame)
+    }
+
+    // `frame` signed by `key`, with the checksum over the signature
+    fn signed(mut frame: Frame, key: &SecretKey) -> Frame {
+        frame.sign(key);
+        frame.sum = frame.checksum();
+        frame
+    }
+
+    fn server(addr: SocketAddr) -> JoinHandle<Result<()>> {
+        let h = thread::spawn(move || {
+            let listener = TcpListener::bind(addr)?;
+            if let Ok((socket, _remote)) = listener.accept() {
+                let mut tx = Tcp::from(socket);
+                let keys = dhke::handshake(
+                    &tx,
+                    DEFAULT_TIMEOUT,
+                    &Group::ALL,
+                )?
+                .keys();
+                tx.set_session_key(keys);
+
+                let frame: Frame =
+                    tx.recv_timeout(DEFAULT_TIMEOUT)?;
+                tx.send(&frame)?;
+            }
+            Ok(())
+        });
+        thread::sleep(Duration::from_millis(100));
+        h
+    }
+
+    #[cfg(any(
+        feature = "tls",
+        feature = "noise",
+        feature = "ws"
+    ))]
+    fn exchange<T: Transport<Keys>>(
+        tx: &mut T,
+        frame: &Frame,
+        timeout: Duration,
+    ) -> Result<Frame> {
+        send(tx, frame)?;
+        recv(tx, timeout)
+    }
+
+    fn config(peer: SocketAddr) -> Config {
+        Config {
+            key: 0xAAAAAAAA,
+            peers: Arc::new(Peers::new(vec![peer])),
+            members: Arc::new(Members::new(
+                ([127, 0, 0, 1], 0).into(),
+                DEFAULT_GOSSIP_INTERVAL * SUSPECT_ROUNDS,
+                DEFAULT_GOSSIP_INTERVAL * DEAD_ROUNDS,
+            )),
+            sync: false,
+            replicated: false,
+            window: DEFAULT_WINDOW,
+            nonces: Arc::new(Mutex::new(Nonces::new(
+                DEFAULT_WINDOW,
+            ))),
+            idle: DEFAULT_IDLE_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_TIMEOUT,
+            json: false,
+            limiter: None,
+            drain: Arc::default(),
+            metrics: Arc::default(),
+            audit: Arc::default(),
+            ledger: Arc::default(),
+            rng: Arc::new(Os),
+            mempool: None,
+            chain: Arc::default(),
+            reads: Reads::Latest,
+            admin: None,
+            namespaces: HashMap::new(),
+            started: Instant::now(),
+            pending: Arc::default(),
+            exchange: Group::ALL.to_vec(),
+            // the other servers', all of this key unless set
+            peer_keys: vec![
+                SecretKey::new(0xAAAAAAAA).public_key()
+            ],
+            rekey: None,
+            psk: None,
+            max_conns: DEFAULT_MAX_CONNECTIONS,
+            reject: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "noise")]
+            noise: None,
+            #[cfg(feature = "ws")]
+            ws: None,
+            #[cfg(feature = "quic")]
+            quic: false,
+            network: None,
+        }
+    }
+
+    // The server end of a connection over the in-memory network: a
+    // read waits for a frame, as one from a socket does, none once
+    // the other end is gone
+    struct Conn(Probe);
+
+    impl Sender<u32> for Conn {
+        fn send(&self, msg: &u32) -> Result<()> {
+            self.0.send(msg)
+        }
+    }
+
+    impl Receiver<u32> for Conn {
+        fn recv(&self) -> Result<Option<u32>> {
+            self.0.recv()
+        }
+
+        fn recv_timeout(&self, within: Duration) -> Result<u32> {
+            self.0.recv_timeout(within)
+        }
+    }
+
+    impl Sender<Frame> for Conn {
+        fn send(&self, msg: &Frame) -> Result<()> {
+            self.0.send(msg)
+        }
+    }
+
+    impl Receiver<Frame> for Conn {
+        fn recv(&self) -> Result<Option<Frame>> {
+            match self.0.recv_timeout(DEFAULT_IDLE_TIMEOUT) {
+                Ok(frame) => Ok(Some(frame)),
+                Err(Error::IO(e))
+                    if e.kind()
+                        == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            }
+        }
+
+        fn recv_timeout(
+            &self,
+            within: Duration,
+        ) -> Result<Frame> {
+            self.0.recv_timeout(within)
+        }
+    }
+
+    impl Transport<Keys> for Conn {
+        fn set_session_key(&mut self, keys: Keys) {
+            self.0.set_session_key(keys);
+        }
+    }
+
+    // `server`, over the in-memory network: a connection to `addr`
+    // (the name listened on) is handled as one over TCP would be
+    fn serve(
+        net: &Network,
+        addr: SocketAddr,
+        db: Arc<Shards<DB<u64, u32>>>,
+        cfg: Config,
+    ) {
+        let listener = net.listen(&addr.to_string());
+        thread::spawn(move || {
+            while !cfg.drain.is_stopping() {
+                let within = Duration::from_millis(10);
+                let Ok(probe) = listener.accept(within) else {
+                    continue;
+                };
+                let (db, cfg) = (db.clone(), cfg.clone());
+                thread::spawn(move || {
+                    let mut tx = Conn(probe);
+                    let _ = handle(&mut tx, db, &cfg, addr.ip());
+                });
+            }
+        });
+    }
+
+    // Servers running in this process, over an in-memory network
+    // (`testkit::Network`, no sockets: the links between them can be
+    // slowed down, made lossy or cut), each the others' peer, with
+    // its own in-memory storage; they are shut down when dropped.
+    // The refreshes are run by the test (see `refresh`), not
+    // triggered by the reads: the shares are only changed when the
+    // test says so.
+    struct Cluster {
+        net: Network,
+        addrs: Vec<SocketAddr>, // names on `net`
+        dbs: Vec<Arc<Shards<DB<u64, u32>>>>,
+        cfgs: Vec<Config>,
+    }
+
+    impl Cluster {
+        fn start(n: usize) -> Self {
+            let net = network();
+            let addrs = (0..n)
+                .map(|i| {
+                    SocketAddr::from((
+                        [10, 0, 0, 1 + i as u8],
+                        1,
+                    ))
+                })
+                .collect::<Vec<_>>();
+            let dbs = (0..n)
+                .map(|_| Arc::new(sharded()))
+                .collect::<Vec<_>>();
+            let cfgs = addrs
+                .iter()
+                .zip(&dbs)
+                .enumerate()
+                .map(|(i, (addr, db))| {
+                    let mut cfg = config(*addr);
+                    cfg.key = 0xAAAAAAAA + i as u32;
+                    cfg.peer_keys = (0..n)
+                        .map(|j| {
+                            SecretKey::new(0xAAAAAAAA + j as u32)
+                                .public_key()
+                        })
+                        .collect();
+                    cfg.peers = Arc::new(Peers::new(
+                        addrs
+                            .iter()
+                            .filter(|peer| *peer != addr)
+                            .cloned()
+                            .collect(),
+                    ));
+                    cfg.members = Arc::new(Members::new(
+                        *addr,
+                        DEFAULT_GOSSIP_INTERVAL * SUSPECT_ROUNDS,
+                        DEFAULT_GOSSIP_INTERVAL * DEAD_ROUNDS,
+                    ));
+                    cfg.network = Some(net.clone());
+                    serve(&net, *addr, db.clone(), cfg.clone());
+                    cfg
+                })
+                .collect();
+            Self {
+                net,
+                addrs,
+                dbs,
+                cfgs,
+            }
+        }
+
+        // of all the servers
+        fn client(&self, key: u32) -> Client {
+            Client::new(
+                SecretKey::new(key),
+                self.addrs.clone(),
+                ClientConfig {
+                    network: Some(self.net.clone()),
+                    ..ClientConfig::default()
+                },
+            )
+        }
+
+        // the latest share of each server
+        fn shares(&self, owner: u64) -> Vec<Option<u32>> {
+            self.dbs
+                .iter()
+                .map(|db| db.lock(owner).get(owner))
+                .collect()
+        }
+
+        // started by the `i`th server
+        fn refresh(&self, i: usize, owner: u64) -> Result<()> {
+            refresh(self.dbs[i].clone(), &self.cfgs[i], owner)
+        }
+    }
+
+    impl Drop for Cluster {
+        fn drop(&mut self) {
+            for cfg in &self.cfgs {
+                cfg.drain.stop();
+            }
+        }
+    }
+
+    #[test]
+    fn test_cluster() -> Result<()> {
+        let cluster = Cluster::start(3);
+        let client = cluster.client(42);
+        let owner = merge(
+            0,
+            SecretKey::new(42).public_key().fingerprint(),
+        );
+        client.set_secret(0xCAFEBABE, Scheme::Xor, 0)?;
+        let shares = cluster.shares(owner);
+        assert!(shares.iter().all(Option::is_some));
+        let merged =
+            shares.iter().flatten().fold(0, |a, s| a ^ s);
+        assert_eq!(merged, 0xCAFEBABE);
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+        // reads change nothing
+        assert_eq!(cluster.shares(owner), shares);
+
+        for i in 0..3 {
+            cluster.refresh(i, owner)?;
+        }
+        let refreshed = cluster.shares(owner);
+        assert!(refreshed
+            .iter()
+            .zip(&shares)
+            .all(|(a, b)| a != b));
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+
+        // the third server cut off: left out of the round, the
+        // others' shares still make the secret with its own
+        let names = cluster
+            .addrs
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        cluster.net.partition(&[&names[0]], &[&names[2]]);
+        assert!(cluster.refresh(0, owner).is_err());
+        let partial = cluster.shares(owner);
+        assert_ne!(partial[0], refreshed[0]);
+        assert_ne!(partial[1], refreshed[1]);
+        assert_eq!(partial[2], refreshed[2]);
+        let merged =
+            partial.iter().flatten().fold(0, |a, s| a ^ s);
+        assert_eq!(merged, 0xCAFEBABE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_echo() -> Result<()> {
+        let port: u16 = 32456;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let server = server(addr);
+
+        let frame: Frame = Frame {
+            idx: 0x01020304,
+            tag: 0x05060708,
+            msg: 0x090A0B0C,
+            key: 0xCAFEBABE,
+            sig: 0x0102030405060708,
+            ext: 0x090A0B0C,
+            ns: 0,
+            sum: 0x0D0E0F00,
+            data: b"arbitrary payload".to_vec(),
+        };
+        let rcvd = client(addr, &frame)?;
+        server.join()??;
+
+        assert_eq!(rcvd, frame);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_checksum() -> Result<()> {
+        let port: u16 = 32457;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let mut frame: Frame = Frame {
+            idx: time(),
+            tag: TAG_PUBLIC_KEY,
+            msg: 0,
+            key: 0xCAFEBABE,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum() ^ 1;
+        let rcvd = client(addr, &frame)?;
+
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_CHECKSUM);
+        assert_eq!(rcvd.sum, rcvd.checksum());
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_set_get() -> Result<()> {
+        let port: u16 = 32458;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        // the owner's key, whoever signs
+        fn owned(tag: u32, msg: u32, secret: u32) -> Frame {
+            let secret_key = SecretKey::new(secret);
+            let public_key = u64::from(&secret_key.public_key());
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg,
+                    key: SecretKey::new(1)
+                        .public_key()
+                        .fingerprint(),
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data: public_key.to_be_bytes().to_vec(),
+                },
+                &secret_key,
+            )
+        }
+
+        let rcvd =
+            client(addr, &owned(TAG_SECRET_SHARE, 42, 1))?;
+        assert_eq!(rcvd.tag, TAG_OK);
+
+        let rcvd = client(addr, &owned(TAG_SECRET_SHARE, 0, 2))?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+
+        let rcvd = client(addr, &owned(TAG_PUBLIC_KEY, 0, 2))?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+
+        let rcvd = client(addr, &owned(TAG_PUBLIC_KEY, 0, 1))?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.msg, 42);
+
+        let rcvd = client(addr, &owned(TAG_DELETE, 0, 2))?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+
+        let rcvd = client(addr, &owned(TAG_DELETE, 0, 1))?;
+        assert_eq!(rcvd.tag, TAG_OK);
+
+        // `msg` is not read: not a replay of the read before
+        let rcvd = client(addr, &owned(TAG_PUBLIC_KEY, 1, 1))?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_NOT_FOUND);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired() -> Result<()> {
+        let port: u16 = 32459;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let mut frame: Frame = Frame {
+            idx: time() - DEFAULT_WINDOW - 1,
+            tag: TAG_PUBLIC_KEY,
+            msg: 0,
+            key: 0xCAFEBABE,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum();
+        let rcvd = client(addr, &frame)?;
+
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_EXPIRED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replayed() -> Result<()> {
+        let port: u16 = 32528;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let secret_key = SecretKey::new(1);
+        let public_key = secret_key.public_key();
+        let frame = signed(
+            Frame {
+                idx: time(),
+                tag: TAG_SECRET_SHARE,
+                msg: 42,
+                key: public_key.fingerprint(),
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: u64::from(&public_key)
+                    .to_be_bytes()
+                    .to_vec(),
+            },
+            &secret_key,
+        );
+
+        // a connection each
+        let rcvd = client(addr, &frame)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+
+        let rcvd = client(addr, &frame)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_EXPIRED);
+
+        // the other signature of the same, `s` for `N - s`
+        let (r, s) = split(frame.sig);
+        let mut copy = frame.clone();
+        copy.sig = merge(r, (curve::N - s as curve::Int) as u32);
+        copy.sum = copy.checksum();
+        assert!(copy.verify(&public_key));
+        let rcvd = client(addr, &copy)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_EXPIRED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list() -> Result<()> {
+        let port: u16 = 32460;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        for key in 0..(LIST_PAGE_SIZE as u32 + 10) {
+            let id = u64::from(key);
+            db.lock(id).set(id, key);
+        }
+        let _server = super::server(addr, db, config(addr));
+
+        let mut frame: Frame = Frame {
+            idx: time(),
+            tag: TAG_LIST,
+            msg: LIST_PAGE_SIZE as u32,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum();
+        let rcvd = client(addr, &frame)?;
+
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.ext, LIST_PAGE_SIZE as u32 + 10);
+        let keys = pack(&rcvd.data);
+        assert_eq!(
+            keys,
+            (LIST_PAGE_SIZE as u32..)
+                .take(10)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_burst() -> Result<()> {
+        let port: u16 = 32529;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let total = LIST_PAGE_SIZE as u32 * 8;
+        for key in 0..total {
+            let id = u64::from(key);
+            db.lock(id).set(id, key);
+        }
+        let mut cfg = config(addr);
+        cfg.window = 2;
+        cfg.nonces = Arc::new(Mutex::new(Nonces::new(2)));
+        let _server = super::server(addr, db, cfg);
+
+        // the pages but the first in a batch: more frames within
+        // a second than the window is long
+        let client = Client::new(
+            SecretKey::new(42),
+            vec![addr],
+            ClientConfig::default(),
+        );
+        let keys = client.list_keys(&addr)?;
+        assert_eq!(keys, (0..total).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ping() -> Result<()> {
+        let port: u16 = 32461;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let mut frame: Frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 0xCAFEBABE,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum();
+        let rcvd = client(addr, &frame)?;
+
+        assert_eq!(rcvd.tag, TAG_PONG);
+        assert_eq!(rcvd.msg, 0xCAFEBABE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch() -> Result<()> {
+        let port: u16 = 32462;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let frame = |tag: u32, msg: u32| {
+            let mut frame = Frame {
+                idx: time(),
+                tag,
+                msg,
+                key: 0,
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            };
+            frame.sum = frame.checksum();
+            frame
+        };
+
+        let mut tx = Tcp::from(TcpStream::connect(addr)?);
+        let keys =
+            dhke::handshake(&tx, DEFAULT_TIMEOUT, &Group::ALL)?
+                .keys();
+        tx.set_keys(keys.seal, keys.open);
+        tx.send(&frame(TAG_BATCH, 3))?;
+        tx.send(&frame(TAG_PING, 1))?;
+        tx.send(&frame(TAG_LIST, 0))?;
+        tx.send(&frame(TAG_PING, 2))?;
+
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!((rcvd.tag, rcvd.msg), (TAG_PONG, 1));
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!((rcvd.tag, rcvd.msg), (TAG_PONG, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_session() -> Result<()> {
+        let port: u16 = 32463;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let frame = |tag: u32, msg: u32| {
+            let mut frame = Frame {
+                idx: time(),
+                tag,
+                msg,
+                key: 0,
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            };
+            frame.sum = frame.checksum();
+            frame
+        };
+
+        let mut tx = Tcp::from(TcpStream::connect(addr)?);
+        let keys =
+            dhke::handshake(&tx, DEFAULT_TIMEOUT, &Group::ALL)?
+                .keys();
+        tx.set_keys(keys.seal, keys.open);
+        for msg in 1..=3 {
+            tx.send(&frame(TAG_PING, msg))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!((rcvd.tag, rcvd.msg), (TAG_PONG, msg));
+        }
+
+        tx.send(&frame(TAG_CLOSE, 0))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let eof: Option<Frame> = tx.recv()?;
+        assert_eq!(eof, None);
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls() -> Result<()> {
+        use doing_some_blockchain::tls::{
+            client_config, server_config,
+        };
+
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![
+                "127.0.0.1".to_string(),
+            ])
+            .unwrap();
+        let cert = cert.pem().into_bytes();
+        let key = key_pair.serialize_pem().into_bytes();
+        let client = client_config(&cert)?;
+
+        let port: u16 = 32464;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.tls =
+            Some((server_config(&cert, &key)?, client.clone()));
+        let _server = super::server(addr, db, cfg.clone());
+
+        let mut frame: Frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 0xCAFEBABE,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum();
+        let socket = TcpStream::connect(addr)?;
+        let mut tx = Tls::client(socket, client, addr.ip())?;
+        let rcvd = exchange(&mut tx, &frame, DEFAULT_TIMEOUT)?;
+
+        assert_eq!(rcvd.tag, TAG_PONG);
+        assert_eq!(rcvd.msg, 0xCAFEBABE);
+        Ok(())
+    }
+
+    #[cfg(feature = "noise")]
+    #[test]
+    fn test_noise() -> Result<()> {
+        use doing_some_blockchain::noise::generate_keypair;
+
+        let (server_key, server_pub) = generate_keypair()?;
+        let (client_key, _) = generate_keypair()?;
+
+        let port: u16 = 32465;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.noise = Some((server_key, vec![]));
+        let _server = super::server(addr, db, cfg.clone());
+
+        let mut frame: Frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 0xCAFEBABE,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum();
+        let socket = TcpStream::connect(addr)?;
+        let mut tx = Noise::initiator(
+            socket,
+            &client_key,
+            &[server_pub],
+        )?;
+        let rcvd = exchange(&mut tx, &frame, DEFAULT_TIMEOUT)?;
+
+        assert_eq!(rcvd.tag, TAG_PONG);
+        assert_eq!(rcvd.msg, 0xCAFEBABE);
+        Ok(())
+    }
+
+    #[cfg(feature = "ws")]
+    #[test]
+    fn test_ws() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32466).into();
+        let ws: SocketAddr = ([127, 0, 0, 1], 32467).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.ws = Some(ws);
+        let _server = super::server(addr, db, cfg.clone());
+
+        let mut frame: Frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 0xCAFEBABE,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sum = frame.checksum();
+
+        // both raw TCP and WS are served
+        assert_eq!(client(addr, &frame)?.tag, TAG_PONG);
+        frame.ext = 1; // not a replay of the one before
+        frame.sum = frame.checksum();
+        let socket = TcpStream::connect(ws)?;
+        let mut tx =
+            Ws::connect(socket, &format!("ws://{ws}/"))?;
+        handshake(&mut tx, &cfg)?;
+        let rcvd = exchange(&mut tx, &frame, DEFAULT_TIMEOUT)?;
+
+        assert_eq!(rcvd.tag, TAG_PONG);
+        assert_eq!(rcvd.msg, 0xCAFEBABE);
+        Ok(())
+    }
+
+    #[cfg(feature = "quic")]
+    #[test]
+    fn test_quic() -> Result<()> {
+        use doing_some_blockchain::tls::{
+            client_config, server_config,
+        };
+
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![
+                "127.0.0.1".to_string(),
+            ])
+            .unwrap();
+        let cert = cert.pem().into_bytes();
+        let key = key_pair.serialize_pem().into_bytes();
+        let client = client_config(&cert)?;
+
+        let port: u16 = 32468;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.tls =
+            Some((server_config(&cert, &key)?, client.clone()));
+        cfg.quic = true;
+        let _server = super::server(addr, db, cfg);
+
+        let frames = (0..4)
+            .map(|i| {
+                let mut frame = Frame {
+                    idx: time() + i,
+                    tag: TAG_PING,
+                    msg: i,
+                    ..Frame::default()
+                };
+                frame.sum = frame.checksum();
+                frame
+            })
+            .collect::<Vec<_>>();
+        let tx = QuicClient::connect(addr, client)?;
+        let rcvd = tx.call_all(&frames)?;
+
+        for (sent, rcvd) in frames.iter().zip(rcvd.iter()) {
+            assert_eq!(rcvd.tag, TAG_PONG);
+            assert_eq!(rcvd.idx, sent.idx);
+            assert_eq!(rcvd.msg, sent.msg);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_pool() -> Result<()> {
+        let port: u16 = 32469;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let cfg = config(addr);
+
+        // the peer accepts a single connection only
+        let listener = TcpListener::bind(addr)?;
+        let peer = cfg.clone();
+        thread::spawn(move || -> Result<()> {
+            let (socket, remote) = listener.accept()?;
+            handle(
+                &mut Tcp::from(socket),
+                db,
+                &peer,
+                remote.ip(),
+            )
+        });
+
+        for i in 0..3 {
+            let mut frame: Frame = Frame {
+                idx: time() + i,
+                tag: TAG_PING,
+                msg: i,
+                key: 0,
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            };
+            frame.sum = frame.checksum();
+            let rcvd = call_peer(addr, &frame, &cfg)?;
+            assert_eq!(rcvd.tag, TAG_PONG);
+            assert_eq!(rcvd.msg, i);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_heartbeat() -> Result<()> {
+        let port: u16 = 32470;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.idle = Duration::from_millis(100);
+
+        // a single connection, dropped when idle for too long
+        let listener = TcpListener::bind(addr)?;
+        let peer = cfg.clone();
+        thread::spawn(move || -> Result<()> {
+            let (socket, remote) = listener.accept()?;
+            let mut tx = Tcp::from(socket);
+            tx.set_idle_timeout(Some(peer.idle))?;
+            handle(&mut tx, db, &peer, remote.ip())
+        });
+
+        for i in 0..2 {
+            let mut frame: Frame = Frame {
+                idx: time() + i,
+                tag: TAG_PING,
+                msg: i,
+                key: 0,
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            };
+            frame.sum = frame.checksum();
+            let rcvd = call_peer(addr, &frame, &cfg)?;
+            assert_eq!(rcvd.msg, i);
+            thread::sleep(cfg.idle * 3);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_limiter() {
+        let limiter = Limiter::new(10.0, 2.0);
+        let one: IpAddr = [127, 0, 0, 1].into();
+        let two: IpAddr = [127, 0, 0, 2].into();
+        let now = Instant::now();
+
+        assert!(limiter.allow(one, now));
+        assert!(limiter.allow(one, now));
+        assert!(!limiter.allow(one, now));
+        assert!(limiter.allow(two, now)); // own bucket
+
+        // a token per 100ms, never more than the burst
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.allow(one, later));
+        assert!(!limiter.allow(one, later));
+        let much_later = later + Duration::from_secs(60);
+        assert!(limiter.allow(one, much_later));
+        assert!(limiter.allow(one, much_later));
+        assert!(!limiter.allow(one, much_later));
+    }
+
+    #[test]
+    fn test_rate_limited() -> Result<()> {
+        let port: u16 = 32471;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        // 3 tokens: the connection and two requests
+        cfg.limiter = Some(Arc::new(Limiter::new(0.001, 3.0)));
+        let _server = super::server(addr, db, cfg);
+
+        let frame = |msg: u32| {
+            let mut frame = Frame {
+                idx: time(),
+                tag: TAG_PING,
+                msg,
+                key: 0,
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            };
+            frame.sum = frame.checksum();
+            frame
+        };
+
+        let mut tx = Tcp::from(TcpStream::connect(addr)?);
+        let keys =
+            dhke::handshake(&tx, DEFAULT_TIMEOUT, &Group::ALL)?
+                .keys();
+        tx.set_keys(keys.seal, keys.open);
+        for msg in 1..=3 {
+            tx.send(&frame(msg))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            if msg < 3 {
+                assert_eq!(rcvd.tag, TAG_PONG);
+            } else {
+                assert_eq!(rcvd.tag, TAG_SERVER_ERROR);
+                assert_eq!(rcvd.ext, ERR_RATE_LIMITED);
+            }
+        }
+
+        // new connections are turned away after the first frame
+        let rcvd = client(addr, &frame(4))?;
+        assert_eq!(rcvd.tag, TAG_SERVER_ERROR);
+        assert_eq!(rcvd.ext, ERR_RATE_LIMITED);
+        Ok(())
+    }
+
+    fn connect(addr: SocketAddr) -> Result<Tcp> {
+        let mut tx = Tcp::from(TcpStream::connect(addr)?);
+        let keys =
+            dhke::handshake(&tx, DEFAULT_TIMEOUT, &Group::ALL)?
+                .keys();
+        tx.set_keys(keys.seal, keys.open);
+        Ok(tx)
+    }
+
+    #[test]
+    fn test_reject_over_limit() -> Result<()> {
+        let port: u16 = 32472;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.max_conns = 1;
+        cfg.reject = true;
+        let _server = super::server(addr, db, cfg);
+
+        let first = connect(addr)?;
+        assert!(connect(addr).is_err()); // closed right away
+        drop(first);
+
+        // the slot is freed once the server sees EOF
+        let now = Instant::now();
+        while connect(addr).is_err() {
+            assert!(now.elapsed() < DEFAULT_TIMEOUT);
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_queue_over_limit() -> Result<()> {
+        let port: u16 = 32473;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.max_conns = 1;
+        let _server = super::server(addr, db, cfg);
+
+        let first = connect(addr)?;
+        let delay = Duration::from_millis(100);
+        let h = thread::spawn(move || {
+            thread::sleep(delay);
+            drop(first);
+        });
+
+        // waits in the backlog until the first one is closed
+        let now = Instant::now();
+        let _second = connect(addr)?;
+        assert!(now.elapsed() >= delay);
+        h.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown() -> Result<()> {
+        let port: u16 = 32474;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let cfg = config(addr);
+        let server = super::server(addr, db, cfg.clone());
+
+        let tx = connect(addr)?;
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 42,
+            ..Frame::default()
+        };
+        frame.sum = frame.checksum();
+        tx.send(&frame)?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_PONG);
+
+        // the idle session is closed, then the server returns
+        shutdown(&cfg, addr);
+        let eof: Option<Frame> = tx.recv()?;
+        assert_eq!(eof, None);
+        server.join().unwrap()?;
+        assert!(TcpStream::connect(addr).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics() -> Result<()> {
+        let port: u16 = 32475;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let cfg = config(addr);
+        let metrics = cfg.metrics.clone();
+        let _server = super::server(addr, db, cfg);
+
+        let secret_key = SecretKey::new(1);
+        let signed = |tag: u32, msg: u32| {
+            let public_key = u64::from(&secret_key.public_key());
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg,
+                    key: secret_key.public_key().fingerprint(),
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data: public_key.to_be_bytes().to_vec(),
+                },
+                &secret_key,
+            )
+        };
+        let tx = connect(addr)?;
+        for tag in [TAG_SECRET_SHARE, TAG_PUBLIC_KEY, TAG_DELETE]
+        {
+            tx.send(&signed(tag, 42))?;
+            let _: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        }
+        tx.send(&signed(TAG_PUBLIC_KEY, 0))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.ext, ERR_NOT_FOUND);
+
+        // closed before the handshake is done
+        drop(TcpStream::connect(addr)?);
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        // (and the last response is counted once it is sent)
+        while (metrics.handshake_failures.get() == 0
+            || metrics.latency.count() < 4)
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(metrics.received.get(&TAG_PUBLIC_KEY), 2);
+        assert_eq!(metrics.sent.get(&TAG_OK), 3);
+        assert_eq!(metrics.sent.get(&TAG_BAD_REQUEST), 1);
+        assert_eq!(metrics.hits.get(), 1);
+        assert_eq!(metrics.misses.get(), 1);
+        assert_eq!(metrics.handshake_failures.get(), 1);
+        assert_eq!(metrics.latency.count(), 4);
+
+        let text = metrics.render();
+        assert!(text
+            .contains("frames_received_total{tag=\"2\"} 2\n"));
+        assert!(text.contains("storage_hits_total 1\n"));
+        assert!(
+            text.contains("request_duration_seconds_count 4\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() -> Result<()> {
+        let port: u16 = 32476;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        // nothing listens there
+        let mut cfg = config(([127, 0, 0, 1], 32477).into());
+        let admin = SecretKey::new(7);
+        let public_key = u64::from(&admin.public_key());
+        cfg.admin = Some(crc32(&public_key.to_be_bytes()));
+        let _server = super::server(addr, db, cfg);
+
+        let signed =
+            |tag: u32, msg: u32, secret_key: &SecretKey| {
+                let public_key =
+                    u64::from(&secret_key.public_key());
+                let data = public_key.to_be_bytes().to_vec();
+                signed(
+                    Frame {
+                        idx: time(),
+                        tag,
+                        msg,
+                        key: crc32(&data),
+                        sig: 0,
+                        ext: 0,
+                        ns: 0,
+                        sum: 0,
+                        data,
+                    },
+                    secret_key,
+                )
+            };
+        let tx = connect(addr)?;
+        let user = SecretKey::new(1);
+        tx.send(&signed(TAG_SECRET_SHARE, 42, &user))?;
+        let _: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+
+        tx.send(&signed(TAG_STATUS, 0, &user))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+
+        tx.send(&signed(TAG_STATUS, 0, &admin))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let text = String::from_utf8(rcvd.data).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("uptime="));
+        assert_eq!(
+            &lines[1..],
+            &[
+                "keys=1",
+                "refreshes=0",
+                "refresh_failures=0",
+                "patches=0",
+                "peer=127.0.0.1:32477,down",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit() -> Result<()> {
+        let port: u16 = 32493;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        let admin = SecretKey::new(7);
+        let public_key = u64::from(&admin.public_key());
+        cfg.admin = Some(crc32(&public_key.to_be_bytes()));
+        let _server = super::server(addr, db, cfg);
+
+        let signed =
+            |tag: u32,
+             msg: u32,
+             ext: u32,
+             secret_key: &SecretKey| {
+                let public_key =
+                    u64::from(&secret_key.public_key());
+                let mut data = public_key.to_be_bytes().to_vec();
+                if tag == TAG_SECRET_SHARE {
+                    data.extend(0u32.to_be_bytes()); // no TTL
+                }
+                signed(
+                    Frame {
+                        idx: time(),
+                        tag,
+                        msg,
+                        key: crc32(&data[..8]),
+                        sig: 0,
+                        ext,
+                        ns: 0,
+                        sum: 0,
+                        data,
+                    },
+                    secret_key,
+                )
+            };
+        let (user, other) =
+            (SecretKey::new(1), SecretKey::new(2));
+        let tx = connect(addr)?;
+        for frame in [
+            signed(TAG_SECRET_SHARE, 42, 0, &user),
+            signed(TAG_PUBLIC_KEY, 0, 0, &user),
+            signed(TAG_PUBLIC_KEY, 0, 0, &other),
+            signed(TAG_DELETE, 0, 0, &user),
+            signed(TAG_PING, 0, 0, &user), // not audited
+        ] {
+            tx.send(&frame)?;
+            let _: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        }
+
+        tx.send(&signed(TAG_AUDIT, 10, 0, &user))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+
+        let audit = |n: u32, of: u32| -> Result<Vec<String>> {
+            tx.send(&signed(TAG_AUDIT, n, of, &admin))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_OK);
+            let text = String::from_utf8(rcvd.data).unwrap();
+            Ok(text
+                .lines()
+                .map(|line| {
+                    // no time
+                    line.split_once(' ').unwrap().1.to_string()
+                })
+                .collect())
+        };
+        let key = |secret_key: &SecretKey| {
+            let public_key = u64::from(&secret_key.public_key());
+            crc32(&public_key.to_be_bytes())
+        };
+        let (user, other) = (key(&user), key(&other));
+        let line = |op: &str, key: u32, outcome: &str| {
+            format!(
+                "op={op} ns=0 key={key:0x} \
+                 peer=127.0.0.1 outcome={outcome}"
+            )
+        };
+        let not_found = ERR_NOT_FOUND.to_string();
+        assert_eq!(
+            audit(10, 0)?,
+            vec![
+                line("set", user, "ok"),
+                line("get", user, "ok"),
+                line("get", other, &not_found),
+                line("delete", user, "ok"),
+            ]
+        );
+        assert_eq!(audit(1, 0)?.len(), 1);
+        assert_eq!(audit(10, other)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks() -> Result<()> {
+        let port: u16 = 32516;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.mempool = Some(Arc::default());
+        cfg.chain = Arc::new(Mutex::new(
+            Chain::genesis(b"seed", 4)
+                .with_check(mempool::check),
+        ));
+        let _server = super::server(addr, db, cfg.clone());
+
+        let user = SecretKey::new(1);
+        let signed = |tag: u32, msg: u32| {
+            let public_key = u64::from(&user.public_key());
+            let mut data = public_key.to_be_bytes().to_vec();
+            if tag == TAG_SECRET_SHARE {
+                data.extend(0u32.to_be_bytes()); // no TTL
+            }
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg,
+                    key: crc32(&data[..8]),
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                &user,
+            )
+        };
+        let tx = connect(addr)?;
+        let mut sent = vec![];
+        for frame in [
+            signed(TAG_SECRET_SHARE, 42),
+            signed(TAG_PUBLIC_KEY, 0), // not an operation
+            signed(TAG_SECRET_SHARE, 43),
+            signed(TAG_DELETE, 0),
+            signed(TAG_DELETE, 0), // not found: not taken
+        ] {
+            tx.send(&frame)?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            if rcvd.tag == TAG_OK && frame.tag != TAG_PUBLIC_KEY
+            {
+                sent.push(frame);
+            }
+        }
+        assert_eq!(sent.len(), 3);
+        assert_eq!(cfg.mempool.as_ref().unwrap().len(), 3);
+
+        // a last block at shutdown
+        cfg.drain.stop();
+        package(&cfg, Duration::from_secs(60));
+        let chain = cfg.chain.lock().unwrap();
+        assert_eq!(chain.len(), 2); // genesis first
+        assert_eq!(
+            chain.blocks()[0],
+            Chain::genesis(b"seed", 4).blocks()[0]
+        );
+        // signed by the owner, checked
+        chain.validate()?;
+        let block = &chain.blocks()[1];
+        assert!(block.work() >= 4);
+        assert_eq!(mempool::ops(&block.payload)?, sent);
+        assert_eq!(cfg.metrics.blocks.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync() -> Result<()> {
+        let port: u16 = 32517;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let public_key =
+            |key: u32| SecretKey::new(key).public_key();
+        let (a, b, c) = (0xAAAAAAAA, 0xBBBBBBBB, 0xCCCCCCCC);
+
+        // a is ahead, with a block bigger than a frame
+        let mut cfg_a = config(addr);
+        cfg_a.peer_keys = vec![public_key(b)];
+        let mut chain = Chain::genesis(b"seed", 4);
+        for payload in
+            [vec![1; 10], vec![2; 100_000], vec![3; 10]]
+        {
+            let mut block = chain.next(time(), payload);
+            block.mine(4);
+            chain.append(block)?;
+        }
+        cfg_a.chain = Arc::new(Mutex::new(chain.clone()));
+        let _server =
+            super::server(addr, Arc::new(sharded()), cfg_a);
+
+        // b restarted: the genesis block only
+        let mut cfg_b = config(addr);
+        cfg_b.key = b;
+        cfg_b.peer_keys = vec![public_key(a)];
+        cfg_b.chain =
+            Arc::new(Mutex::new(Chain::genesis(b"seed", 4)));
+        assert_eq!(catch_up(&cfg_b)?, 3);
+        assert_eq!(
+            cfg_b.chain.lock().unwrap().blocks(),
+            chain.blocks()
+        );
+        assert_eq!(cfg_b.metrics.synced.get(), 3);
+        assert_eq!(catch_up(&cfg_b)?, 0);
+
+        // not on another genesis block, nor for a server not a peer
+        let mut cfg_c = config(addr);
+        cfg_c.key = b;
+        cfg_c.peer_keys = vec![public_key(a)];
+        cfg_c.chain =
+            Arc::new(Mutex::new(Chain::genesis(b"other", 4)));
+        assert_eq!(catch_up(&cfg_c)?, 0);
+        assert_eq!(cfg_c.chain.lock().unwrap().len(), 1);
+        assert_eq!(get_blocks(addr, 1, &cfg_c)?.1.len(), 3);
+        cfg_c.key = c;
+        let e = get_blocks(addr, 0, &cfg_c).unwrap_err();
+        assert!(
+            matches!(e, Error::App(e) if e.contains("ext=32009"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork() -> Result<()> {
+        let port: u16 = 32518;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let public_key =
+            |key: u32| SecretKey::new(key).public_key();
+        let (a, b) = (0xAAAAAAAA, 0xBBBBBBBB);
+        let op = |key: u32| Frame {
+            idx: key,
+            tag: TAG_DELETE,
+            msg: 0,
+            key,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        let mine = |chain: &mut Chain, ops: &[Frame]| {
+            let mut block =
+                chain.next(time(), mempool::payload(ops));
+            block.mine(4);
+            chain.append(block).unwrap();
+        };
+
+        // a and b mined a block each on top of the genesis block, a
+        // another one since: b goes with a's branch
+        let genesis = Chain::genesis(b"seed", 4);
+        let mut chain_a = genesis.clone();
+        mine(&mut chain_a, &[op(1), op(2)]);
+        mine(&mut chain_a, &[op(3)]);
+        let mut chain_b = genesis.clone();
+        mine(&mut chain_b, &[op(4), op(2), op(5)]);
+
+        let mut cfg_a = config(addr);
+        cfg_a.peer_keys = vec![public_key(b)];
+        cfg_a.chain = Arc::new(Mutex::new(chain_a.clone()));
+        let _server =
+            super::server(addr, Arc::new(sharded()), cfg_a);
+
+        let mut cfg_b = config(addr);
+        cfg_b.key = b;
+        cfg_b.peer_keys = vec![public_key(a)];
+        cfg_b.chain = Arc::new(Mutex::new(chain_b));
+        let mempool = Arc::new(Mempool::new());
+        mempool.push(op(6));
+        cfg_b.mempool = Some(mempool.clone());
+        assert_eq!(catch_up(&cfg_b)?, 2);
+        assert_eq!(cfg_b.metrics.reorgs.get(), 1);
+        assert_eq!(
+            cfg_b.chain.lock().unwrap().blocks(),
+            chain_a.blocks()
+        );
+
+        // b's operations a's branch does not have are applied again,
+        // on top of it
+        cfg_b.drain.stop();
+        package(&cfg_b, Duration::from_secs(60));
+        let chain = cfg_b.chain.lock().unwrap();
+        assert_eq!(chain.len(), 4);
+        let ops = mempool::ops(&chain.blocks()[3].payload)?;
+        let keys =
+            ops.iter().map(|op| op.key).collect::<Vec<_>>();
+        assert_eq!(keys, vec![4, 5, 6]);
+
+        // a shorter branch, or the same one, is not taken
+        drop(chain);
+        assert_eq!(catch_up(&cfg_b)?, 0);
+        let mut cfg_c = config(addr);
+        cfg_c.key = b;
+        cfg_c.peer_keys = vec![public_key(a)];
+        let mut longer = genesis.clone();
+        for key in 7..10 {
+            mine(&mut longer, &[op(key)]);
+        }
+        cfg_c.chain = Arc::new(Mutex::new(longer.clone()));
+        assert_eq!(catch_up(&cfg_c)?, 0);
+        assert_eq!(
+            cfg_c.chain.lock().unwrap().blocks(),
+            longer.blocks()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> Result<()> {
+        let port: u16 = 32492;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let mut db = DB::<u64, u32>::new();
+        // more than fits in a frame
+        for key in 1..=3 {
+            db.set(u64::from(key), key);
+            db.attach(key.into(), |_| vec![key as u8; 30_000]);
+        }
+        let expected = db.snapshot();
+        let db = Arc::new(sharded());
+        db.restore(&expected)?;
+        let mut cfg = config(addr);
+        let admin = SecretKey::new(7);
+        let public_key = u64::from(&admin.public_key());
+        cfg.admin = Some(crc32(&public_key.to_be_bytes()));
+        let _server = super::server(addr, db, cfg);
+
+        let signed = |msg: u32, secret_key: &SecretKey| {
+            let public_key = u64::from(&secret_key.public_key());
+            let data = public_key.to_be_bytes().to_vec();
+            signed(
+                Frame {
+                    idx: time(),
+                    tag: TAG_SNAPSHOT,
+                    msg,
+                    key: crc32(&data),
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                secret_key,
+            )
+        };
+        let tx = connect(addr)?;
+        tx.send(&signed(0, &SecretKey::new(1)))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+
+        let mut snapshot = vec![];
+        loop {
+            tx.send(&signed(snapshot.len() as u32, &admin))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_OK);
+            assert_eq!(rcvd.msg as usize, expected.len());
+            assert_eq!(rcvd.ext, crc32(&expected));
+            assert!(rcvd.data.len() <= MAX_PAYLOAD_LEN);
+            snapshot.extend(rcvd.data);
+            if snapshot.len() == rcvd.msg as usize {
+                break;
+            }
+        }
+        assert_eq!(snapshot, expected);
+
+        // into a single shard, the same as into any number of them
+        let db = Shards::from(DB::new());
+        restore(&db, Some(snapshot));
+        assert_eq!(db.keys(), vec![1, 2, 3]);
+        assert_eq!(
+            db.lock(2u64).attachment(2, 0),
+            vec![2; 30_000]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_peers() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32478).into(),
+            ([127, 0, 0, 1], 32479).into(),
+        ];
+        let secret = 0xCAFEBABE;
+        let owner = 0x12345678;
+        let shares = xor::split(secret, 3, random);
+
+        let dbs = shares
+            .iter()
+            .map(|share| {
+                let mut db = DB::new();
+                db.set(owner, *share);
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        for (peer, db) in peers.iter().zip(&dbs[1..]) {
+            let _server =
+                super::server(*peer, db.clone(), config(*peer));
+        }
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers));
+
+        for _ in 0..3 {
+            refresh(dbs[0].clone(), &cfg, owner)?;
+        }
+        let latest = dbs
+            .iter()
+            .map(|db| {
+                let db = db.lock(owner);
+                db.versions(owner).to_vec()
+            })
+            .collect::<Vec<_>>();
+        // all the shares got refreshed, each time
+        assert!(latest
+            .iter()
+            .all(|versions| versions.len() == 4));
+        let last = latest
+            .iter()
+            .map(|versions| *versions.last().unwrap())
+            .collect::<Vec<_>>();
+        assert_ne!(last, shares);
+        assert_eq!(xor::merge(&last), secret);
+        assert_eq!(cfg.metrics.refreshes.get(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_seeded() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32520).into(),
+            ([127, 0, 0, 1], 32521).into(),
+        ];
+        let owner = 0x12345678;
+        let shares = [0xAAAAAAAA, 0xBBBBBBBB, 0xCCCCCCCC];
+        let dbs = shares
+            .iter()
+            .map(|share| {
+                let mut db = DB::new();
+                db.set(owner, *share);
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        for (peer, db) in peers.iter().zip(&dbs[1..]) {
+            let _server =
+                super::server(*peer, db.clone(), config(*peer));
+        }
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers));
+        cfg.rng = Arc::new(Seeded::new(42));
+        for _ in 0..2 {
+            refresh(dbs[0].clone(), &cfg, owner)?;
+        }
+
+        // the masks of `xor::refresh`, this server's share first:
+        // known from the seed
+        let rng = Seeded::new(42);
+        let mut expected = shares;
+        for _ in 0..2 {
+            xor::refresh(&mut expected, || rng.next_u32());
+        }
+        let last = dbs
+            .iter()
+            .map(|db| db.lock(owner).get(owner).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(last, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_threshold() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32480).into(),
+            ([127, 0, 0, 1], 32481).into(),
+        ];
+        let secret = 0xCAFEBABE;
+        let owner = 0x12345678;
+        let shares = shamir::split(secret, 2, 3, random);
+
+        let dbs = shares
+            .iter()
+            .map(|(x, y)| {
+                let mut db = DB::new();
+                db.set(owner, *y);
+                db.set_scheme(owner, 2 << 16 | x);
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        for (peer, db) in peers.iter().zip(&dbs[1..]) {
+            let _server =
+                super::server(*peer, db.clone(), config(*peer));
+        }
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers));
+
+        for _ in 0..3 {
+            refresh(dbs[0].clone(), &cfg, owner)?;
+        }
+        let last = dbs
+            .iter()
+            .zip(&shares)
+            .map(|(db, (x, _))| {
+                let db = db.lock(owner);
+                (*x, *db.versions(owner).last().unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_ne!(last, shares);
+        // any two of the refreshed shares will do
+        for (i, j) in [(0, 1), (1, 2), (0, 2)] {
+            assert_eq!(
+                shamir::merge(&[last[i], last[j]]),
+                secret
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verifiable_share() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32482).into();
+        let db = Arc::new(sharded());
+        let _server = super::server(addr, db, config(addr));
+
+        let user = SecretKey::new(1);
+        let secret = 0xCAFEBABE;
+        let (shares, commitments) =
+            vss::split(secret, 2, 3, random);
+        let share = |(x, y): (u32, u32)| {
+            let public_key = u64::from(&user.public_key());
+            let data = public_key.to_be_bytes().to_vec();
+            let mut frame = Frame {
+                idx: time(),
+                tag: TAG_SECRET_SHARE,
+                msg: y,
+                key: crc32(&data),
+                sig: 0,
+                ext: VERIFIABLE | 2 << 16 | x,
+                ns: 0,
+                sum: 0,
+                data,
+            };
+            frame.data.extend(0u32.to_be_bytes()); // no TTL
+            frame.data.extend(
+                commitments.iter().flat_map(|c| c.to_be_bytes()),
+            );
+            signed(frame, &user)
+        };
+        let tx = connect(addr)?;
+
+        // a share that does not match the commitments is rejected
+        let (x, y) = shares[0];
+        tx.send(&share((x, vss::add(y, 1))))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SHARE);
+
+        tx.send(&share(shares[0]))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+
+        let mut get = share(shares[0]);
+        get.tag = TAG_PUBLIC_KEY;
+        get.ext = 0; // the latest version
+        get = signed(get, &user);
+        tx.send(&get)?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.msg, y);
+        assert_eq!(rcvd.ext, VERIFIABLE | 2 << 16 | x);
+        assert_eq!(&rcvd.data[..4], &[0; 4]); // epoch
+        assert_eq!(pack64(&rcvd.data[4..]), commitments);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_verifiable() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32483).into(),
+            ([127, 0, 0, 1], 32484).into(),
+        ];
+        let secret = 0xCAFEBABE;
+        let owner = 0x12345678;
+        let (shares, commitments) =
+            vss::split(secret, 2, 3, random);
+
+        let dbs = shares
+            .iter()
+            .map(|(x, y)| {
+                let mut db = DB::new();
+                db.set(owner, *y);
+                db.set_scheme(owner, VERIFIABLE | 2 << 16 | x);
+                db.attach(owner, |_| unpack64(&commitments));
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        for (peer, db) in peers.iter().zip(&dbs[1..]) {
+            let _server =
+                super::server(*peer, db.clone(), config(*peer));
+        }
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers));
+
+        for _ in 0..3 {
+            refresh(dbs[0].clone(), &cfg, owner)?;
+        }
+        let last = dbs
+            .iter()
+            .zip(&shares)
+            .map(|(db, (x, _))| {
+                let mut db = db.lock(owner);
+                let share =
+                    (*x, *db.versions(owner).last().unwrap());
+                (share, pack64(&db.attachment(owner, 3)))
+            })
+            .collect::<Vec<_>>();
+        // all the servers have the same (refreshed) commitments,
+        // each share still matches them
+        let refreshed = last[0].1.clone();
+        assert_ne!(refreshed, commitments);
+        for (share, commitments) in &last {
+            assert_eq!(commitments, &refreshed);
+            assert!(vss::verify(*share, commitments));
+        }
+        assert_eq!(vss::merge(&[last[0].0, last[2].0]), secret);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_bytes() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32485).into(),
+            ([127, 0, 0, 1], 32486).into(),
+        ];
+        let secret = b"correct horse battery staple".to_vec();
+        let owner = 0x12345678;
+        let shares = xor::split_bytes(&secret, 3, random);
+
+        let dbs = shares
+            .iter()
+            .map(|share| {
+                let mut db = DB::new();
+                db.set(owner, 0);
+                db.set_scheme(owner, BYTES);
+                db.attach(owner, |_| share.clone());
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        for (peer, db) in peers.iter().zip(&dbs[1..]) {
+            let _server =
+                super::server(*peer, db.clone(), config(*peer));
+        }
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers));
+
+        for _ in 0..3 {
+            refresh(dbs[0].clone(), &cfg, owner)?;
+        }
+        let last = dbs
+            .iter()
+            .map(|db| {
+                let mut db = db.lock(owner);
+                assert_eq!(db.versions(owner).len(), 4);
+                db.attachment(owner, 3)
+            })
+            .collect::<Vec<_>>();
+        assert_ne!(last, shares);
+        assert!(last
+            .iter()
+            .all(|share| share.len() == secret.len()));
+        assert_eq!(xor::merge_bytes(&last), secret);
+        Ok(())
+    }
+
+    #[test]
+    fn test_epochs() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32487).into(),
+            ([127, 0, 0, 1], 32488).into(),
+        ];
+        let secret = 0xCAFEBABE;
+        let owner = 0x12345678;
+        let shares = xor::split(secret, 3, random);
+
+        let dbs = shares
+            .iter()
+            .map(|share| {
+                let mut db = DB::new();
+                db.set(owner, *share);
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        let _server = super::server(
+            peers[0],
+            dbs[1].clone(),
+            config(peers[0]),
+        );
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers.clone()));
+
+        // the second peer is not up yet and misses the first round
+        assert!(refresh(dbs[0].clone(), &cfg, owner).is_err());
+        let _server = super::server(
+            peers[1],
+            dbs[2].clone(),
+            config(peers[1]),
+        );
+        // nor does it take the next one, out of step (see
+        // `anti_entropy`)
+        let e =
+            refresh(dbs[0].clone(), &cfg, owner).unwrap_err();
+        assert!(
+            matches!(e, Error::App(e) if e.ends_with(&format!("ext={ERR_STALE}")))
+        );
+
+        let epochs = dbs
+            .iter()
+            .map(|db| db.lock(owner).epochs(owner))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            epochs,
+            vec![vec![0, 1, 2], vec![0, 1, 2], vec![0]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_anti_entropy() -> Result<()> {
+        let peers: Vec<SocketAddr> = vec![
+            ([127, 0, 0, 1], 32514).into(),
+            ([127, 0, 0, 1], 32515).into(),
+        ];
+        let secret = 0xCAFEBABE;
+        let owner = 0x12345678;
+        let shares = shamir::split(secret, 2, 3, random);
+        let dbs = shares
+            .iter()
+            .map(|(x, y)| {
+                let mut db = DB::new();
+                db.set(owner, *y);
+                db.set_scheme(owner, 2 << 16 | x);
+                // in line everywhere
+                db.set(1, 42);
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        // all of them with the same key, of the peers
+        let config = |peer: SocketAddr| {
+            let mut cfg = config(peer);
+            cfg.peer_keys =
+                vec![SecretKey::new(cfg.key).public_key()];
+            cfg
+        };
+        let _server = super::server(
+            peers[0],
+            dbs[1].clone(),
+            config(peers[0]),
+        );
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers.clone()));
+
+        // the second peer misses a round: its share is of another
+        // polynomial than the others' from then on
+        assert!(refresh(dbs[0].clone(), &cfg, owner).is_err());
+        let _server = super::server(
+            peers[1],
+            dbs[2].clone(),
+            config(peers[1]),
+        );
+        let last = |db: &Arc<Shards<DB<u64, u32>>>, x: u32| {
+            (x, db.lock(owner).get(owner).unwrap())
+        };
+        let (x, y) = (shares[1].0, shares[2].0);
+        assert_ne!(
+            shamir::merge(&[last(&dbs[1], x), last(&dbs[2], y)]),
+            secret
+        );
+
+        // seen once, then repaired if still there
+        let mut diverged = HashMap::new();
+        assert_eq!(
+            anti_entropy(&dbs[0], &cfg, &mut diverged)?,
+            0
+        );
+        assert_eq!(diverged.len(), 1);
+        assert_eq!(
+            anti_entropy(&dbs[0], &cfg, &mut diverged)?,
+            1
+        );
+        let epochs = dbs
+            .iter()
+            .map(|db| db.lock(owner).epochs(owner))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            epochs,
+            vec![vec![0, 1, 2], vec![0, 1, 2], vec![0, 2]]
+        );
+        for (i, j) in [(0, 1), (1, 2), (0, 2)] {
+            assert_eq!(
+                shamir::merge(&[
+                    last(&dbs[i], shares[i].0),
+                    last(&dbs[j], shares[j].0)
+                ]),
+                secret
+            );
+        }
+        assert_eq!(
+            anti_entropy(&dbs[0], &cfg, &mut diverged)?,
+            0
+        );
+        assert!(diverged.is_empty());
+        assert_eq!(cfg.metrics.repairs.get(), 1);
+
+        // the keys are not for a client to see
+        let digest = |by: Option<&SecretKey>| {
+            let frame = Frame {
+                idx: next_idx((TAG_DIGEST, 0, 1, 0)),
+                tag: TAG_DIGEST,
+                key: 1,
+                ..Frame::default()
+            };
+            match by {
+                Some(key) => signed(frame, key),
+                None => Frame {
+                    sum: frame.checksum(),
+                    ..frame
+                },
+            }
+        };
+        for by in [None, Some(&SecretKey::new(1))] {
+            let rcvd = client(peers[0], &digest(by))?;
+            assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+            assert_eq!(rcvd.ext, ERR_FORBIDDEN);
+        }
+        let rcvd = client(
+            peers[0],
+            &digest(Some(&SecretKey::new(cfg.key))),
+        )?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule() -> Result<()> {
+        let peer: SocketAddr = ([127, 0, 0, 1], 32489).into();
+        let secret = 0xCAFEBABE;
+        let owner = 0x12345678;
+        let shares = xor::split(secret, 2, random);
+
+        let dbs = shares
+            .iter()
+            .map(|share| {
+                let mut db = DB::new();
+                db.set(owner, *share);
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        let _server =
+            super::server(peer, dbs[1].clone(), config(peer));
+        let cfg = config(peer);
+
+        let scheduler = {
+            let db = dbs[0].clone();
+            let cfg = cfg.clone();
+            let interval = Duration::from_millis(50);
+            thread::spawn(move || schedule(db, &cfg, interval))
+        };
+        thread::sleep(Duration::from_millis(300));
+        cfg.drain.stop();
+        scheduler.join().unwrap();
+
+        let last = dbs
+            .iter()
+            .map(|db| {
+                let db = db.lock(owner);
+                assert!(db.versions(owner).len() > 2);
+                *db.versions(owner).last().unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(xor::merge(&last), secret);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl() -> Result<()> {
+        let port: u16 = 32490;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let _server =
+            super::server(addr, db.clone(), config(addr));
+
+        let user = SecretKey::new(1);
+        let public_key = u64::from(&user.public_key());
+        let key = crc32(&public_key.to_be_bytes());
+        let id = u64::from(key); // in the default namespace
+        let signed = |tag: u32, msg: u32, ttl: Option<u32>| {
+            let mut data = public_key.to_be_bytes().to_vec();
+            data.extend(
+                ttl.map(u32::to_be_bytes).unwrap_or_default(),
+            );
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg,
+                    key,
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                &user,
+            )
+        };
+        let tx = connect(addr)?;
+        tx.send(&signed(TAG_SECRET_SHARE, 42, Some(3600)))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let at = db.lock(id).expiry(id).unwrap();
+        assert!(at >= time() + 3599 && at <= time() + 3600);
+
+        tx.send(&signed(TAG_PUBLIC_KEY, 0, None))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.msg, 42);
+
+        db.lock(id).set_expiry(id, time() - 1);
+        tx.send(&signed(TAG_PUBLIC_KEY, 0, None))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_EXPIRED);
+
+        // without a TTL the secret is kept until deleted
+        let mut db = db.lock(1u64);
+        db.set(1, 0);
+        assert_eq!(purge(&mut *db, time()), 1);
+        assert_eq!(db.keys(), vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_once() -> Result<()> {
+        let port: u16 = 32494;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        cfg.reads = Reads::Once;
+        let _server = super::server(addr, db.clone(), cfg);
+
+        let user = SecretKey::new(1);
+        let public_key = u64::from(&user.public_key());
+        let key = crc32(&public_key.to_be_bytes());
+        let signed = |tag: u32, msg: u32| {
+            let mut data = public_key.to_be_bytes().to_vec();
+            if tag == TAG_SECRET_SHARE {
+                data.extend(0u32.to_be_bytes()); // no TTL
+            }
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg,
+                    key,
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                &user,
+            )
+        };
+        let tx = connect(addr)?;
+        tx.send(&signed(TAG_SECRET_SHARE, 42))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+
+        tx.send(&signed(TAG_PUBLIC_KEY, 0))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.msg, 42);
+        let id = u64::from(key);
+        assert!(db.lock(id).deleted(id).is_some());
+
+        // `msg` only to make it a different frame
+        tx.send(&signed(TAG_PUBLIC_KEY, 1))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_NOT_FOUND);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_version() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32495).into();
+        let db = Arc::new(sharded());
+        let _server =
+            super::server(addr, db.clone(), config(addr));
+
+        let user = SecretKey::new(1);
+        let public_key = u64::from(&user.public_key());
+        let key = crc32(&public_key.to_be_bytes());
+        let signed = |tag: u32, ext: u32| {
+            let mut data = public_key.to_be_bytes().to_vec();
+            if tag == TAG_SECRET_SHARE {
+                data.extend(0u32.to_be_bytes()); // no TTL
+            }
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg: 42,
+                    key,
+                    sig: 0,
+                    ext,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                &user,
+            )
+        };
+        let tx = connect(addr)?;
+        tx.send(&signed(TAG_SECRET_SHARE, 0))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        {
+            // refreshed twice, one round missed
+            let id = u64::from(key);
+            let mut db = db.lock(id);
+            db.patch(id, 1);
+            db.patch(id, 2);
+            db.set_epoch(id, 3);
+        }
+
+        let read = |ext: u32| -> Result<(u32, u32)> {
+            tx.send(&signed(TAG_PUBLIC_KEY, ext))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_OK);
+            let epoch = rcvd.data[..4].try_into().unwrap();
+            Ok((rcvd.msg, u32::from_be_bytes(epoch)))
+        };
+        assert_eq!(read(0)?, (42 ^ 1 ^ 2, 3));
+        assert_eq!(read(1)?, (42, 0));
+        assert_eq!(read(2)?, (42 ^ 1, 1));
+        assert_eq!(read(4)?, (42 ^ 1 ^ 2, 3));
+
+        // no version of the epoch 2
+        tx.send(&signed(TAG_PUBLIC_KEY, 3))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_NOT_FOUND);
+        Ok(())
+    }
+
+    #[test]
+    fn test_namespaces() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32496).into();
+        let db = Arc::new(sharded());
+        let member = SecretKey::new(1);
+        let other = SecretKey::new(2);
+        let fingerprint = |secret_key: &SecretKey| {
+            let public_key = u64::from(&secret_key.public_key());
+            crc32(&public_key.to_be_bytes())
+        };
+        let mut cfg = config(addr);
+        cfg.namespaces.insert(1, vec![fingerprint(&member)]);
+        let _server = super::server(addr, db.clone(), cfg);
+
+        // the member's key, in both namespaces
+        let key = fingerprint(&member);
+        let request = |user: &SecretKey, tag: u32, ns: u32| {
+            let public_key = u64::from(&user.public_key());
+            let mut data = public_key.to_be_bytes().to_vec();
+            if tag == TAG_SECRET_SHARE {
+                data.extend(0u32.to_be_bytes()); // no TTL
+            }
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg: 42 + ns,
+                    key: fingerprint(user),
+                    sig: 0,
+                    ext: 0,
+                    ns,
+                    sum: 0,
+                    data,
+                },
+                user,
+            )
+        };
+        let tx = connect(addr)?;
+        let call = |frame: Frame| -> Result<Frame> {
+            tx.send(&frame)?;
+            tx.recv_timeout(DEFAULT_TIMEOUT)
+        };
+
+        // not listed for the namespace
+        for (user, tag, ns) in [
+            (&other, TAG_SECRET_SHARE, 1),
+            (&member, TAG_SECRET_SHARE, 2),
+            (&other, TAG_LIST, 1),
+        ] {
+            let rcvd = call(request(user, tag, ns))?;
+            assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+            assert_eq!(rcvd.ext, ERR_FORBIDDEN);
+        }
+
+        let rcvd = call(request(&member, TAG_SECRET_SHARE, 0))?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let rcvd = call(request(&member, TAG_SECRET_SHARE, 1))?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let rcvd = call(request(&member, TAG_PUBLIC_KEY, 0))?;
+        assert_eq!(rcvd.msg, 42);
+        let rcvd = call(request(&member, TAG_PUBLIC_KEY, 1))?;
+        assert_eq!(rcvd.msg, 43);
+        assert_eq!(
+            db.keys(),
+            vec![u64::from(key), 1 << 32 | u64::from(key)]
+        );
+
+        let mut list = request(&member, TAG_LIST, 1);
+        list.msg = 0;
+        list = signed(list, &member);
+        let rcvd = call(list)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.ext, 1);
+        assert_eq!(pack(&rcvd.data), vec![key]);
+
+        let rcvd = call(request(&member, TAG_DELETE, 1))?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(db.keys(), vec![u64::from(key)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tombstone() -> Result<()> {
+        let peer: SocketAddr = ([127, 0, 0, 1], 32491).into();
+        let db = Arc::new(sharded());
+        let _server =
+            super::server(peer, db.clone(), config(peer));
+
+        let user = SecretKey::new(1);
+        let public_key = u64::from(&user.public_key());
+        let owner = crc32(&public_key.to_be_bytes());
+        let id = u64::from(owner); // in the default namespace
+        let signed = |tag: u32, msg: u32, idx: u32| {
+            signed(
+                Frame {
+                    idx,
+                    tag,
+                    msg,
+                    key: owner,
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data: public_key.to_be_bytes().to_vec(),
+                },
+                &user,
+            )
+        };
+        let tx = connect(peer)?;
+        tx.send(&signed(TAG_SECRET_SHARE, 42, time()))?;
+        let _: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        tx.send(&signed(TAG_DELETE, 0, time()))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert!(db.lock(id).deleted(id).is_some());
+
+        // a share sent before the deletion does not bring it back
+        tx.send(&signed(TAG_SECRET_SHARE, 42, time() - 5))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_DELETED);
+        assert!(db.keys().is_empty());
+
+        // nor does a refresh, the one refreshing deletes it instead
+        let local = Arc::new(sharded());
+        local.lock(id).set(id, 7);
+        refresh(local.clone(), &config(peer), id)?;
+        let mut local = local.lock(id);
+        assert!(local.keys().is_empty());
+        assert!(local.deleted(id).is_some());
+        assert!(db.keys().is_empty());
+
+        // set again after the deletion
+        tx.send(&signed(TAG_SECRET_SHARE, 43, time()))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(db.lock(id).deleted(id), None);
+
+        let mut db = db.lock(id);
+        db.delete(id, 1);
+        assert_eq!(purge(&mut *db, time()), 1);
+        assert_eq!(db.deleted(id), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_threshold_sign() -> Result<()> {
+        let peers: Vec<SocketAddr> = (32497..32500)
+            .map(|port| ([127, 0, 0, 1], port).into())
+            .collect();
+        let dbs = peers
+            .iter()
+            .map(|peer| {
+                let db = Arc::new(sharded());
+                let _server = super::server(
+                    *peer,
+                    db.clone(),
+                    config(*peer),
+                );
+                db
+            })
+            .collect::<Vec<_>>();
+
+        let user = SecretKey::new(1);
+        let public_key = u64::from(&user.public_key());
+        let owner = crc32(&public_key.to_be_bytes());
+        let signed =
+            |tag: u32, msg: u32, ext: u32, data: &[u8]| {
+                let mut frame = Frame {
+                    idx: time(),
+                    tag,
+                    msg,
+                    key: owner,
+                    sig: 0,
+                    ext,
+                    ns: 0,
+                    sum: 0,
+                    data: public_key.to_be_bytes().to_vec(),
+                };
+                frame.data.extend(data);
+                signed(frame, &user)
+            };
+
+        // 2-of-3 shares of the signing key
+        let (shares, group_key) =
+            frost::split(0xCAFEBABE, 2, 3, random);
+        let txs = peers
+            .iter()
+            .map(|peer| connect(*peer))
+            .collect::<Result<Vec<_>>>()?;
+        for (tx, (x, y)) in txs.iter().zip(&shares) {
+            let data = [
+                0u32.to_be_bytes().to_vec(),
+                group_key.to_bytes(),
+            ]
+            .concat(); // no TTL
+            tx.send(&signed(
+                TAG_SECRET_SHARE,
+                *y,
+                SIGNING | 2 << 16 | x,
+                &data,
+            ))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_OK);
+        }
+
+        let call = |i: usize, frame: &Frame| -> Result<Frame> {
+            txs[i].send(frame)?;
+            txs[i].recv_timeout(DEFAULT_TIMEOUT)
+        };
+        // by the servers `signers`, of `msg` (the message of a
+        // commitment is random, the frames must not be replays)
+        let sign = |signers: &[usize], msg: u32| -> Result<_> {
+            let mut commitments = vec![];
+            for i in signers {
+                let rcvd = call(
+                    *i,
+                    &signed(TAG_SIGN_COMMIT, random(), 0, &[]),
+                )?;
+                assert_eq!(rcvd.tag, TAG_OK);
+                assert_eq!(rcvd.msg, shares[*i].0);
+                assert_eq!(
+                    &rcvd.data[28..],
+                    group_key.to_bytes()
+                );
+                commitments.push(
+                    Commitment::parse(&rcvd.data[..20])?[0],
+                );
+            }
+            let data = commitments
+                .iter()
+                .flat_map(Commitment::to_bytes)
+                .collect::<Vec<_>>();
+            let frame = signed(TAG_SIGN_SHARE, msg, 0, &data);
+            let mut z = vec![];
+            for i in signers {
+                let rcvd = call(*i, &frame)?;
+                assert_eq!(rcvd.tag, TAG_OK);
+                z.push(rcvd.msg);
+            }
+            let sig = frost::aggregate(
+                msg,
+                &commitments,
+                &z,
+                &group_key,
+            );
+            Ok((sig, data))
+        };
+
+        let msg = 0x12345678;
+        for signers in [[0, 1], [1, 2], [0, 2]] {
+            let (sig, _) = sign(&signers, msg)?;
+            assert!(group_key.is_valid(&msg, &sig));
+        }
+
+        // the nonces are gone once used, and one signer is too few
+        let (_, data) = sign(&[0, 1], msg)?;
+        let rcvd =
+            call(0, &signed(TAG_SIGN_SHARE, msg + 1, 0, &data))?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_EXPIRED);
+        let rcvd =
+            call(0, &signed(TAG_SIGN_COMMIT, random(), 0, &[]))?;
+        let rcvd = call(
+            0,
+            &signed(TAG_SIGN_SHARE, msg, 0, &rcvd.data[..20]),
+        )?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SHARE);
+
+        // refreshed shares sign for the same group key
+        let id = u64::from(owner);
+        let mut cfg = config(peers[0]);
+        cfg.peers = Arc::new(Peers::new(peers[1..].to_vec()));
+        refresh(dbs[0].clone(), &cfg, id)?;
+        let (sig, _) = sign(&[0, 2], msg)?;
+        assert!(group_key.is_valid(&msg, &sig));
+        assert_ne!(
+            *dbs[0].lock(id).versions(id).last().unwrap(),
+            shares[0].1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovered_key() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32500).into();
+        let db = Arc::new(sharded());
+        let _server =
+            super::server(addr, db.clone(), config(addr));
+
+        // no public key in the payload, only its fingerprint
+        let user = SecretKey::new(1);
+        let owner = user.public_key().fingerprint();
+        let request = |tag: u32, msg: u32, by: &SecretKey| {
+            let mut frame = Frame {
+                idx: next_idx((tag, msg, owner, 0)),
+                tag,
+                msg,
+                key: owner,
+                sig: 0,
+                ext: 0,
+                ns: 0,
+                sum: 0,
+                data: vec![],
+            };
+            if tag == TAG_SECRET_SHARE {
+                frame.data.extend(60u32.to_be_bytes()); // TTL
+            }
+            signed(frame, by)
+        };
+        let tx = connect(addr)?;
+        tx.send(&request(TAG_SECRET_SHARE, 42, &user))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let id = u64::from(owner);
+        assert_eq!(
+            db.lock(id).owner(id),
+            Some(user.public_key())
+        );
+        assert!(db.lock(id).expiry(id).is_some());
+
+        tx.send(&request(TAG_PUBLIC_KEY, 0, &user))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.msg, 42);
+
+        // another key claiming the fingerprint, for a new key too
+        let other = SecretKey::new(2);
+        for tag in [TAG_PUBLIC_KEY, TAG_DELETE] {
+            tx.send(&request(tag, 0, &other))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+            assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+        }
+        let mut frame = request(TAG_SECRET_SHARE, 7, &other);
+        frame.key ^= 1;
+        frame = signed(frame, &other);
+        tx.send(&frame)?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_carried_key() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32525).into();
+        let db = Arc::new(sharded());
+        let _server =
+            super::server(addr, db.clone(), config(addr));
+
+        // signed by one key, carrying it, for another one's id
+        let (user, other) =
+            (SecretKey::new(1), SecretKey::new(2));
+        let owner = other.public_key().fingerprint();
+        let frame = signed(
+            Frame {
+                idx: time(),
+                tag: TAG_SECRET_SHARE,
+                msg: 42,
+                key: owner,
+                data: u64::from(&user.public_key())
+                    .to_be_bytes()
+                    .into_iter()
+                    .chain(60u32.to_be_bytes())
+                    .collect(),
+                ..Frame::default()
+            },
+            &user,
+        );
+        assert!(frame.carried().is_none());
+        let tx = connect(addr)?;
+        tx.send(&frame)?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+        assert_eq!(rcvd.ext, ERR_BAD_SIGNATURE);
+        let id = u64::from(owner);
+        assert_eq!(db.lock(id).owner(id), None);
+        assert_eq!(db.lock(id).get(id), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_authenticated_handshake() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32501).into();
+        let cfg = config(addr);
+        let server_key = SecretKey::new(cfg.key).public_key();
+        let _server =
+            super::server(addr, Arc::new(sharded()), cfg);
+
+        let ping = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 42,
+            ..Frame::default()
+        };
+        let connect = |trusted: &[PublicKey]| -> Result<Tcp> {
+            let mut tx = Tcp::from(TcpStream::connect(addr)?);
+            let key = SecretKey::new(7);
+            let auth = Auth {
+                key: &key,
+                trusted,
+                required: true,
+            };
+            let (shared, peer) = dhke::authenticated(
+                &tx,
+                DEFAULT_TIMEOUT,
+                &Group::ALL,
+                &auth,
+            )?;
+            assert_eq!(peer.as_ref(), trusted.first());
+            let keys = shared.keys();
+            tx.set_keys(keys.seal, keys.open);
+            Ok(tx)
+        };
+        let tx = connect(&[server_key])?;
+        let mut frame = ping.clone();
+        frame.sum = frame.checksum();
+        tx.send(&frame)?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!((rcvd.tag, rcvd.msg), (TAG_PONG, 42));
+
+        // not the server's key: a man in the middle, as far as the
+        // client can tell
+        let other = SecretKey::new(8).public_key();
+        assert!(connect(&[other]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_psk() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32503).into();
+        let mut cfg = config(addr);
+        cfg.psk = Some(b"out of band".to_vec());
+        let metrics = cfg.metrics.clone();
+        let _server =
+            super::server(addr, Arc::new(sharded()), cfg);
+
+        let connect = |psk: &[u8]| -> Result<Tcp> {
+            let mut tx = Tcp::from(TcpStream::connect(addr)?);
+            let keys = dhke::psk(&tx, DEFAULT_TIMEOUT, psk)?;
+            tx.set_keys(keys.seal, keys.open);
+            Ok(tx)
+        };
+        let tx = connect(b"out of band")?;
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: 42,
+            ..Frame::default()
+        };
+        frame.sum = frame.checksum();
+        tx.send(&frame)?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!((rcvd.tag, rcvd.msg), (TAG_PONG, 42));
+
+        // fails on both sides
+        assert!(connect(b"another key").is_err());
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        while metrics.handshake_failures.get() == 0
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(metrics.handshake_failures.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rekey() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32502).into();
+        let mut cfg = config(addr);
+        cfg.rekey = Some(Rekey {
+            frames: 3,
+            every: Duration::from_secs(60),
+        });
+        let _server =
+            super::server(addr, Arc::new(sharded()), cfg);
+
+        // both sides start a rekey now and then, on the same
+        // connection
+        let mut tx = connect(addr)?;
+        tx.set_rekey(Some(Rekey {
+            frames: 2,
+            every: Duration::from_secs(60),
+        }));
+        for msg in 1..=10 {
+            let mut frame = Frame {
+                idx: time(),
+                tag: TAG_PING,
+                msg,
+                ..Frame::default()
+            };
+            frame.sum = frame.checksum();
+            tx.send(&frame)?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!((rcvd.tag, rcvd.msg), (TAG_PONG, msg));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gossip() -> Result<()> {
+        // a knows b, b knows c, c knows nobody
+        let addrs: Vec<SocketAddr> = (32504..32507)
+            .map(|port| ([127, 0, 0, 1], port).into())
+            .collect();
+        let interval = Duration::from_millis(20);
+        let cfgs = addrs
+            .iter()
+            .zip([vec![addrs[1]], vec![addrs[2]], vec![]])
+            .map(|(addr, seeds)| {
+                let mut cfg = config(*addr);
+                cfg.members = Arc::new(Members::new(
+                    *addr,
+                    interval * SUSPECT_ROUNDS,
+                    interval * DEAD_ROUNDS,
+                ));
+                for seed in seeds {
+                    cfg.members.add(seed);
+                }
+                let db = Arc::new(sharded());
+                let _server =
+                    super::server(*addr, db, cfg.clone());
+                let gossip = cfg.clone();
+                thread::spawn(move || {
+                    super::gossip(&gossip, interval)
+                });
+                cfg
+            })
+            .collect::<Vec<_>>();
+
+        // each of them hears of the others
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        for (i, cfg) in cfgs.iter().enumerate() {
+            let others = addrs
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, addr)| *addr)
+                .collect::<Vec<_>>();
+            while cfg.members.live() != others {
+                assert!(Instant::now() < deadline);
+                thread::sleep(interval);
+            }
+        }
+
+        // a client asks any of them
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_GOSSIP,
+            ..Frame::default()
+        };
+        frame.sum = frame.checksum();
+        let rcvd = client(addrs[2], &frame)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        let view = gossip::decode(&rcvd.data)?;
+        assert_eq!(view.len(), 3);
+        assert_eq!(view[0].0, addrs[2]);
+
+        // a view from anyone else is not taken in
+        let made_up = Members::new(
+            ([127, 0, 0, 1], 1).into(),
+            interval,
+            interval,
+        );
+        let mut frame = Frame {
+            idx: time(),
+            tag: TAG_GOSSIP,
+            data: made_up.encode(),
+            ..Frame::default()
+        };
+        frame.sum = frame.checksum();
+        let rcvd = client(addrs[2], &frame)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(gossip::decode(&rcvd.data)?.len(), 3);
+        assert_eq!(cfgs[2].members.live(), addrs[..2].to_vec());
+
+        // c goes down: dead to the others
+        shutdown(&cfgs[2], addrs[2]);
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        while cfgs[0].members.live() != vec![addrs[1]] {
+            assert!(Instant::now() < deadline);
+            thread::sleep(interval);
+        }
+        assert_eq!(
+            cfgs[0].members.members()[1],
+            (addrs[2], State::Dead)
+        );
+        for cfg in &cfgs[..2] {
+            shutdown(cfg, cfg.members.me());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_peers() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}",
+            random()
+        ));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("peers");
+        let addr = |port: u16| -> SocketAddr {
+            ([127, 0, 0, 1], port).into()
+        };
+        let peers = Peers::open(path.clone(), vec![addr(1)])?;
+        assert_eq!(peers.list(), vec![addr(1)]);
+        assert!(peers.add(addr(2))?);
+        assert!(!peers.add(addr(2))?);
+        assert!(!peers.remove(addr(3))?);
+
+        // the ones kept, not the ones given
+        let peers = Peers::open(path.clone(), vec![addr(3)])?;
+        assert_eq!(peers.list(), vec![addr(1), addr(2)]);
+        assert!(peers.remove(addr(1))?);
+        let peers = Peers::open(path.clone(), vec![])?;
+        assert_eq!(peers.list(), vec![addr(2)]);
+
+        fs::write(&path, "127.0.0.1\n")?;
+        assert!(Peers::open(path, vec![]).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_leave() -> Result<()> {
+        // a and b keep the secrets, c joins, then b leaves
+        let addrs: Vec<SocketAddr> = (32507..32510)
+            .map(|port| ([127, 0, 0, 1], port).into())
+            .collect();
+        let (word, bytes, shamir) = (1u64, 2u64, 3u64);
+        let secret = 0xCAFEBABE;
+        let secret_bytes = b"a secret of some bytes".to_vec();
+        let shares = xor::split(secret, 2, random);
+        let pads = xor::split_bytes(&secret_bytes, 2, random);
+        let dbs = (0..3)
+            .map(|i| {
+                let mut db = DB::new();
+                if i < 2 {
+                    db.set(word, shares[i]);
+                    db.set(bytes, 0);
+                    db.set_scheme(bytes, BYTES);
+                    db.attach(bytes, |_| pads[i].clone());
+                    db.set(shamir, 42);
+                    db.set_scheme(
+                        shamir,
+                        (2 << 16) | (i as u32 + 1),
+                    );
+                }
+                Arc::new(Shards::from(db))
+            })
+            .collect::<Vec<_>>();
+        let admin = SecretKey::new(7);
+        let public_key = u64::from(&admin.public_key());
+        let cfgs = addrs
+            .iter()
+            .zip(&dbs)
+            .enumerate()
+            .map(|(i, (addr, db))| {
+                let mut cfg = config(*addr);
+                cfg.key = 0xAAAAAAA0 + i as u32;
+                cfg.peer_keys = (0..3)
+                    .map(|j| {
+                        SecretKey::new(0xAAAAAAA0 + j)
+                            .public_key()
+                    })
+                    .collect();
+                cfg.admin =
+                    Some(crc32(&public_key.to_be_bytes()));
+                cfg.members = Arc::new(Members::new(
+                    *addr,
+                    DEFAULT_GOSSIP_INTERVAL * SUSPECT_ROUNDS,
+                    DEFAULT_GOSSIP_INTERVAL * DEAD_ROUNDS,
+                ));
+                let peers = addrs
+                    .iter()
+                    .filter(|peer| *peer != addr)
+                    .take(if i < 2 { 1 } else { 2 })
+                    .cloned()
+                    .collect();
+                cfg.peers = Arc::new(Peers::new(peers));
+                let _server = super::server(
+                    *addr,
+                    db.clone(),
+                    cfg.clone(),
+                );
+                cfg
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(cfgs[0].peers.list(), vec![addrs[1]]);
+        assert_eq!(cfgs[1].peers.list(), vec![addrs[0]]);
+        assert_eq!(cfgs[2].peers.list(), addrs[..2].to_vec());
+
+        let send =
+            |to: usize, tag: u32, msg: u32, who: usize| {
+                let data = public_key
+                    .to_be_bytes()
+                    .into_iter()
+                    .chain(addrs[who].to_string().into_bytes())
+                    .collect::<Vec<_>>();
+                let frame = signed(
+                    Frame {
+                        idx: time(),
+                        tag,
+                        msg,
+                        key: crc32(&public_key.to_be_bytes()),
+                        sig: 0,
+                        ext: 0,
+                        ns: 0,
+                        sum: 0,
+                        data,
+                    },
+                    &admin,
+                );
+                client(addrs[to], &frame)
+            };
+        let latest = |i: usize| {
+            let mut db = dbs[i].lock(word);
+            let epoch = *db.epochs(word).last().unwrap();
+            let version = db.version(bytes);
+            (
+                db.get(word).unwrap(),
+                db.attachment(bytes, version),
+                epoch,
+            )
+        };
+
+        // not the admin
+        let frame = signed(
+            Frame {
+                idx: time(),
+                tag: TAG_JOIN,
+                msg: 1,
+                data: addrs[2].to_string().into_bytes(),
+                ..Frame::default()
+            },
+            &SecretKey::new(1),
+        );
+        assert_eq!(
+            client(addrs[0], &frame)?.ext,
+            ERR_BAD_SIGNATURE
+        );
+
+        // b takes c as a peer, a hands the shares over to both
+        assert_eq!(send(1, TAG_JOIN, 0, 2)?.tag, TAG_OK);
+        let rcvd = send(0, TAG_JOIN, 1, 2)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(rcvd.msg, 0);
+        assert_eq!(
+            String::from_utf8(rcvd.data).unwrap(),
+            format!(
+                "peer={}\npeer={}\nhanded=2\nskipped=1\nfailed=0",
+                addrs[1], addrs[2]
+            )
+        );
+        assert_eq!(
+            cfgs[1].peers.list(),
+            vec![addrs[0], addrs[2]]
+        );
+        let all = (0..3).map(latest).collect::<Vec<_>>();
+        assert_eq!(
+            xor::merge(&[all[0].0, all[1].0, all[2].0]),
+            secret
+        );
+        assert_eq!(
+            xor::merge_bytes(&[
+                all[0].1.clone(),
+                all[1].1.clone(),
+                all[2].1.clone()
+            ]),
+            secret_bytes
+        );
+        // a round of its own, the same for all of them
+        assert!(all.iter().all(|(_, _, epoch)| *epoch == 1));
+        assert!(dbs[2].lock(shamir).get(shamir).is_none());
+
+        // b hands its shares over to a and c, which drop it
+        let rcvd = send(1, TAG_LEAVE, 0, 1)?;
+        assert_eq!((rcvd.tag, rcvd.msg), (TAG_OK, 0));
+        for i in [0, 2] {
+            assert_eq!(send(i, TAG_LEAVE, 0, 1)?.tag, TAG_OK);
+        }
+        assert_eq!(cfgs[0].peers.list(), vec![addrs[2]]);
+        assert_eq!(cfgs[2].peers.list(), vec![addrs[0]]);
+        let all = (0..3).map(latest).collect::<Vec<_>>();
+        assert_eq!(all[1].0, 0);
+        assert!(all[1].1.iter().all(|b| *b == 0));
+        assert_eq!(all[0].0 ^ all[2].0, secret);
+        assert_eq!(
+            xor::merge_bytes(&[
+                all[0].1.clone(),
+                all[2].1.clone()
+            ]),
+            secret_bytes
+        );
+        assert!(all.iter().all(|(_, _, epoch)| *epoch == 2));
+
+        for (addr, cfg) in addrs.iter().zip(&cfgs) {
+            shutdown(cfg, *addr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_transfer_forbidden() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32526).into();
+        let db = Arc::new(sharded());
+        let mut cfg = config(addr);
+        let peer = SecretKey::new(0xAAAAAAA1);
+        cfg.peer_keys = vec![peer.public_key()];
+        let _server = super::server(addr, db.clone(), cfg);
+
+        // a share for the key 1, owned by whoever sent it
+        let (id, user) = (1u64, SecretKey::new(1));
+        let transfer = |by: Option<&SecretKey>| {
+            let frame = Frame {
+                idx: next_idx((TAG_TRANSFER, 42, 0xAAAAAAA1, 0)),
+                tag: TAG_TRANSFER,
+                msg: 42,
+                key: 0xAAAAAAA1,
+                ext: 1,
+                data: [1, 0, 0]
+                    .into_iter()
+                    .flat_map(u32::to_be_bytes)
+                    .chain(user.public_key().to_bytes())
+                    .collect(),
+                ..Frame::default()
+            };
+            match by {
+                Some(key) => signed(frame, key),
+                None => Frame {
+                    sum: frame.checksum(),
+                    ..frame
+                },
+            }
+        };
+        let tx = connect(addr)?;
+        for by in [None, Some(&user)] {
+            tx.send(&transfer(by))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+            assert_eq!(rcvd.ext, ERR_FORBIDDEN);
+        }
+        assert_eq!(db.lock(id).get(id), None);
+        assert_eq!(db.lock(id).owner(id), None);
+
+        tx.send(&transfer(Some(&peer)))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(db.lock(id).get(id), Some(42));
+        assert_eq!(
+            db.lock(id).owner(id),
+            Some(user.public_key())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_forbidden() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32527).into();
+        let id = 1u64;
+        let mut db = DB::new();
+        db.set(id, 42);
+        db.patch(id, 1);
+        db.set_epoch(id, 1);
+        let db = Arc::new(Shards::from(db));
+        let mut cfg = config(addr);
+        let peer = SecretKey::new(0xAAAAAAA1);
+        cfg.peer_keys = vec![peer.public_key()];
+        let _server = super::server(addr, db.clone(), cfg);
+
+        // back to the share of the epoch 0, at 2
+        let repair = |by: Option<&SecretKey>| {
+            let frame = Frame {
+                idx: next_idx((TAG_REPAIR, 0, 0xAAAAAAA1, 0)),
+                tag: TAG_REPAIR,
+                key: 0xAAAAAAA1,
+                ext: id as u32,
+                data: unpack(&[0, 2], 8),
+                ..Frame::default()
+            };
+            match by {
+                Some(key) => signed(frame, key),
+                None => Frame {
+                    sum: frame.checksum(),
+                    ..frame
+                },
+            }
+        };
+        let tx = connect(addr)?;
+        for by in [None, Some(&SecretKey::new(1))] {
+            tx.send(&repair(by))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+            assert_eq!(rcvd.ext, ERR_FORBIDDEN);
+        }
+        assert_eq!(db.lock(id).get(id), Some(42 ^ 1));
+        assert_eq!(db.lock(id).epochs(id), vec![0, 1]);
+
+        tx.send(&repair(Some(&peer)))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(db.lock(id).get(id), Some(42));
+        assert_eq!(db.lock(id).epochs(id), vec![0, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_forbidden() -> Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 32530).into();
+        let id = 1u64;
+        let mut db = DB::new();
+        db.set(id, 42);
+        let db = Arc::new(Shards::from(db));
+        let cfg = config(addr);
+        let peer = SecretKey::new(cfg.key);
+        let _server = super::server(addr, db.clone(), cfg);
+
+        // masked with 1, at the epoch 1
+        let refresh = |by: Option<&SecretKey>| {
+            let frame = Frame {
+                idx: next_idx((TAG_REFRESH, 1, 0xAAAAAAAA, 0)),
+                tag: TAG_REFRESH,
+                msg: 1,
+                key: 0xAAAAAAAA,
+                ext: id as u32,
+                data: 1u32.to_be_bytes().to_vec(),
+                ..Frame::default()
+            };
+            match by {
+                Some(key) => signed(frame, key),
+                None => Frame {
+                    sum: frame.checksum(),
+                    ..frame
+                },
+            }
+        };
+        let tx = connect(addr)?;
+        for by in [None, Some(&SecretKey::new(1))] {
+            tx.send(&refresh(by))?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_BAD_REQUEST);
+            assert_eq!(rcvd.ext, ERR_FORBIDDEN);
+        }
+        assert_eq!(db.lock(id).get(id), Some(42));
+        assert_eq!(db.lock(id).epochs(id), vec![0]);
+
+        tx.send(&refresh(Some(&peer)))?;
+        let rcvd: Frame = tx.recv_timeout(DEFAULT_TIMEOUT)?;
+        assert_eq!(rcvd.tag, TAG_OK);
+        assert_eq!(db.lock(id).get(id), Some(42 ^ 1));
+        assert_eq!(db.lock(id).epochs(id), vec![0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replicated() -> Result<()> {
+        // two shares, each kept by two servers
+        let addrs: Vec<SocketAddr> = (32510..32514)
+            .map(|port| ([127, 0, 0, 1], port).into())
+            .collect();
+        let dbs = (0..4)
+            .map(|_| Arc::new(sharded()))
+            .collect::<Vec<_>>();
+        let cfgs = addrs
+            .iter()
+            .zip(&dbs)
+            .map(|(addr, db)| {
+                let mut cfg = config(*addr);
+                cfg.replicated = true;
+                cfg.peers = Arc::new(Peers::new(
+                    addrs
+                        .iter()
+                        .filter(|peer| *peer != addr)
+                        .cloned()
+                        .collect(),
+                ));
+                let _server = super::server(
+                    *addr,
+                    db.clone(),
+                    cfg.clone(),
+                );
+                cfg
+            })
+            .collect::<Vec<_>>();
+
+        let key = SecretKey::new(42);
+        let owner = merge(0, key.public_key().fingerprint());
+        let client = Client::new(
+            key,
+            addrs.clone(),
+            ClientConfig {
+                quorum: Quorum::majority(2),
+                ..ClientConfig::default()
+            },
+        );
+        client.set_secret(0xCAFEBABE, Scheme::Xor, 0)?;
+        let shares = dbs
+            .iter()
+            .map(|db| db.lock(owner).get(owner).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(shares[0], shares[1]);
+        assert_eq!(shares[2], shares[3]);
+        assert_eq!(shares[0] ^ shares[2], 0xCAFEBABE);
+        // the epoch is the write's, on every replica
+        let epochs = dbs
+            .iter()
+            .map(|db| db.lock(owner).epochs(owner))
+            .collect::<Vec<_>>();
+        assert!(epochs[0][0] > 0);
+        assert!(epochs.iter().all(|e| *e == epochs[0]));
+
+        // the replicas would drift apart
+        refresh(dbs[0].clone(), &cfgs[0], owner)?;
+        assert!(!hand_over(&dbs[0], &cfgs[0], owner, true)?);
+        assert!(dbs.iter().all(|db| db
+            .lock(owner)
+            .versions(owner)
+            .len()
+            == 1));
+
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+        assert_eq!(client.acks().len(), 2);
+        drop(client);
+        for (cfg, addr) in cfgs.iter().zip(&addrs) {
+            shutdown(cfg, *addr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_ledger() -> Result<()> {
+        let port: u16 = 32519;
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+        let ledger = Arc::new(Ledger::new());
+        let db = Shards::new(
+            (0..4)
+                .map(|_| {
+                    Ledgered::new(DB::new(), ledger.clone())
+                })
+                .collect(),
+        );
+        let mut cfg = config(addr);
+        cfg.ledger = ledger.clone();
+        let server_key = SecretKey::new(cfg.key).public_key();
+        let _server = super::server(addr, Arc::new(db), cfg);
+
+        let user = SecretKey::new(1);
+        let frame = |tag: u32, data: Vec<u8>| {
+            signed(
+                Frame {
+                    idx: time(),
+                    tag,
+                    msg: 42,
+                    key: crc32(&data[..8]),
+                    sig: 0,
+                    ext: 0,
+                    ns: 0,
+                    sum: 0,
+                    data,
+                },
+                &user,
+            )
+        };
+        let public_key =
+            u64::from(&user.public_key()).to_be_bytes().to_vec();
+        let no_ttl = [public_key.clone(), vec![0; 4]].concat();
+        let tx = connect(addr)?;
+        let head = || -> Result<Head> {
+            // anyone may ask, the signature is not checked
+            let mut ask = frame(TAG_LEDGER, vec![0; 8]);
+            ask.ext = random();
+            ask.sum = ask.checksum();
+            tx.send(&ask)?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_OK);
+            Head::decode(&rcvd.data)
+        };
+        assert_eq!(head()?.len, 0);
+
+        for frame in [
+            frame(TAG_SECRET_SHARE, no_ttl),
+            frame(TAG_PUBLIC_KEY, public_key.clone()), // a read
+            frame(TAG_DELETE, public_key),
+        ] {
+            tx.send(&frame)?;
+            let rcvd: Frame =
+                tx.recv_timeout(DEFAULT_TIMEOUT)?;
+            assert_eq!(rcvd.tag, TAG_OK);
+        }
+        let head = head()?;
+        assert_eq!(head.len, 2);
+        assert_eq!(head.hash, ledger.head().hash);
+        assert!(head.verify(&server_key));
+        assert!(!head.verify(&user.public_key()));
         Ok(())
     }
 }
@@ -0,0 +1,684 @@
+use std::fmt;
+
+use crate::{
+    api::{Error, Result},
+    util::{hash, to_hex},
+};
+
+pub type Hash = [u8; 32];
+
+// Of the payload of a block, the genesis block's aside (see
+// `Chain::with_check`)
+pub type Check = fn(&[u8]) -> std::result::Result<(), Reason>;
+
+// prev, time, nonce and the length of the payload
+const HEADER_LEN: usize = 32 + 4 + 8 + 4;
+
+// A block of a `Chain`: the hash of the block before (zeros for the
+// first one), when it was made (unix seconds), what it carries, and
+// a nonce. Its hash covers all of them, so a block changed after the
+// fact no longer matches the hash the next one has of it, and a
+// nonce found for a block (`mine`) does not do for another one.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Block {
+    pub prev: Hash,
+    pub time: u32,
+    pub payload: Vec<u8>,
+    pub nonce: u64,
+}
+
+impl Block {
+    pub fn hash(&self) -> Hash {
+        hash(&self.encode())
+    }
+
+    // The first block of a chain, the same for the same seed (e.g.
+    // of the config all the servers share): on top of nothing, from
+    // the start of time, carrying the seed
+    pub fn genesis(seed: &[u8]) -> Self {
+        Self {
+            prev: [0; 32],
+            time: 0,
+            payload: seed.to_vec(),
+            nonce: 0,
+        }
+    }
+
+    // Leading zero bits of the hash: each one takes twice as many
+    // hashes on average to find a nonce for
+    pub fn work(&self) -> u32 {
+        let hash = self.hash();
+        let zeros = hash.iter().take_while(|b| **b == 0).count();
+        let bits =
+            hash.get(zeros).map_or(0, |b| b.leading_zeros());
+        8 * zeros as u32 + bits
+    }
+
+    // Proof of work: the nonces from zero up, until the hash has
+    // `difficulty` leading zero bits (see `work`), how many of them
+    // were tried
+    pub fn mine(&mut self, difficulty: u32) -> u64 {
+        assert!(difficulty <= 256, "difficulty over 256 bits");
+        self.nonce = 0;
+        while self.work() < difficulty {
+            self.nonce += 1;
+        }
+        self.nonce + 1
+    }
+
+    // The header (the hash of the block before, time, nonce, length
+    // of the payload), then the payload
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.extend(self.prev);
+        bytes.extend(self.time.to_be_bytes());
+        bytes.extend(self.nonce.to_be_bytes());
+        bytes.extend((self.payload.len() as u32).to_be_bytes());
+        bytes.extend(&self.payload);
+        bytes
+    }
+
+    // A block at the start of `bytes` (see `encode`), and how many
+    // bytes it took
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize)> {
+        let header =
+            bytes.get(..HEADER_LEN).ok_or_else(|| {
+                Error::App(format!(
+                    "invalid block: {} bytes",
+                    bytes.len()
+                ))
+            })?;
+        let len = u32::from_be_bytes(
+            header[44..48].try_into().unwrap(),
+        ) as usize;
+        let payload = bytes
+            .get(HEADER_LEN..HEADER_LEN + len)
+            .ok_or_else(|| {
+                Error::App(format!(
+                    "invalid block: payload of {len} bytes"
+                ))
+            })?;
+        let block = Self {
+            prev: header[..32].try_into().unwrap(),
+            time: u32::from_be_bytes(
+                header[32..36].try_into().unwrap(),
+            ),
+            nonce: u64::from_be_bytes(
+                header[36..44].try_into().unwrap(),
+            ),
+            payload: payload.to_vec(),
+        };
+        Ok((block, HEADER_LEN + len))
+    }
+}
+
+// Blocks one after the other (see `Block::encode`), not checked
+pub fn decode(mut bytes: &[u8]) -> Result<Vec<Block>> {
+    let mut blocks = vec![];
+    while !bytes.is_empty() {
+        let (block, len) = Block::decode(bytes)?;
+        blocks.push(block);
+        bytes = &bytes[len..];
+    }
+    Ok(blocks)
+}
+
+// Why a block does not go on a chain
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reason {
+    Genesis, // the first block is not the chain's genesis block
+    Prev { prev: Hash, tip: Hash }, // not on top of the one before
+    Time { time: u32, prev: u32 },  // made before the one before
+    Work { work: u32, difficulty: u32 },
+    Payload(String), // not what the chain's blocks carry
+    Signature(usize), // of the operation at this index
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Genesis => {
+                write!(f, "not the genesis block")
+            }
+            Reason::Prev { prev, tip } => write!(
+                f,
+                "prev {} is not {}",
+                to_hex(prev),
+                to_hex(tip)
+            ),
+            Reason::Time { time, prev } => {
+                write!(f, "time {time} is before {prev}")
+            }
+            Reason::Work { work, difficulty } => {
+                write!(f, "work {work} is under {difficulty}")
+            }
+            Reason::Payload(e) => write!(f, "payload: {e}"),
+            Reason::Signature(i) => {
+                write!(f, "operation {i}: invalid signature")
+            }
+        }
+    }
+}
+
+// The first block of a chain that does not go on it, and why
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Invalid {
+    pub height: usize,
+    pub reason: Reason,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}: {}", self.height, self.reason)
+    }
+}
+
+impl From<Invalid> for Error {
+    fn from(e: Invalid) -> Self {
+        Error::App(e.to_string())
+    }
+}
+
+// A branch taking the place of the blocks of a chain from `height`
+// on (see `Chain::reorg`)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reorg {
+    pub height: usize,
+    pub undone: Vec<Block>, // rolled back, the lowest first
+    pub done: Vec<Block>,   // in their place
+}
+
+// Blocks, each one with the hash of the one before and (as proof of
+// work) at least `difficulty` leading zero bits in its own: appending
+// checks the new block follows the last one, `validate` checks them
+// all (a chain put together from elsewhere, e.g. read from a file).
+// A chain may start with a genesis block (`genesis`), the same for
+// the same seed, and check what its blocks carry (`with_check`).
+#[derive(Clone, Debug, Default)]
+pub struct Chain {
+    blocks: Vec<Block>,
+    difficulty: u32,
+    genesis: Option<Hash>,
+    check: Option<Check>,
+}
+
+impl Chain {
+    // No proof of work
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_difficulty(difficulty: u32) -> Self {
+        Self {
+            difficulty,
+            ..Self::default()
+        }
+    }
+
+    // The genesis block of the seed (mined, the same nonce every
+    // time) and nothing else yet
+    pub fn genesis(seed: &[u8], difficulty: u32) -> Self {
+        let mut block = Block::genesis(seed);
+        block.mine(difficulty);
+        Self {
+            genesis: Some(block.hash()),
+            blocks: vec![block],
+            difficulty,
+            check: None,
+        }
+    }
+
+    pub fn with_check(self, check: Check) -> Self {
+        Self {
+            check: Some(check),
+            ..self
+        }
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    // The hash of the last block, zeros if there is none
+    pub fn tip(&self) -> Hash {
+        self.blocks.last().map_or([0; 32], Block::hash)
+    }
+
+    // Where a block on top of the one with this hash goes: the
+    // height after it, zero for zeros (nothing under it), none if
+    // the chain has no such block
+    pub fn above(&self, prev: &Hash) -> Option<usize> {
+        if prev == &[0; 32] {
+            return Some(0);
+        }
+        self.blocks
+            .iter()
+            .rposition(|block| &block.hash() == prev)
+            .map(|height| height + 1)
+    }
+
+    // A block with `payload` to go on top of the chain (not mined,
+    // nor appended yet)
+    pub fn next(&self, time: u32, payload: Vec<u8>) -> Block {
+        Block {
+            prev: self.tip(),
+            time,
+            payload,
+            nonce: 0,
+        }
+    }
+
+    pub fn append(
+        &mut self,
+        block: Block,
+    ) -> std::result::Result<(), Invalid> {
+        self.check(self.len(), &block)?;
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    // The blocks, one after the other (see `Block::encode`)
+    pub fn encode(&self) -> Vec<u8> {
+        self.blocks.iter().flat_map(Block::encode).collect()
+    }
+
+    // The blocks of `encode`, each one appended (so checked) in turn
+    pub fn decode(
+        bytes: &[u8],
+        difficulty: u32,
+    ) -> Result<Self> {
+        let mut chain = Self::with_difficulty(difficulty);
+        for block in decode(bytes)? {
+            chain.append(block)?;
+        }
+        Ok(chain)
+    }
+
+    // Fork choice: a branch of blocks, one on top of the other, the
+    // first one on top of a block of the chain, takes the place of
+    // the blocks above that one (the ones that differ) if it has
+    // more work done; all the
+    // blocks are of the same difficulty, so the branch with the most
+    // work is the longest one (the chain is kept on a tie, it came
+    // first). The blocks rolled back and the ones put in their
+    // place, for the caller to do the same with what they carry;
+    // none if the chain is kept.
+    pub fn reorg(
+        &mut self,
+        branch: Vec<Block>,
+    ) -> std::result::Result<Option<Reorg>, Invalid> {
+        let Some(first) = branch.first() else {
+            return Ok(None);
+        };
+        let height = self.above(&first.prev).ok_or(Invalid {
+            height: self.len(),
+            reason: Reason::Prev {
+                prev: first.prev,
+                tip: self.tip(),
+            },
+        })?;
+        if height + branch.len() <= self.len() {
+            return Ok(None);
+        }
+        // the blocks the chain has already are not rolled back
+        let same = branch
+            .iter()
+            .zip(&self.blocks[height..])
+            .take_while(|(block, own)| block == own)
+            .count();
+        let (height, branch) = (height + same, &branch[same..]);
+        let mut chain = Self {
+            blocks: self.blocks[..height].to_vec(),
+            ..self.clone()
+        };
+        for block in branch {
+            chain.append(block.clone())?;
+        }
+        let done = chain.blocks[height..].to_vec();
+        let undone = std::mem::replace(self, chain)
+            .blocks
+            .split_off(height);
+        Ok(Some(Reorg {
+            height,
+            undone,
+            done,
+        }))
+    }
+
+    // Every block goes where it is (see `check`): the first one
+    // that does not, if any
+    pub fn validate(&self) -> std::result::Result<(), Invalid> {
+        self.blocks.iter().enumerate().try_for_each(
+            |(height, block)| self.check(height, block),
+        )
+    }
+
+    // The block at `height`: the genesis block of the chain, if it
+    // has one, for the first; otherwise with the hash of the one
+    // before (zeros for the first), not made before it (the same
+    // second will do), with the work done and the payload checked
+    fn check(
+        &self,
+        height: usize,
+        block: &Block,
+    ) -> std::result::Result<(), Invalid> {
+        let invalid = |reason| Invalid { height, reason };
+        if let (Some(genesis), 0) = (self.genesis, height) {
+            return match block.hash() == genesis {
+                true => Ok(()),
+                false => Err(invalid(Reason::Genesis)),
+            };
+        }
+        let work = block.work();
+        if work < self.difficulty {
+            return Err(invalid(Reason::Work {
+                work,
+                difficulty: self.difficulty,
+            }));
+        }
+        let prev =
+            height.checked_sub(1).map(|i| &self.blocks[i]);
+        let tip = prev.map_or([0; 32], Block::hash);
+        if block.prev != tip {
+            return Err(invalid(Reason::Prev {
+                prev: block.prev,
+                tip,
+            }));
+        }
+        if let Some(prev) = prev.filter(|p| block.time < p.time)
+        {
+            return Err(invalid(Reason::Time {
+                time: block.time,
+                prev: prev.time,
+            }));
+        }
+        match self.check {
+            Some(check) => {
+                check(&block.payload).map_err(invalid)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block() -> Result<()> {
+        let block = Block {
+            prev: [1; 32],
+            time: 42,
+            payload: b"payload".to_vec(),
+            nonce: 7,
+        };
+        let mut bytes = block.encode();
+        bytes.extend(b"next");
+        assert_eq!(
+            Block::decode(&bytes)?,
+            (block.clone(), HEADER_LEN + 7)
+        );
+        assert!(Block::decode(&bytes[..HEADER_LEN - 1]).is_err());
+        assert!(Block::decode(&bytes[..HEADER_LEN + 6]).is_err());
+
+        let other = Block {
+            nonce: 8,
+            ..block.clone()
+        };
+        assert_ne!(block.hash(), other.hash());
+        assert_eq!(block.hash(), hash(&block.encode()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mine() -> Result<()> {
+        let mut chain = Chain::with_difficulty(8);
+        for (time, payload) in [(1, "a"), (2, "b")] {
+            let mut block = chain.next(time, payload.into());
+            let tries = block.mine(8);
+            assert!(block.work() >= 8);
+            assert_eq!(tries, block.nonce + 1);
+            chain.append(block)?;
+        }
+
+        // a nonce that does not do
+        let mut block = chain.next(3, b"c".to_vec());
+        while block.work() >= 8 {
+            block.nonce += 1;
+        }
+        let e = chain.append(block.clone()).unwrap_err();
+        assert_eq!(e.height, 2);
+        assert!(matches!(e.reason, Reason::Work { .. }));
+        // nor once the block has changed
+        block.mine(8);
+        block.payload = b"d".to_vec();
+        while block.work() >= 8 {
+            block.payload.push(0);
+        }
+        assert!(chain.append(block).is_err());
+
+        let bytes = chain.encode();
+        let decoded = Chain::decode(&bytes, 8)?;
+        assert_eq!(decoded.blocks(), chain.blocks());
+        let most = chain.blocks().iter().map(Block::work).max();
+        assert!(
+            Chain::decode(&bytes, most.unwrap() + 1).is_err()
+        );
+        assert!(
+            Chain::decode(&bytes[..bytes.len() - 1], 8).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain() -> Result<()> {
+        let mut chain = Chain::new();
+        assert_eq!(chain.tip(), [0; 32]);
+        for (time, payload) in [(1, "a"), (2, "b"), (2, "c")] {
+            let block = chain.next(time, payload.into());
+            chain.append(block)?;
+        }
+        assert_eq!(chain.len(), 3);
+        assert_eq!(
+            chain.blocks()[1].prev,
+            chain.blocks()[0].hash()
+        );
+        assert_eq!(chain.tip(), chain.blocks()[2].hash());
+        chain.validate()?;
+
+        // not on top of the last one, or from before it
+        let stale = Block {
+            prev: chain.blocks()[1].hash(),
+            ..chain.next(3, b"d".to_vec())
+        };
+        assert!(chain.append(stale).is_err());
+        assert!(chain
+            .append(chain.next(1, b"d".to_vec()))
+            .is_err());
+        assert_eq!(chain.len(), 3);
+
+        // a block changed after the fact
+        let mut tampered = chain.clone();
+        tampered.blocks[1].payload = b"x".to_vec();
+        let e = tampered.validate().unwrap_err();
+        assert_eq!(e.height, 2);
+        assert!(matches!(e.reason, Reason::Prev { .. }));
+        assert!(e.to_string().starts_with("block 2: prev"));
+        Ok(())
+    }
+
+    // On top of the chain, each one mined
+    fn mined(chain: &Chain, payloads: &[&str]) -> Vec<Block> {
+        let mut chain = chain.clone();
+        for payload in payloads {
+            let mut block =
+                chain.next(7, payload.as_bytes().to_vec());
+            block.mine(chain.difficulty());
+            chain.append(block).unwrap();
+        }
+        chain.blocks()[chain.len() - payloads.len()..].to_vec()
+    }
+
+    #[test]
+    fn test_reorg() -> Result<()> {
+        let mut chain = Chain::genesis(b"seed", 4);
+        for block in mined(&chain, &["a", "b"]) {
+            chain.append(block)?;
+        }
+        let base = Chain {
+            blocks: chain.blocks()[..2].to_vec(),
+            ..chain.clone()
+        };
+        assert_eq!(
+            chain.above(&chain.blocks()[1].hash()),
+            Some(2)
+        );
+        assert_eq!(chain.above(&[0; 32]), Some(0));
+        assert_eq!(chain.above(&[1; 32]), None);
+
+        // competing branches from above "a": as long, kept
+        let kept = chain.clone();
+        assert_eq!(chain.reorg(mined(&base, &["x"]))?, None);
+        assert_eq!(chain.blocks(), kept.blocks());
+        // longer: the chain goes with it
+        let branch = mined(&base, &["x", "y"]);
+        let reorg = chain.reorg(branch.clone())?.unwrap();
+        assert_eq!(reorg.height, 2);
+        assert_eq!(reorg.undone, kept.blocks()[2..]);
+        assert_eq!(reorg.done, branch);
+        assert_eq!(chain.len(), 4);
+        assert_eq!(chain.blocks()[..2], kept.blocks()[..2]);
+        chain.validate()?;
+        // then back again, a longer one still
+        let back =
+            [&kept.blocks()[2..], &mined(&kept, &["c", "d"])]
+                .concat();
+        let reorg = chain.reorg(back)?.unwrap();
+        assert_eq!(reorg.undone, branch);
+        assert_eq!(chain.len(), 5);
+        // on top of the tip, along with blocks the chain has:
+        // appended
+        let next = mined(&chain, &["e"]);
+        let reorg = chain
+            .reorg(
+                [&chain.blocks()[3..], next.as_slice()].concat(),
+            )?
+            .unwrap();
+        assert_eq!(
+            (reorg.height, reorg.undone, reorg.done),
+            (5, vec![], next)
+        );
+
+        // not on top of any block, not checking out, not on the
+        // genesis block: the chain is kept
+        let tip = chain.tip();
+        let mut other = Chain::genesis(b"other", 4);
+        let stray =
+            mined(&other, &["x", "y", "z", "w", "v", "u"]);
+        let e = chain.reorg(stray[1..].to_vec()).unwrap_err();
+        assert!(matches!(e.reason, Reason::Prev { .. }));
+        let mut forged =
+            mined(&base, &["x", "y", "z", "w", "v"]);
+        forged[2].time = 1;
+        forged[2].mine(4);
+        let e = chain.reorg(forged).unwrap_err();
+        assert_eq!(e.height, 4);
+        other.blocks.extend(stray);
+        let e = chain.reorg(other.blocks).unwrap_err();
+        assert_eq!(
+            e,
+            Invalid {
+                height: 0,
+                reason: Reason::Genesis
+            }
+        );
+        assert_eq!(chain.tip(), tip);
+        Ok(())
+    }
+
+    #[test]
+    fn test_genesis() -> Result<()> {
+        let chain = Chain::genesis(b"seed", 8);
+        assert_eq!(chain.len(), 1);
+        assert_eq!(
+            chain.blocks(),
+            Chain::genesis(b"seed", 8).blocks()
+        );
+        assert_ne!(
+            chain.tip(),
+            Chain::genesis(b"other", 8).tip()
+        );
+        assert!(chain.blocks()[0].work() >= 8);
+        chain.validate()?;
+
+        // even blocks going one after the other are not of the chain
+        // without the genesis block first
+        let mut other = Chain::genesis(b"other", 8);
+        let mut block = other.next(1, b"a".to_vec());
+        block.mine(8);
+        other.append(block)?;
+        let mut forged = chain.clone();
+        forged.blocks = other.blocks.clone();
+        let e = forged.validate().unwrap_err();
+        assert_eq!(e.reason, Reason::Genesis);
+        assert_eq!(e.height, 0);
+
+        // the first invalid block, its payload checked
+        fn check(
+            payload: &[u8],
+        ) -> std::result::Result<(), Reason> {
+            match payload {
+                b"forged" => Err(Reason::Signature(0)),
+                _ => Ok(()),
+            }
+        }
+        let mut chain = chain.with_check(check);
+        for (time, payload) in [(5, "a"), (5, "b")] {
+            let mut block = chain.next(time, payload.into());
+            block.mine(8);
+            chain.append(block)?;
+        }
+        let mut block = chain.next(6, b"forged".to_vec());
+        block.mine(8);
+        let e = chain.append(block.clone()).unwrap_err();
+        assert_eq!(
+            e,
+            Invalid {
+                height: 3,
+                reason: Reason::Signature(0)
+            }
+        );
+
+        let mut late = chain.next(4, b"c".to_vec());
+        late.mine(8);
+        assert_eq!(
+            chain.append(late).unwrap_err().reason,
+            Reason::Time { time: 4, prev: 5 }
+        );
+
+        let mut tampered = chain.clone();
+        tampered.blocks.push(block);
+        tampered.blocks[1].time = 6;
+        tampered.blocks[1].mine(8);
+        // the first one: the block after the one changed (and
+        // mined again), not the forged one
+        let e = tampered.validate().unwrap_err();
+        assert_eq!(e.height, 2);
+        assert!(matches!(e.reason, Reason::Prev { .. }));
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+// ChaCha20-Poly1305 (RFC 8439), hand-rolled as the rest of the
+// crypto here (`seal` takes the crate, for the storage only): the
+// AEAD of the session frames, see `tcp::Tcp`.
+
+pub const TAG_LEN: usize = 16;
+
+// "expand 32-byte k"
+const SIGMA: [u32; 4] =
+    [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn quarter(
+    s: &mut [u32; 16],
+    a: usize,
+    b: usize,
+    c: usize,
+    d: usize,
+) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+// 2.3: a 64-byte block of the key stream
+pub fn block(
+    key: &[u8; 32],
+    counter: u32,
+    nonce: &[u8; 12],
+) -> [u8; 64] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&SIGMA);
+    for i in 0..8 {
+        state[4 + i] = le32(&key[4 * i..]);
+    }
+    state[12] = counter;
+    for i in 0..3 {
+        state[13 + i] = le32(&nonce[4 * i..]);
+    }
+
+    let mut s = state;
+    for _ in 0..10 {
+        quarter(&mut s, 0, 4, 8, 12);
+        quarter(&mut s, 1, 5, 9, 13);
+        quarter(&mut s, 2, 6, 10, 14);
+        quarter(&mut s, 3, 7, 11, 15);
+        quarter(&mut s, 0, 5, 10, 15);
+        quarter(&mut s, 1, 6, 11, 12);
+        quarter(&mut s, 2, 7, 8, 13);
+        quarter(&mut s, 3, 4, 9, 14);
+    }
+    let mut out = [0u8; 64];
+    for i in 0..16 {
+        let word = s[i].wrapping_add(state[i]);
+        out[4 * i..4 * i + 4]
+            .copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+// 2.4: XOR with the key stream, from the block `counter` on
+pub fn chacha20(
+    key: &[u8; 32],
+    counter: u32,
+    nonce: &[u8; 12],
+    data: &mut [u8],
+) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = block(key, counter + i as u32, nonce);
+        for (b, k) in chunk.iter_mut().zip(stream) {
+            *b ^= k;
+        }
+    }
+}
+
+// 2.5: the one-time authenticator, mod 2^130 - 5 in five 26-bit
+// limbs (so that the products fit `u64`)
+pub fn poly1305(key: &[u8; 32], msg: &[u8]) -> [u8; TAG_LEN] {
+    const MASK: u64 = (1 << 26) - 1;
+    // r is clamped along the way
+    let r0 = (le32(&key[0..]) & 0x3ffffff) as u64;
+    let r1 = ((le32(&key[3..]) >> 2) & 0x3ffff03) as u64;
+    let r2 = ((le32(&key[6..]) >> 4) & 0x3ffc0ff) as u64;
+    let r3 = ((le32(&key[9..]) >> 6) & 0x3f03fff) as u64;
+    let r4 = ((le32(&key[12..]) >> 8) & 0x00fffff) as u64;
+    let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+    let mut h = [0u64; 5];
+    for chunk in msg.chunks(16) {
+        // the block with a one byte on top
+        let mut m = [0u8; 17];
+        m[..chunk.len()].copy_from_slice(chunk);
+        m[chunk.len()] = 1;
+        h[0] += (le32(&m[0..]) & 0x3ffffff) as u64;
+        h[1] += ((le32(&m[3..]) >> 2) & 0x3ffffff) as u64;
+        h[2] += ((le32(&m[6..]) >> 4) & 0x3ffffff) as u64;
+        h[3] += ((le32(&m[9..]) >> 6) & 0x3ffffff) as u64;
+        h[4] +=
+            (le32(&m[12..]) >> 8) as u64 | (m[16] as u64) << 24;
+
+        let d = [
+            h[0] * r0
+                + h[1] * s4
+                + h[2] * s3
+                + h[3] * s2
+                + h[4] * s1,
+            h[0] * r1
+                + h[1] * r0
+                + h[2] * s4
+                + h[3] * s3
+                + h[4] * s2,
+            h[0] * r2
+                + h[1] * r1
+                + h[2] * r0
+                + h[3] * s4
+                + h[4] * s3,
+            h[0] * r3
+                + h[1] * r2
+                + h[2] * r1
+                + h[3] * r0
+                + h[4] * s4,
+            h[0] * r4
+                + h[1] * r3
+                + h[2] * r2
+                + h[3] * r1
+                + h[4] * r0,
+        ];
+        let mut c = 0;
+        for i in 0..5 {
+            let x = d[i] + c;
+            h[i] = x & MASK;
+            c = x >> 26;
+        }
+        // 2^130 = 5 (mod p)
+        h[0] += c * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= MASK;
+    }
+
+    // fully carried, then minus p if that is at least p
+    for i in 1..4 {
+        h[i + 1] += h[i] >> 26;
+        h[i] &= MASK;
+    }
+    h[0] += (h[4] >> 26) * 5;
+    h[4] &= MASK;
+    h[1] += h[0] >> 26;
+    h[0] &= MASK;
+
+    let mut g = [0u64; 5];
+    let mut c = 5;
+    for i in 0..5 {
+        let x = h[i] + c;
+        g[i] = x & MASK;
+        c = x >> 26;
+    }
+    // h + 5 carries out of 2^130: h >= p, take g = h - p
+    let h = if c > 0 { g } else { h };
+
+    // mod 2^128, plus s
+    let acc =
+        h.iter().enumerate().fold(0u128, |acc, (i, &limb)| {
+            acc.wrapping_add((limb as u128) << (26 * i))
+        });
+    let s = u128::from_le_bytes(key[16..].try_into().unwrap());
+    acc.wrapping_add(s).to_le_bytes()
+}
+
+// 2.8: the ciphertext followed by the tag
+pub fn seal(
+    key: &[u8; 32],
+    nonce: &[u8; 12],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let mut sealed = plaintext.to_vec();
+    chacha20(key, 1, nonce, &mut sealed);
+    let tag = tag(key, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+// none if the tag does not match (anything was changed)
+pub fn open(
+    key: &[u8; 32],
+    nonce: &[u8; 12],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    let len = sealed.len().checked_sub(TAG_LEN)?;
+    let (ciphertext, received) = sealed.split_at(len);
+    let expected = tag(key, nonce, aad, ciphertext);
+    // compared in full, not up to the first difference
+    let diff = expected
+        .iter()
+        .zip(received)
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    if diff != 0 {
+        return None;
+    }
+    let mut plaintext = ciphertext.to_vec();
+    chacha20(key, 1, nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+fn tag(
+    key: &[u8; 32],
+    nonce: &[u8; 12],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> [u8; TAG_LEN] {
+    let otk: [u8; 32] =
+        block(key, 0, nonce)[..32].try_into().unwrap();
+    let pad = |len: usize| vec![0u8; (16 - len % 16) % 16];
+    let mut data =
+        Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+    data.extend_from_slice(aad);
+    data.extend(pad(aad.len()));
+    data.extend_from_slice(ciphertext);
+    data.extend(pad(ciphertext.len()));
+    data.extend((aad.len() as u64).to_le_bytes());
+    data.extend((ciphertext.len() as u64).to_le_bytes());
+    poly1305(&otk, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{from_hex, to_hex};
+
+    fn key() -> [u8; 32] {
+        std::array::from_fn(|i| i as u8)
+    }
+
+    #[test]
+    fn test_block() {
+        // RFC 8439, 2.3.2
+        let nonce =
+            from_hex("000000090000004a00000000").unwrap();
+        let out = block(&key(), 1, &nonce.try_into().unwrap());
+        assert_eq!(
+            to_hex(&out),
+            "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4ed2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+        );
+    }
+
+    #[test]
+    fn test_poly1305() {
+        // RFC 8439, 2.5.2
+        let key = from_hex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b").unwrap();
+        let tag = poly1305(
+            &key.try_into().unwrap(),
+            b"Cryptographic Forum Research Group",
+        );
+        assert_eq!(
+            to_hex(&tag),
+            "a8061dc1305136c6c22b8baf0c0127a9"
+        );
+    }
+
+    #[test]
+    fn test_seal() {
+        let nonce = [7u8; 12];
+        let sealed = seal(&key(), &nonce, b"aad", b"plaintext");
+        assert_eq!(sealed.len(), 9 + TAG_LEN);
+        assert_eq!(
+            open(&key(), &nonce, b"aad", &sealed).unwrap(),
+            b"plaintext"
+        );
+        // any change: the ciphertext, the tag, the data, the nonce
+        for i in 0..sealed.len() {
+            let mut off = sealed.clone();
+            off[i] ^= 1;
+            assert!(open(&key(), &nonce, b"aad", &off).is_none());
+        }
+        assert!(open(&key(), &nonce, b"aaD", &sealed).is_none());
+        assert!(
+            open(&key(), &[8; 12], b"aad", &sealed).is_none()
+        );
+        assert!(open(&key(), &nonce, b"aad", &[0; 15]).is_none());
+        assert_eq!(
+            open(
+                &key(),
+                &nonce,
+                &[],
+                &seal(&key(), &nonce, &[], &[])
+            )
+            .unwrap(),
+            b""
+        );
+    }
+
+    // the same as the crate's, for lengths around the block sizes
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn test_crate() {
+        use chacha20poly1305::{
+            aead::{Aead, KeyInit, Payload},
+            ChaCha20Poly1305,
+        };
+        let cipher = ChaCha20Poly1305::new(&key().into());
+        let nonce = [3u8; 12];
+        for len in [0, 1, 15, 16, 17, 63, 64, 65, 200] {
+            let msg =
+                (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let aad = &msg[..len % 20];
+            let expected = cipher
+                .encrypt(
+                    &nonce.into(),
+                    Payload { msg: &msg, aad },
+                )
+                .unwrap();
+            assert_eq!(
+                seal(&key(), &nonce, aad, &msg),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,2070 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt,
+    net::{SocketAddr, TcpStream},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info_span, warn};
+
+use crate::{
+    api::{
+        Error, Frame, Receiver, Result, Sender, MAX_BATCH_SIZE,
+        MAX_PAYLOAD_LEN, TAG_AUDIT, TAG_BATCH, TAG_CLOSE,
+        TAG_DELETE, TAG_GOSSIP, TAG_JOIN, TAG_LEAVE, TAG_LEDGER,
+        TAG_LIST, TAG_OK, TAG_PING, TAG_PONG, TAG_PUBLIC_KEY,
+        TAG_SECRET_SHARE, TAG_SIGN_COMMIT, TAG_SIGN_SHARE,
+        TAG_SNAPSHOT, TAG_STATUS,
+    },
+    dhke::{self, Auth, Group},
+    ec::{
+        self, curve, Encoding, PublicKey, SecretKey, Signature,
+    },
+    frost::{self, Commitment, SIGNING},
+    gossip::{self, State},
+    ledger::Head,
+    mux::Mux,
+    nonce::next_idx,
+    pool::Pool,
+    retry::Retry,
+    shamir,
+    tcp::{Rekey, Tcp},
+    util::{
+        crc32, pack, pack64, random, time, to_hex, unpack64,
+    },
+    vss::{self, VERIFIABLE},
+    xor::{self, BYTES},
+};
+
+#[cfg(feature = "noise")]
+use crate::noise::Noise;
+#[cfg(feature = "quic")]
+use crate::quic::QuicClient;
+#[cfg(feature = "tls")]
+use crate::tls::Tls;
+
+const MAX_IDLE: usize = 4; // pooled sessions per server
+const SNAPSHOT_ATTEMPTS: usize = 3;
+
+// How to reach the servers, see the `client` binary for where each
+// of these comes from there
+#[derive(Clone)]
+pub struct Config {
+    pub timeout: Duration, // of a response, and of the handshake
+    pub connect_timeout: Duration, // of a connection to a server
+    pub retry: Retry,      // on transient errors
+    pub namespace: u32,    // the secret is kept in
+    pub exchange: Vec<Group>, // offered in the handshake
+    pub server_keys: Vec<PublicKey>, // to authenticate it with
+    // each server's own key (see `known_hosts`), pinned: it must
+    // sign the handshake with it, a server not there is refused
+    pub known_hosts: HashMap<SocketAddr, PublicKey>,
+    pub psk: Option<Vec<u8>>, // pre-shared key, no key exchange then
+    pub rekey: Option<Rekey>, // of raw TCP sessions
+    pub quorum: Quorum,       // of the replicas of each share
+    #[cfg(feature = "tls")]
+    pub tls: Option<std::sync::Arc<rustls::ClientConfig>>,
+    #[cfg(feature = "quic")]
+    pub quic: bool, // QUIC instead of TCP, needs `tls`
+    // own static private key, trusted static keys of the servers
+    #[cfg(feature = "noise")]
+    pub noise: Option<(Vec<u8>, Vec<Vec<u8>>)>,
+    // the servers' addresses are names on this in-memory network
+    // instead, for tests (a session per call, see `Client::call`)
+    #[cfg(feature = "testkit")]
+    pub network: Option<crate::testkit::Network>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(2),
+            connect_timeout: Duration::from_secs(2),
+            retry: Retry::default(),
+            namespace: 0,
+            exchange: Group::ALL.to_vec(),
+            server_keys: vec![],
+            known_hosts: HashMap::new(),
+            psk: None,
+            rekey: None,
+            quorum: Quorum::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "quic")]
+            quic: false,
+            #[cfg(feature = "noise")]
+            noise: None,
+            #[cfg(feature = "testkit")]
+            network: None,
+        }
+    }
+}
+
+// Servers keeping each share (`n`, consecutive ones in `peers`, see
+// `Client::groups`), how many of them must take it for a write to
+// count (`w`) and answer with it for a read (`r`): with w + r > n, a
+// read hears from at least one that took the latest write
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Quorum {
+    pub n: usize,
+    pub w: usize,
+    pub r: usize,
+}
+
+impl Default for Quorum {
+    fn default() -> Self {
+        Self::majority(1)
+    }
+}
+
+impl Quorum {
+    // A majority of the replicas for a write, the fewest a read can
+    // do with then
+    pub fn majority(n: usize) -> Self {
+        let w = n / 2 + 1;
+        Self { n, w, r: n + 1 - w }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        let Quorum { n, w, r } = *self;
+        if w == 0 || r == 0 || w > n || r > n || w + r <= n {
+            return Err(Error::App(format!(
+                "invalid quorum: n={n} w={w} r={r}, w + r must be over n"
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Which replicas of a share (by its index) acknowledged the last
+// write (took the share) or read (answered with it), and which
+// failed to; the ones a read did not need to ask are in neither
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Acks {
+    pub share: usize,
+    pub acked: Vec<SocketAddr>,
+    pub failed: Vec<SocketAddr>,
+}
+
+// The secret of the owner of `key`, a share of it per server in
+// `peers` (per `Quorum::n` of them). Sessions are kept
+// (`pool::Pool`) and reused across calls, per client, so clients
+// with different configs never share one.
+pub struct Client {
+    pub key: SecretKey,
+    pub peers: Vec<SocketAddr>,
+    pub config: Config,
+    tcp: Pool<Tcp>,
+    #[cfg(feature = "tls")]
+    tls: Pool<Tls>,
+    #[cfg(feature = "noise")]
+    noise: Pool<Noise>,
+    #[cfg(feature = "quic")]
+    quic: Pool<QuicClient>,
+    outcomes: Mutex<Vec<Outcome>>, // see `outcomes`
+    acks: Mutex<Vec<Acks>>,        // see `acks`
+}
+
+// How a request to a server went
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    pub peer: SocketAddr,
+    pub tag: u32,              // of the request
+    pub status: Option<u32>,   // tag of the response, if any
+    pub code: u32,             // `ext` of an error response
+    pub elapsed: Duration,     // retries included
+    pub error: Option<String>, // no response: why
+}
+
+// Either kind of secret `get` can return
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Secret {
+    Word(u32),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::Word(word) => write!(f, "{word:0x}"),
+            Secret::Bytes(bytes) => {
+                write!(f, "{}", to_hex(bytes))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Scheme {
+    Xor,
+    Shamir(usize),  // threshold
+    Feldman(usize), // threshold
+    Frost(usize),   // threshold
+}
+
+// Shares of different epochs (a server missed a refresh, or the
+// refresh the read triggered got to some of the servers first) do
+// not make the secret, they are fetched again, of the oldest epoch
+// among them, up to this many times
+const EPOCH_RETRIES: usize = 3;
+
+impl Client {
+    pub fn new(
+        key: SecretKey,
+        peers: Vec<SocketAddr>,
+        config: Config,
+    ) -> Self {
+        Self {
+            key,
+            peers,
+            config,
+            tcp: Pool::new(MAX_IDLE),
+            #[cfg(feature = "tls")]
+            tls: Pool::new(MAX_IDLE),
+            #[cfg(feature = "noise")]
+            noise: Pool::new(MAX_IDLE),
+            #[cfg(feature = "quic")]
+            quic: Pool::new(MAX_IDLE),
+            outcomes: Mutex::default(),
+            acks: Mutex::default(),
+        }
+    }
+
+    // Of the last `get_secret` or `set_secret` (any of the sets), a
+    // share each
+    pub fn acks(&self) -> Vec<Acks> {
+        std::mem::take(&mut self.acks.lock().unwrap())
+    }
+
+    // The replicas of each share: `peers` in consecutive groups of
+    // `Quorum::n`, in the order given
+    pub fn groups(&self) -> Result<Vec<&[SocketAddr]>> {
+        let quorum = self.config.quorum;
+        quorum.check()?;
+        if self.peers.is_empty()
+            || !self.peers.len().is_multiple_of(quorum.n)
+        {
+            return Err(Error::App(format!(
+                "{} servers are not groups of {} replicas",
+                self.peers.len(),
+                quorum.n
+            )));
+        }
+        Ok(self.peers.chunks(quorum.n).collect())
+    }
+
+    // Of the requests made since the last time, in order: what each
+    // server made of the last operation, e.g. for `--json` of the
+    // `client` binary
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        std::mem::take(&mut self.outcomes.lock().unwrap())
+    }
+
+    // Retried on transient errors, see `Config::retry`, until the
+    // frame is sent: the server may have applied it then, and takes
+    // the same frame again for a replay (see `pool::Pool::with`)
+    fn client(
+        &self,
+        addr: &SocketAddr,
+        frame: &Frame,
+    ) -> Result<Frame> {
+        let _span = info_span!("peer", %addr).entered();
+        let now = Instant::now();
+        let sent = Cell::new(false);
+        let response = self
+            .config
+            .retry
+            .call(|| match self.call(addr, frame, &sent) {
+                Err(e) if sent.get() => Ok(Err(e)),
+                response => response.map(Ok),
+            })
+            .and_then(|response| response);
+        let outcome = Outcome {
+            peer: *addr,
+            tag: frame.tag,
+            status: response.as_ref().ok().map(|r| r.tag),
+            code: match &response {
+                Ok(r)
+                    if r.tag != TAG_OK && r.tag != TAG_PONG =>
+                {
+                    r.ext
+                }
+                _ => 0,
+            },
+            elapsed: now.elapsed(),
+            error: response
+                .as_ref()
+                .err()
+                .map(|e| format!("{e:?}")),
+        };
+        self.outcomes.lock().unwrap().push(outcome);
+        response
+    }
+
+    // `sent` is set once the frame went out
+    fn call(
+        &self,
+        addr: &SocketAddr,
+        frame: &Frame,
+        sent: &Cell<bool>,
+    ) -> Result<Frame> {
+        let mut frame = frame.clone();
+        frame.sum = frame.checksum();
+        let addr = *addr;
+        #[cfg(feature = "testkit")]
+        if let Some(net) = &self.config.network {
+            let mut tx =
+                net.connect("client", &addr.to_string())?;
+            let keys = self.handshake(&tx, &addr)?;
+            tx.set_keys(keys.seal, keys.open);
+            self.send(&tx, &frame)
+                .inspect(|()| sent.set(true))?;
+            return self.recv(&tx);
+        }
+        #[cfg(feature = "quic")]
+        if let Some(tls) = self.quic_config() {
+            let connect = || QuicClient::connect(addr, tls);
+            let frame = self.quic.with(
+                addr,
+                connect,
+                |tx| {
+                    debug!(?frame, "send");
+                    tx.send(&frame).inspect(|_| sent.set(true))
+                },
+                |tx, stream| tx.recv(stream),
+            )?;
+            debug!(?frame, "recv");
+            return checked(frame);
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.config.tls.clone() {
+            let connect = || {
+                let socket = self.socket(&addr)?;
+                Tls::client(socket, tls, addr.ip())
+            };
+            return self.tls.with(
+                addr,
+                connect,
+                |tx| {
+                    self.send(tx, &frame)
+                        .inspect(|()| sent.set(true))
+                },
+                |tx, _| self.recv(tx),
+            );
+        }
+        #[cfg(feature = "noise")]
+        if let Some((key, trusted)) = &self.config.noise {
+            let connect = || {
+                let socket = self.socket(&addr)?;
+                Noise::initiator(socket, key, trusted)
+            };
+            return self.noise.with(
+                addr,
+                connect,
+                |tx| {
+                    self.send(tx, &frame)
+                        .inspect(|()| sent.set(true))
+                },
+                |tx, _| self.recv(tx),
+            );
+        }
+        self.tcp.with(
+            addr,
+            || self.connect(&addr),
+            |tx| {
+                self.send(tx, &frame)
+                    .inspect(|()| sent.set(true))
+            },
+            |tx, _| self.recv(tx),
+        )
+    }
+
+    #[cfg(feature = "quic")]
+    fn quic_config(
+        &self,
+    ) -> Option<std::sync::Arc<rustls::ClientConfig>> {
+        self.config
+            .quic
+            .then(|| self.config.tls.clone())
+            .flatten()
+    }
+
+    fn socket(&self, addr: &SocketAddr) -> Result<TcpStream> {
+        let timeout = self.config.connect_timeout;
+        Ok(TcpStream::connect_timeout(addr, timeout)?)
+    }
+
+    // The keys the server at `addr` may sign the handshake with:
+    // its pinned one, any of `Config::server_keys` if none are
+    // pinned (not authenticated if none either)
+    fn trusted(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<&[PublicKey]> {
+        let Config {
+            known_hosts,
+            server_keys,
+            ..
+        } = &self.config;
+        if known_hosts.is_empty() {
+            return Ok(server_keys);
+        }
+        known_hosts
+            .get(addr)
+            .map(std::slice::from_ref)
+            .ok_or_else(|| {
+                Error::App(format!("unknown host: {addr}"))
+            })
+    }
+
+    // New session: connected and DHKE handshake done
+    fn connect(&self, addr: &SocketAddr) -> Result<Tcp> {
+        let socket = self.socket(addr)?;
+        let mut tx = Tcp::from(socket);
+        let keys = self.handshake(&tx, addr)?;
+        tx.set_keys(keys.seal, keys.open);
+        tx.set_rekey(self.config.rekey);
+        Ok(tx)
+    }
+
+    // The session keys with the server at `addr`
+    fn handshake<T: Sender<u32> + Receiver<u32>>(
+        &self,
+        tx: &T,
+        addr: &SocketAddr,
+    ) -> Result<dhke::Keys> {
+        let trusted = self.trusted(addr)?;
+        let Config {
+            timeout,
+            exchange,
+            psk,
+            ..
+        } = &self.config;
+        let keys = if let Some(psk) = psk {
+            dhke::psk(tx, *timeout, psk)?
+        } else if trusted.is_empty() {
+            dhke::handshake(tx, *timeout, exchange)?.keys()
+        } else {
+            // the servers do not know the client (its frames are
+            // signed anyway), it signs with a one-off key
+            let key = SecretKey::generate();
+            let auth = Auth {
+                key: &key,
+                trusted,
+                required: true,
+            };
+            dhke::authenticated(tx, *timeout, exchange, &auth)?
+                .0
+                .keys()
+        };
+        Ok(keys)
+    }
+
+    fn send<T: Sender<Frame>>(
+        &self,
+        tx: &T,
+        frame: &Frame,
+    ) -> Result<()> {
+        tx.send(frame)?;
+        debug!(?frame, "send");
+        Ok(())
+    }
+
+    fn recv<T: Receiver<Frame>>(&self, tx: &T) -> Result<Frame> {
+        let frame: Frame =
+            tx.recv_timeout(self.config.timeout)?;
+        debug!(?frame, "recv");
+        checked(frame)
+    }
+
+    // Send all the frames on a single connection (after a TAG_BATCH
+    // header) without waiting for responses, responses are matched
+    // back to requests by `idx`.
+    fn batch(
+        &self,
+        addr: &SocketAddr,
+        frames: &[Frame],
+    ) -> Result<Vec<Frame>> {
+        // a stream per frame, no TAG_BATCH needed
+        #[cfg(feature = "quic")]
+        if let Some(tls) = self.quic_config() {
+            let tx = QuicClient::connect(*addr, tls)?;
+            let frames = frames
+                .iter()
+                .cloned()
+                .map(|mut frame| {
+                    frame.sum = frame.checksum();
+                    frame
+                })
+                .collect::<Vec<_>>();
+            return tx
+                .call_all(&frames)?
+                .into_iter()
+                .map(checked)
+                .collect();
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.config.tls.clone() {
+            let socket = self.socket(addr)?;
+            let tx = Tls::client(socket, tls, addr.ip())?;
+            return self.pipeline(tx, frames);
+        }
+        #[cfg(feature = "noise")]
+        if let Some((key, trusted)) = &self.config.noise {
+            let socket = self.socket(addr)?;
+            let tx = Noise::initiator(socket, key, trusted)?;
+            return self.pipeline(tx, frames);
+        }
+        self.pipeline(self.connect(addr)?, frames)
+    }
+
+    fn pipeline<T>(
+        &self,
+        tx: T,
+        frames: &[Frame],
+    ) -> Result<Vec<Frame>>
+    where
+        T: Sender<Frame>
+            + Receiver<Frame>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let mut header = Frame {
+            idx: time(),
+            tag: TAG_BATCH,
+            msg: frames.len() as u32,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        header.sum = header.checksum();
+        tx.send(&header)?;
+
+        let mux = Mux::new(tx);
+        let mut receipts = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let mut frame = frame.clone();
+            frame.sum = frame.checksum();
+            receipts.push(mux.send(&frame)?);
+            debug!(?frame, "send");
+        }
+
+        let mut responses = Vec::with_capacity(frames.len());
+        for receipt in receipts {
+            let frame = receipt.wait(self.config.timeout)?;
+            debug!(?frame, "recv");
+            if frame.sum != frame.checksum() {
+                return Err(Error::App(
+                    "invalid checksum".to_string(),
+                ));
+            }
+            responses.push(frame);
+        }
+
+        let mut close = Frame {
+            idx: time(),
+            tag: TAG_CLOSE,
+            msg: 0,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        close.sum = close.checksum();
+        mux.call(&close, self.config.timeout)?;
+        Ok(responses)
+    }
+
+    // The stored secret is identified by the fingerprint of the
+    // owner's public key, within the namespace. The key itself is
+    // not carried in the payload: the server recovers it from the
+    // signature.
+    fn signed(&self, tag: u32, msg: u32) -> Frame {
+        let key = self.key.public_key().fingerprint();
+        let ns = self.config.namespace;
+        let mut frame = Frame {
+            idx: next_idx((tag, msg, key, ns)),
+            tag,
+            msg,
+            key,
+            sig: 0,
+            ext: 0,
+            ns,
+            sum: 0,
+            data: vec![],
+        };
+        frame.sign(&self.key);
+        frame
+    }
+
+    pub fn get_secret(&self) -> Result<Secret> {
+        let mut epoch = None;
+        for retry in 0..=EPOCH_RETRIES {
+            match self.fetch(epoch)? {
+                Ok(secret) => return Ok(secret),
+                Err(oldest) => epoch = Some(oldest),
+            }
+            debug!(retry, ?epoch, "shares of different epochs");
+        }
+        Err(Error::App("shares of different epochs".to_string()))
+    }
+
+    // The shares of the `epoch` if there is one (the latest ones
+    // otherwise), the oldest epoch among them if they do not match
+    fn fetch(
+        &self,
+        epoch: Option<u32>,
+    ) -> Result<std::result::Result<Secret, u32>> {
+        let peers = &self.peers;
+        let mut frame = self.signed(TAG_PUBLIC_KEY, 0);
+        if let Some(epoch) = epoch {
+            frame.ext = epoch + 1;
+            frame.sign(&self.key);
+        }
+        let key = frame.key;
+        debug!(
+            ?peers,
+            ?epoch,
+            key = %format_args!("{key:0x}"),
+            "get secret"
+        );
+
+        let mut secret: u32 = 0;
+        let mut blobs = Vec::with_capacity(peers.len());
+        let mut points = Vec::with_capacity(peers.len());
+        let mut threshold = 0;
+        // of the first valid Feldman share, the rest must match
+        let mut published: Option<Vec<u64>> = None;
+        // of the first share, same
+        let mut expected: Option<u32> = None;
+        let mut signing = false;
+        let mut oldest = u32::MAX;
+        let mut stale = false;
+
+        let mut errors = Vec::with_capacity(peers.len());
+        for share in self.read(&frame)? {
+            let (addr, response) = match share {
+                Ok(share) => share,
+                Err(message) => {
+                    errors.push(message);
+                    continue;
+                }
+            };
+            // the share's epoch, then what was stored along with it
+            let Some((e, data)) =
+                response.data.split_at_checked(4)
+            else {
+                errors.push(format!(
+                    "error: peer={addr} no epoch"
+                ));
+                continue;
+            };
+            let e = u32::from_be_bytes(e.try_into().unwrap());
+            oldest = oldest.min(e);
+            let expected = *expected.get_or_insert(e);
+            if e != expected {
+                let message = format!(
+                    "error: peer={addr} epoch={e} expected={expected}"
+                );
+                errors.push(message);
+                stale = true;
+                continue;
+            }
+            // a share of a byte secret, see `set_bytes`
+            if response.ext & BYTES != 0 {
+                blobs.push(data.to_vec());
+                continue;
+            }
+            // non-zero `ext`: a Shamir share, see `set_secret`
+            if response.ext != 0 {
+                let x = response.ext & 0xFFFF;
+                if response.ext & VERIFIABLE != 0 {
+                    let commitments = pack64(data);
+                    if !vss::verify(
+                        (x, response.msg),
+                        &commitments,
+                    ) {
+                        let message = format!(
+                            "error: peer={addr} invalid share"
+                        );
+                        errors.push(message);
+                        continue;
+                    }
+                    if *published
+                        .get_or_insert(commitments.clone())
+                        != commitments
+                    {
+                        let message = format!(
+                            "error: peer={addr} inconsistent share"
+                        );
+                        errors.push(message);
+                        continue;
+                    }
+                }
+                signing = response.ext & SIGNING != 0;
+                threshold =
+                    ((response.ext >> 16) & 0x1FFF) as usize;
+                points.push((x, response.msg));
+            }
+            secret ^= response.msg;
+        }
+
+        // any `threshold` of the servers will do
+        if threshold > 0 && points.len() >= threshold {
+            if !errors.is_empty() {
+                warn!(
+                    errors = errors.join("; "),
+                    "some servers failed"
+                );
+            }
+            let points = &points[..threshold];
+            return Ok(Ok(Secret::Word(
+                if published.is_some() {
+                    vss::merge(points)
+                } else if signing {
+                    frost::merge(points)
+                } else {
+                    shamir::merge(points)
+                },
+            )));
+        }
+
+        if stale {
+            debug!(errors = errors.join("; "), "stale shares");
+            return Ok(Err(oldest));
+        }
+        if !errors.is_empty() {
+            return Err(Error::App(errors.join("; ")));
+        }
+
+        if !blobs.is_empty() {
+            return Ok(Ok(Secret::Bytes(xor::merge_bytes(
+                &blobs,
+            ))));
+        }
+        Ok(Ok(Secret::Word(secret)))
+    }
+
+    // The response to `frame` for each share (see `groups`): of the
+    // first `Quorum::r` of its replicas to answer with it, the one
+    // of the latest write (highest epoch, see the server's REPLICAS),
+    // or why fewer of them did
+    fn read(
+        &self,
+        frame: &Frame,
+    ) -> Result<
+        Vec<std::result::Result<(SocketAddr, Frame), String>>,
+    > {
+        let r = self.config.quorum.r;
+        let groups = self.groups()?;
+        let mut shares = Vec::with_capacity(groups.len());
+        let mut acks = Vec::with_capacity(groups.len());
+        for (share, group) in groups.into_iter().enumerate() {
+            let mut answered = Vec::with_capacity(r);
+            let mut failed = vec![];
+            let mut errors = vec![];
+            for addr in group {
+                if answered.len() == r {
+                    break;
+                }
+                let message = match self.client(addr, frame) {
+                    Ok(response) if response.tag == TAG_OK => {
+                        answered.push((*addr, response));
+                        continue;
+                    }
+                    Ok(response) => format!(
+                        "error: peer={addr} tag={} ext={}",
+                        response.tag, response.ext
+                    ),
+                    Err(e) => {
+                        format!("error: peer={addr} err={e:?}")
+                    }
+                };
+                errors.push(message);
+                failed.push(*addr);
+            }
+            acks.push(Acks {
+                share,
+                acked: answered
+                    .iter()
+                    .map(|(a, _)| *a)
+                    .collect(),
+                failed,
+            });
+            if answered.len() < r {
+                shares.push(Err(errors.join("; ")));
+                continue;
+            }
+            if !errors.is_empty() {
+                warn!(
+                    share,
+                    errors = errors.join("; "),
+                    "some replicas failed"
+                );
+            }
+            let latest = answered
+                .into_iter()
+                .max_by_key(|(_, response)| {
+                    response.data.get(..4).map_or(0, |e| {
+                        u32::from_be_bytes(e.try_into().unwrap())
+                    })
+                })
+                .unwrap();
+            shares.push(Ok(latest));
+        }
+        *self.acks.lock().unwrap() = acks;
+        Ok(shares)
+    }
+
+    // A share per group of replicas (see `groups`). Fails unless
+    // enough shares were taken (by `Quorum::w` of their replicas
+    // each) to get the secret back (all of them for XOR shares,
+    // `threshold` of them otherwise), returns the servers that did
+    // not take theirs: they still have whatever share they had, the
+    // secret has to be set again for them to take part
+    pub fn set_secret(
+        &self,
+        secret: u32,
+        scheme: Scheme,
+        ttl: u32,
+    ) -> Result<Vec<SocketAddr>> {
+        let peers = &self.peers;
+        debug!(?peers, ?scheme, ttl, "set secret");
+        let n = self.groups()?.len();
+
+        // `ext` of a Shamir share is the threshold (high 16 bits)
+        // and the share's x (low 16 bits), zero for XOR shares; a
+        // Feldman share has the `VERIFIABLE` bit set and the
+        // commitments follow the TTL in the payload, a share of a
+        // signing key has the `SIGNING` bit set and the group key
+        // follows instead
+        let mut attachment = vec![];
+        let shares: Vec<(u32, u32)> = match scheme {
+            Scheme::Shamir(k) => {
+                let shares = shamir::split(secret, k, n, random);
+                assert_eq!(shamir::merge(&shares[..k]), secret);
+                shares
+                    .into_iter()
+                    .map(|(x, y)| (y, (k as u32) << 16 | x))
+                    .collect::<Vec<_>>()
+            }
+            Scheme::Feldman(k) => {
+                if secret as u64 >= vss::Q {
+                    return Err(Error::App(format!(
+                        "secret must be less than {:0x}",
+                        vss::Q
+                    )));
+                }
+                let (shares, published) =
+                    vss::split(secret, k, n, random);
+                assert_eq!(vss::merge(&shares[..k]), secret);
+                attachment = unpack64(&published);
+                shares
+                    .into_iter()
+                    .map(|(x, y)| {
+                        (y, VERIFIABLE | (k as u32) << 16 | x)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Scheme::Frost(k) => {
+                if !(1..curve::N)
+                    .contains(&(secret as curve::Int))
+                {
+                    return Err(Error::App(format!(
+                        "signing key must be in [1, {:0x})",
+                        curve::N
+                    )));
+                }
+                let (shares, group_key) =
+                    frost::split(secret, k, n, random);
+                assert_eq!(frost::merge(&shares[..k]), secret);
+                attachment = group_key.to_bytes();
+                shares
+                    .into_iter()
+                    .map(|(x, y)| {
+                        (y, SIGNING | (k as u32) << 16 | x)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Scheme::Xor => {
+                let shares = xor::split(secret, n, random);
+                assert_eq!(xor::merge(&shares), secret); // better safe than sorry!
+                shares.into_iter().map(|y| (y, 0)).collect()
+            }
+        };
+        let shares = shares
+            .into_iter()
+            .map(|(msg, ext)| (msg, ext, attachment.clone()))
+            .collect();
+        let needed = match scheme {
+            Scheme::Xor => n,
+            Scheme::Shamir(k)
+            | Scheme::Feldman(k)
+            | Scheme::Frost(k) => k,
+        };
+        self.store(shares, needed, ttl)
+    }
+
+    // XOR shares of a byte secret, each share as long as the
+    // secret: `ext` has the `BYTES` bit set, and the share follows
+    // the TTL in the payload. Every share must be taken.
+    pub fn set_bytes(
+        &self,
+        secret: &[u8],
+        ttl: u32,
+    ) -> Result<Vec<SocketAddr>> {
+        let peers = &self.peers;
+        debug!(?peers, len = secret.len(), ttl, "set bytes");
+        let n = self.groups()?.len();
+        if secret.len() + 4 > MAX_PAYLOAD_LEN {
+            return Err(Error::App(format!(
+                "secret is too long: {} bytes",
+                secret.len()
+            )));
+        }
+
+        let shares = xor::split_bytes(secret, n, random);
+        assert_eq!(xor::merge_bytes(&shares), secret);
+        let shares = shares
+            .into_iter()
+            .map(|share| (0, BYTES, share))
+            .collect();
+        self.store(shares, n, ttl)
+    }
+
+    // A share per group of replicas: `msg`, `ext` and the bytes to
+    // follow the TTL (seconds, zero: until deleted) in the payload,
+    // the same frame to each replica. At least `needed` of the
+    // shares must be taken by `Quorum::w` of their replicas, the
+    // servers that did not take theirs are returned.
+    fn store(
+        &self,
+        shares: Vec<(u32, u32, Vec<u8>)>,
+        needed: usize,
+        ttl: u32,
+    ) -> Result<Vec<SocketAddr>> {
+        let groups = self.groups()?;
+        let w = self.config.quorum.w;
+        // the time of the write, the same for all the shares: the
+        // epoch they are kept with by servers with REPLICAS set
+        let idx = next_idx((
+            TAG_SECRET_SHARE,
+            0,
+            self.key.public_key().fingerprint(),
+            self.config.namespace,
+        ));
+        let mut errors = Vec::with_capacity(self.peers.len());
+        let mut missing = Vec::with_capacity(self.peers.len());
+        let mut acks = Vec::with_capacity(groups.len());
+        let mut stored = 0;
+        for (share, (group, (msg, ext, data))) in
+            groups.into_iter().zip(shares).enumerate()
+        {
+            let mut frame = self.signed(TAG_SECRET_SHARE, msg);
+            frame.idx = idx;
+            frame.ext = ext;
+            frame.data.extend(ttl.to_be_bytes());
+            frame.data.extend(data);
+            frame.sign(&self.key);
+            let mut acked = Vec::with_capacity(group.len());
+            let mut failed = vec![];
+            for addr in group {
+                let message = match self.client(addr, &frame) {
+                    Ok(response) if response.tag == TAG_OK => {
+                        acked.push(*addr);
+                        continue;
+                    }
+                    Ok(response) => format!(
+                        "error: peer={addr} tag={} ext={}",
+                        response.tag, response.ext
+                    ),
+                    Err(e) => {
+                        format!("error: peer={addr} err={e:?}")
+                    }
+                };
+                errors.push(message);
+                failed.push(*addr);
+            }
+            if acked.len() >= w {
+                stored += 1;
+            }
+            missing.extend(&failed);
+            acks.push(Acks {
+                share,
+                acked,
+                failed,
+            });
+        }
+        *self.acks.lock().unwrap() = acks;
+
+        if stored < needed {
+            return Err(Error::App(errors.join("; ")));
+        }
+        if !errors.is_empty() {
+            warn!(
+                errors = errors.join("; "),
+                "some servers have no share"
+            );
+        }
+        Ok(missing)
+    }
+
+    // Either kind of secret, `scheme` is for a word only (bytes
+    // are XOR-shared)
+    pub fn set(
+        &self,
+        secret: &Secret,
+        scheme: Scheme,
+        ttl: u32,
+    ) -> Result<Vec<SocketAddr>> {
+        match secret {
+            Secret::Word(word) => {
+                self.set_secret(*word, scheme, ttl)
+            }
+            Secret::Bytes(bytes) => self.set_bytes(bytes, ttl),
+        }
+    }
+
+    // Replace the secret with `new`, or with a random one of the
+    // same kind (as long, for bytes) that the scheme can take: the
+    // current one must be there to begin with, and is stored again
+    // (fresh shares) if not every server took its share of the new
+    // one, so that either all of them have the new one or all of
+    // them the old one (unless that fails too). Returns the new one.
+    pub fn rotate(
+        &self,
+        new: Option<Secret>,
+        scheme: Scheme,
+        ttl: u32,
+    ) -> Result<Secret> {
+        let old = self.get_secret()?;
+        let new = new.unwrap_or_else(|| match &old {
+            Secret::Word(_) => Secret::Word(fresh(scheme)),
+            Secret::Bytes(bytes) => Secret::Bytes(
+                (0..bytes.len())
+                    .map(|_| random() as u8)
+                    .collect(),
+            ),
+        });
+        debug!(peers = ?self.peers, ?scheme, "rotate secret");
+
+        let e = match self.set(&new, scheme, ttl) {
+            Ok(missing) if missing.is_empty() => return Ok(new),
+            Ok(missing) => {
+                Error::App(format!("no share on {missing:?}"))
+            }
+            Err(e) => e,
+        };
+        warn!(?e, "rotate failed, restoring the secret");
+        if let Err(restore) = self.set(&old, scheme, ttl) {
+            return Err(Error::App(format!(
+                "rotate: {e:?}; restore: {restore:?}"
+            )));
+        }
+        Err(Error::App(format!("rotate: {e:?} (restored)")))
+    }
+
+    // Threshold signature of `msg` with the signing key shared by
+    // the servers (see `frost`): each one commits to fresh nonces,
+    // then signs with its share given the commitments of all of
+    // them; each share of the signature is checked, so that a server
+    // getting it wrong is named, before the shares are put together
+    pub fn sign(
+        &self,
+        msg: u32,
+    ) -> Result<(PublicKey, Signature)> {
+        let peers = &self.peers;
+        let frame = self.signed(TAG_SIGN_COMMIT, 0);
+        let key = frame.key;
+        debug!(?peers, key = %format_args!("{key:0x}"), msg, "sign");
+
+        // the commitment, the public counterpart of the share and
+        // the group key, see `commit` of the server
+        let parse = |data: &[u8]| -> Result<_> {
+            if data.len() != 36 {
+                return Err(Error::App(format!(
+                    "invalid commitment: {} bytes",
+                    data.len()
+                )));
+            }
+            let commitment = Commitment::parse(&data[..20])?[0];
+            let key_share =
+                PublicKey::from_bytes(&data[20..28])?;
+            let group_key = PublicKey::from_bytes(&data[28..])?
+                .with_scheme(ec::Scheme::Schnorr);
+            Ok((commitment, key_share, group_key))
+        };
+        let mut signers = Vec::with_capacity(peers.len());
+        let mut group_key = None;
+        let mut errors = Vec::with_capacity(peers.len());
+        for addr in peers {
+            let response = match self.client(addr, &frame) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    let message =
+                        format!("error: peer={addr} err={e:?}");
+                    errors.push(message);
+                    continue;
+                }
+            };
+            if response.tag != TAG_OK {
+                let message = format!(
+                    "error: peer={addr} tag={} ext={}",
+                    response.tag, response.ext
+                );
+                errors.push(message);
+                continue;
+            }
+            let (commitment, key_share, key) =
+                match parse(&response.data) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        let message = format!(
+                            "error: peer={addr} err={e:?}"
+                        );
+                        errors.push(message);
+                        continue;
+                    }
+                };
+            if *group_key.get_or_insert(key.clone()) != key {
+                let message = format!(
+                    "error: peer={addr} other group key"
+                );
+                errors.push(message);
+                continue;
+            }
+            signers.push((addr, commitment, key_share));
+        }
+        // whoever did not commit does not sign, the servers tell if
+        // there are too few of those who did
+        let Some(group_key) = group_key else {
+            return Err(Error::App(errors.join("; ")));
+        };
+        if !errors.is_empty() {
+            warn!(
+                errors = errors.join("; "),
+                "some servers failed"
+            );
+            errors.clear();
+        }
+
+        signers.sort_by_key(|(_, commitment, _)| commitment.x);
+        // a signer per share, the first of its replicas to commit
+        signers.dedup_by_key(|(_, commitment, _)| commitment.x);
+        let commitments = signers
+            .iter()
+            .map(|(_, commitment, _)| *commitment)
+            .collect::<Vec<_>>();
+        let mut frame = self.signed(TAG_SIGN_SHARE, msg);
+        frame.data.extend(
+            commitments.iter().flat_map(Commitment::to_bytes),
+        );
+        frame.sign(&self.key);
+
+        let mut shares = Vec::with_capacity(signers.len());
+        for (addr, commitment, key_share) in &signers {
+            let response = match self.client(addr, &frame) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    let message =
+                        format!("error: peer={addr} err={e:?}");
+                    errors.push(message);
+                    continue;
+                }
+            };
+            if response.tag != TAG_OK {
+                let message = format!(
+                    "error: peer={addr} tag={} ext={}",
+                    response.tag, response.ext
+                );
+                errors.push(message);
+                continue;
+            }
+            if !frost::verify(
+                commitment.x,
+                response.msg,
+                key_share,
+                msg,
+                &commitments,
+                &group_key,
+            ) {
+                errors.push(format!(
+                    "error: peer={addr} invalid share"
+                ));
+                continue;
+            }
+            shares.push(response.msg);
+        }
+        if !errors.is_empty() {
+            return Err(Error::App(errors.join("; ")));
+        }
+
+        let sig = frost::aggregate(
+            msg,
+            &commitments,
+            &shares,
+            &group_key,
+        );
+        if !group_key.is_valid(&msg, &sig) {
+            return Err(Error::App(
+                "invalid signature".to_string(),
+            ));
+        }
+        Ok((group_key, sig))
+    }
+
+    pub fn delete_secret(&self) -> Result<()> {
+        let peers = &self.peers;
+        let frame = self.signed(TAG_DELETE, 0);
+        let key = frame.key;
+        debug!(?peers, key = %format_args!("{key:0x}"), "delete secret");
+        let mut errors = Vec::with_capacity(peers.len());
+        for addr in peers {
+            let response = match self.client(addr, &frame) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    let message =
+                        format!("error: peer={addr} err={e:?}");
+                    errors.push(message);
+                    continue;
+                }
+            };
+
+            if response.tag != TAG_OK {
+                let message = format!(
+                    "error: peer={addr} tag={} ext={}",
+                    response.tag, response.ext
+                );
+                errors.push(message);
+                continue;
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::App(errors.join("; ")));
+        }
+
+        Ok(())
+    }
+
+    // First page tells the page size and the total number of keys,
+    // then all the remaining pages are requested in batches.
+    pub fn list_keys(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<Vec<u32>> {
+        let check = |response: &Frame| {
+            if response.tag != TAG_OK {
+                return Err(Error::App(format!(
+                    "error: peer={addr} tag={} ext={}",
+                    response.tag, response.ext
+                )));
+            }
+            Ok(())
+        };
+
+        let response =
+            self.client(addr, &self.signed(TAG_LIST, 0))?;
+        check(&response)?;
+        let mut keys = pack(&response.data);
+        let total = response.ext as usize;
+        let page = keys.len();
+        if page == 0 {
+            return Ok(keys);
+        }
+
+        let frames = (page..total)
+            .step_by(page)
+            .map(|offset| self.signed(TAG_LIST, offset as u32))
+            .collect::<Vec<_>>();
+        for chunk in frames.chunks(MAX_BATCH_SIZE) {
+            for response in self
+                .config
+                .retry
+                .call(|| self.batch(addr, chunk))?
+            {
+                check(&response)?;
+                keys.extend(pack(&response.data));
+            }
+        }
+        Ok(keys)
+    }
+
+    // `name=value` lines, the server's ADMIN_KEY must be the
+    // fingerprint of the key's public key (as shown by `list`)
+    pub fn status(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<Vec<String>> {
+        let response =
+            self.client(addr, &self.signed(TAG_STATUS, 0))?;
+        if response.tag != TAG_OK {
+            return Err(Error::App(format!(
+                "error: peer={addr} tag={} ext={}",
+                response.tag, response.ext
+            )));
+        }
+        let text = String::from_utf8_lossy(&response.data);
+        Ok(text.lines().map(str::to_string).collect())
+    }
+
+    // The latest entries of the audit log (as many as the server
+    // sends at once), of a single key if there is one; needs
+    // ADMIN_KEY, same as `status`
+    pub fn audit(
+        &self,
+        addr: &SocketAddr,
+        key: Option<u32>,
+    ) -> Result<Vec<String>> {
+        let mut frame = self.signed(TAG_AUDIT, u32::MAX);
+        frame.ext = key.unwrap_or_default();
+        frame.sign(&self.key);
+        let response = self.client(addr, &frame)?;
+        if response.tag != TAG_OK {
+            return Err(Error::App(format!(
+                "error: peer={addr} tag={} ext={}",
+                response.tag, response.ext
+            )));
+        }
+        let text = String::from_utf8_lossy(&response.data);
+        Ok(text.lines().map(str::to_string).collect())
+    }
+
+    // The head of the server's ledger, signed by the server (see
+    // `ledger::Head::verify`), for anyone to keep
+    pub fn ledger(&self, addr: &SocketAddr) -> Result<Head> {
+        let response =
+            self.client(addr, &self.signed(TAG_LEDGER, 0))?;
+        if response.tag != TAG_OK {
+            return Err(Error::App(format!(
+                "error: peer={addr} tag={} ext={}",
+                response.tag, response.ext
+            )));
+        }
+        Head::decode(&response.data)
+    }
+
+    // The snapshot page by page, all the pages of the same one (same
+    // crc32), starting over if it changed on the way; needs
+    // ADMIN_KEY, same as `status`
+    pub fn snapshot(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<Vec<u8>> {
+        'snapshot: for _ in 0..SNAPSHOT_ATTEMPTS {
+            let mut buf = vec![];
+            let mut sum = None;
+            loop {
+                let offset = buf.len() as u32;
+                let response = self.client(
+                    addr,
+                    &self.signed(TAG_SNAPSHOT, offset),
+                )?;
+                if response.tag != TAG_OK {
+                    return Err(Error::App(format!(
+                        "error: peer={addr} tag={} ext={}",
+                        response.tag, response.ext
+                    )));
+                }
+                if *sum.get_or_insert(response.ext)
+                    != response.ext
+                {
+                    continue 'snapshot;
+                }
+                buf.extend(&response.data);
+                if buf.len() >= response.msg as usize
+                    || response.data.is_empty()
+                {
+                    break;
+                }
+            }
+            if Some(crc32(&buf)) == sum {
+                return Ok(buf);
+            }
+        }
+        Err(Error::App(format!(
+            "error: peer={addr} snapshot keeps changing"
+        )))
+    }
+
+    // Round-trip time of the PING/PONG exchange (handshake included)
+    pub fn ping(&self, addr: &SocketAddr) -> Result<Duration> {
+        let nonce = random();
+        let frame = Frame {
+            idx: time(),
+            tag: TAG_PING,
+            msg: nonce,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        let now = Instant::now();
+        let response = self.client(addr, &frame)?;
+        let rtt = now.elapsed();
+        if response.tag != TAG_PONG || response.msg != nonce {
+            return Err(Error::App(format!(
+                "error: peer={addr} tag={} msg={:0x}",
+                response.tag, response.msg
+            )));
+        }
+        Ok(rtt)
+    }
+
+    // The cluster as the server at `addr` sees it (see `gossip`):
+    // the server itself first, then the members it does not take
+    // for dead
+    pub fn members(
+        &self,
+        addr: &SocketAddr,
+    ) -> Result<Vec<(SocketAddr, State)>> {
+        let frame = Frame {
+            idx: time(),
+            tag: TAG_GOSSIP,
+            msg: 0,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        };
+        let response = self.client(addr, &frame)?;
+        if response.tag != TAG_OK {
+            return Err(Error::App(format!(
+                "error: peer={addr} tag={} ext={}",
+                response.tag, response.ext
+            )));
+        }
+        Ok(gossip::decode(&response.data)?
+            .into_iter()
+            .map(|(addr, _, state)| (addr, state))
+            .collect())
+    }
+
+    // The live members of the cluster (by address), as the first of
+    // `peers` to answer sees them: any server will do to bootstrap
+    pub fn discover(&self) -> Result<Vec<SocketAddr>> {
+        let mut errors = Vec::with_capacity(self.peers.len());
+        for addr in &self.peers {
+            match self.members(addr) {
+                Ok(members) => {
+                    let mut live = members
+                        .into_iter()
+                        .filter(|(_, state)| {
+                            *state == State::Alive
+                        })
+                        .map(|(addr, _)| addr)
+                        .collect::<Vec<_>>();
+                    live.sort();
+                    return Ok(live);
+                }
+                Err(e) => errors.push(format!(
+                    "error: peer={addr} err={e:?}"
+                )),
+            }
+        }
+        Err(Error::App(errors.join("; ")))
+    }
+
+    // The server at `addr` takes `new` as a peer, and hands its XOR
+    // shares over to it (and to its other peers) if `hand_over`:
+    // one server is to do that, for each secret to be shared by
+    // all of them at once; needs ADMIN_KEY, same as `status`
+    pub fn join(
+        &self,
+        addr: &SocketAddr,
+        new: SocketAddr,
+        hand_over: bool,
+    ) -> Result<Vec<String>> {
+        self.membership(addr, TAG_JOIN, new, hand_over as u32)
+    }
+
+    // The server at `addr` drops `leaving` from its peers, or, if it
+    // is the one leaving, hands its XOR shares over to them
+    pub fn leave(
+        &self,
+        addr: &SocketAddr,
+        leaving: SocketAddr,
+    ) -> Result<Vec<String>> {
+        self.membership(addr, TAG_LEAVE, leaving, 0)
+    }
+
+    // `name=value` lines, as for `status`: the server's peers, and
+    // how many keys were handed over, skipped (threshold shares)
+    // and failed to be, failing if any did
+    fn membership(
+        &self,
+        addr: &SocketAddr,
+        tag: u32,
+        peer: SocketAddr,
+        msg: u32,
+    ) -> Result<Vec<String>> {
+        let mut frame = self.signed(tag, msg);
+        frame.data = peer.to_string().into_bytes();
+        frame.sign(&self.key);
+        let response = self.client(addr, &frame)?;
+        if response.tag != TAG_OK {
+            return Err(Error::App(format!(
+                "error: peer={addr} tag={} ext={}",
+                response.tag, response.ext
+            )));
+        }
+        let text = String::from_utf8_lossy(&response.data);
+        let lines =
+            text.lines().map(str::to_string).collect::<Vec<_>>();
+        if response.msg > 0 {
+            return Err(Error::App(format!(
+                "error: peer={addr} {}",
+                lines.join(" ")
+            )));
+        }
+        Ok(lines)
+    }
+}
+
+// `<address> <public key>` lines (hex, as logged by the server on
+// startup), `#` for comments, see `Config::known_hosts`
+pub fn known_hosts(
+    text: &str,
+) -> Result<HashMap<SocketAddr, PublicKey>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| {
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(n, line)| {
+            let invalid = || {
+                Error::App(format!(
+                    "line {n}: <address> <public key> expected"
+                ))
+            };
+            let (addr, key) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(invalid)?;
+            let addr = addr.parse().map_err(|_| invalid())?;
+            let key = PublicKey::from_hex(key.trim())
+                .map_err(|_| invalid())?;
+            Ok((addr, key))
+        })
+        .collect()
+}
+
+// A random secret the scheme takes, see `set_secret`
+fn fresh(scheme: Scheme) -> u32 {
+    loop {
+        let secret = random();
+        let valid = match scheme {
+            Scheme::Feldman(_) => (secret as u64) < vss::Q,
+            Scheme::Frost(_) => {
+                (1..curve::N).contains(&(secret as curve::Int))
+            }
+            Scheme::Xor | Scheme::Shamir(_) => true,
+        };
+        if valid {
+            return secret;
+        }
+    }
+}
+
+fn checked(frame: Frame) -> Result<Frame> {
+    if frame.sum != frame.checksum() {
+        return Err(Error::App("invalid checksum".to_string()));
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+    use crate::api::TAG_BAD_REQUEST;
+
+    const SERVER_KEY: u32 = 0xAAAAAAAA;
+
+    // Keeps the last share it was sent (epoch zero), one connection
+    // at a time; rejects the `fail`-th share (from one on, none if
+    // zero)
+    fn server(fail: usize) -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        thread::spawn(move || {
+            let mut share = None;
+            let mut stored = 0;
+            for socket in listener.incoming().flatten() {
+                let tx = Tcp::from(socket);
+                let _ = serve(tx, &mut share, &mut stored, fail);
+            }
+        });
+        Ok(addr)
+    }
+
+    fn serve(
+        mut tx: Tcp,
+        share: &mut Option<(u32, u32, Vec<u8>)>,
+        stored: &mut usize,
+        fail: usize,
+    ) -> Result<()> {
+        let timeout = Duration::from_secs(1);
+        // authenticated if the client asks
+        let auth = Auth {
+            key: &SecretKey::new(SERVER_KEY),
+            trusted: &[],
+            required: false,
+        };
+        let keys = dhke::authenticated(
+            &tx,
+            timeout,
+            &Group::ALL,
+            &auth,
+        )?
+        .0
+        .keys();
+        tx.set_keys(keys.seal, keys.open);
+        while let Some(frame) = Receiver::<Frame>::recv(&tx)? {
+            let mut response = Frame {
+                idx: frame.idx,
+                tag: TAG_OK,
+                ..Frame::default()
+            };
+            match frame.tag {
+                TAG_SECRET_SHARE if *stored + 1 == fail => {
+                    *stored += 1;
+                    response.tag = TAG_BAD_REQUEST;
+                }
+                TAG_SECRET_SHARE => {
+                    *stored += 1;
+                    let data = frame.data[4..].to_vec();
+                    *share = Some((frame.msg, frame.ext, data));
+                }
+                TAG_PUBLIC_KEY if share.is_some() => {
+                    let (msg, ext, data) =
+                        share.clone().unwrap();
+                    response.msg = msg;
+                    response.ext = ext;
+                    response.data = 0u32.to_be_bytes().to_vec();
+                    response.data.extend(data);
+                }
+                TAG_DELETE => *share = None,
+                // a cluster of itself (as 127.0.0.1:1) and of
+                // 127.0.0.1:2
+                TAG_GOSSIP => {
+                    let members = gossip::Members::new(
+                        ([127, 0, 0, 1], 1).into(),
+                        Duration::MAX,
+                        Duration::MAX,
+                    );
+                    members.add(([127, 0, 0, 1], 2).into());
+                    response.data = members.encode();
+                }
+                // the address as a peer, a key that failed to be
+                // handed over if asked to
+                TAG_JOIN | TAG_LEAVE => {
+                    let addr =
+                        String::from_utf8_lossy(&frame.data);
+                    response.msg = frame.msg;
+                    response.data =
+                        format!("peer={addr}").into();
+                }
+                _ => response.tag = TAG_BAD_REQUEST,
+            }
+            response.sum = response.checksum();
+            tx.send(&response)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_client() -> Result<()> {
+        let peers =
+            (0..3).map(|_| server(0)).collect::<Result<_>>()?;
+        let client = Client::new(
+            SecretKey::new(42),
+            peers,
+            Config::default(),
+        );
+
+        client.set_secret(0xCAFEBABE, Scheme::Xor, 0)?;
+        let outcomes = client.outcomes();
+        assert_eq!(outcomes.len(), 3);
+        assert!(outcomes
+            .iter()
+            .all(|o| o.tag == TAG_SECRET_SHARE
+                && o.status == Some(TAG_OK)
+                && o.error.is_none()));
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+        client.set_secret(0xC0FFEE, Scheme::Shamir(2), 0)?;
+        assert_eq!(client.get_secret()?, Secret::Word(0xC0FFEE));
+        client.set_bytes(b"secret", 0)?;
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Bytes(b"secret".to_vec())
+        );
+
+        client.delete_secret()?;
+        client.outcomes();
+        assert!(client.get_secret().is_err());
+        let outcomes = client.outcomes();
+        assert!(outcomes
+            .iter()
+            .all(|o| o.tag == TAG_PUBLIC_KEY
+                && o.status == Some(TAG_BAD_REQUEST)));
+
+        // nobody there
+        let addr =
+            TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let config = Config {
+            retry: Retry::NONE,
+            ..Config::default()
+        };
+        let client =
+            Client::new(SecretKey::new(42), vec![addr], config);
+        assert!(client.get_secret().is_err());
+        let outcomes = client.outcomes();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].status, None);
+        assert!(outcomes[0].error.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sent_once() -> Result<()> {
+        // a server whose responses are all lost: it takes the
+        // request, and the connection is gone
+        let net = crate::testkit::network();
+        let addr: SocketAddr = ([10, 0, 0, 1], 1).into();
+        let listener = net.listen(&addr.to_string());
+        let server = thread::spawn(move || -> Result<usize> {
+            let timeout = Duration::from_secs(1);
+            let mut requests = 0;
+            while let Ok(mut tx) = listener.accept(timeout) {
+                let keys =
+                    dhke::handshake(&tx, timeout, &Group::ALL)?
+                        .keys();
+                tx.set_keys(keys.seal, keys.open);
+                let _: Frame = tx.recv_timeout(timeout)?;
+                requests += 1;
+            }
+            Ok(requests)
+        });
+
+        let config = Config {
+            timeout: Duration::from_millis(100),
+            retry: Retry {
+                retries: 2,
+                backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                jitter: 0.0,
+            },
+            network: Some(net.clone()),
+            ..Config::default()
+        };
+        let client =
+            Client::new(SecretKey::new(42), vec![addr], config);
+        assert!(client.set_secret(42, Scheme::Xor, 0).is_err());
+        assert_eq!(server.join().unwrap()?, 1);
+
+        // no handshake: not sent, retried
+        let listener = net.listen(&addr.to_string());
+        assert!(client.ping(&addr).is_err());
+        let mut connects = 0;
+        while listener.accept(Duration::ZERO).is_ok() {
+            connects += 1;
+        }
+        assert_eq!(connects, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial() -> Result<()> {
+        // nobody there
+        let down =
+            TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let peers = vec![server(0)?, down, server(0)?];
+        let config = Config {
+            retry: Retry::NONE,
+            ..Config::default()
+        };
+        let client =
+            Client::new(SecretKey::new(42), peers, config);
+
+        let missing = client.set_secret(
+            0xCAFEBABE,
+            Scheme::Shamir(2),
+            0,
+        )?;
+        assert_eq!(missing, vec![down]);
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+        let missing = client.set_secret(
+            0xC0FFEE,
+            Scheme::Feldman(2),
+            0,
+        )?;
+        assert_eq!(missing, vec![down]);
+        assert_eq!(client.get_secret()?, Secret::Word(0xC0FFEE));
+
+        // not enough of them
+        let e = client
+            .set_secret(0xCAFEBABE, Scheme::Shamir(3), 0)
+            .unwrap_err();
+        assert!(
+            matches!(e, Error::App(e) if e.contains(&down.to_string()))
+        );
+        assert!(client.set_secret(1, Scheme::Xor, 0).is_err());
+        assert!(client.set_bytes(b"secret", 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_quorum() -> Result<()> {
+        assert_eq!(
+            Quorum::default(),
+            Quorum { n: 1, w: 1, r: 1 }
+        );
+        assert_eq!(
+            Quorum::majority(3),
+            Quorum { n: 3, w: 2, r: 2 }
+        );
+        assert_eq!(
+            Quorum::majority(4),
+            Quorum { n: 4, w: 3, r: 2 }
+        );
+        assert!(Quorum { n: 3, w: 1, r: 2 }.check().is_err());
+        assert!(Quorum { n: 3, w: 4, r: 1 }.check().is_err());
+        assert!(Quorum { n: 3, w: 3, r: 0 }.check().is_err());
+        assert!(Quorum { n: 3, w: 3, r: 1 }.check().is_ok());
+
+        // nobody there
+        let down =
+            TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        // rejects the share
+        let bad = server(1)?;
+        let (a, b, c, d) =
+            (server(0)?, server(0)?, server(0)?, server(0)?);
+        let peers = vec![down, a, b, bad, c, d];
+        let config = Config {
+            retry: Retry::NONE,
+            quorum: Quorum::majority(3),
+            ..Config::default()
+        };
+        let client =
+            Client::new(SecretKey::new(42), peers, config);
+        assert_eq!(client.groups()?.len(), 2);
+
+        let missing =
+            client.set_secret(0xCAFEBABE, Scheme::Xor, 0)?;
+        assert_eq!(missing, vec![down, bad]);
+        let acks = vec![
+            Acks {
+                share: 0,
+                acked: vec![a, b],
+                failed: vec![down],
+            },
+            Acks {
+                share: 1,
+                acked: vec![c, d],
+                failed: vec![bad],
+            },
+        ];
+        assert_eq!(client.acks(), acks);
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+        assert_eq!(client.acks(), acks);
+        client.set_bytes(b"secret", 0)?;
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Bytes(b"secret".to_vec())
+        );
+
+        // all the replicas of each share needed
+        let all = Config {
+            retry: Retry::NONE,
+            quorum: Quorum { n: 3, w: 3, r: 1 },
+            ..Config::default()
+        };
+        // the servers serve one session at a time
+        drop(client);
+        let peers = vec![down, a, b, bad, c, d];
+        let client = Client::new(SecretKey::new(42), peers, all);
+        assert!(client.set_secret(1, Scheme::Xor, 0).is_err());
+        assert_eq!(client.acks()[0].acked, vec![a, b]);
+
+        // not a whole number of groups
+        let config = Config {
+            quorum: Quorum::majority(4),
+            ..Config::default()
+        };
+        let client =
+            Client::new(SecretKey::new(42), vec![a, b], config);
+        assert!(client.groups().is_err());
+        assert!(client.get_secret().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover() -> Result<()> {
+        // nobody there
+        let down =
+            TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let config = Config {
+            retry: Retry::NONE,
+            ..Config::default()
+        };
+        let client = Client::new(
+            SecretKey::new(42),
+            vec![down, server(0)?],
+            config,
+        );
+        let members = client.members(&client.peers[1])?;
+        assert_eq!(members.len(), 2);
+        assert!(members.iter().all(|(_, s)| *s == State::Alive));
+        assert_eq!(
+            client.discover()?,
+            vec![
+                ([127, 0, 0, 1], 1).into(),
+                ([127, 0, 0, 1], 2).into()
+            ]
+        );
+        assert!(client.members(&down).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_membership() -> Result<()> {
+        let addr = server(0)?;
+        let client = Client::new(
+            SecretKey::new(42),
+            vec![addr],
+            Config::default(),
+        );
+        let new = ([127, 0, 0, 1], 10003).into();
+        assert_eq!(
+            client.join(&addr, new, false)?,
+            vec!["peer=127.0.0.1:10003".to_string()]
+        );
+        assert!(client.join(&addr, new, true).is_err());
+        assert_eq!(client.leave(&addr, new)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_hosts() -> Result<()> {
+        let key = SecretKey::new(SERVER_KEY).public_key();
+        let other = SecretKey::new(42).public_key();
+        let text = format!(
+            "# pinned\n127.0.0.1:10001 {}\n\n  [::1]:10002\t{} \n",
+            key.to_hex(),
+            other.to_hex()
+        );
+        let pinned = known_hosts(&text)?;
+        assert_eq!(pinned.len(), 2);
+        assert_eq!(
+            pinned[&"127.0.0.1:10001".parse().unwrap()],
+            key
+        );
+        assert_eq!(
+            pinned[&"[::1]:10002".parse().unwrap()],
+            other
+        );
+        assert!(known_hosts("127.0.0.1:10001").is_err());
+        assert!(known_hosts("localhost:10001 00").is_err());
+
+        let addr = server(0)?;
+        let client = |pinned: Vec<(SocketAddr, PublicKey)>| {
+            let config = Config {
+                retry: Retry::NONE,
+                known_hosts: pinned.into_iter().collect(),
+                ..Config::default()
+            };
+            Client::new(SecretKey::new(42), vec![addr], config)
+        };
+        let trusted = || client(vec![(addr, key.clone())]);
+        trusted().set_secret(0xCAFEBABE, Scheme::Xor, 0)?;
+
+        // an imposter: no share is sent to it
+        let imposter = client(vec![(addr, other.clone())]);
+        let e =
+            imposter.set_secret(1, Scheme::Xor, 0).unwrap_err();
+        assert!(
+            matches!(e, Error::App(e) if e.contains("untrusted"))
+        );
+        // not pinned
+        let unknown = client(vec![(
+            "127.0.0.1:1".parse().unwrap(),
+            other,
+        )]);
+        let e = unknown.get_secret().unwrap_err();
+        assert!(
+            matches!(e, Error::App(e) if e.contains("unknown host"))
+        );
+        assert_eq!(
+            trusted().get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate() -> Result<()> {
+        let peers = vec![server(0)?, server(0)?, server(2)?];
+        let client = Client::new(
+            SecretKey::new(42),
+            peers,
+            Config::default(),
+        );
+        assert!(client.rotate(None, Scheme::Xor, 0).is_err());
+
+        client.set_secret(0xCAFEBABE, Scheme::Xor, 0)?;
+        // the second share fails on one server: the old secret
+        // is back
+        let e = client.rotate(None, Scheme::Xor, 0).unwrap_err();
+        assert!(
+            matches!(e, Error::App(e) if e.contains("restored"))
+        );
+        assert_eq!(
+            client.get_secret()?,
+            Secret::Word(0xCAFEBABE)
+        );
+
+        let new = client.rotate(None, Scheme::Shamir(2), 0)?;
+        assert_ne!(new, Secret::Word(0xCAFEBABE));
+        assert_eq!(client.get_secret()?, new);
+        let new = Secret::Word(0xC0FFEE);
+        client.rotate(
+            Some(new.clone()),
+            Scheme::Feldman(2),
+            0,
+        )?;
+        assert_eq!(client.get_secret()?, new);
+
+        client.set_bytes(b"secret", 0)?;
+        let Secret::Bytes(new) =
+            client.rotate(None, Scheme::Xor, 0)?
+        else {
+            panic!("bytes expected");
+        };
+        assert_eq!(new.len(), 6);
+        assert_eq!(client.get_secret()?, Secret::Bytes(new));
+        Ok(())
+    }
+}
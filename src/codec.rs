@@ -0,0 +1,174 @@
+use crate::api::{Error, Frame, Result};
+
+// Wire encoding of a frame (without the length prefix).
+pub trait Codec: Send + Sync {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>>;
+    fn decode(&self, bytes: &[u8]) -> Result<Frame>;
+
+    // Plain-text frames separated by `\n` (not length-prefixed)
+    fn is_line_delimited(&self) -> bool {
+        false
+    }
+}
+
+// Big-endian u32 words, see `Frame::encode`.
+pub struct Raw;
+
+impl Codec for Raw {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>> {
+        let bytes = frame
+            .encode()
+            .into_iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect();
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Frame> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(Error::App(format!(
+                "invalid frame length: {} bytes",
+                bytes.len()
+            )));
+        }
+        let words = bytes
+            .chunks_exact(4)
+            .map(|w| {
+                u32::from_be_bytes([w[0], w[1], w[2], w[3]])
+            })
+            .collect::<Vec<_>>();
+        Frame::decode(&words)
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>> {
+        bincode::serialize(frame)
+            .map_err(|e| Error::App(format!("bincode: {e}")))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Frame> {
+        bincode::deserialize(bytes)
+            .map_err(|e| Error::App(format!("bincode: {e}")))
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>> {
+        postcard::to_allocvec(frame)
+            .map_err(|e| Error::App(format!("postcard: {e}")))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Frame> {
+        postcard::from_bytes(bytes)
+            .map_err(|e| Error::App(format!("postcard: {e}")))
+    }
+}
+
+// Newline-delimited JSON, for debugging (e.g. with `nc`).
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode(&self, frame: &Frame) -> Result<Vec<u8>> {
+        serde_json::to_vec(frame)
+            .map_err(|e| Error::App(format!("json: {e}")))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Frame> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| Error::App(format!("json: {e}")))
+    }
+
+    fn is_line_delimited(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::arbitrary;
+
+    fn frame() -> Frame {
+        Frame {
+            idx: 0x01020304,
+            tag: 0x05060708,
+            msg: 0x090A0B0C,
+            key: 0xCAFEBABE,
+            sig: 0x0102030405060708,
+            ext: 0x090A0B0C,
+            ns: 0x11121314,
+            sum: 0x0D0E0F00,
+            data: b"payload".to_vec(),
+        }
+    }
+
+    fn round_trip(codec: &dyn Codec) {
+        let frame = frame();
+        let bytes = codec.encode(&frame).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_raw() {
+        round_trip(&Raw);
+        assert!(Raw.decode(&[0u8; 35]).is_err());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode() {
+        round_trip(&Bincode);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard() {
+        round_trip(&Postcard);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        round_trip(&Json);
+        let line = Json.encode(&frame()).unwrap();
+        assert!(!line.contains(&b'\n'));
+    }
+
+    // every codec built in
+    fn codecs() -> Vec<Box<dyn Codec>> {
+        let codecs: Vec<Box<dyn Codec>> = vec![
+            Box::new(Raw),
+            #[cfg(feature = "bincode")]
+            Box::new(Bincode),
+            #[cfg(feature = "postcard")]
+            Box::new(Postcard),
+            #[cfg(feature = "json")]
+            Box::new(Json),
+        ];
+        codecs
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_frame(frame in arbitrary::frame()) {
+            for codec in codecs() {
+                let bytes = codec.encode(&frame).unwrap();
+                let decoded = codec.decode(&bytes).unwrap();
+                prop_assert_eq!(decoded, frame.clone());
+            }
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use std::time::Duration;
+use std::{str::FromStr, time::Duration};
 
-use crate::api::{Receiver, Result, Sender};
+use rand::{rngs::OsRng, RngCore};
+
+use crate::{
+    api::{Error, Receiver, Result, Sender},
+    ec::{PublicKey, SecretKey, Signature},
+    sha256::{hmac_sha256, sha256},
+    util::{merge, split, Os, Rng},
+    x25519::{x25519, BASEPOINT},
+};
 
 pub type Int = u64;
 
@@ -43,12 +51,471 @@ pub fn dhke_handshake<T: Sender<u32> + Receiver<u32>>(
     Ok(secret as u32)
 }
 
+// Key exchanges a side can offer in its hello (see `handshake`)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Group {
+    Mersenne, // `dhke_handshake`, brute-forced in no time
+    X25519,
+}
+
+impl Group {
+    // by preference
+    pub const ALL: [Group; 2] = [Group::X25519, Group::Mersenne];
+
+    fn bit(self) -> u32 {
+        match self {
+            Group::Mersenne => 1,
+            Group::X25519 => 2,
+        }
+    }
+}
+
+impl FromStr for Group {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dhke" => Ok(Group::Mersenne),
+            "x25519" => Ok(Group::X25519),
+            _ => Err(Error::App(format!(
+                "invalid key exchange: {s}"
+            ))),
+        }
+    }
+}
+
+// The top bit, never set in a public value of the 31-bit group,
+// `AUTH` if the side asks for authentication, then the bits of the
+// groups on offer
+const HELLO: u32 = 1 << 31;
+const AUTH: u32 = 1 << 30;
+
+// The secret of the key exchange agreed on: 4 bytes (big-endian) of
+// the 31-bit group, 32 of X25519
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Shared {
+    pub group: Group,
+    pub secret: Vec<u8>,
+    // SHA-256 of the hellos and the public values, of the side with
+    // the lower public value first (the same on both sides)
+    pub transcript: [u8; 32],
+    first: bool, // this side's public value is the lower one
+}
+
+// Session keys, never the raw secret: a key per direction to mask
+// the frames with (for the transports that still do), a key for
+// MACs, and a ChaCha20-Poly1305 key per direction (`tcp::Tcp`)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Keys {
+    pub send: u32,
+    pub recv: u32,
+    pub mac: [u8; 32],
+    pub seal: [u8; 32], // to send with
+    pub open: [u8; 32], // to receive with
+}
+
+impl Shared {
+    // HKDF of the secret, salted with the transcript: the key from
+    // the first side to the second, the one back, the MAC key, then
+    // the AEAD keys the same way
+    pub fn keys(&self) -> Keys {
+        derive(&self.transcript, &self.secret, self.first)
+    }
+}
+
+fn derive(
+    transcript: &[u8; 32],
+    secret: &[u8],
+    first: bool,
+) -> Keys {
+    let okm = hkdf(transcript, secret, b"session keys", 104);
+    let word = |i: usize| {
+        u32::from_be_bytes(okm[i..i + 4].try_into().unwrap())
+    };
+    let key = |i: usize| -> [u8; 32] {
+        okm[i..i + 32].try_into().unwrap()
+    };
+    let (there, back) = (word(0), word(4));
+    let (send, recv) =
+        if first { (there, back) } else { (back, there) };
+    let (there, back) = (key(40), key(72));
+    let (seal, open) =
+        if first { (there, back) } else { (back, there) };
+    Keys {
+        send,
+        recv,
+        mac: key(8),
+        seal,
+        open,
+    }
+}
+
+// HKDF-SHA256 (RFC 5869): extract (HMAC of the input key material
+// keyed with the salt), then expand to `len` bytes (up to 255 * 32)
+pub fn hkdf(
+    salt: &[u8],
+    ikm: &[u8],
+    info: &[u8],
+    len: usize,
+) -> Vec<u8> {
+    assert!(len <= 255 * 32, "hkdf: too long");
+    let prk = hmac_sha256(salt, &[ikm]);
+    let mut okm = Vec::with_capacity(len);
+    let mut t = Vec::new();
+    for i in 1..=len.div_ceil(32) {
+        t = hmac_sha256(&prk, &[&t, info, &[i as u8]]).to_vec();
+        okm.extend_from_slice(&t);
+    }
+    okm.truncate(len);
+    okm
+}
+
+// Authenticated handshake: after the exchange, each side sends its
+// long-term public key and a signature over the transcript (both
+// hellos and both public values, see `transcript`), and checks the
+// other's before the secret is used, so that a man in the middle
+// (running an exchange with each side) is found out
+pub struct Auth<'a> {
+    pub key: &'a SecretKey, // own long-term key
+    // the peer's key must be one of these (any key if empty, the
+    // signature still has to be valid)
+    pub trusted: &'a [PublicKey],
+    // ask the peer to authenticate, or only if the peer asks (then
+    // both sides do)
+    pub required: bool,
+}
+
+// Both sides send a hello with the groups they offer, and run the
+// exchange of the first group of `Group::ALL` both of them offered
+pub fn handshake<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    offer: &[Group],
+) -> Result<Shared> {
+    handshake_with(transport, timeout, offer, &Os)
+}
+
+// The same, with the exponent drawn from `rng` (a seeded one makes
+// the handshake reproducible, for tests only)
+pub fn handshake_with<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    offer: &[Group],
+    rng: &dyn Rng,
+) -> Result<Shared> {
+    run(transport, timeout, offer, None, rng)
+        .map(|(shared, _)| shared)
+}
+
+// The same, authenticated if either side asks for it: then the
+// peer's (verified) public key comes along with the secret
+pub fn authenticated<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    offer: &[Group],
+    auth: &Auth,
+) -> Result<(Shared, Option<PublicKey>)> {
+    run(transport, timeout, offer, Some(auth), &Os)
+}
+
+fn run<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    offer: &[Group],
+    auth: Option<&Auth>,
+    rng: &dyn Rng,
+) -> Result<(Shared, Option<PublicKey>)> {
+    let mut bits =
+        offer.iter().fold(0, |bits, g| bits | g.bit());
+    if auth.is_some_and(|auth| auth.required) {
+        bits |= AUTH;
+    }
+    transport.send(&(HELLO | bits))?;
+    let hello = transport.recv_timeout(timeout)?;
+    if hello & HELLO == 0 {
+        return Err(Error::App(format!(
+            "invalid hello: {hello:08x}"
+        )));
+    }
+    let group = Group::ALL
+        .into_iter()
+        .find(|g| bits & hello & g.bit() != 0)
+        .ok_or_else(|| {
+            Error::App("no key exchange in common".to_string())
+        })?;
+    let (ours, theirs, secret) = match group {
+        Group::Mersenne => mersenne(transport, timeout, rng)?,
+        Group::X25519 => {
+            x25519_handshake(transport, timeout, rng)?
+        }
+    };
+    // a reflected public value (or the odd collision of the 31-bit
+    // group) leaves no order to the sides
+    if ours == theirs {
+        return Err(Error::App(
+            "handshake: the same public value".to_string(),
+        ));
+    }
+    let first = ours < theirs;
+    // this side's view of the transcript, and the peer's (its hello
+    // and public value first)
+    let mine =
+        transcript(&[HELLO | bits, hello], &ours, &theirs);
+    let peers =
+        transcript(&[hello, HELLO | bits], &theirs, &ours);
+    let shared = Shared {
+        group,
+        secret,
+        transcript: if first { mine } else { peers },
+        first,
+    };
+
+    confirm(
+        transport,
+        timeout,
+        &shared.keys().mac,
+        &mine,
+        &peers,
+    )?;
+
+    if (bits | hello) & AUTH == 0 {
+        return Ok((shared, None));
+    }
+    let Some(auth) = auth else {
+        return Err(Error::App(
+            "handshake: the peer asks for authentication"
+                .to_string(),
+        ));
+    };
+
+    let digest = leading(&mine);
+    let sig = auth.key.sign(&digest);
+    let public_key = auth.key.public_key();
+    let (key_hi, key_lo) = split(u64::from(&public_key));
+    let (sig_hi, sig_lo) = split(u64::from(&sig));
+    for word in [key_hi, key_lo, sig_hi, sig_lo] {
+        transport.send(&word)?;
+    }
+    let mut words = [0u32; 4];
+    for word in words.iter_mut() {
+        *word = transport.recv_timeout(timeout)?;
+    }
+    let peer = PublicKey::from(merge(words[0], words[1]));
+    let sig = Signature::from(merge(words[2], words[3]));
+    let digest = leading(&peers);
+    if !peer.is_on_curve() || !peer.is_valid(&digest, &sig) {
+        return Err(Error::App(
+            "handshake: invalid signature".to_string(),
+        ));
+    }
+    if !auth.trusted.is_empty() && !auth.trusted.contains(&peer)
+    {
+        return Err(Error::App(format!(
+            "handshake: untrusted key {:08x}",
+            peer.fingerprint()
+        )));
+    }
+    Ok((shared, Some(peer)))
+}
+
+// SHA-256 of the hellos and the public values, the given side's
+// first (for a side to sign, its leading bytes, see `leading`)
+fn transcript(
+    hellos: &[u32; 2],
+    ours: &[u8],
+    theirs: &[u8],
+) -> [u8; 32] {
+    let bytes = hellos
+        .iter()
+        .flat_map(|hello| hello.to_be_bytes())
+        .chain(ours.iter().copied())
+        .chain(theirs.iter().copied())
+        .collect::<Vec<_>>();
+    sha256(&bytes)
+}
+
+// Key confirmation: keys that came out different on the two sides
+// fail the handshake here, not as garbled frames later
+fn confirm<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    mac: &[u8; 32],
+    mine: &[u8; 32],
+    peers: &[u8; 32],
+) -> Result<()> {
+    for word in confirmation(mac, mine) {
+        transport.send(&word)?;
+    }
+    let mut words = [0u32; 2];
+    for word in words.iter_mut() {
+        *word = transport.recv_timeout(timeout)?;
+    }
+    if words != confirmation(mac, peers) {
+        return Err(Error::App(
+            "handshake: key confirmation failed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// HMAC of a side's view of the transcript under the MAC key, the
+// leading 64 bits
+fn confirmation(
+    mac: &[u8; 32],
+    transcript: &[u8; 32],
+) -> [u32; 2] {
+    let h = hmac_sha256(mac, &[b"key confirmation", transcript]);
+    [
+        u32::from_be_bytes([h[0], h[1], h[2], h[3]]),
+        u32::from_be_bytes([h[4], h[5], h[6], h[7]]),
+    ]
+}
+
+fn leading(h: &[u8; 32]) -> u32 {
+    u32::from_be_bytes([h[0], h[1], h[2], h[3]])
+}
+
+// Pre-shared key (distributed out of band): no key exchange, each
+// side sends a fresh 64-bit nonce, the keys are derived from the key
+// and both nonces (as from a secret, see `Shared::keys`) and
+// confirmed; a side with another key fails the confirmation
+pub fn psk<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    key: &[u8],
+) -> Result<Keys> {
+    let nonce = OsRng.next_u64();
+    let (hi, lo) = split(nonce);
+    transport.send(&hi)?;
+    transport.send(&lo)?;
+    let hi = transport.recv_timeout(timeout)?;
+    let lo = transport.recv_timeout(timeout)?;
+    let (ours, theirs) =
+        (nonce.to_be_bytes(), merge(hi, lo).to_be_bytes());
+    if ours == theirs {
+        return Err(Error::App(
+            "handshake: the same nonce".to_string(),
+        ));
+    }
+    let first = ours < theirs;
+    let mine = transcript(&[0, 0], &ours, &theirs);
+    let peers = transcript(&[0, 0], &theirs, &ours);
+    let keys =
+        derive(if first { &mine } else { &peers }, key, first);
+    confirm(transport, timeout, &keys.mac, &mine, &peers)?;
+    Ok(keys)
+}
+
+// A fresh X25519 key pair to rekey a session with (see
+// `tcp::Rekey`): the sides swap the public values in frames of the
+// session instead of running another handshake
+pub struct Ephemeral {
+    secret: [u8; 32],
+    pub public: [u8; 32],
+}
+
+impl Ephemeral {
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self {
+            secret,
+            public: x25519(&secret, &BASEPOINT),
+        }
+    }
+
+    // the same checks as the handshake, no hellos in the transcript
+    pub fn shared(&self, theirs: &[u8]) -> Result<Shared> {
+        let theirs: [u8; 32] =
+            theirs.try_into().map_err(|_| {
+                Error::App(format!(
+                    "invalid public value: {} bytes",
+                    theirs.len()
+                ))
+            })?;
+        if theirs == self.public {
+            return Err(Error::App(
+                "handshake: the same public value".to_string(),
+            ));
+        }
+        let secret = x25519(&self.secret, &theirs);
+        if secret == [0; 32] {
+            return Err(Error::App(
+                "invalid public value: of small order"
+                    .to_string(),
+            ));
+        }
+        let first = self.public < theirs;
+        Ok(Shared {
+            group: Group::X25519,
+            secret: secret.to_vec(),
+            transcript: if first {
+                transcript(&[0, 0], &self.public, &theirs)
+            } else {
+                transcript(&[0, 0], &theirs, &self.public)
+            },
+            first,
+        })
+    }
+}
+
+// Own public value, the peer's, and the secret
+type Exchanged = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn mersenne<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    rng: &dyn Rng,
+) -> Result<Exchanged> {
+    let a = rng.next_u32();
+    let pow = modular_pow(BASE, a as Int, MODULUS) as u32;
+    transport.send(&pow)?;
+    let b = transport.recv_timeout(timeout)?;
+    let secret = modular_pow(b as Int, a as Int, MODULUS) as u32;
+    Ok((
+        pow.to_be_bytes().to_vec(),
+        b.to_be_bytes().to_vec(),
+        secret.to_be_bytes().to_vec(),
+    ))
+}
+
+// The public values as 8 words each
+fn x25519_handshake<T: Sender<u32> + Receiver<u32>>(
+    transport: &T,
+    timeout: Duration,
+    rng: &dyn Rng,
+) -> Result<Exchanged> {
+    let mut a = [0u8; 32];
+    rng.fill(&mut a);
+    let public = x25519(&a, &BASEPOINT);
+    for word in public.chunks_exact(4) {
+        let word = u32::from_be_bytes(word.try_into().unwrap());
+        transport.send(&word)?;
+    }
+    let mut b = [0u8; 32];
+    for word in b.chunks_exact_mut(4) {
+        let received = transport.recv_timeout(timeout)?;
+        word.copy_from_slice(&received.to_be_bytes());
+    }
+    let secret = x25519(&a, &b);
+    // a point of small order gives it away (RFC 7748, 6.1)
+    if secret == [0; 32] {
+        return Err(Error::App(
+            "invalid public value: of small order".to_string(),
+        ));
+    }
+    Ok((public.to_vec(), b.to_vec(), secret.to_vec()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread;
 
     use super::*;
-    use crate::testkit::*;
+    use crate::{
+        testkit::*,
+        util::{from_hex, to_hex, Seeded},
+    };
 
     #[test]
     fn test_dfke_handshake() {
@@ -85,6 +552,371 @@ mod tests {
         assert_eq!(s1, s2);
     }
 
+    #[test]
+    fn test_dhke_link() {
+        let ms = Duration::from_millis;
+        let run = |link: Link, timeout: Duration| {
+            let network = network();
+            network.link("1", "2", link);
+            network.link("2", "1", link);
+            let open = |from: &str, to: &str| {
+                Probe::open(&(
+                    from.to_string(),
+                    to.to_string(),
+                    network.clone(),
+                ))
+                .unwrap()
+            };
+            let (t1, t2) = (open("1", "2"), open("2", "1"));
+            let h1 = thread::spawn(move || {
+                dhke_handshake(&t1, timeout, 30303030)
+            });
+            let h2 = thread::spawn(move || {
+                dhke_handshake(&t2, timeout, 40404040)
+            });
+            let s = (h1.join().unwrap(), h2.join().unwrap());
+            (s, network.now())
+        };
+
+        // a second each way, as long as it is waited for
+        let latency = Link {
+            latency: ms(1000),
+            ..Link::default()
+        };
+        let ((s1, s2), now) = run(latency, ms(2000));
+        assert_eq!(s1.unwrap(), s2.unwrap());
+        assert_eq!(now, ms(1000));
+        let ((s1, s2), _) = run(latency, ms(500));
+        assert!(s1.is_err() && s2.is_err());
+
+        let lost = Link {
+            drop: 1.0,
+            ..Link::default()
+        };
+        let ((s1, s2), _) = run(lost, ms(10));
+        assert!(s1.is_err() && s2.is_err());
+        // nothing tells a public value with a bit flipped: the
+        // secrets just differ
+        let corrupt = Link {
+            corrupt: 1.0,
+            ..Link::default()
+        };
+        let ((s1, s2), _) = run(corrupt, ms(10));
+        assert_ne!(s1.unwrap(), s2.unwrap());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let open = |from: &str, to: &str, network: &Network| {
+            Probe::open(&(
+                from.to_string(),
+                to.to_string(),
+                network.clone(),
+            ))
+            .unwrap()
+        };
+        let timeout = Duration::from_millis(100);
+        let run = |one: &'static [Group],
+                   two: &'static [Group]| {
+            let network = network();
+            let t1 = open("1", "2", &network);
+            let t2 = open("2", "1", &network);
+            let h1 = thread::spawn(move || {
+                handshake(&t1, timeout, one)
+            });
+            let h2 = thread::spawn(move || {
+                handshake(&t2, timeout, two)
+            });
+            (h1.join().unwrap(), h2.join().unwrap())
+        };
+
+        // X25519 if both offer it, a 256-bit secret
+        let (s1, s2) = run(&Group::ALL, &Group::ALL);
+        let (s1, s2) = (s1.unwrap(), s2.unwrap());
+        assert_eq!(s1.secret, s2.secret);
+        assert_eq!(s1.transcript, s2.transcript);
+        assert_eq!(s1.group, Group::X25519);
+        assert_eq!(s1.secret.len(), 32);
+        // one side's sending key is the other's receiving one
+        let (k1, k2) = (s1.keys(), s2.keys());
+        assert_eq!((k1.send, k1.recv), (k2.recv, k2.send));
+        assert_ne!(k1.send, k1.recv);
+        assert_eq!(k1.mac, k2.mac);
+        assert_eq!((k1.seal, k1.open), (k2.open, k2.seal));
+        assert_ne!(k1.seal, k1.open);
+
+        let (s1, s2) = run(&Group::ALL, &[Group::Mersenne]);
+        let (s1, s2) = (s1.unwrap(), s2.unwrap());
+        assert_eq!(s1.secret, s2.secret);
+        assert_eq!(s1.keys().send, s2.keys().recv);
+        assert_eq!(s1.group, Group::Mersenne);
+        assert_eq!(s1.secret.len(), 4);
+
+        let (s1, s2) = run(&[Group::X25519], &[Group::Mersenne]);
+        assert!(s1.is_err() && s2.is_err());
+
+        assert_eq!(
+            "x25519".parse::<Group>().unwrap(),
+            Group::X25519
+        );
+        assert_eq!(
+            "dhke".parse::<Group>().unwrap(),
+            Group::Mersenne
+        );
+        assert!("rsa".parse::<Group>().is_err());
+    }
+
+    #[test]
+    fn test_handshake_seeded() {
+        let timeout = Duration::from_millis(100);
+        let run = |group: Group, seeds: (u64, u64)| {
+            let network = network();
+            let open = |from: &str, to: &str| {
+                Probe::open(&(
+                    from.to_string(),
+                    to.to_string(),
+                    network.clone(),
+                ))
+                .unwrap()
+            };
+            let side = move |t: Probe, seed: u64| {
+                thread::spawn(move || {
+                    let rng = Seeded::new(seed);
+                    handshake_with(&t, timeout, &[group], &rng)
+                        .unwrap()
+                })
+            };
+            let h1 = side(open("1", "2"), seeds.0);
+            let h2 = side(open("2", "1"), seeds.1);
+            let (s1, s2) =
+                (h1.join().unwrap(), h2.join().unwrap());
+            assert_eq!(s1.secret, s2.secret);
+            s1
+        };
+        for group in Group::ALL {
+            // the same secret and transcript each time
+            let s = run(group, (1, 2));
+            let again = run(group, (1, 2));
+            assert_eq!(
+                (s.secret.clone(), s.transcript),
+                (again.secret, again.transcript)
+            );
+            assert_ne!(run(group, (1, 3)).secret, s.secret);
+        }
+    }
+
+    #[test]
+    fn test_authenticated() {
+        let timeout = Duration::from_millis(100);
+        type Side = (u32, Vec<PublicKey>, bool); // key, trusted, required
+        let run = |one: Side, two: Side| {
+            let network = network();
+            let open = |from: &str, to: &str| {
+                Probe::open(&(
+                    from.to_string(),
+                    to.to_string(),
+                    network.clone(),
+                ))
+                .unwrap()
+            };
+            let (t1, t2) = (open("1", "2"), open("2", "1"));
+            let side = move |t: Probe, (key, trusted, required): Side| {
+                thread::spawn(move || {
+                    let key = SecretKey::new(key);
+                    let auth = Auth {
+                        key: &key,
+                        trusted: &trusted,
+                        required,
+                    };
+                    authenticated(&t, timeout, &Group::ALL, &auth)
+                })
+            };
+            let (h1, h2) = (side(t1, one), side(t2, two));
+            (h1.join().unwrap(), h2.join().unwrap())
+        };
+        let (server, client) =
+            (SecretKey::new(7), SecretKey::new(42));
+
+        // the client knows the server's key, the server takes any
+        let (s1, s2) = run(
+            (42, vec![server.public_key()], true),
+            (7, vec![], false),
+        );
+        let ((s1, k1), (s2, k2)) = (s1.unwrap(), s2.unwrap());
+        assert_eq!(s1.keys().send, s2.keys().recv);
+        assert_eq!(k1, Some(server.public_key()));
+        assert_eq!(k2, Some(client.public_key()));
+
+        // nobody asks
+        let (s1, s2) =
+            run((42, vec![], false), (7, vec![], false));
+        assert_eq!(s1.unwrap().1, None);
+        assert_eq!(s2.unwrap().1, None);
+
+        // another key than the trusted one (a man in the middle)
+        let (s1, _) = run(
+            (42, vec![server.public_key()], true),
+            (8, vec![], false),
+        );
+        assert!(s1.is_err());
+
+        // asked for, but the peer does not authenticate
+        let network = network();
+        let t1 = Probe::open(&(
+            "1".to_string(),
+            "2".to_string(),
+            network.clone(),
+        ))
+        .unwrap();
+        let t2 = Probe::open(&(
+            "2".to_string(),
+            "1".to_string(),
+            network,
+        ))
+        .unwrap();
+        let h = thread::spawn(move || {
+            handshake(&t2, timeout, &Group::ALL)
+        });
+        let auth = Auth {
+            key: &client,
+            trusted: &[],
+            required: true,
+        };
+        assert!(authenticated(&t1, timeout, &Group::ALL, &auth)
+            .is_err());
+        assert!(h.join().unwrap().is_err());
+
+        // a signature of one transcript is not of another
+        let digest = leading(&transcript(&[1, 2], &[3], &[4]));
+        assert_ne!(
+            digest,
+            leading(&transcript(&[2, 1], &[3], &[4]))
+        );
+        assert_ne!(
+            digest,
+            leading(&transcript(&[1, 2], &[4], &[3]))
+        );
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let network = network();
+        let open = |from: &str, to: &str| {
+            Probe::open(&(
+                from.to_string(),
+                to.to_string(),
+                network.clone(),
+            ))
+            .unwrap()
+        };
+        let (t1, t2) = (open("1", "2"), open("2", "1"));
+        let timeout = Duration::from_millis(100);
+        // the peer runs the exchange, but ends up with other keys
+        let h = thread::spawn(move || -> Result<()> {
+            t2.send(&(HELLO | Group::X25519.bit()))?;
+            let _: u32 = t2.recv_timeout(timeout)?;
+            for word in 1..=8 {
+                t2.send(&word)?;
+            }
+            for _ in 0..8 {
+                let _: u32 = t2.recv_timeout(timeout)?;
+            }
+            let mac = [0u8; 32];
+            for word in confirmation(&mac, &[0; 32]) {
+                t2.send(&word)?;
+            }
+            Ok(())
+        });
+        let e =
+            handshake(&t1, timeout, &Group::ALL).unwrap_err();
+        assert!(
+            matches!(&e, Error::App(e) if e.contains("confirmation")),
+            "{e:?}"
+        );
+        h.join().unwrap().unwrap();
+
+        assert_ne!(
+            confirmation(&[1; 32], &[2; 32]),
+            confirmation(&[1; 32], &[3; 32])
+        );
+        assert_ne!(
+            confirmation(&[1; 32], &[2; 32]),
+            confirmation(&[0; 32], &[2; 32])
+        );
+    }
+
+    #[test]
+    fn test_psk() {
+        let timeout = Duration::from_millis(100);
+        let run = |one: &'static [u8], two: &'static [u8]| {
+            let network = network();
+            let open = |from: &str, to: &str| {
+                Probe::open(&(
+                    from.to_string(),
+                    to.to_string(),
+                    network.clone(),
+                ))
+                .unwrap()
+            };
+            let (t1, t2) = (open("1", "2"), open("2", "1"));
+            let h1 =
+                thread::spawn(move || psk(&t1, timeout, one));
+            let h2 =
+                thread::spawn(move || psk(&t2, timeout, two));
+            (h1.join().unwrap(), h2.join().unwrap())
+        };
+
+        let (k1, k2) = run(b"secret", b"secret");
+        let (k1, k2) = (k1.unwrap(), k2.unwrap());
+        assert_eq!((k1.send, k1.recv), (k2.recv, k2.send));
+        assert_eq!(k1.mac, k2.mac);
+        // fresh nonces: other keys for every connection
+        let (k3, _) = run(b"secret", b"secret");
+        assert_ne!(k3.unwrap().mac, k1.mac);
+
+        let (k1, k2) = run(b"secret", b"secret!");
+        assert!(k1.is_err() && k2.is_err());
+    }
+
+    #[test]
+    fn test_ephemeral() -> Result<()> {
+        let (a, b) =
+            (Ephemeral::generate(), Ephemeral::generate());
+        let (x, y) =
+            (a.shared(&b.public)?, b.shared(&a.public)?);
+        assert_eq!(x.secret, y.secret);
+        assert_eq!(x.transcript, y.transcript);
+        let (kx, ky) = (x.keys(), y.keys());
+        assert_eq!((kx.send, kx.recv), (ky.recv, ky.send));
+        assert_eq!(kx.mac, ky.mac);
+
+        assert!(a.shared(&a.public).is_err());
+        assert!(a.shared(&[0; 32]).is_err());
+        assert!(a.shared(&b.public[1..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hkdf() {
+        // RFC 5869, test case 1
+        let okm = hkdf(
+            &from_hex("000102030405060708090a0b0c").unwrap(),
+            &[0x0b; 22],
+            &from_hex("f0f1f2f3f4f5f6f7f8f9").unwrap(),
+            42,
+        );
+        assert_eq!(
+            to_hex(&okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+        // a prefix of a longer one
+        let longer = hkdf(b"salt", b"ikm", b"info", 100);
+        assert_eq!(
+            &longer[..40],
+            &hkdf(b"salt", b"ikm", b"info", 40)[..]
+        );
+    }
+
     #[test]
     fn test_dfke_math() {
         let a: Int = 101010;
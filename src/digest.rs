@@ -0,0 +1,218 @@
+use crate::{
+    api::{Error, Result, MAX_PAYLOAD_LEN},
+    util::crc32,
+};
+
+// Buckets the keys are spread over (by their low byte), a digest
+// each: the digests are compared first, then the keys of the
+// buckets that differ
+pub const BUCKETS: usize = 256;
+// of each key, the latest ones
+pub const EPOCHS: usize = 8;
+
+// The keys a server stores and the epochs of their versions, by
+// bucket, for anti-entropy (TAG_DIGEST, see the server): two
+// servers with the same digest for a bucket have the same latest
+// epoch for all its keys, so only the keys of the buckets that
+// differ are sent over. A server takes a refresh only at the epoch
+// after its latest one, so having the same latest epoch is being
+// in line.
+#[derive(Debug)]
+pub struct Digest {
+    buckets: Vec<Vec<(u64, Vec<u32>)>>,
+}
+
+impl Digest {
+    // Keys and their epochs, any order, the latest EPOCHS of them
+    // are kept
+    pub fn new(
+        keys: impl IntoIterator<Item = (u64, Vec<u32>)>,
+    ) -> Self {
+        let mut buckets = vec![vec![]; BUCKETS];
+        for (key, epochs) in keys {
+            let skip = epochs.len().saturating_sub(EPOCHS);
+            buckets[bucket(key)]
+                .push((key, epochs[skip..].to_vec()));
+        }
+        for bucket in &mut buckets {
+            bucket.sort();
+        }
+        Self { buckets }
+    }
+
+    // The digest of each bucket, of its keys and their latest
+    // epochs (zero for an empty one)
+    pub fn roots(&self) -> Vec<u32> {
+        self.buckets
+            .iter()
+            .map(|keys| {
+                if keys.is_empty() {
+                    return 0;
+                }
+                let latest = keys
+                    .iter()
+                    .map(|(key, epochs)| {
+                        (
+                            *key,
+                            epochs
+                                .last()
+                                .into_iter()
+                                .copied()
+                                .collect(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                crc32(&encode(&latest))
+            })
+            .collect()
+    }
+
+    pub fn keys(&self, bucket: usize) -> &[(u64, Vec<u32>)] {
+        self.buckets.get(bucket).map_or(&[], |keys| keys)
+    }
+
+    // The keys of a bucket as sent over, as many as fit in a frame
+    pub fn encode(&self, bucket: usize) -> Vec<u8> {
+        let mut data = vec![];
+        for entry in self.keys(bucket) {
+            let entry = encode(std::slice::from_ref(entry));
+            if data.len() + entry.len() > MAX_PAYLOAD_LEN {
+                break;
+            }
+            data.extend(entry);
+        }
+        data
+    }
+}
+
+pub fn bucket(key: u64) -> usize {
+    key as usize % BUCKETS
+}
+
+// key, number of epochs, epochs
+fn encode(keys: &[(u64, Vec<u32>)]) -> Vec<u8> {
+    keys.iter()
+        .flat_map(|(key, epochs)| {
+            key.to_be_bytes()
+                .into_iter()
+                .chain((epochs.len() as u32).to_be_bytes())
+                .chain(
+                    epochs.iter().flat_map(|e| e.to_be_bytes()),
+                )
+        })
+        .collect()
+}
+
+// The keys of a bucket received (see `Digest::encode`)
+pub fn decode(mut data: &[u8]) -> Result<Vec<(u64, Vec<u32>)>> {
+    let invalid = || Error::App("invalid digest".to_string());
+    let mut keys = vec![];
+    while !data.is_empty() {
+        let key = data.get(..8).ok_or_else(invalid)?;
+        let n = data.get(8..12).ok_or_else(invalid)?;
+        let key = u64::from_be_bytes(key.try_into().unwrap());
+        let n =
+            u32::from_be_bytes(n.try_into().unwrap()) as usize;
+        if n > EPOCHS {
+            return Err(invalid());
+        }
+        let epochs =
+            data.get(12..12 + 4 * n).ok_or_else(invalid)?;
+        let epochs = epochs
+            .chunks(4)
+            .map(|e| u32::from_be_bytes(e.try_into().unwrap()))
+            .collect();
+        keys.push((key, epochs));
+        data = &data[12 + 4 * n..];
+    }
+    Ok(keys)
+}
+
+// How to bring the servers' versions of a key back in line, given
+// the epochs of this server's and of the peers': back to the latest
+// epoch all of them have, as a version at the epoch after the
+// latest of any of them. None if there is no such epoch (among the
+// latest EPOCHS), or nothing to do (the same latest epoch on all of
+// them, whatever came before).
+pub fn repair(
+    own: &[u32],
+    theirs: &[Vec<u32>],
+) -> Option<(u32, u32)> {
+    if theirs.iter().all(|epochs| epochs.last() == own.last()) {
+        return None;
+    }
+    let common = own.iter().rev().find(|e| {
+        theirs.iter().all(|epochs| epochs.contains(e))
+    })?;
+    let latest = std::iter::once(own)
+        .chain(theirs.iter().map(|epochs| epochs.as_slice()))
+        .filter_map(|epochs| epochs.last())
+        .max()?;
+    Some((*common, latest + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() -> Result<()> {
+        let a = Digest::new(vec![
+            (0x100, vec![0, 1, 2, 3]),
+            (0x200, vec![0]),
+            (0x301, (0..20).collect()),
+        ]);
+        // a round missed, and repaired since
+        let b = Digest::new(vec![
+            (0x200, vec![0]),
+            (0x100, vec![0, 2, 3]),
+            (0x301, (10..20).collect()),
+        ]);
+        // the latest ones only
+        assert_eq!(a.keys(1), &[(0x301, (12..20).collect())]);
+        assert_eq!(a.roots(), b.roots());
+        assert_eq!(a.roots()[2], 0);
+
+        let c = Digest::new(vec![
+            (0x100, vec![0, 1]),
+            (0x200, vec![0]),
+        ]);
+        let differ = (0..BUCKETS)
+            .filter(|b| a.roots()[*b] != c.roots()[*b])
+            .collect::<Vec<_>>();
+        assert_eq!(differ, vec![0, 1]);
+        assert_eq!(decode(&c.encode(0))?, c.keys(0));
+        assert_eq!(decode(&a.encode(1))?, a.keys(1));
+        assert!(decode(&c.encode(5))?.is_empty());
+
+        assert!(decode(&[0; 11]).is_err());
+        let mut invalid = c.encode(0);
+        invalid.pop();
+        assert!(decode(&invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair() {
+        assert_eq!(repair(&[0, 1], &[vec![0, 1]]), None);
+        assert_eq!(
+            repair(&[0, 1, 2, 3], &[vec![0, 2, 3]]),
+            None
+        );
+        // missed the last round
+        assert_eq!(
+            repair(&[0, 1, 2], &[vec![0, 1]]),
+            Some((1, 3))
+        );
+        // missed one before
+        assert_eq!(
+            repair(&[0, 1, 2], &[vec![0, 2], vec![0, 1]]),
+            Some((0, 3))
+        );
+        assert_eq!(
+            repair(&[0, 2], &[vec![0, 1, 2, 3]]),
+            Some((2, 4))
+        );
+        assert_eq!(repair(&[5], &[vec![7]]), None);
+    }
+}
@@ -1,85 +1,500 @@
-use crate::util::crc32;
+use std::{collections::HashMap, sync::OnceLock};
 
-#[derive(Debug)]
-pub struct SecretKey(u32);
+use crate::{
+    api::{Error, Result},
+    sha256::sha256,
+    util::{from_hex, to_hex},
+};
 
-#[derive(Debug)]
-pub struct PublicKey(u32, u32);
+pub mod rfc6979;
+pub mod schnorr;
+pub mod secp256k1;
+pub mod u256;
+
+// Keys and signatures as fixed-size big-endian bytes (the two
+// coordinates of a point, or the two halves of a signature, one
+// after the other), to be kept in files or carried in frames; what
+// is decoded is checked: a key in range, a point on the curve
+pub trait Encoding: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+
+    fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = from_hex(hex).ok_or_else(|| {
+            Error::App(format!("invalid hex: {hex}"))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+// the halves of `bytes` (of `2 * n` bytes) as `T`s
+pub(crate) fn halves<'a, T: TryFrom<&'a [u8]>>(
+    bytes: &'a [u8],
+    n: usize,
+    what: &str,
+) -> Result<(T, T)> {
+    let invalid = || {
+        Error::App(format!(
+            "invalid {what}: {} bytes",
+            bytes.len()
+        ))
+    };
+    if bytes.len() != 2 * n {
+        return Err(invalid());
+    }
+    let (a, b) = bytes.split_at(n);
+    Ok((
+        a.try_into().map_err(|_| invalid())?,
+        b.try_into().map_err(|_| invalid())?,
+    ))
+}
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct SecretKey(u32, Scheme);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct PublicKey(u32, u32, Scheme);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
 pub struct Signature(u32, u32);
 
+// How a key signs (and its public key verifies), chosen along with
+// the key: the public key (decoded, or registered by a server) is
+// of ECDSA unless told otherwise, frames are signed with ECDSA
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub enum Scheme {
+    #[default]
+    Ecdsa,
+    Schnorr,
+}
+
 impl SecretKey {
     pub fn new(secret: u32) -> Self {
-        Self(secret)
+        Self(secret, Scheme::Ecdsa)
+    }
+
+    pub fn with_scheme(secret: u32, scheme: Scheme) -> Self {
+        Self(secret, scheme)
+    }
+
+    // uniformly random in [1, N), from the OS (not a seeded PRNG)
+    pub fn generate() -> Self {
+        use rand::{rngs::OsRng, RngCore};
+        loop {
+            let secret = OsRng.next_u32();
+            if (1..curve::N).contains(&(secret as curve::Int)) {
+                return Self::new(secret);
+            }
+        }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.1
     }
 
     pub fn public_key(&self) -> PublicKey {
-        let (x, y) = mul(self.0 as curve::Int, curve::G);
-        PublicKey(x as u32, y as u32)
+        let (x, y) = mul_ct(self.0 as curve::Int, curve::G);
+        PublicKey(x as u32, y as u32, self.1)
     }
 
     pub fn sign(&self, msg: &u32) -> Signature {
-        let h = crc32(&msg.to_be_bytes());
-        let k = crc32(
-            h.to_be_bytes()
-                .into_iter()
-                .chain(
-                    crc32(&self.0.to_be_bytes()).to_be_bytes(),
-                )
-                .collect::<Vec<_>>()
-                .as_ref(),
-        );
-        let h = h as curve::Int;
-        let k = k as curve::Int;
-
-        let r = mul(k, curve::G).0;
+        match self.1 {
+            Scheme::Ecdsa => self.ecdsa(msg),
+            Scheme::Schnorr => schnorr::sign(self, msg),
+        }
+    }
 
-        let k_inv = modular_inv(k);
-        let key = self.0 as curve::Int;
-        let s = k_inv * (h + r * key) % curve::M;
+    fn ecdsa(&self, msg: &u32) -> Signature {
+        use curve::N;
+        let h = digest(msg);
+        let key = self.0 as curve::Int % N;
 
-        println!("sig: msg={msg} h={h} r={r} k={k} k'={k_inv} key={key} s={s}");
-        Signature(r as u32, s as u32)
+        // nonce must be in [1, N), skip those giving a degenerate
+        // `r` or `s`
+        let nonces = rfc6979::Nonces::new(
+            &(key as u32).to_be_bytes(),
+            &(h as u32).to_be_bytes(),
+        );
+        nonces
+            .map(|k| {
+                u32::from_be_bytes([k[0], k[1], k[2], k[3]])
+                    as curve::Int
+            })
+            .filter(|k| (1..N).contains(k))
+            .find_map(|k| {
+                let r = mul_ct(k, curve::G).0 % N;
+                let k_inv = extended_gcd(k, N);
+                let s =
+                    mul_mod(k_inv, h + mul_mod(r, key, N), N);
+                tracing::trace!(
+                    msg, h, r, k, k_inv, key, s, "sign"
+                );
+                (r > 0 && s > 0)
+                    .then_some(Signature(r as u32, s as u32))
+            })
+            .expect("out of nonces")
     }
 }
 
 impl PublicKey {
+    pub fn is_on_curve(&self) -> bool {
+        let (x, y) =
+            (self.0 as curve::Int, self.1 as curve::Int);
+        x < curve::M && y < curve::M && fits((x, y))
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.2
+    }
+
+    // the same point, verifying signatures of the `scheme`
+    pub fn with_scheme(self, scheme: Scheme) -> Self {
+        Self(self.0, self.1, scheme)
+    }
+
     pub fn is_valid(&self, msg: &u32, sig: &Signature) -> bool {
-        let h = crc32(&msg.to_be_bytes()) as curve::Int;
-        let (r, s) = (sig.0, sig.1);
-        let r = r as curve::Int;
-        let s_inv = modular_inv(s as curve::Int);
-
-        let a = mul(h * s_inv, curve::G);
-        let b = mul(
-            r * s_inv,
-            (self.0 as curve::Int, self.1 as curve::Int),
-        );
+        self.verify(msg, sig, &Table::new(self.point()))
+    }
+
+    // crc32 of the encoding, as in the `key` of a frame
+    pub fn fingerprint(&self) -> u32 {
+        crate::util::crc32(&self.to_bytes())
+    }
+
+    pub fn point(&self) -> curve::Point {
+        (self.0 as curve::Int, self.1 as curve::Int)
+    }
+
+    // SEC1-like: 2 for an even y or 3 for an odd one, then x (5
+    // bytes instead of 8), y is found again with `sqrt`
+    pub fn to_compressed(&self) -> Vec<u8> {
+        let mut bytes = vec![2 | (self.1 & 1) as u8];
+        bytes.extend(self.0.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_compressed(bytes: &[u8]) -> Result<Self> {
+        let invalid = |why: &str| {
+            Error::App(format!("invalid public key: {why}"))
+        };
+        let (prefix, x) = match bytes {
+            [prefix @ (2 | 3), x @ ..] if x.len() == 4 => {
+                (*prefix, x)
+            }
+            _ => {
+                return Err(invalid(&format!(
+                    "{} bytes compressed",
+                    bytes.len()
+                )))
+            }
+        };
+        let x = u32::from_be_bytes([x[0], x[1], x[2], x[3]]);
+        if x as curve::Int >= curve::M {
+            return Err(invalid("not on the curve"));
+        }
+        let y = sqrt(rhs(x as curve::Int))
+            .ok_or_else(|| invalid("not on the curve"))?;
+        // one of y and M - y is odd (M is), unless y is zero
+        let y = if y & 1 == (prefix & 1) as curve::Int {
+            y
+        } else {
+            (curve::M - y) % curve::M
+        };
+        let key = Self(x, y as u32, Scheme::Ecdsa);
+        if !key.is_on_curve() || key.1 & 1 != (prefix & 1) as u32
+        {
+            return Err(invalid("not on the curve"));
+        }
+        Ok(key)
+    }
+
+    // with the multiples of the key in `table`
+    fn verify(
+        &self,
+        msg: &u32,
+        sig: &Signature,
+        table: &Table,
+    ) -> bool {
+        match self.2 {
+            Scheme::Ecdsa => self.ecdsa(msg, sig, table),
+            Scheme::Schnorr => schnorr::verify(msg, sig, table),
+        }
+    }
+
+    fn ecdsa(
+        &self,
+        msg: &u32,
+        sig: &Signature,
+        table: &Table,
+    ) -> bool {
+        use curve::N;
+        let h = digest(msg);
+        let (r, s) = (sig.0 as curve::Int, sig.1 as curve::Int);
+        if !(1..N).contains(&r) || !(1..N).contains(&s) {
+            return false;
+        }
+        let s_inv = extended_gcd(s, N);
+
+        let a = Table::g().mul(mul_mod(h, s_inv, N));
+        let b = table.mul(mul_mod(r, s_inv, N));
         let p = add(a, b);
+        if p == curve::O {
+            return false;
+        }
+
+        tracing::trace!(r, s, s_inv, h, ?a, ?b, ?p, "verify");
+        p.0 % N == r
+    }
+}
+
+// Whether all the signatures are valid (if not, `is_valid` tells
+// which ones are not). Multiples of G, and of each public key, are
+// found once for all the signatures: a multiplication is then only
+// the additions of the multiples (`Table`), without the doublings.
+pub fn verify_batch(
+    items: &[(u32, Signature, PublicKey)],
+) -> bool {
+    let mut tables = HashMap::new();
+    items.iter().all(|(msg, sig, key)| {
+        let table = tables
+            .entry(key.point())
+            .or_insert_with(|| Table::new(key.point()));
+        key.verify(msg, sig, table)
+    })
+}
+
+// Public keys an ECDSA signature of `msg` can be of (up to four):
+// the x of R = kG is `r` (or `r + N`, if below M), either of the two
+// y's that fit, then Q = r^-1 * (sR - hG). It is up to the caller to
+// tell which one is the signer's, e.g. by its fingerprint.
+pub fn recover(msg: &u32, sig: &Signature) -> Vec<PublicKey> {
+    use curve::{M, N};
+    let h = digest(msg);
+    let (r, s) = (sig.0 as curve::Int, sig.1 as curve::Int);
+    if !(1..N).contains(&r) || !(1..N).contains(&s) {
+        return vec![];
+    }
+    let r_inv = extended_gcd(r, N);
+    [r, r + N]
+        .into_iter()
+        .filter(|x| *x < M)
+        .filter_map(|x| Some((x, sqrt(rhs(x))?)))
+        .flat_map(|(x, y)| [(x, y), (x, (M - y) % M)])
+        .filter_map(|p| {
+            // sR - hG = sR + (N - h)G
+            let q = add(mul(s, p), mul(N - h, curve::G));
+            let q = mul(r_inv, q);
+            (q != curve::O).then_some(PublicKey(
+                q.0 as u32,
+                q.1 as u32,
+                Scheme::Ecdsa,
+            ))
+        })
+        .filter(|key| key.is_valid(msg, sig))
+        .collect()
+}
+
+// Multiples of a point by the powers of two (up to 2^31: scalars
+// are below N, of 32 bits)
+pub struct Table(Vec<curve::Point>);
 
-        println!(
-            "ver: r={r} s={s} s'={s_inv} h={h} LHS={a:?} RHS={b:?} SUM={p:?}"
+impl Table {
+    pub fn new(p: curve::Point) -> Self {
+        let points = std::iter::successors(Some(p), |p| {
+            Some(add(*p, *p))
+        })
+        .take(32)
+        .collect();
+        Self(points)
+    }
+
+    // of G, found once
+    pub fn g() -> &'static Self {
+        static G: OnceLock<Table> = OnceLock::new();
+        G.get_or_init(|| Self::new(curve::G))
+    }
+
+    pub fn mul(&self, k: curve::Int) -> curve::Point {
+        assert!(
+            (0..1 << 32).contains(&k),
+            "scalar out of range"
         );
-        p.0 == r
+        (0..32)
+            .filter(|i| (k >> i) & 1 == 1)
+            .fold(curve::O, |r, i| add(r, self.0[i]))
+    }
+}
+
+impl Encoding for SecretKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; 4] = bytes.try_into().map_err(|_| {
+            Error::App(format!(
+                "invalid secret key: {} bytes",
+                bytes.len()
+            ))
+        })?;
+        let key = u32::from_be_bytes(bytes);
+        if key as curve::Int % curve::N == 0 {
+            return Err(Error::App(
+                "invalid secret key: zero".to_string(),
+            ));
+        }
+        Ok(Self(key, Scheme::Ecdsa))
+    }
+}
+
+impl Encoding for PublicKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        u64::from(self).to_be_bytes().to_vec()
+    }
+
+    // either of the encodings, the compressed one is 5 bytes long
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() == 5 {
+            return Self::from_compressed(bytes);
+        }
+        let (x, y) = halves(bytes, 4, "public key")?;
+        let key = Self(
+            u32::from_be_bytes(x),
+            u32::from_be_bytes(y),
+            Scheme::Ecdsa,
+        );
+        if !key.is_on_curve() {
+            return Err(Error::App(
+                "invalid public key: not on the curve"
+                    .to_string(),
+            ));
+        }
+        Ok(key)
+    }
+}
+
+impl Encoding for Signature {
+    fn to_bytes(&self) -> Vec<u8> {
+        u64::from(self).to_be_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (r, s) = halves(bytes, 4, "signature")?;
+        let (r, s) =
+            (u32::from_be_bytes(r), u32::from_be_bytes(s));
+        let in_range =
+            |x: u32| (1..curve::N).contains(&(x as curve::Int));
+        if !in_range(r) || !in_range(s) {
+            return Err(Error::App(
+                "invalid signature: out of range".to_string(),
+            ));
+        }
+        Ok(Self(r, s))
+    }
+}
+
+impl From<&PublicKey> for u64 {
+    fn from(key: &PublicKey) -> Self {
+        crate::util::merge(key.0, key.1)
+    }
+}
+
+impl From<u64> for PublicKey {
+    fn from(x: u64) -> Self {
+        let (hi, lo) = crate::util::split(x);
+        Self(hi, lo, Scheme::Ecdsa)
+    }
+}
+
+impl From<&Signature> for u64 {
+    fn from(sig: &Signature) -> Self {
+        crate::util::merge(sig.0, sig.1)
+    }
+}
+
+impl From<u64> for Signature {
+    fn from(x: u64) -> Self {
+        let (hi, lo) = crate::util::split(x);
+        Self(hi, lo)
     }
 }
 
+// SHA-256 of the message, its leading bits as many as of N (all of
+// them: N is of 32 bits), reduced
+fn digest(msg: &u32) -> curve::Int {
+    let h = sha256(&msg.to_be_bytes());
+    u32::from_be_bytes([h[0], h[1], h[2], h[3]]) as curve::Int
+        % curve::N
+}
+
 pub mod curve {
     // (y^2) % M = (x^3 + a*x + b) % M
 
+    // wide enough for a product of two residues, each reduced
     pub type Int = i128;
     pub type Point = (Int, Int);
 
-    // pub const M: Int = 4224215813;
-    // pub const A: Int = 3357810478;
-    // pub const B: Int = 1876092379;
-    // pub const G: (Int, Int) = (42887013, 2256698221);
+    // y^2 = x^3 + a*x + b over Z_m, with the base point `g` of prime
+    // order `n`; `m` and `n` below 2^32, for the products to fit
+    // `Int` and the scalars `mul_ct`
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct Curve {
+        pub m: Int,
+        pub a: Int,
+        pub b: Int,
+        pub g: Point,
+        pub n: Int,
+    }
 
-    pub const M: Int = 2267;
-    pub const A: Int = 1600;
-    pub const B: Int = 1384;
-    pub const G: (Int, Int) = (2056, 1998);
+    // the curve of the protocol (found with SageMath): a point fits
+    // `PublicKey`, a signature `Signature`, as both M and N are below
+    // 2^32
+    pub const CURVE: Curve = Curve {
+        m: 4224215813,
+        a: 3357810478,
+        b: 1876092379,
+        g: (42887013, 2256698221),
+        n: 4224125273,
+    };
+
+    // the textbook one, y^2 = x^3 + 2x + 2 (mod 17), G of order 19:
+    // for the tests, small enough to check by hand
+    pub const TINY: Curve = Curve {
+        m: 17,
+        a: 2,
+        b: 2,
+        g: (5, 1),
+        n: 19,
+    };
+
+    pub const M: Int = CURVE.m;
+    pub const A: Int = CURVE.a;
+    pub const B: Int = CURVE.b;
+    pub const G: Point = CURVE.g;
+    pub const N: Int = CURVE.n; // order of G (prime)
+
+    // point at infinity: (0, 0) does not fit the curve as B != 0
+    pub const O: Point = (0, 0);
 }
 
 pub fn extended_gcd(a: curve::Int, p: curve::Int) -> curve::Int {
@@ -121,85 +536,205 @@ pub fn extended_gcd(a: curve::Int, p: curve::Int) -> curve::Int {
 }
 
 pub fn modular_inv(x: curve::Int) -> curve::Int {
-    extended_gcd(x, curve::M)
+    extended_gcd(x.rem_euclid(curve::M), curve::M)
+}
+
+// (a * b) % m, the operands reduced first (in [0, m))
+pub fn mul_mod(
+    a: curve::Int,
+    b: curve::Int,
+    m: curve::Int,
+) -> curve::Int {
+    a.rem_euclid(m) * b.rem_euclid(m) % m
 }
 
 pub fn fits(p: curve::Point) -> bool {
-    use curve::*;
-    let (x, y) = p;
+    curve::CURVE.fits(p)
+}
 
-    let lhs = (y.pow(2)) % M;
-    let rhs = (x.pow(3) + A * x + B) % M;
+// x^3 + a*x + b (mod M), what y^2 is for a point on the curve
+fn rhs(x: curve::Int) -> curve::Int {
+    curve::CURVE.rhs(x)
+}
+
+pub fn pow_mod(
+    base: curve::Int,
+    exp: curve::Int,
+    m: curve::Int,
+) -> curve::Int {
+    let mut r = 1;
+    let mut base = base.rem_euclid(m);
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            r = mul_mod(r, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    r
+}
 
-    lhs == rhs
+// A square root of `a` (mod M), if there is one: M = 5 (mod 8), so
+// (Atkin) with b = (2a)^((M-5)/8) and i = 2ab^2 (a square root of
+// -1), it is ab(i - 1); the other one is M minus that
+pub fn sqrt(a: curve::Int) -> Option<curve::Int> {
+    use curve::M;
+    let a = a.rem_euclid(M);
+    let b = pow_mod(2 * a, (M - 5) / 8, M);
+    let i = mul_mod(2 * a, mul_mod(b, b, M), M);
+    let x = mul_mod(mul_mod(a, b, M), i - 1, M);
+    (mul_mod(x, x, M) == a).then_some(x)
 }
 
 pub fn add(p: curve::Point, q: curve::Point) -> curve::Point {
-    use curve::*;
-    let (px, py) = p;
-    let (qx, qy) = q;
-
-    let d = if px == qx {
-        let z = modular_inv(2 * py);
-        (3 * px * px + A) * z // "attempt to multiply with overflow"
-    } else {
-        let z = modular_inv(qx - px);
-        (qy - py) * z
-    };
+    curve::CURVE.add(p, q)
+}
 
-    let x = d * d - px - qx;
-    let y = d * (px - x) - py;
+pub fn mul(k: curve::Int, p: curve::Point) -> curve::Point {
+    curve::CURVE.mul(k, p)
+}
+
+// Montgomery ladder, for secret scalars (keys, nonces), see
+// `Curve::mul_ct`
+pub fn mul_ct(k: curve::Int, p: curve::Point) -> curve::Point {
+    curve::CURVE.mul_ct(k, p)
+}
 
-    {
-        let x = x % M;
-        let y = y % M;
-        let lhs = (y * y) % M;
-        let rhs = (x * x * x + A * x + B) % M;
-        assert!(lhs == rhs);
+// The math on a curve given by its parameters: the free functions
+// above are of the curve of the protocol (`curve::CURVE`), any other
+// one (e.g. `curve::TINY`, small enough to check by hand) can be
+// picked at run time
+impl curve::Curve {
+    pub fn fits(&self, p: curve::Point) -> bool {
+        let (x, y) = p;
+        mul_mod(y, y, self.m) == self.rhs(x)
     }
 
-    (x, y)
-}
+    fn rhs(&self, x: curve::Int) -> curve::Int {
+        let m = self.m;
+        (mul_mod(mul_mod(x, x, m), x, m)
+            + mul_mod(self.a, x, m)
+            + self.b)
+            % m
+    }
 
-pub fn mul(mut k: curve::Int, p: curve::Point) -> curve::Point {
-    let mut r = None;
-    let mut p = p;
+    fn inv(&self, x: curve::Int) -> curve::Int {
+        extended_gcd(x.rem_euclid(self.m), self.m)
+    }
 
-    while k > 0 {
-        if k % 2 > 0 {
-            r = match r {
-                Some(r) => Some(add(r, p)),
-                None => Some(p),
-            };
+    pub fn add(
+        &self,
+        p: curve::Point,
+        q: curve::Point,
+    ) -> curve::Point {
+        use curve::O;
+        let m = self.m;
+        if p == O {
+            return q;
         }
-        p = add(p, p);
-        k >>= 2;
+        if q == O {
+            return p;
+        }
+        let (px, py) = p;
+        let (qx, qy) = q;
+        if px == qx && (py + qy) % m == 0 {
+            return O;
+        }
+
+        let d = if px == qx {
+            let z = self.inv(2 * py);
+            mul_mod(3 * mul_mod(px, px, m) + self.a, z, m)
+        } else {
+            let z = self.inv(qx - px);
+            mul_mod(qy - py, z, m)
+        };
+
+        let x = (mul_mod(d, d, m) - px - qx).rem_euclid(m);
+        let y = (mul_mod(d, px - x, m) - py).rem_euclid(m);
+        assert!(self.fits((x, y)));
+
+        (x, y)
     }
 
-    let r = r.unwrap_or(p);
-    assert!(fits(r));
-    r
+    pub fn mul(
+        &self,
+        mut k: curve::Int,
+        p: curve::Point,
+    ) -> curve::Point {
+        let mut r = curve::O;
+        let mut p = p;
+
+        while k > 0 {
+            if k % 2 > 0 {
+                r = self.add(r, p);
+            }
+            p = self.add(p, p);
+            k >>= 1;
+        }
+
+        assert!(r == curve::O || self.fits(r));
+        r
+    }
+
+    // The same doubling and addition for each of the 32 bits
+    // whatever the bit is, which only picks (by masking, not by
+    // branching) the point to double. `add` itself still branches
+    // (the point at infinity, the inverse by gcd), the field
+    // arithmetic is not constant-time.
+    pub fn mul_ct(
+        &self,
+        k: curve::Int,
+        p: curve::Point,
+    ) -> curve::Point {
+        assert!(
+            (0..1 << 32).contains(&k),
+            "scalar of over 32 bits"
+        );
+        // (k >> i) * P and that plus P
+        let mut r = (curve::O, p);
+        for i in (0..32).rev() {
+            let bit = (k >> i) & 1;
+            swap(&mut r, bit);
+            r = (self.add(r.0, r.0), self.add(r.0, r.1));
+            swap(&mut r, bit);
+        }
+        r.0
+    }
+}
+
+// the points swapped if `bit` is 1, the same operations either way
+fn swap(r: &mut (curve::Point, curve::Point), bit: curve::Int) {
+    let mask = -bit;
+    let t = mask & (r.0 .0 ^ r.1 .0);
+    r.0 .0 ^= t;
+    r.1 .0 ^= t;
+    let t = mask & (r.0 .1 ^ r.1 .1);
+    r.0 .1 ^= t;
+    r.1 .1 ^= t;
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
+    use crate::arbitrary;
     use curve::*;
 
     #[test]
     fn text_mod_inv() {
         fn check(a: Int, b: Int) -> bool {
-            (a * b) % M == 1
+            mul_mod(a, b, M) == 1
         }
 
-        for a in [12345, 123456, 1234567] {
+        for a in [12345, 123456, 1234567, M - 1, -5] {
             let x = modular_inv(a);
             assert!(check(a, x));
         }
     }
 
     #[test]
-    #[ignore = "'attempt to multiply with overflow'"]
     fn test_math() {
         let g = curve::G;
         assert!(fits(g));
@@ -217,10 +752,239 @@ mod tests {
             let y = mul(a + b, g);
             assert_eq!(x, y, "[a={b} b={b}] {x:?} != {y:?}");
         }
+
+        // G is of order N, and large multiples do not overflow
+        assert_eq!(mul(N, g), O);
+        assert_eq!(mul(N - 1, g), (g.0, M - g.1));
+        let (a, b) = (N - 2, 0xDEADBEEF);
+        assert_eq!(
+            add(mul(a, g), mul(b, g)),
+            mul((a + b) % N, g)
+        );
+    }
+
+    #[test]
+    fn test_curves() {
+        // known multiples on the textbook curve
+        let tiny = TINY;
+        assert!(tiny.fits(tiny.g));
+        assert_eq!(tiny.mul(2, tiny.g), (6, 3));
+        assert_eq!(tiny.mul(3, tiny.g), (10, 6));
+        assert_eq!(tiny.mul(tiny.n - 1, tiny.g), (5, 16));
+        assert_eq!(tiny.mul(tiny.n, tiny.g), O);
+        // G generates all of the n - 1 points besides O
+        let mut points = (1..tiny.n)
+            .map(|k| tiny.mul(k, tiny.g))
+            .collect::<Vec<_>>();
+        assert!(points.iter().all(|p| tiny.fits(*p)));
+        points.sort();
+        points.dedup();
+        assert_eq!(points.len() as Int, tiny.n - 1);
+        for k in 0..=2 * tiny.n {
+            assert_eq!(
+                tiny.mul_ct(k, tiny.g),
+                tiny.mul(k, tiny.g)
+            );
+        }
+        // the free functions are of the protocol's curve
+        assert_eq!(CURVE.mul(12345, G), mul(12345, G));
+        assert_ne!(tiny, CURVE);
+        assert!(!CURVE.fits(tiny.g));
+    }
+
+    #[test]
+    fn test_encoding() -> Result<()> {
+        let secret_key = SecretKey::new(0xCAFEBABE);
+        assert_eq!(secret_key.to_hex(), "cafebabe");
+        let decoded = SecretKey::from_hex("cafebabe")?;
+        assert_eq!(
+            decoded.public_key(),
+            secret_key.public_key()
+        );
+        assert!(SecretKey::from_hex("00000000").is_err());
+        assert!(SecretKey::from_hex("cafe").is_err());
+        assert!(SecretKey::from_hex("cafebabx").is_err());
+
+        let public_key = secret_key.public_key();
+        let bytes = public_key.to_bytes();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(PublicKey::from_bytes(&bytes)?, public_key);
+        assert_eq!(
+            PublicKey::from_hex(&public_key.to_hex())?,
+            public_key
+        );
+        // off the curve, the point at infinity, out of the field
+        let mut off = bytes.clone();
+        off[7] ^= 1;
+        assert!(PublicKey::from_bytes(&off).is_err());
+        assert!(PublicKey::from_bytes(&[0; 8]).is_err());
+        let (x, y) = (1..)
+            .map(|k| mul(k, G))
+            .find(|p| p.0 + M <= u32::MAX as Int)
+            .unwrap();
+        let mut wrapped =
+            ((x + M) as u32).to_be_bytes().to_vec();
+        wrapped.extend((y as u32).to_be_bytes());
+        assert!(PublicKey::from_bytes(&wrapped).is_err());
+        assert!(PublicKey::from_bytes(&bytes[..7]).is_err());
+
+        let sig = secret_key.sign(&42);
+        let decoded = Signature::from_bytes(&sig.to_bytes())?;
+        assert!(public_key.is_valid(&42, &decoded));
+        assert!(Signature::from_bytes(&[0; 8]).is_err());
+        assert!(Signature::from_hex(&"ff".repeat(8)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate() {
+        let keys = (0..16)
+            .map(|_| SecretKey::generate())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            assert!((1..N).contains(&(key.0 as Int)));
+            assert_eq!(key.scheme(), Scheme::Ecdsa);
+            assert!(key.public_key().is_on_curve());
+        }
+        // 16 draws of ~2^32 do not repeat (but for ~2^-24)
+        let mut secrets =
+            keys.iter().map(|key| key.0).collect::<Vec<_>>();
+        secrets.sort();
+        secrets.dedup();
+        assert_eq!(secrets.len(), keys.len());
+    }
+
+    #[test]
+    fn test_ladder() {
+        let g = curve::G;
+        for k in [0, 1, 2, 3, 42, N - 1, N, N + 1, 0xFFFFFFFF] {
+            assert_eq!(mul_ct(k, g), mul(k, g), "k={k}");
+        }
+        let p = mul(0xCAFEBABE, g);
+        for k in [1, 0xDEADBEEF, N - 2] {
+            assert_eq!(mul_ct(k, p), mul(k, p), "k={k}");
+        }
+        let mut r = (g, p);
+        swap(&mut r, 0);
+        assert_eq!(r, (g, p));
+        swap(&mut r, 1);
+        assert_eq!(r, (p, g));
+    }
+
+    #[test]
+    fn test_compressed() -> Result<()> {
+        for secret in [1, 2, 42, 0xCAFEBABE, 0xDEADBEEF] {
+            let public_key = SecretKey::new(secret).public_key();
+            let bytes = public_key.to_compressed();
+            assert_eq!(bytes.len(), 5);
+            assert_eq!(bytes[0], 2 | (public_key.1 & 1) as u8);
+            assert_eq!(
+                PublicKey::from_compressed(&bytes)?,
+                public_key
+            );
+            assert_eq!(
+                PublicKey::from_bytes(&bytes)?,
+                public_key
+            );
+
+            // the other parity is the negated point
+            let mut other = bytes.clone();
+            other[0] ^= 1;
+            let negated = PublicKey::from_compressed(&other)?;
+            assert_eq!(negated.0, public_key.0);
+            assert_eq!(
+                negated.1 as Int,
+                M - public_key.1 as Int
+            );
+        }
+        // not a prefix, not a length, x of no point, x out of the
+        // field
+        let bytes =
+            SecretKey::new(42).public_key().to_compressed();
+        for prefix in [0, 1, 4] {
+            let mut off = bytes.clone();
+            off[0] = prefix;
+            assert!(PublicKey::from_compressed(&off).is_err());
+        }
+        assert!(PublicKey::from_compressed(&bytes[..4]).is_err());
+        assert!(PublicKey::from_compressed(&[2; 6]).is_err());
+        let x = (0..).find(|x| sqrt(rhs(*x)).is_none()).unwrap();
+        let mut off = vec![2];
+        off.extend((x as u32).to_be_bytes());
+        assert!(PublicKey::from_compressed(&off).is_err());
+        let mut off = vec![2];
+        off.extend((M as u32).to_be_bytes());
+        assert!(PublicKey::from_compressed(&off).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(M % 8, 5);
+        for x in [0, 1, 2, 12345, M - 1] {
+            let a = mul_mod(x, x, M);
+            let y = sqrt(a).unwrap();
+            assert!(y == x || y == M - x);
+        }
+        // half of the residues are not squares, 2 is not for M
+        assert_eq!(pow_mod(2, (M - 1) / 2, M), M - 1);
+        assert_eq!(sqrt(2), None);
+    }
+
+    #[test]
+    fn test_recover() {
+        for secret in [1, 42, 0xCAFEBABE] {
+            let secret_key = SecretKey::new(secret);
+            let public_key = secret_key.public_key();
+            for msg in [0, 1, 0xDEADBEEF] {
+                let sig = secret_key.sign(&msg);
+                let keys = recover(&msg, &sig);
+                assert!(keys.contains(&public_key));
+                assert!(keys.len() <= 4);
+                assert!(keys
+                    .iter()
+                    .all(|k| k.is_valid(&msg, &sig)));
+                // of another message, another key
+                assert!(!recover(&(msg + 1), &sig)
+                    .contains(&public_key));
+            }
+        }
+        assert!(recover(&1, &Signature(0, 1)).is_empty());
+    }
+
+    #[test]
+    fn test_batch() {
+        let keys = [
+            SecretKey::new(1),
+            SecretKey::new(0xCAFEBABE),
+            SecretKey::with_scheme(42, Scheme::Schnorr),
+        ];
+        let mut items = (0..30u32)
+            .map(|msg| {
+                let key = &keys[msg as usize % keys.len()];
+                (msg, key.sign(&msg), key.public_key())
+            })
+            .collect::<Vec<_>>();
+        assert!(verify_batch(&items));
+        assert!(verify_batch(&[]));
+        assert!(items
+            .iter()
+            .all(|(msg, sig, key)| key.is_valid(msg, sig)));
+
+        // a single bad one spoils the batch
+        items[17].0 += 1;
+        assert!(!verify_batch(&items));
+
+        let g = Table::g();
+        assert_eq!(g.mul(0), O);
+        assert_eq!(g.mul(N - 1), mul(N - 1, G));
+        assert_eq!(
+            g.mul(u32::MAX as Int),
+            mul(u32::MAX as Int, G)
+        );
     }
 
     #[test]
-    #[ignore = "'attempt to multiply with overflow'"]
     fn test_sign() {
         let secret = u32::from_be_bytes(*b"LOL!");
         let secret_key = SecretKey::new(secret);
@@ -232,5 +996,47 @@ mod tests {
             public_key.is_valid(&msg, &sig),
             "false negative: invalid signature"
         );
+
+        // the same nonce, the same signature
+        assert_eq!(secret_key.sign(&msg), sig);
+        assert_ne!(secret_key.sign(&(msg + 1)).0, sig.0);
+
+        let other = SecretKey::new(secret + 1).public_key();
+        assert!(
+            !other.is_valid(&msg, &sig),
+            "false positive: valid signature"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_key(
+            secret_key in arbitrary::secret_key(),
+            msg in any::<u32>(),
+        ) {
+            let public_key = secret_key.public_key();
+            let sig = secret_key.sign(&msg);
+            prop_assert!(public_key.is_valid(&msg, &sig));
+            prop_assert!(!public_key.is_valid(&(msg ^ 1), &sig));
+
+            let scheme = secret_key.scheme();
+            let bytes = secret_key.to_bytes();
+            let decoded = SecretKey::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(
+                decoded.public_key().with_scheme(scheme),
+                public_key.clone()
+            );
+            let full = public_key.to_bytes();
+            let compressed = public_key.to_compressed();
+            for bytes in [full, compressed] {
+                let decoded = PublicKey::from_bytes(&bytes);
+                prop_assert_eq!(
+                    decoded.unwrap().with_scheme(scheme),
+                    public_key.clone()
+                );
+            }
+            let decoded = Signature::from_bytes(&sig.to_bytes());
+            prop_assert_eq!(decoded.unwrap(), sig);
+        }
     }
 }
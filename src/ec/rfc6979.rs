@@ -0,0 +1,103 @@
+// Deterministic nonces for signing (RFC 6979, 3.2, HMAC-SHA256), so
+// that the nonce depends on both the key and the message and never
+// repeats for different messages, without a random number generator.
+// For a group order of a whole number of bytes only (as both curves
+// here), so that bits2int is taking the leading bytes.
+use crate::sha256::hmac_sha256;
+
+// Candidate nonces (big-endian, as many bytes as the group order):
+// it is up to the signer to skip a candidate that is not in [1, q),
+// or that makes a degenerate signature, and take the next one
+pub struct Nonces {
+    k: [u8; 32],
+    v: [u8; 32],
+    len: usize,
+    first: bool,
+}
+
+impl Nonces {
+    // `key` is the secret key, `digest` the message digest reduced
+    // modulo the group order, both as many bytes as the group order
+    pub fn new(key: &[u8], digest: &[u8]) -> Self {
+        Self::with_data(key, digest, &[])
+    }
+
+    // with additional data (3.6), so that other uses of the same key
+    // for the same digest (e.g. another signature scheme) get other
+    // nonces
+    pub fn with_data(
+        key: &[u8],
+        digest: &[u8],
+        data: &[u8],
+    ) -> Self {
+        assert_eq!(key.len(), digest.len());
+        assert!(key.len() <= 32, "group order of over 256 bits");
+        let v = [0x01; 32];
+        let k = hmac_sha256(
+            &[0x00; 32],
+            &[&v, &[0x00], key, digest, data],
+        );
+        let v = hmac_sha256(&k, &[&v]);
+        let k =
+            hmac_sha256(&k, &[&v, &[0x01], key, digest, data]);
+        let v = hmac_sha256(&k, &[&v]);
+        Self {
+            k,
+            v,
+            len: key.len(),
+            first: true,
+        }
+    }
+}
+
+impl Iterator for Nonces {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.first {
+            self.k = hmac_sha256(&self.k, &[&self.v, &[0x00]]);
+            self.v = hmac_sha256(&self.k, &[&self.v]);
+        }
+        self.first = false;
+        self.v = hmac_sha256(&self.k, &[&self.v]);
+        Some(self.v[..self.len].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sha256::sha256, util::to_hex};
+
+    #[test]
+    fn test_nonces() {
+        // secp256k1, the key of one
+        let mut key = [0u8; 32];
+        key[31] = 1;
+        let digest = sha256(b"Satoshi Nakamoto");
+        let mut nonces = Nonces::new(&key, &digest);
+        let k = nonces.next().unwrap();
+        assert_eq!(
+            to_hex(&k),
+            "8f8a276c19f4149656b280621e358cce24f5f52542772691ee69063b74f15d15"
+        );
+        assert_ne!(nonces.next().unwrap(), k);
+        // deterministic, and of the message
+        assert_eq!(
+            Nonces::new(&key, &digest).next().unwrap(),
+            k
+        );
+        let mut with_data =
+            Nonces::with_data(&key, &digest, b"x");
+        assert_ne!(with_data.next().unwrap(), k);
+        let other = sha256(b"Satoshi Nakamoto!");
+        assert_ne!(Nonces::new(&key, &other).next().unwrap(), k);
+        assert_eq!(
+            Nonces::new(&key[28..], &other[..4])
+                .next()
+                .unwrap()
+                .len(),
+            4
+        );
+    }
+}
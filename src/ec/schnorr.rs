@@ -0,0 +1,116 @@
+// Schnorr signatures over the same curve, in the (e, s) form so that
+// they fit `Signature` as ECDSA ones do: for the nonce k, R = kG and
+// the challenge e = H(R || P || msg), s = k + e*x (mod N); valid if
+// the challenge of R = sG - eP is e. Linear in the keys and nonces,
+// that is what makes it possible to aggregate signatures.
+use super::{
+    curve::{self, Int, Point, N},
+    digest, mul_ct, mul_mod, rfc6979, sha256, PublicKey,
+    SecretKey, Signature, Table,
+};
+
+pub fn sign(secret_key: &SecretKey, msg: &u32) -> Signature {
+    let key = secret_key.0 as Int % N;
+    let public_key = secret_key.public_key();
+    let p = (public_key.0 as Int, public_key.1 as Int);
+
+    // nonce must be in [1, N), skip those giving a degenerate `e`
+    // or `s`; never the one of an ECDSA signature of the same key
+    // and message, as the two together give the key away
+    let nonces = rfc6979::Nonces::with_data(
+        &(key as u32).to_be_bytes(),
+        &(digest(msg) as u32).to_be_bytes(),
+        b"schnorr",
+    );
+    nonces
+        .map(|k| {
+            u32::from_be_bytes([k[0], k[1], k[2], k[3]]) as Int
+        })
+        .filter(|k| (1..N).contains(k))
+        .find_map(|k| {
+            let e = challenge(mul_ct(k, curve::G), p, msg);
+            let s = (k + mul_mod(e, key, N)) % N;
+            tracing::trace!(msg, k, e, s, "sign (schnorr)");
+            (e > 0 && s > 0)
+                .then_some(Signature(e as u32, s as u32))
+        })
+        .expect("out of nonces")
+}
+
+pub fn is_valid(
+    public_key: &PublicKey,
+    msg: &u32,
+    sig: &Signature,
+) -> bool {
+    verify(msg, sig, &Table::new(public_key.point()))
+}
+
+// with the multiples of the public key in `table`
+pub(super) fn verify(
+    msg: &u32,
+    sig: &Signature,
+    table: &Table,
+) -> bool {
+    let (e, s) = (sig.0 as Int, sig.1 as Int);
+    if !(1..N).contains(&e) || !(1..N).contains(&s) {
+        return false;
+    }
+    let p = table.0[0];
+    // sG - eP = sG + (N - e)P
+    let r = super::add(Table::g().mul(s), table.mul(N - e));
+    if r == curve::O {
+        return false;
+    }
+    tracing::trace!(e, s, ?r, "verify (schnorr)");
+    challenge(r, p, msg) == e
+}
+
+// H(R || P || msg), the leading 32 bits, reduced
+pub(crate) fn challenge(r: Point, p: Point, msg: &u32) -> Int {
+    let bytes = [r.0, r.1, p.0, p.1]
+        .into_iter()
+        .flat_map(|x| (x as u32).to_be_bytes())
+        .chain(msg.to_be_bytes())
+        .collect::<Vec<_>>();
+    let h = sha256(&bytes);
+    u32::from_be_bytes([h[0], h[1], h[2], h[3]]) as Int % N
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Scheme;
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        let secret = u32::from_be_bytes(*b"LOL!");
+        let secret_key =
+            SecretKey::with_scheme(secret, Scheme::Schnorr);
+        let public_key = secret_key.public_key();
+        assert_eq!(public_key.scheme(), Scheme::Schnorr);
+
+        let msg = 0xCAFEBABEu32;
+        let sig = secret_key.sign(&msg);
+        assert!(
+            public_key.is_valid(&msg, &sig),
+            "false negative: invalid signature"
+        );
+        assert_eq!(secret_key.sign(&msg), sig);
+        assert!(!public_key.is_valid(&(msg + 1), &sig));
+
+        let other =
+            SecretKey::with_scheme(secret + 1, Scheme::Schnorr);
+        assert!(
+            !other.public_key().is_valid(&msg, &sig),
+            "false positive: valid signature"
+        );
+
+        // the schemes do not mix: the same point, the other scheme
+        let ecdsa =
+            public_key.clone().with_scheme(Scheme::Ecdsa);
+        assert!(!ecdsa.is_valid(&msg, &sig));
+        let sig = SecretKey::new(secret).sign(&msg);
+        assert!(ecdsa.is_valid(&msg, &sig));
+        assert!(!public_key.is_valid(&msg, &sig));
+    }
+}
@@ -0,0 +1,355 @@
+// ECDSA as in `ec`, over secp256k1 (y^2 = x^3 + 7, 256-bit): keys
+// and signatures are too wide for the 64-bit fields of a frame, so
+// the protocol sticks to the 32-bit curve, the message is a digest
+// (of up to 256 bits) made by the caller.
+use super::{halves, rfc6979::Nonces, u256::U256, Encoding};
+use crate::api::{Error, Result};
+
+pub type Point = (U256, U256);
+
+pub const P: U256 = U256([
+    0xFFFFFFFEFFFFFC2F,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+]);
+pub const B: U256 = U256([7, 0, 0, 0]);
+pub const G: Point = (
+    U256([
+        0x59F2815B16F81798,
+        0x029BFCDB2DCE28D9,
+        0x55A06295CE870B07,
+        0x79BE667EF9DCBBAC,
+    ]),
+    U256([
+        0x9C47D08FFB10D4B8,
+        0xFD17B448A6855419,
+        0x5DA4FBFC0E1108A8,
+        0x483ADA7726A3C465,
+    ]),
+);
+// order of G (prime)
+pub const N: U256 = U256([
+    0xBFD25E8CD0364141,
+    0xBAAEDCE6AF48A03B,
+    0xFFFFFFFFFFFFFFFE,
+    0xFFFFFFFFFFFFFFFF,
+]);
+
+// point at infinity: (0, 0) does not fit the curve as B != 0
+pub const O: Point = (U256::ZERO, U256::ZERO);
+
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct SecretKey(U256);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct PublicKey(U256, U256);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Signature(U256, U256);
+
+impl SecretKey {
+    // must be in [1, N)
+    pub fn new(secret: U256) -> Self {
+        assert!(!secret.is_zero() && secret < N, "invalid key");
+        Self(secret)
+    }
+
+    pub fn random() -> Self {
+        Self(random_scalar())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let (x, y) = mul(&self.0, G);
+        PublicKey(x, y)
+    }
+
+    pub fn sign(&self, msg: &U256) -> Signature {
+        let h = msg.rem(&N);
+        // nonce in [1, N), skip those giving a degenerate `r` or `s`
+        Nonces::new(&self.0.to_be_bytes(), &h.to_be_bytes())
+            .map(|k| U256::from_be_bytes(k.try_into().unwrap()))
+            .filter(|k| !k.is_zero() && *k < N)
+            .find_map(|k| {
+                let r = mul(&k, G).0.rem(&N);
+                let s = k.inv_mod(&N).mul_mod(
+                    &h.add_mod(&r.mul_mod(&self.0, &N), &N),
+                    &N,
+                );
+                (!r.is_zero() && !s.is_zero())
+                    .then_some(Signature(r, s))
+            })
+            .expect("out of nonces")
+    }
+}
+
+impl PublicKey {
+    pub fn is_on_curve(&self) -> bool {
+        self.0 < P && self.1 < P && fits((self.0, self.1))
+    }
+
+    pub fn is_valid(&self, msg: &U256, sig: &Signature) -> bool {
+        let Signature(r, s) = sig;
+        let in_range = |x: &U256| !x.is_zero() && *x < N;
+        if !in_range(r) || !in_range(s) || !self.is_on_curve() {
+            return false;
+        }
+        let h = msg.rem(&N);
+        let s_inv = s.inv_mod(&N);
+
+        let a = mul(&h.mul_mod(&s_inv, &N), G);
+        let b = mul(&r.mul_mod(&s_inv, &N), (self.0, self.1));
+        let p = add(a, b);
+        p != O && p.0.rem(&N) == *r
+    }
+}
+
+impl Encoding for SecretKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; 32] =
+            bytes.try_into().map_err(|_| {
+                Error::App(format!(
+                    "invalid secret key: {} bytes",
+                    bytes.len()
+                ))
+            })?;
+        let key = U256::from_be_bytes(bytes);
+        if key.is_zero() || key >= N {
+            return Err(Error::App(
+                "invalid secret key: out of range".to_string(),
+            ));
+        }
+        Ok(Self(key))
+    }
+}
+
+// uncompressed: x then y, without the SEC1 prefix
+impl Encoding for PublicKey {
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.0.to_be_bytes(), self.1.to_be_bytes()].concat()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (x, y) = halves(bytes, 32, "public key")?;
+        let key =
+            Self(U256::from_be_bytes(x), U256::from_be_bytes(y));
+        if !key.is_on_curve() {
+            return Err(Error::App(
+                "invalid public key: not on the curve"
+                    .to_string(),
+            ));
+        }
+        Ok(key)
+    }
+}
+
+impl Encoding for Signature {
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.0.to_be_bytes(), self.1.to_be_bytes()].concat()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (r, s) = halves(bytes, 32, "signature")?;
+        let (r, s) =
+            (U256::from_be_bytes(r), U256::from_be_bytes(s));
+        let in_range = |x: &U256| !x.is_zero() && *x < N;
+        if !in_range(&r) || !in_range(&s) {
+            return Err(Error::App(
+                "invalid signature: out of range".to_string(),
+            ));
+        }
+        Ok(Self(r, s))
+    }
+}
+
+// uniformly random in [1, N)
+fn random_scalar() -> U256 {
+    loop {
+        let x = U256(rand::random());
+        if !x.is_zero() && x < N {
+            return x;
+        }
+    }
+}
+
+pub fn fits(p: Point) -> bool {
+    let (x, y) = p;
+    let lhs = y.mul_mod(&y, &P);
+    let rhs = x.mul_mod(&x, &P).mul_mod(&x, &P).add_mod(&B, &P);
+    lhs == rhs
+}
+
+pub fn add(p: Point, q: Point) -> Point {
+    if p == O {
+        return q;
+    }
+    if q == O {
+        return p;
+    }
+    let (px, py) = p;
+    let (qx, qy) = q;
+    if px == qx && py.add_mod(&qy, &P).is_zero() {
+        return O;
+    }
+
+    let d = if px == qx {
+        // (3 * x^2) / (2 * y), A being zero
+        let xx = px.mul_mod(&px, &P);
+        let z = py.add_mod(&py, &P).inv_mod(&P);
+        xx.add_mod(&xx, &P).add_mod(&xx, &P).mul_mod(&z, &P)
+    } else {
+        let z = qx.sub_mod(&px, &P).inv_mod(&P);
+        qy.sub_mod(&py, &P).mul_mod(&z, &P)
+    };
+
+    let x = d.mul_mod(&d, &P).sub_mod(&px, &P).sub_mod(&qx, &P);
+    let y = d.mul_mod(&px.sub_mod(&x, &P), &P).sub_mod(&py, &P);
+    debug_assert!(fits((x, y)));
+
+    (x, y)
+}
+
+pub fn mul(k: &U256, p: Point) -> Point {
+    let mut r = O;
+    let mut p = p;
+    for i in 0..k.bits() {
+        if k.bit(i) {
+            r = add(r, p);
+        }
+        p = add(p, p);
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sha256::sha256, util::from_hex};
+
+    #[test]
+    fn test_math() {
+        assert!(fits(G));
+        let two = U256::from(2);
+        assert_eq!(mul(&U256::ONE, G), G);
+        assert_eq!(mul(&two, G), add(G, G));
+        // 2G, a known value
+        assert_eq!(
+            mul(&two, G).0.to_be_bytes(),
+            [
+                0xC6, 0x04, 0x7F, 0x94, 0x41, 0xED, 0x7D, 0x6D,
+                0x30, 0x45, 0x40, 0x6E, 0x95, 0xC0, 0x7C, 0xD8,
+                0x5C, 0x77, 0x8E, 0x4B, 0x8C, 0xEF, 0x3C, 0xA7,
+                0xAB, 0xAC, 0x09, 0xB9, 0x5C, 0x70, 0x9E, 0xE5,
+            ]
+        );
+        assert_eq!(mul(&N, G), O);
+        let minus_one = N.overflowing_sub(&U256::ONE).0;
+        assert_eq!(mul(&minus_one, G), (G.0, G.1.neg_mod(&P)));
+
+        let (a, b) = (U256([1, 2, 3, 4]), U256([5, 6, 7, 8]));
+        assert_eq!(
+            add(mul(&a, G), mul(&b, G)),
+            mul(&a.add_mod(&b, &N), G)
+        );
+    }
+
+    #[test]
+    fn test_sign() {
+        let secret_key = SecretKey::random();
+        let public_key = secret_key.public_key();
+        assert!(public_key.is_on_curve());
+
+        let msg = U256([0xCAFEBABE, 0, 0, 1 << 60]);
+        let sig = secret_key.sign(&msg);
+        assert!(
+            public_key.is_valid(&msg, &sig),
+            "false negative: invalid signature"
+        );
+        let other = U256([0xCAFEBABF, 0, 0, 1 << 60]);
+        assert!(!public_key.is_valid(&other, &sig));
+
+        let other = SecretKey::new(U256::from(42)).public_key();
+        assert!(
+            !other.is_valid(&msg, &sig),
+            "false positive: valid signature"
+        );
+        assert!(!public_key
+            .is_valid(&msg, &Signature(sig.0, U256::ZERO)));
+        assert!(!PublicKey(U256::ONE, U256::ONE)
+            .is_valid(&msg, &sig));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let hex = |s: &str| {
+            U256::from_be_bytes(
+                from_hex(s).unwrap().try_into().unwrap(),
+            )
+        };
+        // RFC 6979 nonce, a known signature
+        let secret_key = SecretKey::new(U256::ONE);
+        let msg =
+            U256::from_be_bytes(sha256(b"Satoshi Nakamoto"));
+        let sig = secret_key.sign(&msg);
+        assert_eq!(
+            sig,
+            Signature(
+                hex("934b1ea10a4b3c1757e2b0c017d0b6143ce3c9a7e6a4a49860d7a6ab210ee3d8"),
+                hex("dbbd3162d46e9f9bef7feb87c16dc13b4f6568a87f4e83f728e2443ba586675c"),
+            )
+        );
+        assert!(secret_key.public_key().is_valid(&msg, &sig));
+        assert_eq!(secret_key.sign(&msg), sig);
+    }
+
+    #[test]
+    fn test_encoding() -> Result<()> {
+        let secret_key = SecretKey::new(U256::from(0xCAFEBABE));
+        let hex = secret_key.to_hex();
+        assert_eq!(hex, format!("{}cafebabe", "0".repeat(56)));
+        let decoded = SecretKey::from_hex(&hex)?;
+        assert_eq!(decoded.0, secret_key.0);
+        assert!(SecretKey::from_bytes(&[0; 32]).is_err());
+        assert!(SecretKey::from_bytes(&N.to_be_bytes()).is_err());
+
+        // G, a known encoding
+        let public_key = SecretKey::new(U256::ONE).public_key();
+        assert_eq!(
+            &public_key.to_hex()[..16],
+            "79be667ef9dcbbac"
+        );
+        let bytes = public_key.to_bytes();
+        assert_eq!(bytes.len(), 64);
+        assert_eq!(PublicKey::from_bytes(&bytes)?, public_key);
+        let mut off = bytes.clone();
+        off[63] ^= 1;
+        assert!(PublicKey::from_bytes(&off).is_err());
+        assert!(PublicKey::from_bytes(&[0; 64]).is_err());
+        assert!(PublicKey::from_bytes(&bytes[1..]).is_err());
+
+        let msg = U256::from(42);
+        let sig = secret_key.sign(&msg);
+        let decoded = Signature::from_hex(&sig.to_hex())?;
+        assert!(secret_key
+            .public_key()
+            .is_valid(&msg, &decoded));
+        assert!(Signature::from_bytes(&[0xff; 64]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use std::cmp::Ordering;
+
+// Unsigned 256-bit integer, 64-bit limbs least significant first.
+// Only what the 256-bit curves (see `secp256k1`) need: arithmetic
+// modulo `m` for operands already reduced (in [0, m)) and `m` above
+// 2^255.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct U256(pub [u64; 4]);
+
+impl U256 {
+    pub const ZERO: Self = Self([0; 4]);
+    pub const ONE: Self = Self([1, 0, 0, 0]);
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, chunk) in bytes.chunks_exact(8).enumerate() {
+            let mut limb = [0u8; 8];
+            limb.copy_from_slice(chunk);
+            limbs[3 - i] = u64::from_be_bytes(limb);
+        }
+        Self(limbs)
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, chunk) in bytes.chunks_exact_mut(8).enumerate() {
+            chunk.copy_from_slice(&self.0[3 - i].to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    pub fn bit(&self, i: usize) -> bool {
+        (self.0[i / 64] >> (i % 64)) & 1 == 1
+    }
+
+    // position of the highest bit set plus one, zero for zero
+    pub fn bits(&self) -> usize {
+        (0..4).rev().find(|i| self.0[*i] != 0).map_or(0, |i| {
+            64 * i + 64 - self.0[i].leading_zeros() as usize
+        })
+    }
+
+    pub fn overflowing_add(&self, other: &Self) -> (Self, bool) {
+        let mut r = [0u64; 4];
+        let mut carry = false;
+        for (i, r) in r.iter_mut().enumerate() {
+            let (x, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (x, c2) = x.overflowing_add(carry as u64);
+            *r = x;
+            carry = c1 || c2;
+        }
+        (Self(r), carry)
+    }
+
+    pub fn overflowing_sub(&self, other: &Self) -> (Self, bool) {
+        let mut r = [0u64; 4];
+        let mut borrow = false;
+        for (i, r) in r.iter_mut().enumerate() {
+            let (x, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (x, b2) = x.overflowing_sub(borrow as u64);
+            *r = x;
+            borrow = b1 || b2;
+        }
+        (Self(r), borrow)
+    }
+
+    // self % m, for any value of self
+    pub fn rem(&self, m: &Self) -> Self {
+        reduce(
+            [
+                self.0[0], self.0[1], self.0[2], self.0[3], 0,
+                0, 0, 0,
+            ],
+            m,
+        )
+    }
+
+    pub fn add_mod(&self, other: &Self, m: &Self) -> Self {
+        let (x, carry) = self.overflowing_add(other);
+        if carry || x >= *m {
+            x.overflowing_sub(m).0
+        } else {
+            x
+        }
+    }
+
+    pub fn sub_mod(&self, other: &Self, m: &Self) -> Self {
+        let (x, borrow) = self.overflowing_sub(other);
+        if borrow {
+            x.overflowing_add(m).0
+        } else {
+            x
+        }
+    }
+
+    pub fn neg_mod(&self, m: &Self) -> Self {
+        Self::ZERO.sub_mod(self, m)
+    }
+
+    pub fn mul_mod(&self, other: &Self, m: &Self) -> Self {
+        reduce(mul_wide(&self.0, &other.0), m)
+    }
+
+    pub fn pow_mod(&self, exp: &Self, m: &Self) -> Self {
+        let mut r = Self::ONE.rem(m);
+        for i in (0..exp.bits()).rev() {
+            r = r.mul_mod(&r, m);
+            if exp.bit(i) {
+                r = r.mul_mod(self, m);
+            }
+        }
+        r
+    }
+
+    // by Fermat's little theorem: `m` must be prime
+    pub fn inv_mod(&self, m: &Self) -> Self {
+        if self.is_zero() {
+            panic!("division by zero");
+        }
+        let two = Self([2, 0, 0, 0]);
+        self.pow_mod(&m.overflowing_sub(&two).0, m)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(x: u64) -> Self {
+        Self([x, 0, 0, 0])
+    }
+}
+
+fn mul_wide(a: &[u64; 4], b: &[u64; 4]) -> [u64; 8] {
+    let mut r = [0u64; 8];
+    for i in 0..4 {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let t = a[i] as u128 * b[j] as u128
+                + r[i + j] as u128
+                + carry;
+            r[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        r[i + 4] = carry as u64;
+    }
+    r
+}
+
+// x % m for x = hi * 2^256 + lo: as 2^256 = c (mod m) where
+// c = 2^256 - m, x = hi * c + lo, which is folded again until
+// there is no `hi`; with m above 2^255 every round at least halves
+// `hi` (and for the curve moduli, close to 2^256, it takes 2 or 3)
+fn reduce(mut x: [u64; 8], m: &U256) -> U256 {
+    debug_assert!(m.bit(255), "modulus below 2^255");
+    let c = U256::ZERO.overflowing_sub(m).0;
+    while x[4..] != [0; 4] {
+        let hi = [x[4], x[5], x[6], x[7]];
+        let mut t = mul_wide(&hi, &c.0);
+        let mut carry = false;
+        for i in 0..8 {
+            let lo = if i < 4 { x[i] } else { 0 };
+            let (s, c1) = t[i].overflowing_add(lo);
+            let (s, c2) = s.overflowing_add(carry as u64);
+            t[i] = s;
+            carry = c1 || c2;
+        }
+        x = t;
+    }
+    let r = U256([x[0], x[1], x[2], x[3]]);
+    if r >= *m {
+        r.overflowing_sub(m).0
+    } else {
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // secp256k1: field prime and group order
+    const P: U256 = U256([
+        0xFFFFFFFEFFFFFC2F,
+        0xFFFFFFFFFFFFFFFF,
+        0xFFFFFFFFFFFFFFFF,
+        0xFFFFFFFFFFFFFFFF,
+    ]);
+    const N: U256 = U256([
+        0xBFD25E8CD0364141,
+        0xBAAEDCE6AF48A03B,
+        0xFFFFFFFFFFFFFFFE,
+        0xFFFFFFFFFFFFFFFF,
+    ]);
+
+    #[test]
+    fn test_bytes() {
+        let x = U256([1, 2, 3, 4]);
+        let bytes = x.to_be_bytes();
+        assert_eq!(bytes[7], 4);
+        assert_eq!(bytes[31], 1);
+        assert_eq!(U256::from_be_bytes(bytes), x);
+        assert!(U256([0, 0, 0, 1]) > U256([u64::MAX, 0, 0, 0]));
+        assert_eq!(U256([0, 0, 0, 1]).bits(), 193);
+        assert_eq!(U256::ZERO.bits(), 0);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        for m in [P, N] {
+            let one = U256::ONE;
+            let minus_one = m.overflowing_sub(&one).0;
+            assert_eq!(minus_one.add_mod(&one, &m), U256::ZERO);
+            assert_eq!(U256::ZERO.sub_mod(&one, &m), minus_one);
+            assert_eq!(minus_one.mul_mod(&minus_one, &m), one);
+            assert_eq!(minus_one.neg_mod(&m), one);
+            let max = U256([u64::MAX; 4]);
+            assert_eq!(max.rem(&m), max.overflowing_sub(&m).0);
+
+            // small values, against u128
+            let (a, b) =
+                (0xDEADBEEFCAFEBABEu64, 0x0123456789ABCDEFu64);
+            let ab = a as u128 * b as u128;
+            let x = U256::from(a).mul_mod(&U256::from(b), &m);
+            assert_eq!(
+                x,
+                U256([ab as u64, (ab >> 64) as u64, 0, 0])
+            );
+
+            let x = U256([a, b, a, b]).rem(&m);
+            let inv = x.inv_mod(&m);
+            assert_eq!(x.mul_mod(&inv, &m), one);
+            assert_eq!(
+                x.pow_mod(&U256::from(3), &m),
+                x.mul_mod(&x, &m).mul_mod(&x, &m)
+            );
+        }
+        // 2^256 mod P = 2^32 + 977
+        let x = U256([0, 0, 0, 1 << 63]);
+        assert_eq!(
+            x.add_mod(&x, &P),
+            U256::from((1 << 32) + 977)
+        );
+    }
+}
@@ -0,0 +1,450 @@
+// Threshold Schnorr signatures (FROST, two rounds, trusted dealer):
+// the signing key x is Shamir-shared over Z_N (N is the order of the
+// curve), and any k holders of the shares sign a message together,
+// each with its own share, without x being put together anywhere.
+// What they make is a Schnorr signature (see `ec::schnorr`) of the
+// group key P = xG.
+//
+// Round one: each signer i picks the nonces (d_i, e_i), for a single
+// signature, and commits to them: D_i = d_iG, E_i = e_iG. Round two,
+// given the message m and the commitments B of all the signers: the
+// binding factor r_i = H(i || m || B), the group commitment
+// R = Σ D_j + r_jE_j, the challenge c = H(R || P || m), and the
+// share of the signature z_i = d_i + e_ir_i + λ_ix_ic (λ_i is the
+// Lagrange coefficient of i among the signers). Then z = Σ z_i, and
+// zG - cP = R: (c, z) is the signature.
+use crate::{
+    api::{Error, Result},
+    ec::{
+        self,
+        curve::{self, Int, Point, N},
+        extended_gcd, mul, mul_mod, schnorr, Encoding,
+        PublicKey, Scheme, SecretKey, Signature,
+    },
+    sha256::sha256,
+    util::merge as merge64,
+};
+
+// `ext` bit of a share frame: a share of a signing key, the rest of
+// `ext` is the same as for a Shamir share, the group key follows the
+// TTL in the payload
+pub const SIGNING: u32 = 1 << 29;
+
+// x, then D and E as public keys (see `Encoding`)
+const COMMITMENT_LEN: usize = 20;
+
+// (a + b) mod N
+pub fn add(a: u32, b: u32) -> u32 {
+    ((a as Int + b as Int) % N) as u32
+}
+
+// Value of the polynomial at `x` (mod N), `coeffs[0]` is the
+// constant term
+pub fn eval(coeffs: &[u32], x: u32) -> u32 {
+    coeffs.iter().rev().fold(0, |acc, c| {
+        let acc = mul_mod(acc as Int, x as Int, N);
+        ((acc + *c as Int) % N) as u32
+    })
+}
+
+// `n` shares of the signing key `s` (share `i` is at x = i + 1), `k`
+// of which sign, and the group key to check the signatures with
+pub fn split(
+    s: u32,
+    k: usize,
+    n: usize,
+    f: impl Fn() -> u32,
+) -> (Vec<(u32, u32)>, PublicKey) {
+    assert!(k >= 1 && k <= n, "invalid threshold: {k} of {n}");
+    assert!(
+        (1..N).contains(&(s as Int)),
+        "key out of range: {s}"
+    );
+    let coeffs = std::iter::once(s)
+        .chain((1..k).map(|_| (f() as Int % N) as u32))
+        .collect::<Vec<_>>();
+    let shares =
+        (1..=n as u32).map(|x| (x, eval(&coeffs, x))).collect();
+    let group_key =
+        SecretKey::with_scheme(s, Scheme::Schnorr).public_key();
+    (shares, group_key)
+}
+
+// Lagrange coefficient of `x` at zero (mod N), among the `signers`
+pub fn lagrange(x: u32, signers: &[u32]) -> Int {
+    let (num, den) = signers.iter().filter(|j| **j != x).fold(
+        (1, 1),
+        |(num, den), j| {
+            let j = *j as Int;
+            (mul_mod(num, j, N), mul_mod(den, j - x as Int, N))
+        },
+    );
+    mul_mod(num, extended_gcd(den, N), N)
+}
+
+// The signing key back from any `k` of the shares
+pub fn merge(shares: &[(u32, u32)]) -> u32 {
+    let signers =
+        shares.iter().map(|(x, _)| *x).collect::<Vec<_>>();
+    shares.iter().fold(0, |acc, (x, y)| {
+        let term = mul_mod(*y as Int, lagrange(*x, &signers), N);
+        ((acc as Int + term) % N) as u32
+    })
+}
+
+// The public counterpart of a share (Y_i = y_iG), to check the
+// signer's share of a signature against
+pub fn key_share(y: u32) -> PublicKey {
+    SecretKey::new(y).public_key()
+}
+
+// Nonces of a signer for a single signature: the share of the key
+// can be found from two signatures made with the same nonces, hence
+// `sign` takes them away
+pub struct Nonce(Int, Int);
+
+impl Nonce {
+    pub fn random(f: impl Fn() -> u32) -> Self {
+        let scalar = || loop {
+            let k = f() as Int;
+            if (1..N).contains(&k) {
+                return k;
+            }
+        };
+        Self(scalar(), scalar())
+    }
+
+    pub fn commitment(&self, x: u32) -> Commitment {
+        Commitment {
+            x,
+            d: ec::mul_ct(self.0, curve::G),
+            e: ec::mul_ct(self.1, curve::G),
+        }
+    }
+}
+
+// not to end up in logs
+impl std::fmt::Debug for Nonce {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.write_str("Nonce(..)")
+    }
+}
+
+// Of the signer at `x` to its nonces
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Commitment {
+    pub x: u32,
+    pub d: Point,
+    pub e: Point,
+}
+
+impl Commitment {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.x
+            .to_be_bytes()
+            .into_iter()
+            .chain(encode(self.d))
+            .chain(encode(self.e))
+            .collect()
+    }
+
+    // The commitments of all the signers, one after the other: each
+    // on the curve, ordered by x (no x twice, nor zero, the secret's
+    // own), so that everyone binds to the same bytes
+    pub fn parse(bytes: &[u8]) -> Result<Vec<Self>> {
+        if bytes.is_empty()
+            || !bytes.len().is_multiple_of(COMMITMENT_LEN)
+        {
+            return Err(Error::App(format!(
+                "invalid commitments: {} bytes",
+                bytes.len()
+            )));
+        }
+        let commitments = bytes
+            .chunks_exact(COMMITMENT_LEN)
+            .map(|chunk| {
+                let (x, points) = chunk.split_at(4);
+                Ok(Self {
+                    x: u32::from_be_bytes(x.try_into().unwrap()),
+                    d: decode(&points[..8])?,
+                    e: decode(&points[8..])?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if commitments[0].x == 0
+            || !commitments.windows(2).all(|w| w[0].x < w[1].x)
+        {
+            return Err(Error::App(
+                "invalid commitments: not ordered by x"
+                    .to_string(),
+            ));
+        }
+        Ok(commitments)
+    }
+}
+
+fn encode(p: Point) -> Vec<u8> {
+    PublicKey::from(merge64(p.0 as u32, p.1 as u32)).to_bytes()
+}
+
+fn decode(bytes: &[u8]) -> Result<Point> {
+    PublicKey::from_bytes(bytes).map(|key| key.point())
+}
+
+// H(x || msg || commitments), the leading 32 bits, reduced
+fn binding(x: u32, msg: u32, commitments: &[Commitment]) -> Int {
+    let bytes = x
+        .to_be_bytes()
+        .into_iter()
+        .chain(msg.to_be_bytes())
+        .chain(commitments.iter().flat_map(Commitment::to_bytes))
+        .collect::<Vec<_>>();
+    let h = sha256(&bytes);
+    u32::from_be_bytes([h[0], h[1], h[2], h[3]]) as Int % N
+}
+
+// R, and the challenge c of it
+fn challenge(
+    msg: u32,
+    commitments: &[Commitment],
+    group_key: &PublicKey,
+) -> Int {
+    let r = commitments.iter().fold(curve::O, |r, c| {
+        let rho = binding(c.x, msg, commitments);
+        ec::add(r, ec::add(c.d, mul(rho, c.e)))
+    });
+    schnorr::challenge(r, group_key.point(), &msg)
+}
+
+// The signer's share of the signature (z_i), none if the signer's
+// commitment (of the `nonce`) is not among the `commitments`
+pub fn sign(
+    share: (u32, u32),
+    nonce: Nonce,
+    msg: u32,
+    commitments: &[Commitment],
+    group_key: &PublicKey,
+) -> Option<u32> {
+    let (x, y) = share;
+    let own = commitments.iter().find(|c| c.x == x)?;
+    if *own != nonce.commitment(x) {
+        return None;
+    }
+    let signers =
+        commitments.iter().map(|c| c.x).collect::<Vec<_>>();
+    let rho = binding(x, msg, commitments);
+    let c = challenge(msg, commitments, group_key);
+    let z = nonce.0
+        + mul_mod(nonce.1, rho, N)
+        + mul_mod(
+            mul_mod(lagrange(x, &signers), y as Int, N),
+            c,
+            N,
+        );
+    Some((z % N) as u32)
+}
+
+// Whether the share of the signature (z_i) is of the signer's share
+// of the key (Y_i, see `key_share`): z_iG = D_i + r_iE_i + cλ_iY_i
+pub fn verify(
+    x: u32,
+    z: u32,
+    key_share: &PublicKey,
+    msg: u32,
+    commitments: &[Commitment],
+    group_key: &PublicKey,
+) -> bool {
+    let Some(own) = commitments.iter().find(|c| c.x == x) else {
+        return false;
+    };
+    let signers =
+        commitments.iter().map(|c| c.x).collect::<Vec<_>>();
+    let rho = binding(x, msg, commitments);
+    let c = challenge(msg, commitments, group_key);
+    let lhs = mul(z as Int, curve::G);
+    let rhs = ec::add(
+        ec::add(own.d, mul(rho, own.e)),
+        mul(
+            mul_mod(c, lagrange(x, &signers), N),
+            key_share.point(),
+        ),
+    );
+    lhs == rhs
+}
+
+// The signature from the shares of it, of all the signers
+pub fn aggregate(
+    msg: u32,
+    commitments: &[Commitment],
+    shares: &[u32],
+    group_key: &PublicKey,
+) -> Signature {
+    let c = challenge(msg, commitments, group_key);
+    let z = shares.iter().fold(0, |z, s| add(z, *s));
+    Signature::from(merge64(c as u32, z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::random;
+
+    #[test]
+    fn test_split_merge() {
+        let secret = 0xCAFEBABE;
+        let (shares, group_key) = split(secret, 3, 5, random);
+        assert_eq!(merge(&shares[..3]), secret);
+        assert_eq!(merge(&shares[2..]), secret);
+        assert_eq!(
+            merge(&[shares[0], shares[2], shares[4]]),
+            secret
+        );
+        assert_ne!(merge(&shares[..2]), secret);
+        assert_eq!(group_key.scheme(), Scheme::Schnorr);
+
+        // a polynomial with zero constant term keeps the key
+        let delta =
+            [0, random() % N as u32, random() % N as u32];
+        let refreshed = shares
+            .iter()
+            .map(|(x, y)| (*x, add(*y, eval(&delta, *x))))
+            .collect::<Vec<_>>();
+        assert_ne!(refreshed, shares);
+        assert_eq!(merge(&refreshed[1..4]), secret);
+    }
+
+    fn round(
+        shares: &[(u32, u32)],
+        msg: u32,
+        group_key: &PublicKey,
+    ) -> (Vec<Commitment>, Vec<u32>) {
+        let nonces = shares
+            .iter()
+            .map(|_| Nonce::random(random))
+            .collect::<Vec<_>>();
+        let commitments = shares
+            .iter()
+            .zip(&nonces)
+            .map(|((x, _), nonce)| nonce.commitment(*x))
+            .collect::<Vec<_>>();
+        let z = shares
+            .iter()
+            .zip(nonces)
+            .map(|(share, nonce)| {
+                sign(*share, nonce, msg, &commitments, group_key)
+                    .unwrap()
+            })
+            .collect();
+        (commitments, z)
+    }
+
+    #[test]
+    fn test_sign() {
+        let (shares, group_key) =
+            split(u32::from_be_bytes(*b"LOL!"), 2, 3, random);
+        let msg = 0xCAFEBABE;
+
+        // any two of the three
+        for signers in [[0, 1], [1, 2], [0, 2]] {
+            let shares = signers.map(|i| shares[i]).to_vec();
+            let (commitments, z) =
+                round(&shares, msg, &group_key);
+            for ((x, y), z) in shares.iter().zip(&z) {
+                assert!(verify(
+                    *x,
+                    *z,
+                    &key_share(*y),
+                    msg,
+                    &commitments,
+                    &group_key
+                ));
+            }
+            let sig =
+                aggregate(msg, &commitments, &z, &group_key);
+            assert!(
+                group_key.is_valid(&msg, &sig),
+                "false negative: invalid signature"
+            );
+            assert!(!group_key.is_valid(&(msg + 1), &sig));
+
+            // a wrong share is caught, and spoils the signature
+            let (x, y) = shares[0];
+            let wrong = add(z[0], 1);
+            assert!(!verify(
+                x,
+                wrong,
+                &key_share(y),
+                msg,
+                &commitments,
+                &group_key
+            ));
+            let sig = aggregate(
+                msg,
+                &commitments,
+                &[wrong, z[1]],
+                &group_key,
+            );
+            assert!(!group_key.is_valid(&msg, &sig));
+        }
+
+        // fewer than the threshold do not make a signature
+        let (commitments, z) =
+            round(&shares[..1], msg, &group_key);
+        let sig = aggregate(msg, &commitments, &z, &group_key);
+        assert!(!group_key.is_valid(&msg, &sig));
+    }
+
+    #[test]
+    fn test_nonce() {
+        let (shares, group_key) = split(42, 2, 2, random);
+        let nonce = Nonce::random(random);
+        let other = Nonce::random(random);
+        let commitments = [
+            nonce.commitment(shares[0].0),
+            other.commitment(shares[1].0),
+        ];
+        // not the nonce committed to, nor a signer
+        let another = Nonce::random(random);
+        assert!(sign(
+            shares[0],
+            another,
+            1,
+            &commitments,
+            &group_key
+        )
+        .is_none());
+        assert!(sign(
+            (3, shares[0].1),
+            nonce,
+            1,
+            &commitments,
+            &group_key
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_commitments() -> Result<()> {
+        let a = Nonce::random(random).commitment(1);
+        let b = Nonce::random(random).commitment(2);
+        let bytes = [a.to_bytes(), b.to_bytes()].concat();
+        assert_eq!(bytes.len(), 2 * COMMITMENT_LEN);
+        assert_eq!(Commitment::parse(&bytes)?, vec![a, b]);
+
+        let swapped = [b.to_bytes(), a.to_bytes()].concat();
+        assert!(Commitment::parse(&swapped).is_err());
+        let twice = [a.to_bytes(), a.to_bytes()].concat();
+        assert!(Commitment::parse(&twice).is_err());
+        assert!(Commitment::parse(&bytes[1..]).is_err());
+        assert!(Commitment::parse(&[]).is_err());
+        let zero = Nonce::random(random).commitment(0);
+        assert!(Commitment::parse(&zero.to_bytes()).is_err());
+        let mut off = bytes.clone();
+        off[11] ^= 1;
+        assert!(Commitment::parse(&off).is_err());
+        Ok(())
+    }
+}
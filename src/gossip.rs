@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::api::{Error, Result, MAX_PAYLOAD_LEN};
+
+// ip (IPv4-mapped for IPv4), port, heartbeat, state
+const ENTRY_LEN: usize = 16 + 2 + 8 + 1;
+
+// Liveness of a member as seen by a node: its heartbeat went up
+// within `suspect` (alive), within `dead` (suspect), or not since
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        let state = match self {
+            State::Alive => "alive",
+            State::Suspect => "suspect",
+            State::Dead => "dead",
+        };
+        write!(f, "{state}")
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Member {
+    heartbeat: u64,
+    updated: Instant, // when the heartbeat last went up
+}
+
+// The membership view of a node: the members it heard of (directly
+// or through others) and their heartbeats. Each node bumps its own
+// heartbeat every round (`tick`) and sends its view to a random
+// member (TAG_GOSSIP, see the server), and takes the higher
+// heartbeat of each member from the views it gets (`merge`): a
+// member whose heartbeat stopped going up is suspect, then dead.
+// A heartbeat is the member's clock in milliseconds, so that it
+// keeps going up across restarts; only how long it has not changed
+// for is local.
+#[derive(Debug)]
+pub struct Members {
+    me: SocketAddr,
+    suspect: Duration,
+    dead: Duration,
+    view: Mutex<View>,
+}
+
+#[derive(Debug)]
+struct View {
+    heartbeat: u64, // own
+    members: HashMap<SocketAddr, Member>,
+}
+
+impl Members {
+    pub fn new(
+        me: SocketAddr,
+        suspect: Duration,
+        dead: Duration,
+    ) -> Self {
+        Self {
+            me,
+            suspect,
+            dead,
+            view: Mutex::new(View {
+                heartbeat: millis(),
+                members: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn me(&self) -> SocketAddr {
+        self.me
+    }
+
+    pub fn tick(&self) {
+        let mut view = self.view.lock().unwrap();
+        view.heartbeat = millis().max(view.heartbeat + 1);
+    }
+
+    // A member to begin with (e.g. a seed), alive until its
+    // heartbeat tells otherwise
+    pub fn add(&self, addr: SocketAddr) {
+        if addr == self.me {
+            return;
+        }
+        let mut view = self.view.lock().unwrap();
+        view.members.entry(addr).or_insert(Member {
+            heartbeat: 0,
+            updated: Instant::now(),
+        });
+    }
+
+    // The members of a view received (see `encode`), the ones not
+    // heard of yet are taken in
+    pub fn merge(&self, data: &[u8]) -> Result<()> {
+        let entries = decode(data)?;
+        let now = Instant::now();
+        let mut view = self.view.lock().unwrap();
+        for (addr, heartbeat, _) in entries {
+            if addr == self.me {
+                continue;
+            }
+            let member =
+                view.members.entry(addr).or_insert(Member {
+                    heartbeat,
+                    updated: now,
+                });
+            if heartbeat > member.heartbeat {
+                member.heartbeat = heartbeat;
+                member.updated = now;
+            }
+        }
+        Ok(())
+    }
+
+    // This node (alive, first) and the members not dead, as many
+    // as fit in a frame
+    pub fn encode(&self) -> Vec<u8> {
+        let now = Instant::now();
+        let view = self.view.lock().unwrap();
+        let members = view
+            .members
+            .iter()
+            .map(|(addr, m)| {
+                (*addr, m.heartbeat, self.state(m, now))
+            })
+            .filter(|(_, _, state)| *state != State::Dead);
+        std::iter::once((self.me, view.heartbeat, State::Alive))
+            .chain(members)
+            .take(MAX_PAYLOAD_LEN / ENTRY_LEN)
+            .flat_map(|(addr, heartbeat, state)| {
+                entry(addr, heartbeat, state)
+            })
+            .collect()
+    }
+
+    // All the members heard of but this node, by address
+    pub fn members(&self) -> Vec<(SocketAddr, State)> {
+        let now = Instant::now();
+        let view = self.view.lock().unwrap();
+        let mut members = view
+            .members
+            .iter()
+            .map(|(addr, m)| (*addr, self.state(m, now)))
+            .collect::<Vec<_>>();
+        members.sort_by_key(|(addr, _)| *addr);
+        members
+    }
+
+    // The members not dead
+    pub fn live(&self) -> Vec<SocketAddr> {
+        self.members()
+            .into_iter()
+            .filter(|(_, state)| *state != State::Dead)
+            .map(|(addr, _)| addr)
+            .collect()
+    }
+
+    // The member to gossip with next: any of the live ones, any at
+    // all if none (to find the way back after a partition)
+    pub fn pick(&self, random: u32) -> Option<SocketAddr> {
+        let live = self.live();
+        let members = if live.is_empty() {
+            self.members()
+                .into_iter()
+                .map(|(addr, _)| addr)
+                .collect()
+        } else {
+            live
+        };
+        if members.is_empty() {
+            return None;
+        }
+        Some(members[random as usize % members.len()])
+    }
+
+    fn state(&self, member: &Member, now: Instant) -> State {
+        let silent = now.duration_since(member.updated);
+        if silent < self.suspect {
+            State::Alive
+        } else if silent < self.dead {
+            State::Suspect
+        } else {
+            State::Dead
+        }
+    }
+}
+
+// The entries of a view: address, heartbeat and the state the
+// sender sees the member in
+pub fn decode(
+    data: &[u8],
+) -> Result<Vec<(SocketAddr, u64, State)>> {
+    if !data.len().is_multiple_of(ENTRY_LEN) {
+        return Err(Error::App(format!(
+            "invalid view: {} bytes",
+            data.len()
+        )));
+    }
+    data.chunks(ENTRY_LEN)
+        .map(|chunk| {
+            let ip: [u8; 16] = chunk[..16].try_into().unwrap();
+            let ip = Ipv6Addr::from(ip).to_canonical();
+            let port = u16::from_be_bytes(
+                chunk[16..18].try_into().unwrap(),
+            );
+            let heartbeat = u64::from_be_bytes(
+                chunk[18..26].try_into().unwrap(),
+            );
+            let state = match chunk[26] {
+                0 => State::Alive,
+                1 => State::Suspect,
+                other => {
+                    return Err(Error::App(format!(
+                        "invalid view: state {other}"
+                    )))
+                }
+            };
+            Ok((SocketAddr::new(ip, port), heartbeat, state))
+        })
+        .collect()
+}
+
+fn entry(
+    addr: SocketAddr,
+    heartbeat: u64,
+    state: State,
+) -> Vec<u8> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let state = match state {
+        State::Alive => 0u8,
+        _ => 1,
+    };
+    ip.octets()
+        .into_iter()
+        .chain(addr.port().to_be_bytes())
+        .chain(heartbeat.to_be_bytes())
+        .chain([state])
+        .collect()
+}
+
+fn millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn members(port: u16) -> Members {
+        let ms = Duration::from_millis;
+        Members::new(addr(port), ms(50), ms(150))
+    }
+
+    #[test]
+    fn test_encode() -> Result<()> {
+        let a = members(1);
+        a.add(addr(2));
+        a.add(addr(1)); // itself
+        let v6 = "[::1]:3".parse().unwrap();
+        a.merge(&entry(v6, 42, State::Suspect))?;
+
+        let mut entries = decode(&a.encode())?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].0, addr(1));
+        assert_eq!(entries[0].2, State::Alive);
+        entries.sort_by_key(|(addr, _, _)| *addr);
+        assert_eq!(entries[1].0, addr(2));
+        assert_eq!(entries[2], (v6, 42, State::Alive));
+
+        assert!(decode(&[0; ENTRY_LEN - 1]).is_err());
+        let mut invalid = entry(addr(2), 1, State::Alive);
+        invalid[26] = 2;
+        assert!(decode(&invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gossip() -> Result<()> {
+        // a knows b, b knows c: a hears of c through b
+        let (a, b, c) = (members(1), members(2), members(3));
+        a.add(addr(2));
+        b.add(addr(3));
+        b.merge(&c.encode())?;
+        a.merge(&b.encode())?;
+        assert_eq!(a.live(), vec![addr(2), addr(3)]);
+        assert_eq!(a.pick(0), Some(addr(2)));
+        assert_eq!(a.pick(1), Some(addr(3)));
+
+        // c stops: suspect, then dead, while b keeps going
+        thread::sleep(Duration::from_millis(60));
+        b.tick();
+        a.merge(&b.encode())?;
+        assert_eq!(
+            a.members(),
+            vec![
+                (addr(2), State::Alive),
+                (addr(3), State::Suspect)
+            ]
+        );
+        thread::sleep(Duration::from_millis(100));
+        b.tick();
+        a.merge(&b.encode())?;
+        // an old heartbeat does not bring it back
+        a.merge(&entry(addr(3), 0, State::Alive))?;
+        assert_eq!(a.live(), vec![addr(2)]);
+        assert_eq!(a.members()[1], (addr(3), State::Dead));
+
+        // c is back: its heartbeat goes up
+        c.tick();
+        a.merge(&c.encode())?;
+        assert_eq!(a.live(), vec![addr(2), addr(3)]);
+        Ok(())
+    }
+}
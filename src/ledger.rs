@@ -0,0 +1,587 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    api::{Error, Result},
+    block::Hash,
+    ec::{PublicKey, SecretKey, Signature},
+    storage::Storage,
+    util::{from_hex, hash, hash32, time, to_hex},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Op {
+    Set,
+    Patch,
+    Delete,
+    Restore,
+}
+
+impl Op {
+    const ALL: [Op; 4] =
+        [Op::Set, Op::Patch, Op::Delete, Op::Restore];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Set => "set",
+            Op::Patch => "patch",
+            Op::Delete => "delete",
+            Op::Restore => "restore",
+        }
+    }
+}
+
+// A change of the storage: the key (zero for a restore, all of them)
+// and its version after the change. The share itself is not there,
+// what it is a version of is all that can be told from the ledger.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub at: u32, // unix seconds
+    pub op: Op,
+    pub key: u64,
+    pub version: u32,
+}
+
+impl Entry {
+    fn bytes(&self) -> Vec<u8> {
+        self.at
+            .to_be_bytes()
+            .into_iter()
+            .chain([Op::ALL
+                .iter()
+                .position(|op| op == &self.op)
+                .unwrap() as u8])
+            .chain(self.key.to_be_bytes())
+            .chain(self.version.to_be_bytes())
+            .collect()
+    }
+}
+
+// `time=<secs> op=<op> key=<hex> version=<n>`
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "time={} op={} key={:0x} version={}",
+            self.at,
+            self.op.name(),
+            self.key,
+            self.version
+        )
+    }
+}
+
+impl FromStr for Entry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let invalid = || {
+            Error::App(format!("invalid ledger entry: {line}"))
+        };
+        let mut fields = line.split(' ').map(|field| {
+            field.split_once('=').map(|(_, value)| value)
+        });
+        let mut next =
+            || fields.next().flatten().ok_or_else(invalid);
+        let at = next()?.parse().map_err(|_| invalid())?;
+        let op = next()?;
+        let op = Op::ALL
+            .into_iter()
+            .find(|o| o.name() == op)
+            .ok_or_else(invalid)?;
+        let key = u64::from_str_radix(next()?, 16)
+            .map_err(|_| invalid())?;
+        let version = next()?.parse().map_err(|_| invalid())?;
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            at,
+            op,
+            key,
+            version,
+        })
+    }
+}
+
+// The head after one more entry: the hash of the one before and of
+// the entry, so that no entry can be changed, dropped or moved
+// without changing every head after it
+pub fn next(head: &Hash, entry: &Entry) -> Hash {
+    let bytes = head
+        .iter()
+        .copied()
+        .chain(entry.bytes())
+        .collect::<Vec<_>>();
+    hash(&bytes)
+}
+
+// The number of entries and the head after the last one (zeros for
+// none), signed by the node for an auditor to hold it to: a head
+// the node signed that the ledger it shows later does not go
+// through is proof it was rewritten
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Head {
+    pub len: u64,
+    pub hash: Hash,
+    pub sig: u64,
+}
+
+impl Head {
+    fn digest(&self) -> u32 {
+        let bytes = self
+            .len
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.hash)
+            .collect::<Vec<_>>();
+        hash32(&bytes)
+    }
+
+    pub fn sign(&mut self, secret_key: &SecretKey) {
+        self.sig = u64::from(&secret_key.sign(&self.digest()));
+    }
+
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        public_key
+            .is_valid(&self.digest(), &Signature::from(self.sig))
+    }
+
+    // length, hash, signature
+    pub fn encode(&self) -> Vec<u8> {
+        self.len
+            .to_be_bytes()
+            .into_iter()
+            .chain(self.hash)
+            .chain(self.sig.to_be_bytes())
+            .collect()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 48 {
+            return Err(Error::App(format!(
+                "invalid ledger head: {} bytes",
+                bytes.len()
+            )));
+        }
+        Ok(Self {
+            len: u64::from_be_bytes(
+                bytes[..8].try_into().unwrap(),
+            ),
+            hash: bytes[8..40].try_into().unwrap(),
+            sig: u64::from_be_bytes(
+                bytes[40..].try_into().unwrap(),
+            ),
+        })
+    }
+}
+
+// The heads after each line of a ledger file, checked against the
+// ones written down: an error at the first line that is not an
+// entry or whose head does not follow from the ones before
+pub fn verify(text: &str) -> Result<Vec<Hash>> {
+    let mut heads = vec![];
+    let mut head = Hash::default();
+    for (i, line) in text.lines().enumerate() {
+        let invalid = || {
+            Error::App(format!("ledger line {}: {line}", i + 1))
+        };
+        let (entry, written) =
+            line.rsplit_once(" head=").ok_or_else(invalid)?;
+        let entry = entry.parse::<Entry>()?;
+        head = next(&head, &entry);
+        if from_hex(written).as_deref() != Some(&head[..]) {
+            return Err(invalid());
+        }
+        heads.push(head);
+    }
+    Ok(heads)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    file: Option<File>,
+    len: u64,
+    head: Hash,
+}
+
+// Append-only, hash-linked record of the changes of a node's storage
+// (see `Ledgered`): a line per entry in the file (if any), the entry
+// and the head after it, the head kept in memory to be queried
+// (TAG_LEDGER, see the server). The file is only appended to, and
+// checked from the start when opened; a line torn by a crash is
+// cut off.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    state: Mutex<State>,
+}
+
+impl Ledger {
+    // in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                String::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if !text.ends_with('\n') {
+            text.truncate(text.rfind('\n').map_or(0, |i| i + 1));
+            file.set_len(text.len() as u64)?;
+        }
+        let heads = verify(&text)?;
+        Ok(Self {
+            state: Mutex::new(State {
+                file: Some(file),
+                len: heads.len() as u64,
+                head: heads.last().copied().unwrap_or_default(),
+            }),
+        })
+    }
+
+    // in the head even if it could not be written down
+    pub fn record(&self, entry: Entry) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.head = next(&state.head, &entry);
+        state.len += 1;
+        let line =
+            format!("{entry} head={}\n", to_hex(&state.head));
+        match state.file.as_mut() {
+            Some(file) => Ok(file.write_all(line.as_bytes())?),
+            None => Ok(()),
+        }
+    }
+
+    // unsigned
+    pub fn head(&self) -> Head {
+        let state = self.state.lock().unwrap();
+        Head {
+            len: state.len,
+            hash: state.head,
+            sig: 0,
+        }
+    }
+}
+
+// A storage whose changes (set, patch or update, delete, restore)
+// are recorded in a ledger, shared by the shards of a node. An entry
+// is recorded with the shard locked, in the order the changes are
+// made. An entry that could not be written down fails the next
+// `flush`.
+pub struct Ledgered<T> {
+    db: T,
+    ledger: Arc<Ledger>,
+    failed: Option<Error>,
+}
+
+impl<T> Ledgered<T> {
+    pub fn new(db: T, ledger: Arc<Ledger>) -> Self {
+        Self {
+            db,
+            ledger,
+            failed: None,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.db
+    }
+
+    fn record(&mut self, op: Op, key: u64, version: usize) {
+        let entry = Entry {
+            at: time(),
+            op,
+            key,
+            version: version as u32,
+        };
+        if let Err(e) = self.ledger.record(entry) {
+            self.failed.get_or_insert(e);
+        }
+    }
+}
+
+impl<T, S, M> Storage<u64, S, M> for Ledgered<T>
+where
+    T: Storage<u64, S, M>,
+{
+    fn set(&mut self, key: u64, secret: S) {
+        self.db.set(key, secret);
+        let version = self.db.version(key);
+        self.record(Op::Set, key, version);
+    }
+
+    fn get(&mut self, key: u64) -> Option<S> {
+        self.db.get(key)
+    }
+
+    fn get_version(
+        &mut self,
+        key: u64,
+        version: usize,
+    ) -> Option<S> {
+        self.db.get_version(key, version)
+    }
+
+    fn patch(&mut self, key: u64, mask: M) {
+        self.db.patch(key, mask);
+        let version = self.db.version(key);
+        self.record(Op::Patch, key, version);
+    }
+
+    fn update(&mut self, key: u64, f: impl FnOnce(S) -> S) {
+        self.db.update(key, f);
+        let version = self.db.version(key);
+        self.record(Op::Patch, key, version);
+    }
+
+    fn delete(&mut self, key: u64, at: u32) -> bool {
+        let deleted = self.db.delete(key, at);
+        if deleted {
+            self.record(Op::Delete, key, 0);
+        }
+        deleted
+    }
+
+    fn deleted(&mut self, key: u64) -> Option<u32> {
+        self.db.deleted(key)
+    }
+
+    fn forget(&mut self, before: u32) -> usize {
+        self.db.forget(before)
+    }
+
+    fn keys(&mut self) -> Vec<u64> {
+        self.db.keys()
+    }
+
+    fn owner(&mut self, key: u64) -> Option<PublicKey> {
+        self.db.owner(key)
+    }
+
+    fn register(&mut self, key: u64, owner: PublicKey) {
+        self.db.register(key, owner)
+    }
+
+    fn set_scheme(&mut self, key: u64, scheme: u32) {
+        self.db.set_scheme(key, scheme)
+    }
+
+    fn scheme(&mut self, key: u64) -> u32 {
+        self.db.scheme(key)
+    }
+
+    fn attach(
+        &mut self,
+        key: u64,
+        f: impl FnOnce(&[u8]) -> Vec<u8>,
+    ) {
+        self.db.attach(key, f)
+    }
+
+    fn attachment(
+        &mut self,
+        key: u64,
+        version: usize,
+    ) -> Vec<u8> {
+        self.db.attachment(key, version)
+    }
+
+    fn version(&mut self, key: u64) -> usize {
+        self.db.version(key)
+    }
+
+    fn epochs(&mut self, key: u64) -> Vec<u32> {
+        self.db.epochs(key)
+    }
+
+    fn set_epoch(&mut self, key: u64, epoch: u32) {
+        self.db.set_epoch(key, epoch)
+    }
+
+    fn set_expiry(&mut self, key: u64, at: u32) {
+        self.db.set_expiry(key, at)
+    }
+
+    fn expiry(&mut self, key: u64) -> Option<u32> {
+        self.db.expiry(key)
+    }
+
+    fn snapshot(&mut self) -> Vec<u8> {
+        self.db.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        self.db.restore(snapshot)?;
+        self.record(Op::Restore, 0, 0);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush()?;
+        match self.failed.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{storage::DB, util::random};
+
+    use super::*;
+
+    fn entry(at: u32, op: Op, key: u64, version: u32) -> Entry {
+        Entry {
+            at,
+            op,
+            key,
+            version,
+        }
+    }
+
+    #[test]
+    fn test_entry() -> Result<()> {
+        let e = entry(1700000000, Op::Patch, 0x700000001, 2);
+        let line = e.to_string();
+        assert_eq!(
+            line,
+            "time=1700000000 op=patch key=700000001 version=2"
+        );
+        assert_eq!(line.parse::<Entry>()?, e);
+        assert!("time=1 op=set key=1".parse::<Entry>().is_err());
+        assert!("time=1 op=get key=1 version=0"
+            .parse::<Entry>()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ledgered() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}.ledger",
+            random()
+        ));
+        let ledger = Arc::new(Ledger::open(&path)?);
+        let mut db =
+            Ledgered::new(DB::<u64, u32>::new(), ledger.clone());
+        db.set(1, 10);
+        db.get(1);
+        db.patch(1, 3);
+        db.update(1, |s| s + 1);
+        db.set(2, 20);
+        assert!(db.delete(1, 5));
+        // nothing there, nothing changed
+        assert!(!db.delete(1, 6));
+        let snapshot = db.snapshot();
+        db.restore(&snapshot)?;
+        db.flush()?;
+        assert_eq!(db.into_inner().keys(), vec![2]);
+
+        let text = fs::read_to_string(&path)?;
+        let entries = text
+            .lines()
+            .map(|line| line.split(" head=").next().unwrap())
+            .map(|entry| entry.parse::<Entry>())
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .map(|e| (e.op, e.key, e.version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (Op::Set, 1, 0),
+                (Op::Patch, 1, 1),
+                (Op::Patch, 1, 2),
+                (Op::Set, 2, 0),
+                (Op::Delete, 1, 0),
+                (Op::Restore, 0, 0),
+            ]
+        );
+        let head = ledger.head();
+        assert_eq!(head.len, 6);
+        assert_eq!(verify(&text)?.last(), Some(&head.hash));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_head() -> Result<()> {
+        let ledger = Ledger::new();
+        assert_eq!(ledger.head(), Head::default());
+        let e = entry(1, Op::Set, 1, 0);
+        ledger.record(e.clone())?;
+        let mut head = ledger.head();
+        assert_eq!(head.hash, next(&Hash::default(), &e));
+
+        let key = SecretKey::new(7);
+        head.sign(&key);
+        let decoded = Head::decode(&head.encode())?;
+        assert_eq!(decoded, head);
+        assert!(decoded.verify(&key.public_key()));
+        assert!(!decoded.verify(&SecretKey::new(8).public_key()));
+        let rewritten = Head {
+            hash: next(
+                &Hash::default(),
+                &entry(1, Op::Set, 2, 0),
+            ),
+            ..head.clone()
+        };
+        assert!(!rewritten.verify(&key.public_key()));
+        assert!(Head::decode(&[0; 47]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}.ledger",
+            random()
+        ));
+        let head = {
+            let ledger = Ledger::open(&path)?;
+            ledger.record(entry(1, Op::Set, 1, 0))?;
+            ledger.record(entry(2, Op::Patch, 1, 1))?;
+            ledger.head()
+        };
+        // torn by a crash
+        let mut file =
+            OpenOptions::new().append(true).open(&path)?;
+        file.write_all(b"time=3 op=del")?;
+
+        let ledger = Ledger::open(&path)?;
+        assert_eq!(ledger.head(), head);
+        ledger.record(entry(4, Op::Delete, 1, 0))?;
+        let text = fs::read_to_string(&path)?;
+        let heads = verify(&text)?;
+        assert_eq!(heads.len(), 3);
+        assert_eq!(heads[1], head.hash);
+        assert_eq!(heads[2], ledger.head().hash);
+
+        // history rewritten: the entry, or the head along with it
+        let rewritten = text.replacen("op=patch", "op=set", 1);
+        assert!(verify(&rewritten).is_err());
+        let lines = text.lines().collect::<Vec<_>>();
+        let dropped = [lines[0], lines[2]].join("\n");
+        assert!(verify(&dropped).is_err());
+        fs::write(&path, rewritten)?;
+        assert!(Ledger::open(&path).is_err());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,9 +1,45 @@
 pub mod api;
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
+pub mod audit;
+pub mod block;
+pub mod chacha;
+pub mod client;
+pub mod codec;
 pub mod dhke;
+pub mod digest;
 pub mod ec;
+pub mod frost;
+pub mod gossip;
+pub mod ledger;
+pub mod mempool;
+pub mod merkle;
+pub mod metrics;
+pub mod mux;
+#[cfg(feature = "noise")]
+pub mod noise;
+pub mod nonce;
+pub mod pool;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod retry;
+#[cfg(feature = "encrypt")]
+pub mod seal;
+pub mod sha256;
+pub mod shamir;
+pub mod storage;
 pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod trace;
+pub mod udp;
 pub mod util;
+pub mod vss;
+pub mod workers;
+#[cfg(feature = "ws")]
+pub mod ws;
+pub mod x25519;
 pub mod xor;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "testkit"))]
 pub mod testkit;
@@ -0,0 +1,323 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::{
+    api::{
+        Error, Frame, Result, TAG_DELETE, TAG_REFRESH,
+        TAG_REPAIR, TAG_SECRET_SHARE, TAG_TRANSFER,
+    },
+    block::{Block, Chain, Reason},
+    merkle,
+};
+
+// Operations waiting to go in a block, over that the newest ones
+// are turned away
+pub const CAPACITY: usize = 10_000;
+// in a block at most, the rest wait for the next one
+pub const MAX_BLOCK_OPS: usize = 1000;
+// bytes of the operations of a block, one at least whatever its size
+pub const MAX_BLOCK_LEN: usize = 1024 * 1024;
+
+// The frames that change the state: set, patch (refresh, transfer,
+// repair) and delete
+pub fn is_op(tag: u32) -> bool {
+    matches!(
+        tag,
+        TAG_SECRET_SHARE
+            | TAG_REFRESH
+            | TAG_TRANSFER
+            | TAG_REPAIR
+            | TAG_DELETE
+    )
+}
+
+// The operations a server took (signed frames, as received), in the
+// order it took them, until they are packaged into a block on top of
+// its chain: the blocks are then the history of its state, each one
+// of the changes since the one before.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    ops: Mutex<VecDeque<Frame>>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // false if the pool is full
+    pub fn push(&self, frame: Frame) -> bool {
+        let mut ops = self.ops.lock().unwrap();
+        if ops.len() >= CAPACITY {
+            return false;
+        }
+        ops.push_back(frame);
+        true
+    }
+
+    // Operations taken out for a block that is no more (see
+    // `Chain::reorg`), back in front of the others, in order,
+    // whether the pool is full or not
+    pub fn restore(&self, ops: Vec<Frame>) {
+        let mut pool = self.ops.lock().unwrap();
+        for op in ops.into_iter().rev() {
+            pool.push_front(op);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // A block of the oldest operations (MAX_BLOCK_OPS and
+    // MAX_BLOCK_LEN at most) on top of the chain, mined at the
+    // chain's difficulty and appended; none if there are no
+    // operations
+    pub fn package(
+        &self,
+        chain: &mut Chain,
+        time: u32,
+    ) -> Result<Option<Block>> {
+        let ops = {
+            let mut ops = self.ops.lock().unwrap();
+            let mut len = 0;
+            let n = ops
+                .iter()
+                .take(MAX_BLOCK_OPS)
+                .take_while(|op| {
+                    len += 4 * (10 + op.data.len().div_ceil(4));
+                    len <= MAX_BLOCK_LEN
+                })
+                .count()
+                .max(1)
+                .min(ops.len());
+            ops.drain(..n).collect::<Vec<_>>()
+        };
+        if ops.is_empty() {
+            return Ok(None);
+        }
+        let mut block = chain.next(time, payload(&ops));
+        block.mine(chain.difficulty());
+        chain.append(block.clone())?;
+        Ok(Some(block))
+    }
+}
+
+// The Merkle root of the operations, then the operations, each one
+// as sent over (words, big-endian)
+pub fn payload(ops: &[Frame]) -> Vec<u8> {
+    let ops = ops.iter().map(bytes).collect::<Vec<_>>();
+    merkle::root(&ops)
+        .into_iter()
+        .chain(ops.into_iter().flatten())
+        .collect()
+}
+
+// The operations of a block's payload (see `payload`)
+pub fn ops(payload: &[u8]) -> Result<Vec<Frame>> {
+    let invalid = |e: &str| {
+        Error::App(format!("invalid block payload: {e}"))
+    };
+    if payload.len() < 32 || !payload.len().is_multiple_of(4) {
+        return Err(invalid("length"));
+    }
+    let (root, mut rest) = payload.split_at(32);
+    let mut ops = vec![];
+    while !rest.is_empty() {
+        let len =
+            rest.get(36..40).ok_or_else(|| invalid("frame"))?;
+        let len = u32::from_be_bytes(len.try_into().unwrap());
+        let words = 10 + (len as usize).div_ceil(4);
+        let op = rest
+            .get(..4 * words)
+            .ok_or_else(|| invalid("frame"))?
+            .chunks(4)
+            .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        ops.push(Frame::decode(&op)?);
+        rest = &rest[4 * words..];
+    }
+    let leaves = ops.iter().map(bytes).collect::<Vec<_>>();
+    if merkle::root(&leaves) != root {
+        return Err(invalid("root"));
+    }
+    Ok(ops)
+}
+
+// What a block of a server's chain carries (see `Chain::with_check`):
+// operations that can be read, the clients' (set, delete) signed by
+// the owners of the keys. The peers' (refresh, transfer, repair) are
+// not signed, they come over the sessions between the servers.
+pub fn check(payload: &[u8]) -> std::result::Result<(), Reason> {
+    let ops = ops(payload)
+        .map_err(|e| Reason::Payload(format!("{e:?}")))?;
+    let unsigned = ops.iter().position(|op| {
+        matches!(op.tag, TAG_SECRET_SHARE | TAG_DELETE)
+            && op.signer().is_none()
+    });
+    match unsigned {
+        Some(i) => Err(Reason::Signature(i)),
+        None => Ok(()),
+    }
+}
+
+fn bytes(frame: &Frame) -> Vec<u8> {
+    frame
+        .encode()
+        .iter()
+        .flat_map(|w| w.to_be_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ec::SecretKey;
+
+    use super::*;
+
+    fn op(tag: u32, key: u32, data: &[u8]) -> Frame {
+        let mut frame = Frame {
+            idx: key,
+            tag,
+            msg: 0,
+            key,
+            sig: 0x0102030405060708,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: data.to_vec(),
+        };
+        frame.sum = frame.checksum();
+        frame
+    }
+
+    #[test]
+    fn test_payload() -> Result<()> {
+        let frames = vec![
+            op(TAG_SECRET_SHARE, 1, b"share"),
+            op(TAG_REFRESH, 2, &[0; 8]),
+            op(TAG_DELETE, 3, &[]),
+        ];
+        let payload = payload(&frames);
+        assert_eq!(ops(&payload)?, frames);
+        assert!(ops(&super::payload(&[]))?.is_empty());
+
+        let mut invalid = payload.clone();
+        invalid[0] ^= 1; // the root
+        assert!(ops(&invalid).is_err());
+        let mut invalid = payload.clone();
+        invalid[32 + 4] ^= 1; // the tag of the first one
+        assert!(ops(&invalid).is_err());
+        assert!(ops(&payload[..payload.len() - 4]).is_err());
+        assert!(ops(&[0; 31]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() {
+        let owner = SecretKey::new(1);
+        let signed = |tag: u32| {
+            let mut frame =
+                op(tag, owner.public_key().fingerprint(), &[]);
+            frame.data = u64::from(&owner.public_key())
+                .to_be_bytes()
+                .to_vec();
+            frame.sign(&owner);
+            frame
+        };
+        let mut frames = vec![
+            signed(TAG_SECRET_SHARE),
+            op(TAG_REFRESH, 2, &[0; 8]), // a peer's
+            signed(TAG_DELETE),
+        ];
+        assert_eq!(check(&payload(&frames)), Ok(()));
+        frames[2].msg ^= 1;
+        assert_eq!(
+            check(&payload(&frames)),
+            Err(Reason::Signature(2))
+        );
+        frames.insert(0, op(TAG_DELETE, 1, &[]));
+        assert_eq!(
+            check(&payload(&frames)),
+            Err(Reason::Signature(0))
+        );
+        assert!(matches!(
+            check(b"ops"),
+            Err(Reason::Payload(_))
+        ));
+    }
+
+    #[test]
+    fn test_package() -> Result<()> {
+        let pool = Mempool::new();
+        let mut chain = Chain::with_difficulty(4);
+        assert!(pool.package(&mut chain, 1)?.is_none());
+        assert!(chain.is_empty());
+
+        for key in 0..MAX_BLOCK_OPS as u32 + 1 {
+            assert!(pool.push(op(TAG_SECRET_SHARE, key, b"s")));
+        }
+        let block = pool.package(&mut chain, 1)?.unwrap();
+        let first = ops(&block.payload)?;
+        assert_eq!(first.len(), MAX_BLOCK_OPS);
+        assert_eq!(first[0].key, 0);
+        assert_eq!(pool.len(), 1);
+
+        pool.push(op(TAG_DELETE, 0, &[]));
+        pool.package(&mut chain, 2)?;
+        assert!(pool.is_empty());
+        assert_eq!(chain.len(), 2);
+        chain.validate()?;
+        // the history, in order
+        let keys = chain
+            .blocks()
+            .iter()
+            .map(|block| ops(&block.payload))
+            .collect::<Result<Vec<_>>>()?
+            .concat()
+            .into_iter()
+            .map(|op| (op.tag, op.key))
+            .collect::<Vec<_>>();
+        assert_eq!(keys.len(), MAX_BLOCK_OPS + 2);
+        assert_eq!(
+            keys[MAX_BLOCK_OPS..],
+            [
+                (TAG_SECRET_SHARE, MAX_BLOCK_OPS as u32),
+                (TAG_DELETE, 0)
+            ]
+        );
+
+        // as many as fit
+        let big = op(TAG_SECRET_SHARE, 0, &[0; 60_000]);
+        for _ in 0..20 {
+            pool.push(big.clone());
+        }
+        let block = pool.package(&mut chain, 3)?.unwrap();
+        let n = MAX_BLOCK_LEN / bytes(&big).len();
+        assert_eq!(ops(&block.payload)?.len(), n);
+        assert_eq!(pool.len(), 20 - n);
+        while pool.package(&mut chain, 3)?.is_some() {}
+
+        // back in front, in order
+        pool.push(op(TAG_DELETE, 9, &[]));
+        pool.restore(vec![
+            op(TAG_DELETE, 7, &[]),
+            op(TAG_DELETE, 8, &[]),
+        ]);
+        let block = pool.package(&mut chain, 4)?.unwrap();
+        let keys = ops(&block.payload)?
+            .iter()
+            .map(|op| op.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![7, 8, 9]);
+
+        while pool.len() < CAPACITY {
+            pool.push(op(TAG_DELETE, 0, &[]));
+        }
+        assert!(!pool.push(op(TAG_DELETE, 0, &[])));
+        Ok(())
+    }
+}
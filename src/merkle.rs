@@ -0,0 +1,244 @@
+use crate::{
+    api::{Error, Result},
+    block::Hash,
+    util::hash,
+};
+
+// Hashed apart, so that a leaf can't pass for a node (or the other
+// way around) in a proof
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+// A Merkle tree over leaves of any bytes (a block's payload, frames,
+// the digests of keys stored...): the leaves are hashed, then the
+// hashes two by two, level by level up to a single one, the root.
+// The last node of a level with an odd number of them goes up as it
+// is (not paired with itself, so that no two lists of leaves have
+// the same root). The root of no leaves at all is zeros.
+#[derive(Clone, Debug)]
+pub struct Tree {
+    levels: Vec<Vec<Hash>>, // leaves first
+}
+
+impl Tree {
+    pub fn new<T: AsRef<[u8]>>(leaves: &[T]) -> Self {
+        let mut levels = vec![leaves
+            .iter()
+            .map(|leaf| leaf_hash(leaf.as_ref()))
+            .collect::<Vec<_>>()];
+        while levels.last().unwrap().len() > 1 {
+            let level = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [one] => *one,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(level);
+        }
+        Self { levels }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // That the leaf at `index` is in the tree: the hashes next to
+    // the ones on its way up to the root
+    pub fn proof(&self, index: usize) -> Option<Proof> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut path = vec![];
+        let mut i = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(i ^ 1) {
+                path.push(*sibling);
+            }
+            i /= 2;
+        }
+        Some(Proof {
+            index,
+            len: self.len(),
+            path,
+        })
+    }
+}
+
+// The root of a tree over the leaves
+pub fn root<T: AsRef<[u8]>>(leaves: &[T]) -> Hash {
+    Tree::new(leaves).root()
+}
+
+// An inclusion proof: the leaf at `index` of a tree of `len` leaves
+// and the hashes to go up with (which side each one is on follows
+// from the index)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Proof {
+    pub index: usize,
+    pub len: usize,
+    pub path: Vec<Hash>,
+}
+
+impl Proof {
+    // The leaf given is the one at `index` in a tree with this root
+    pub fn verify(&self, root: &Hash, leaf: &[u8]) -> bool {
+        if self.index >= self.len {
+            return false;
+        }
+        let mut path = self.path.iter();
+        let mut hash = leaf_hash(leaf);
+        let (mut i, mut n) = (self.index, self.len);
+        while n > 1 {
+            if i ^ 1 < n {
+                let Some(sibling) = path.next() else {
+                    return false;
+                };
+                hash = if i.is_multiple_of(2) {
+                    node_hash(&hash, sibling)
+                } else {
+                    node_hash(sibling, &hash)
+                };
+            }
+            i /= 2;
+            n = n.div_ceil(2);
+        }
+        path.next().is_none() && &hash == root
+    }
+
+    // index, number of leaves, the hashes
+    pub fn encode(&self) -> Vec<u8> {
+        (self.index as u32)
+            .to_be_bytes()
+            .into_iter()
+            .chain((self.len as u32).to_be_bytes())
+            .chain(self.path.iter().flatten().copied())
+            .collect()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8
+            || !(bytes.len() - 8).is_multiple_of(32)
+        {
+            return Err(Error::App(format!(
+                "invalid proof: {} bytes",
+                bytes.len()
+            )));
+        }
+        let u32_at = |i: usize| {
+            u32::from_be_bytes(
+                bytes[i..i + 4].try_into().unwrap(),
+            ) as usize
+        };
+        Ok(Self {
+            index: u32_at(0),
+            len: u32_at(4),
+            path: bytes[8..]
+                .chunks(32)
+                .map(|hash| hash.try_into().unwrap())
+                .collect(),
+        })
+    }
+}
+
+fn leaf_hash(leaf: &[u8]) -> Hash {
+    let mut bytes = Vec::with_capacity(1 + leaf.len());
+    bytes.push(LEAF);
+    bytes.extend_from_slice(leaf);
+    hash(&bytes)
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut bytes = [0u8; 65];
+    bytes[0] = NODE;
+    bytes[1..33].copy_from_slice(left);
+    bytes[33..].copy_from_slice(right);
+    hash(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree() {
+        assert_eq!(root::<&[u8]>(&[]), [0; 32]);
+        assert_eq!(root(&[b"a"]), leaf_hash(b"a"));
+        let (a, b, c) =
+            (leaf_hash(b"a"), leaf_hash(b"b"), leaf_hash(b"c"));
+        assert_eq!(root(&[b"a", b"b"]), node_hash(&a, &b));
+        // the odd one goes up as it is
+        assert_eq!(
+            root(&[b"a", b"b", b"c"]),
+            node_hash(&node_hash(&a, &b), &c)
+        );
+        // not the same as the last one twice
+        assert_ne!(
+            root(&[b"a", b"b", b"c"]),
+            root(&[b"a", b"b", b"c", b"c"])
+        );
+        // nor as a leaf of the two hashes
+        let ab = [a, b].concat();
+        assert_ne!(root(&[ab]), root(&[b"a", b"b"]));
+    }
+
+    #[test]
+    fn test_proof() -> Result<()> {
+        for n in 1..=17 {
+            let leaves = (0..n)
+                .map(|i| format!("leaf {i}"))
+                .collect::<Vec<_>>();
+            let tree = Tree::new(&leaves);
+            assert_eq!(tree.len(), n);
+            let root = tree.root();
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(proof.verify(&root, leaf.as_bytes()));
+                assert_eq!(
+                    Proof::decode(&proof.encode())?,
+                    proof
+                );
+                // not for another leaf, nor at another index
+                assert!(!proof.verify(&root, b"leaf"));
+                let other = Proof {
+                    index: (i + 1) % n,
+                    ..proof.clone()
+                };
+                assert!(
+                    n == 1
+                        || !other.verify(&root, leaf.as_bytes())
+                );
+                let mut short = proof.clone();
+                if short.path.pop().is_some() {
+                    assert!(
+                        !short.verify(&root, leaf.as_bytes())
+                    );
+                }
+            }
+            assert!(tree.proof(n).is_none());
+        }
+
+        let tree = Tree::new(&[b"a", b"b", b"c"]);
+        let mut proof = tree.proof(2).unwrap();
+        proof.path.push([0; 32]);
+        assert!(!proof.verify(&tree.root(), b"c"));
+        assert!(Proof::decode(&[0; 7]).is_err());
+        assert!(Proof::decode(&[0; 9]).is_err());
+        Ok(())
+    }
+}
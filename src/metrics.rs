@@ -0,0 +1,292 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write as _},
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::api::Result;
+
+// Upper bounds of the latency buckets, seconds
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025,
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Counters by the value of a single label
+#[derive(Debug, Default)]
+pub struct Counters<K>(Mutex<BTreeMap<K, u64>>);
+
+impl<K: Ord> Counters<K> {
+    pub fn inc(&self, key: K) {
+        *self.0.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    pub fn get(&self, key: &K) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// Cumulative buckets, as Prometheus has them: each bucket counts
+// the observations up to its bound (and `+Inf` is `count`).
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    micros: AtomicU64, // sum
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds
+                .iter()
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+        for (bound, bucket) in
+            self.bounds.iter().zip(&self.buckets)
+        {
+            if secs <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.micros.fetch_add(
+            value.as_micros() as u64,
+            Ordering::Relaxed,
+        );
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(LATENCY_BUCKETS)
+    }
+}
+
+// Prometheus text exposition format (version 0.0.4)
+#[derive(Default)]
+pub struct Text(String);
+
+impl Text {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} {kind}");
+    }
+
+    pub fn counter(
+        &mut self,
+        name: &str,
+        help: &str,
+        counter: &Counter,
+    ) {
+        self.header(name, "counter", help);
+        let _ = writeln!(self.0, "{name} {}", counter.get());
+    }
+
+    pub fn counters<K: Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        counters: &Counters<K>,
+    ) {
+        self.header(name, "counter", help);
+        for (key, n) in counters.0.lock().unwrap().iter() {
+            let _ = writeln!(
+                self.0,
+                "{name}{{{label}=\"{key}\"}} {n}"
+            );
+        }
+    }
+
+    pub fn histogram(
+        &mut self,
+        name: &str,
+        help: &str,
+        histogram: &Histogram,
+    ) {
+        self.header(name, "histogram", help);
+        let bounds = histogram.bounds.iter();
+        for (bound, bucket) in bounds.zip(&histogram.buckets) {
+            let n = bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                self.0,
+                "{name}_bucket{{le=\"{bound}\"}} {n}"
+            );
+        }
+        let count = histogram.count();
+        let sum = histogram.micros.load(Ordering::Relaxed)
+            as f64
+            / 1e6;
+        let _ = writeln!(
+            self.0,
+            "{name}_bucket{{le=\"+Inf\"}} {count}"
+        );
+        let _ = writeln!(self.0, "{name}_sum {sum}");
+        let _ = writeln!(self.0, "{name}_count {count}");
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+    }
+}
+
+// Minimal HTTP/1.1 listener for scraping: `GET /metrics` gets the
+// `render`ed text, anything else gets 404. A connection per request.
+pub fn serve(
+    listener: TcpListener,
+    render: impl Fn() -> String,
+) {
+    for socket in listener.incoming().flatten() {
+        let _ = socket
+            .set_read_timeout(Some(Duration::from_secs(5)));
+        let _ = respond(socket, &render);
+    }
+}
+
+fn respond(
+    mut socket: TcpStream,
+    render: &impl Fn() -> String,
+) -> Result<()> {
+    let mut reader = BufReader::new(&socket);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    // the headers are of no interest, but have to be read
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        socket,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    socket.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let h = Histogram::new(&[0.001, 0.01, 0.1]);
+        h.observe(Duration::from_micros(500));
+        h.observe(Duration::from_millis(5));
+        h.observe(Duration::from_secs(1));
+
+        let mut text = Text::default();
+        text.histogram("latency_seconds", "Latency.", &h);
+        let text = text.finish();
+        assert!(
+            text.contains("# TYPE latency_seconds histogram")
+        );
+        assert!(text.contains(
+            "latency_seconds_bucket{le=\"0.001\"} 1\n"
+        ));
+        assert!(text.contains(
+            "latency_seconds_bucket{le=\"0.01\"} 2\n"
+        ));
+        assert!(text
+            .contains("latency_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(text.contains(
+            "latency_seconds_bucket{le=\"+Inf\"} 3\n"
+        ));
+        assert!(text.contains("latency_seconds_sum 1.0055\n"));
+        assert!(text.contains("latency_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn test_counters() {
+        let frames = Counters::default();
+        frames.inc(2);
+        frames.inc(1);
+        frames.inc(2);
+        assert_eq!(frames.get(&2), 2);
+        assert_eq!(frames.get(&3), 0);
+
+        let mut text = Text::default();
+        text.counters("frames_total", "Frames.", "tag", &frames);
+        assert_eq!(
+            text.finish(),
+            "# HELP frames_total Frames.\n\
+             # TYPE frames_total counter\n\
+             frames_total{tag=\"1\"} 1\n\
+             frames_total{tag=\"2\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        thread::spawn(move || {
+            serve(listener, || "up 1\n".to_string())
+        });
+
+        let get = |path: &str| -> Result<String> {
+            let mut socket = TcpStream::connect(addr)?;
+            write!(
+                socket,
+                "GET {path} HTTP/1.1\r\nHost: x\r\n\r\n"
+            )?;
+            let mut response = String::new();
+            socket.read_to_string(&mut response)?;
+            Ok(response)
+        };
+        let ok = get("/metrics")?;
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.ends_with("\r\n\r\nup 1\n"));
+        assert!(get("/")?.starts_with("HTTP/1.1 404"));
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::api::{Error, Frame, Receiver, Result, Sender};
+
+// The callers waiting, by `idx` of the request, in the order sent,
+// each with a token of its own (see `Receipt::wait`)
+type Pending = Arc<
+    Mutex<HashMap<u32, VecDeque<(u64, mpsc::Sender<Frame>)>>>,
+>;
+
+// Multiple in-flight requests on a single connection: a response
+// carries `idx` of the request and is dispatched back to the
+// waiting caller. `idx` is the sender's timestamp and need not be
+// unique: the requests of the same `idx` are told apart by the
+// order they were sent in, the server answers the frames of a
+// session in that order.
+pub struct Mux<T> {
+    tx: Arc<T>,
+    lock: Mutex<()>, // sending a frame is not atomic
+    pending: Pending,
+    tokens: AtomicU64,
+}
+
+pub struct Receipt {
+    rx: mpsc::Receiver<Frame>,
+    idx: u32,
+    token: u64,
+    pending: Pending,
+}
+
+impl<T> Mux<T>
+where
+    T: Sender<Frame> + Receiver<Frame> + Send + Sync + 'static,
+{
+    pub fn new(tx: T) -> Self {
+        let tx = Arc::new(tx);
+        let pending: Pending =
+            Arc::new(Mutex::new(HashMap::new()));
+        {
+            let tx = tx.clone();
+            let pending = pending.clone();
+            thread::spawn(move || {
+                // dropping the senders on exit fails pending calls
+                while let Ok(Some(frame)) = tx.recv() {
+                    let idx = frame.idx;
+                    let mut pending = pending.lock().unwrap();
+                    let Some(callers) = pending.get_mut(&idx)
+                    else {
+                        continue;
+                    };
+                    if let Some((_, sender)) =
+                        callers.pop_front()
+                    {
+                        let _ = sender.send(frame);
+                    }
+                    if callers.is_empty() {
+                        pending.remove(&idx);
+                    }
+                }
+                pending.lock().unwrap().clear();
+            });
+        }
+        Self {
+            tx,
+            lock: Mutex::new(()),
+            pending,
+            tokens: AtomicU64::new(0),
+        }
+    }
+
+    pub fn send(&self, frame: &Frame) -> Result<Receipt> {
+        let (sender, rx) = mpsc::channel();
+        let token = self.tokens.fetch_add(1, Ordering::Relaxed);
+        // queued in the order sent: under the lock of sending
+        let _guard = self.lock.lock().unwrap();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(frame.idx)
+            .or_default()
+            .push_back((token, sender));
+        if let Err(e) = self.tx.send(frame) {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(callers) = pending.get_mut(&frame.idx) {
+                callers.pop_back();
+            }
+            return Err(e);
+        }
+        Ok(Receipt {
+            rx,
+            idx: frame.idx,
+            token,
+            pending: self.pending.clone(),
+        })
+    }
+
+    pub fn call(
+        &self,
+        frame: &Frame,
+        timeout: Duration,
+    ) -> Result<Frame> {
+        self.send(frame)?.wait(timeout)
+    }
+}
+
+impl Receipt {
+    pub fn wait(self, timeout: Duration) -> Result<Frame> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(frame) => Ok(frame),
+            // the request gives up its place: the response to the
+            // next caller of the same `idx` is not to go to this
+            // one, nobody is waiting for it here any more
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let mut pending = self.pending.lock().unwrap();
+                if let Some(callers) = pending.get_mut(&self.idx)
+                {
+                    callers.retain(|(token, _)| {
+                        *token != self.token
+                    });
+                    if callers.is_empty() {
+                        pending.remove(&self.idx);
+                    }
+                }
+                let kind = std::io::ErrorKind::TimedOut;
+                let e = std::io::Error::new(kind, "timeout");
+                Err(Error::IO(e))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(Error::App("connection closed".to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::tcp::Tcp;
+
+    fn frame(idx: u32) -> Frame {
+        Frame {
+            idx,
+            tag: 0,
+            msg: idx * 10,
+            key: 0,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn test_out_of_order() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        // echo all requests back in the reverse order, once the
+        // last one is there
+        let h = thread::spawn(move || -> Result<()> {
+            let rx = Tcp::from(listener.accept()?.0);
+            let mut frames = Vec::new();
+            for _ in 0..4 {
+                let frame: Frame =
+                    rx.recv_timeout(Duration::from_secs(1))?;
+                frames.push(frame);
+            }
+            for frame in frames.iter().rev() {
+                rx.send(frame)?;
+            }
+            Ok(())
+        });
+
+        let mux = Mux::new(Tcp::from(TcpStream::connect(addr)?));
+        let receipts = [1, 2, 3]
+            .into_iter()
+            .map(|idx| mux.send(&frame(idx)))
+            .collect::<Result<Vec<_>>>()?;
+        let last = mux.send(&frame(4))?;
+
+        for (idx, receipt) in [1, 2, 3, 4]
+            .into_iter()
+            .zip(receipts.into_iter().chain([last]))
+        {
+            let rcvd = receipt.wait(Duration::from_secs(1))?;
+            assert_eq!(rcvd, frame(idx));
+        }
+        h.join()??;
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_idx() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        // echo all requests back, in order
+        let h = thread::spawn(move || -> Result<()> {
+            let rx = Tcp::from(listener.accept()?.0);
+            for _ in 0..4 {
+                let frame: Frame =
+                    rx.recv_timeout(Duration::from_secs(1))?;
+                rx.send(&frame)?;
+            }
+            Ok(())
+        });
+
+        // sent within the same second, told apart by `msg`
+        let frames = (0..4)
+            .map(|msg| Frame { msg, ..frame(1) })
+            .collect::<Vec<_>>();
+        let mux = Mux::new(Tcp::from(TcpStream::connect(addr)?));
+        let receipts = frames
+            .iter()
+            .map(|frame| mux.send(frame))
+            .collect::<Result<Vec<_>>>()?;
+        for (frame, receipt) in frames.iter().zip(receipts) {
+            let rcvd = receipt.wait(Duration::from_secs(1))?;
+            assert_eq!(&rcvd, frame);
+        }
+        h.join()??;
+        Ok(())
+    }
+
+    #[test]
+    fn test_timed_out() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        // the first request is never answered, the second one is
+        let h = thread::spawn(move || -> Result<()> {
+            let rx = Tcp::from(listener.accept()?.0);
+            for _ in 0..2 {
+                let frame: Frame =
+                    rx.recv_timeout(Duration::from_secs(1))?;
+                if frame.msg > 0 {
+                    rx.send(&frame)?;
+                }
+            }
+            Ok(())
+        });
+
+        let mux = Mux::new(Tcp::from(TcpStream::connect(addr)?));
+        let first = Frame { msg: 0, ..frame(1) };
+        assert!(mux
+            .call(&first, Duration::from_millis(50))
+            .is_err());
+        assert!(mux.pending.lock().unwrap().is_empty());
+
+        // of the same `idx`
+        let second = frame(1);
+        let rcvd = mux.call(&second, Duration::from_secs(1))?;
+        assert_eq!(rcvd, second);
+        h.join()??;
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::Mutex,
+    time::Duration,
+};
+
+use snow::{
+    params::DHChoice,
+    resolvers::{CryptoResolver, DefaultResolver},
+    Builder, HandshakeState, TransportState,
+};
+
+use crate::{
+    api::{
+        Error, Frame, Receiver, Result, Sender, MAX_FRAME_LEN,
+    },
+    codec::{Codec, Raw},
+    tcp::recv_within,
+};
+
+const PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+const MAX_MESSAGE_LEN: usize = 65535;
+const TAG_LEN: usize = 16; // AEAD tag
+
+// Frames over Noise XX: both sides prove their static keys during
+// the handshake, then the same length-prefixed raw frames as `Tcp`
+// are carried in Noise transport messages (AEAD), each of them
+// prefixed with u16 length on the wire.
+//
+// The cipher state is locked only to encrypt/decrypt, so that
+// reading and writing can happen concurrently (`Mux`).
+pub struct Noise {
+    socket: TcpStream,
+    state: Mutex<TransportState>,
+    plain: Mutex<Vec<u8>>, // decrypted, not yet consumed
+    remote: Vec<u8>,
+}
+
+impl Noise {
+    // `trusted` static keys of the remote side, empty to accept any
+    pub fn initiator(
+        socket: TcpStream,
+        private_key: &[u8],
+        trusted: &[Vec<u8>],
+    ) -> Result<Self> {
+        let mut hs = builder(private_key)?
+            .build_initiator()
+            .map_err(noise)?;
+        write_handshake(&socket, &mut hs)?; // -> e
+        read_handshake(&socket, &mut hs)?; // <- e, ee, s, es
+        write_handshake(&socket, &mut hs)?; // -> s, se
+        Self::new(socket, hs, trusted)
+    }
+
+    pub fn responder(
+        socket: TcpStream,
+        private_key: &[u8],
+        trusted: &[Vec<u8>],
+    ) -> Result<Self> {
+        let mut hs = builder(private_key)?
+            .build_responder()
+            .map_err(noise)?;
+        read_handshake(&socket, &mut hs)?;
+        write_handshake(&socket, &mut hs)?;
+        read_handshake(&socket, &mut hs)?;
+        Self::new(socket, hs, trusted)
+    }
+
+    fn new(
+        socket: TcpStream,
+        hs: HandshakeState,
+        trusted: &[Vec<u8>],
+    ) -> Result<Self> {
+        let remote = hs
+            .get_remote_static()
+            .map(|key| key.to_vec())
+            .unwrap_or_default();
+        if !trusted.is_empty() && !trusted.contains(&remote) {
+            return Err(Error::App(
+                "noise: untrusted remote static key".to_string(),
+            ));
+        }
+        let state = hs.into_transport_mode().map_err(noise)?;
+        Ok(Noise {
+            socket,
+            state: Mutex::new(state),
+            plain: Mutex::new(Vec::new()),
+            remote,
+        })
+    }
+
+    // Static public key of the remote side
+    pub fn remote_key(&self) -> &[u8] {
+        &self.remote
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<()> {
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+        let mut state = self.state.lock().unwrap();
+        for chunk in bytes.chunks(MAX_MESSAGE_LEN - TAG_LEN) {
+            let n = state
+                .write_message(chunk, &mut buf)
+                .map_err(noise)?;
+            write_message(&self.socket, &buf[..n])?;
+        }
+        Ok(())
+    }
+
+    // None on EOF before the first byte
+    fn read(&self, len: usize) -> Result<Option<Vec<u8>>> {
+        let mut plain = self.plain.lock().unwrap();
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+        while plain.len() < len {
+            let message = match read_message(&self.socket)? {
+                Some(message) => message,
+                None if plain.is_empty() => return Ok(None),
+                None => {
+                    let e = std::io::Error::from(
+                        ErrorKind::UnexpectedEof,
+                    );
+                    return Err(Error::IO(e));
+                }
+            };
+            let n = {
+                let mut state = self.state.lock().unwrap();
+                state
+                    .read_message(&message, &mut buf)
+                    .map_err(noise)?
+            };
+            plain.extend_from_slice(&buf[..n]);
+        }
+        Ok(Some(plain.drain(..len).collect()))
+    }
+}
+
+fn noise(e: snow::Error) -> Error {
+    Error::App(format!("noise: {e}"))
+}
+
+fn builder(private_key: &[u8]) -> Result<Builder<'_>> {
+    let params = PARAMS.parse().map_err(noise)?;
+    Ok(Builder::new(params).local_private_key(private_key))
+}
+
+// (private, public) static keypair
+pub fn generate_keypair() -> Result<(Vec<u8>, Vec<u8>)> {
+    let params = PARAMS.parse().map_err(noise)?;
+    let keypair = Builder::new(params)
+        .generate_keypair()
+        .map_err(noise)?;
+    Ok((keypair.private, keypair.public))
+}
+
+pub fn public_key(private_key: &[u8]) -> Result<Vec<u8>> {
+    let mut dh = DefaultResolver
+        .resolve_dh(&DHChoice::Curve25519)
+        .ok_or_else(|| {
+            Error::Other("noise: no Curve25519".to_string())
+        })?;
+    if private_key.len() != dh.priv_len() {
+        return Err(Error::App(format!(
+            "noise: invalid private key length: {}",
+            private_key.len()
+        )));
+    }
+    dh.set(private_key);
+    Ok(dh.pubkey().to_vec())
+}
+
+fn write_handshake(
+    mut socket: &TcpStream,
+    hs: &mut HandshakeState,
+) -> Result<()> {
+    let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+    let n = hs.write_message(&[], &mut buf).map_err(noise)?;
+    write_message(socket, &buf[..n])?;
+    socket.flush()?;
+    Ok(())
+}
+
+fn read_handshake(
+    socket: &TcpStream,
+    hs: &mut HandshakeState,
+) -> Result<()> {
+    let message = read_message(socket)?.ok_or_else(|| {
+        Error::IO(std::io::Error::from(ErrorKind::UnexpectedEof))
+    })?;
+    let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+    hs.read_message(&message, &mut buf).map_err(noise)?;
+    Ok(())
+}
+
+fn write_message(
+    mut socket: &TcpStream,
+    message: &[u8],
+) -> Result<()> {
+    let len = message.len() as u16;
+    let mut buf = Vec::with_capacity(2 + message.len());
+    buf.extend(len.to_be_bytes());
+    buf.extend(message);
+    socket.write_all(&buf)?;
+    Ok(())
+}
+
+fn read_message(
+    mut socket: &TcpStream,
+) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match socket.read_exact(&mut len) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(e) => return Err(Error::IO(e)),
+    }
+    let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+    socket.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+impl Sender<u32> for Noise {
+    fn send(&self, msg: &u32) -> Result<()> {
+        self.write(&msg.to_be_bytes())
+    }
+}
+
+impl Receiver<u32> for Noise {
+    fn recv(&self) -> Result<Option<u32>> {
+        Ok(self.read(4)?.map(|buf| {
+            u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+        }))
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<u32> {
+        recv_within(&self.socket, within, || self.recv())
+    }
+}
+
+impl Sender<Frame> for Noise {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        let frame = Raw.encode(msg)?;
+        let mut buf = Vec::with_capacity(4 + frame.len());
+        buf.extend((frame.len() as u32).to_be_bytes());
+        buf.extend(frame);
+        self.write(&buf)
+    }
+}
+
+impl Receiver<Frame> for Noise {
+    fn recv(&self) -> Result<Option<Frame>> {
+        let len: u32 = match self.recv()? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let len = len as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(Error::App(format!(
+                "invalid frame length: {len} bytes"
+            )));
+        }
+        match self.read(len)? {
+            Some(buf) => Raw.decode(&buf).map(Some),
+            None => {
+                let e = std::io::Error::from(
+                    ErrorKind::UnexpectedEof,
+                );
+                Err(Error::IO(e))
+            }
+        }
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        recv_within(&self.socket, within, || self.recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+    use crate::api::MAX_PAYLOAD_LEN;
+
+    #[test]
+    fn test_public_key() -> Result<()> {
+        let (private, public) = generate_keypair()?;
+        assert_eq!(public_key(&private)?, public);
+        assert!(public_key(&private[1..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_roundtrip() -> Result<()> {
+        let (server, server_pub) = generate_keypair()?;
+        let (client, client_pub) = generate_keypair()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let h = thread::spawn(move || -> Result<Vec<u8>> {
+            let socket = listener.accept()?.0;
+            let tx = Noise::responder(socket, &server, &[])?;
+            let frame: Frame =
+                tx.recv_timeout(Duration::from_secs(1))?;
+            tx.send(&frame)?;
+            Ok(tx.remote_key().to_vec())
+        });
+
+        let socket = TcpStream::connect(addr)?;
+        let tx =
+            Noise::initiator(socket, &client, &[server_pub])?;
+        let frame = Frame {
+            idx: 1,
+            tag: 2,
+            msg: 3,
+            key: 4,
+            sig: 5,
+            ext: 6,
+            ns: 0,
+            sum: 7,
+            data: vec![42; MAX_PAYLOAD_LEN], // many messages
+        };
+        tx.send(&frame)?;
+        let echo: Frame =
+            tx.recv_timeout(Duration::from_secs(1))?;
+        let remote = h.join()??;
+
+        assert_eq!(echo, frame);
+        assert_eq!(remote, client_pub);
+        Ok(())
+    }
+
+    #[test]
+    fn test_untrusted_server() -> Result<()> {
+        let (server, _) = generate_keypair()?;
+        let (client, _) = generate_keypair()?;
+        let (_, other) = generate_keypair()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let h = thread::spawn(move || -> Result<()> {
+            let socket = listener.accept()?.0;
+            let _ = Noise::responder(socket, &server, &[]);
+            Ok(())
+        });
+
+        let socket = TcpStream::connect(addr)?;
+        assert!(
+            Noise::initiator(socket, &client, &[other]).is_err()
+        );
+        h.join()??;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+};
+
+// Replay protection: a frame's `idx` is the sender's timestamp,
+// which must be fresh (within `window` seconds from now), and the
+// frame (`idx`, the sender's namespace and key, and the `digest` of
+// what is signed) must not have been seen before, on any connection
+// the nonces are shared by. Not the checksum `sum`: it covers `sig`,
+// which can be changed without changing the signer (`s` for `N - s`).
+#[derive(Debug)]
+pub struct Nonces {
+    window: u32,
+    seen: BTreeSet<(u32, u64, u32)>,
+    last: u32,
+}
+
+impl Nonces {
+    pub fn new(window: u32) -> Self {
+        Self {
+            window,
+            seen: BTreeSet::new(),
+            last: 0,
+        }
+    }
+
+    // Next `idx` to send: current time, strictly increasing.
+    pub fn next(&mut self, now: u32) -> u32 {
+        self.last = now.max(self.last.saturating_add(1));
+        self.last
+    }
+
+    // `from`: the namespace (high 32 bits) and the key of the sender
+    pub fn check(
+        &mut self,
+        idx: u32,
+        from: u64,
+        digest: u32,
+        now: u32,
+    ) -> bool {
+        if now.abs_diff(idx) > self.window {
+            return false;
+        }
+        let oldest = now.saturating_sub(self.window);
+        self.seen = self.seen.split_off(&(oldest, 0, 0));
+        self.seen.insert((idx, from, digest))
+    }
+}
+
+// Process-wide `Nonces::next`, by what is sent (`tag`, `msg`, `key`
+// and `ns` of the frame): the current time, later than the last
+// `idx` of the same, so that sending it again within a second is
+// not taken for a replay. Different frames share the second, as
+// many of them as there are: a burst does not run `idx` ahead of
+// the clock, out of the window.
+pub fn next_idx(what: (u32, u32, u32, u32)) -> u32 {
+    static LAST: Mutex<BTreeMap<(u32, u32, u32, u32), u32>> =
+        Mutex::new(BTreeMap::new());
+    let now = crate::util::time();
+    let mut last = LAST.lock().unwrap();
+    // the current time will do for anything sent before it
+    last.retain(|_, idx| *idx >= now);
+    let idx = match last.get(&what) {
+        Some(idx) => idx.saturating_add(1),
+        None => now,
+    };
+    last.insert(what, idx);
+    idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freshness() {
+        let mut nonces = Nonces::new(10);
+        let now = 1000;
+        assert!(nonces.check(now - 10, 1, 0, now));
+        assert!(nonces.check(now + 10, 1, 0, now));
+        assert!(!nonces.check(now - 11, 1, 0, now));
+        assert!(!nonces.check(now + 11, 1, 0, now));
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut nonces = Nonces::new(10);
+        let now = 1000;
+        let idx = nonces.next(now);
+        assert_eq!(idx, now);
+        assert!(nonces.check(idx, 1, 42, now));
+        assert!(!nonces.check(idx, 1, 42, now));
+        assert!(nonces.check(idx, 1, 43, now));
+        // the same digest from another key
+        assert!(nonces.check(idx, 2, 42, now));
+
+        let next = nonces.next(now);
+        assert_eq!(next, now + 1);
+        assert!(nonces.check(next, 1, 42, now));
+    }
+
+    #[test]
+    fn test_next_idx() {
+        let a = next_idx((1, 0, 0, 0));
+        let b = next_idx((1, 0, 0, 0));
+        assert!(b > a);
+        assert!(a >= crate::util::time() - 1);
+
+        // a burst: of the same second, but for the repeated frame
+        let now = crate::util::time();
+        let burst = (0..100)
+            .map(|msg| next_idx((2, msg, 0, 0)))
+            .collect::<Vec<_>>();
+        assert!(burst.iter().all(|idx| *idx <= now + 1));
+        assert!(next_idx((2, 0, 0, 0)) > burst[0]);
+    }
+}
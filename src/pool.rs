@@ -0,0 +1,167 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Mutex};
+
+use crate::api::Result;
+
+// Idle connections (established sessions, handshake done) by peer
+// address, to be reused instead of connecting again for each call.
+pub struct Pool<T> {
+    idle: Mutex<BTreeMap<SocketAddr, Vec<T>>>,
+    max_idle: usize, // per peer
+}
+
+impl<T> Pool<T> {
+    pub const fn new(max_idle: usize) -> Self {
+        Pool {
+            idle: Mutex::new(BTreeMap::new()),
+            max_idle,
+        }
+    }
+
+    pub fn take(&self, addr: &SocketAddr) -> Option<T> {
+        let mut idle = self.idle.lock().unwrap();
+        idle.get_mut(addr).and_then(|conns| conns.pop())
+    }
+
+    pub fn put(&self, addr: SocketAddr, conn: T) {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.entry(addr).or_default();
+        if conns.len() < self.max_idle {
+            conns.push(conn);
+        }
+    }
+
+    pub fn idle(&self, addr: &SocketAddr) -> usize {
+        let idle = self.idle.lock().unwrap();
+        idle.get(addr)
+            .map(|conns| conns.len())
+            .unwrap_or_default()
+    }
+
+    // Send a request with `send` on an idle connection if there is
+    // one, or on a new one from `connect` otherwise (also when
+    // sending on the idle one fails, e.g. closed by the peer
+    // meanwhile), then wait for the response with `recv`. A failure
+    // to receive is not retried: the peer may have taken the
+    // request already, and the same request again could be applied
+    // twice. The connection goes back to the pool only if both
+    // succeeded.
+    pub fn with<S, R>(
+        &self,
+        addr: SocketAddr,
+        connect: impl FnOnce() -> Result<T>,
+        mut send: impl FnMut(&mut T) -> Result<S>,
+        recv: impl FnOnce(&mut T, S) -> Result<R>,
+    ) -> Result<R> {
+        let sent = self.take(&addr).and_then(|mut conn| {
+            let sent = send(&mut conn).ok()?;
+            Some((conn, sent))
+        });
+        let (mut conn, sent) = match sent {
+            Some(sent) => sent,
+            None => {
+                let mut conn = connect()?;
+                let sent = send(&mut conn)?;
+                (conn, sent)
+            }
+        };
+        let ret = recv(&mut conn, sent)?;
+        self.put(addr, conn);
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Error;
+
+    #[test]
+    fn test_reuse() -> Result<()> {
+        let pool = Pool::new(2);
+        let addr: SocketAddr = ([127, 0, 0, 1], 10001).into();
+
+        let mut connects = 0;
+        for _ in 0..3 {
+            let id = pool.with(
+                addr,
+                || {
+                    connects += 1;
+                    Ok(connects)
+                },
+                |_| Ok(()),
+                |conn, _| Ok(*conn),
+            )?;
+            assert_eq!(id, 1);
+        }
+        assert_eq!(connects, 1);
+        assert_eq!(pool.idle(&addr), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale() -> Result<()> {
+        let pool = Pool::new(2);
+        let addr: SocketAddr = ([127, 0, 0, 1], 10001).into();
+        pool.put(addr, false); // stale
+
+        let ok = pool.with(
+            addr,
+            || Ok(true),
+            |conn| {
+                if *conn {
+                    Ok(())
+                } else {
+                    Err(Error::App("closed".to_string()))
+                }
+            },
+            |conn, _| Ok(*conn),
+        )?;
+        assert!(ok);
+        assert_eq!(pool.take(&addr), Some(true));
+        assert_eq!(pool.take(&addr), None);
+
+        // failed calls do not return the connection
+        let ret: Result<()> = pool.with(
+            addr,
+            || Ok(true),
+            |_| Ok(()),
+            |_, _| Err(Error::App("nope".to_string())),
+        );
+        assert!(ret.is_err());
+        assert_eq!(pool.idle(&addr), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sent_once() -> Result<()> {
+        let pool = Pool::new(2);
+        let addr: SocketAddr = ([127, 0, 0, 1], 10001).into();
+        pool.put(addr, 0);
+
+        // sent, but no response: not sent again on a new one
+        let mut sent = 0;
+        let ret: Result<()> = pool.with(
+            addr,
+            || Ok(1),
+            |_| {
+                sent += 1;
+                Ok(())
+            },
+            |_, _| Err(Error::App("closed".to_string())),
+        );
+        assert!(ret.is_err());
+        assert_eq!(sent, 1);
+        assert_eq!(pool.idle(&addr), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_idle() {
+        let pool = Pool::new(2);
+        let addr: SocketAddr = ([127, 0, 0, 1], 10001).into();
+        for i in 0..5 {
+            pool.put(addr, i);
+        }
+        assert_eq!(pool.idle(&addr), 2);
+    }
+}
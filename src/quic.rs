@@ -0,0 +1,261 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    Connection, Endpoint, RecvStream, SendStream,
+};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    api::{Error, Frame, Result, MAX_FRAME_LEN},
+    codec::{Codec, Raw},
+};
+
+// Frames over QUIC: each request/response pair gets its own
+// bidirectional stream (raw frame bytes, the end of the stream
+// delimits the frame), so requests on the same connection are
+// multiplexed, encrypted (TLS 1.3) and recovered from loss by QUIC.
+//
+// The async runtime stays inside this module, the API is blocking.
+pub struct QuicClient {
+    rt: Runtime,
+    endpoint: Endpoint,
+    conn: Connection,
+}
+
+impl QuicClient {
+    // `config` verifies the server certificate (see `tls`)
+    pub fn connect(
+        addr: SocketAddr,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<Self> {
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (endpoint, conn) = rt.block_on(async {
+            let local: SocketAddr = if addr.is_ipv4() {
+                ([0, 0, 0, 0], 0).into()
+            } else {
+                ([0u16; 8], 0).into()
+            };
+            let mut endpoint = Endpoint::client(local)?;
+            let config = QuicClientConfig::try_from(config)
+                .map_err(quic)?;
+            endpoint.set_default_client_config(
+                quinn::ClientConfig::new(Arc::new(config)),
+            );
+            let name = addr.ip().to_string();
+            let conn = endpoint
+                .connect(addr, &name)
+                .map_err(quic)?
+                .await
+                .map_err(quic)?;
+            Ok::<_, Error>((endpoint, conn))
+        })?;
+        Ok(QuicClient { rt, endpoint, conn })
+    }
+
+    pub fn call(&self, frame: &Frame) -> Result<Frame> {
+        self.rt.block_on(call(self.conn.clone(), frame.clone()))
+    }
+
+    // `call` in two: the request on a stream of its own, then the
+    // response read from it (see `Pool::with`)
+    pub fn send(&self, frame: &Frame) -> Result<RecvStream> {
+        self.rt.block_on(send(self.conn.clone(), frame.clone()))
+    }
+
+    pub fn recv(&self, stream: RecvStream) -> Result<Frame> {
+        self.rt.block_on(read(stream))
+    }
+
+    // All the frames at once (a stream each), responses are
+    // returned in the same order as requests
+    pub fn call_all(
+        &self,
+        frames: &[Frame],
+    ) -> Result<Vec<Frame>> {
+        let handles = frames
+            .iter()
+            .map(|frame| {
+                let conn = self.conn.clone();
+                self.rt.spawn(call(conn, frame.clone()))
+            })
+            .collect::<Vec<_>>();
+        self.rt.block_on(async {
+            let mut responses =
+                Vec::with_capacity(handles.len());
+            for handle in handles {
+                responses.push(handle.await.map_err(quic)??);
+            }
+            Ok(responses)
+        })
+    }
+}
+
+impl Drop for QuicClient {
+    fn drop(&mut self) {
+        self.conn.close(0u32.into(), b"done");
+        self.rt.block_on(self.endpoint.wait_idle());
+    }
+}
+
+async fn call(conn: Connection, frame: Frame) -> Result<Frame> {
+    read(send(conn, frame).await?).await
+}
+
+async fn send(
+    conn: Connection,
+    frame: Frame,
+) -> Result<RecvStream> {
+    let (mut send, recv) = conn.open_bi().await.map_err(quic)?;
+    send.write_all(&Raw.encode(&frame)?).await.map_err(quic)?;
+    send.finish().map_err(quic)?;
+    Ok(recv)
+}
+
+async fn read(mut recv: RecvStream) -> Result<Frame> {
+    let buf =
+        recv.read_to_end(MAX_FRAME_LEN).await.map_err(quic)?;
+    Raw.decode(&buf)
+}
+
+// Accept QUIC connections on `addr` (UDP) until `until` returns
+// (it is called on a separate thread) or the endpoint fails.
+// `respond` is called (with the remote address) for each request on
+// the blocking thread pool, there is no thread per connection. The
+// `respond` calls in flight are waited for before returning, but
+// their responses are not sent: the connections are closed.
+pub fn serve<F>(
+    addr: SocketAddr,
+    config: Arc<rustls::ServerConfig>,
+    until: impl FnOnce() + Send + 'static,
+    respond: F,
+) -> Result<()>
+where
+    F: Fn(SocketAddr, Frame) -> Frame + Send + Sync + 'static,
+{
+    let rt = Builder::new_multi_thread().enable_all().build()?;
+    let respond = Arc::new(respond);
+    rt.block_on(async move {
+        let config =
+            QuicServerConfig::try_from(config).map_err(quic)?;
+        let config =
+            quinn::ServerConfig::with_crypto(Arc::new(config));
+        let endpoint = Endpoint::server(config, addr)?;
+        {
+            let endpoint = endpoint.clone();
+            std::thread::spawn(move || {
+                until();
+                endpoint.close(0u32.into(), b"shutdown");
+            });
+        }
+        while let Some(incoming) = endpoint.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let conn = incoming.await.map_err(quic)?;
+                let remote = conn.remote_address();
+                // until the client closes the connection
+                while let Ok((send, recv)) =
+                    conn.accept_bi().await
+                {
+                    let respond = respond.clone();
+                    tokio::spawn(stream(
+                        remote, send, recv, respond,
+                    ));
+                }
+                Ok::<_, Error>(())
+            });
+        }
+        Ok(())
+    })
+    // dropping the runtime waits for the blocking pool
+}
+
+async fn stream<F>(
+    remote: SocketAddr,
+    mut send: SendStream,
+    recv: RecvStream,
+    respond: Arc<F>,
+) -> Result<()>
+where
+    F: Fn(SocketAddr, Frame) -> Frame + Send + Sync + 'static,
+{
+    let frame = read(recv).await?;
+    let response = tokio::task::spawn_blocking(move || {
+        respond(remote, frame)
+    })
+    .await
+    .map_err(quic)?;
+    send.write_all(&Raw.encode(&response)?)
+        .await
+        .map_err(quic)?;
+    send.finish().map_err(quic)?;
+    Ok(())
+}
+
+fn quic<E: std::fmt::Display>(e: E) -> Error {
+    Error::App(format!("quic: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    use super::*;
+    use crate::tls::{client_config, server_config};
+
+    #[test]
+    fn test_stream_per_request() -> Result<()> {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![
+                "127.0.0.1".to_string(),
+            ])
+            .unwrap();
+        let cert = cert.pem().into_bytes();
+        let key = key_pair.serialize_pem().into_bytes();
+        let server = server_config(&cert, &key)?;
+        let client = client_config(&cert)?;
+
+        // pick a free UDP port
+        let addr =
+            UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+        let (stop, until) = std::sync::mpsc::channel::<()>();
+        let h = thread::spawn(move || {
+            let until = move || {
+                let _ = until.recv();
+            };
+            serve(addr, server, until, |_remote, mut frame| {
+                frame.msg += 1;
+                frame
+            })
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let frames = (0..16)
+            .map(|idx| Frame {
+                idx,
+                msg: idx * 10,
+                data: vec![42; idx as usize],
+                ..Frame::default()
+            })
+            .collect::<Vec<_>>();
+
+        let tx = QuicClient::connect(addr, client)?;
+        let rcvd = tx.call(&frames[0])?;
+        assert_eq!(rcvd.msg, 1);
+
+        let rcvd = tx.call_all(&frames)?;
+        for (sent, rcvd) in frames.iter().zip(rcvd.iter()) {
+            assert_eq!(rcvd.idx, sent.idx);
+            assert_eq!(rcvd.msg, sent.msg + 1);
+            assert_eq!(rcvd.data, sent.data);
+        }
+        drop(tx);
+
+        // the server stops once told to
+        stop.send(()).unwrap();
+        h.join().unwrap()?;
+        Ok(())
+    }
+}
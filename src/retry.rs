@@ -0,0 +1,167 @@
+use std::{io::ErrorKind, thread, time::Duration};
+
+use crate::{
+    api::{Error, Result},
+    util::random,
+};
+
+// Retry policy for transient failures (the peer is not up yet, the
+// connection got dropped, no response in time): up to `retries`
+// more calls after the first one, waiting `backoff` before the first
+// retry and twice as long before each next one (up to `max_backoff`).
+// Each delay is shortened by a random part of up to `jitter` of it,
+// so that clients failing together do not retry in lockstep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retry {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64, // 0.0 ..= 1.0
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl Retry {
+    pub const NONE: Retry = Retry {
+        retries: 0,
+        backoff: Duration::ZERO,
+        max_backoff: Duration::ZERO,
+        jitter: 0.0,
+    };
+
+    // Delay before retry number `retry` (starting from 0)
+    pub fn delay(
+        &self,
+        retry: u32,
+        random: impl FnOnce() -> u32,
+    ) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        let delay = self
+            .backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0)
+            * (random() as f64 / u32::MAX as f64);
+        delay.mul_f64(1.0 - jitter)
+    }
+
+    // Call `f` until it succeeds, fails with a non-transient error,
+    // or runs out of retries (then the last error is returned).
+    pub fn call<R>(
+        &self,
+        mut f: impl FnMut() -> Result<R>,
+    ) -> Result<R> {
+        let mut retry = 0;
+        loop {
+            match f() {
+                Err(e)
+                    if retry < self.retries
+                        && is_transient(&e) =>
+                {
+                    thread::sleep(self.delay(retry, random));
+                    retry += 1;
+                }
+                ret => return ret,
+            }
+        }
+    }
+}
+
+// Errors worth retrying: connection-level I/O failures and timeouts.
+// Application errors (invalid frame, rejected signature, etc) would
+// only fail again.
+pub fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::IO(e) => matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::BrokenPipe
+                | ErrorKind::TimedOut
+                | ErrorKind::WouldBlock
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::Interrupted
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: u32) -> Retry {
+        Retry {
+            retries,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            jitter: 0.5,
+        }
+    }
+
+    fn refused() -> Error {
+        Error::IO(std::io::Error::from(
+            ErrorKind::ConnectionRefused,
+        ))
+    }
+
+    #[test]
+    fn test_delay() {
+        let retry = policy(5);
+        let millis = (0..5)
+            .map(|i| retry.delay(i, || 0).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(millis, vec![1, 2, 4, 4, 4]);
+
+        // at most `jitter` of the delay is cut off
+        let delay = retry.delay(2, || u32::MAX);
+        assert_eq!(delay, Duration::from_millis(2));
+        assert_eq!(Retry::NONE.delay(10, || 42), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_transient() -> Result<()> {
+        let mut calls = 0;
+        let ret = policy(3).call(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(refused())
+            } else {
+                Ok(calls)
+            }
+        })?;
+        assert_eq!(ret, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_give_up() {
+        let mut calls = 0;
+        let ret: Result<()> = policy(2).call(|| {
+            calls += 1;
+            Err(refused())
+        });
+        assert!(ret.is_err());
+        assert_eq!(calls, 3);
+
+        // application errors are not retried
+        let mut calls = 0;
+        let ret: Result<()> = policy(2).call(|| {
+            calls += 1;
+            Err(Error::App("invalid checksum".to_string()))
+        });
+        assert!(ret.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,100 @@
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    KeyInit, XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+
+use crate::api::{Error, Result};
+
+pub const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+// Authenticated encryption of the data at rest (XChaCha20-Poly1305),
+// with the key derived from a passphrase (Argon2id, salted). Every
+// sealed message gets a random nonce, sent in front of it, so the
+// same key can seal any number of messages. The associated data is
+// not encrypted, but has to match for the message to open.
+pub struct Seal(XChaCha20Poly1305);
+
+impl Seal {
+    pub fn new(passphrase: &[u8], salt: &[u8]) -> Result<Self> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase, salt, &mut key)
+            .map_err(|e| Error::App(format!("seal: {e}")))?;
+        Ok(Self(XChaCha20Poly1305::new(&key.into())))
+    }
+
+    // nonce || ciphertext || tag
+    pub fn seal(&self, aad: &[u8], msg: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let sealed = self
+            .0
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload { msg, aad },
+            )
+            .expect("message too long");
+        nonce.into_iter().chain(sealed).collect()
+    }
+
+    pub fn open(
+        &self,
+        aad: &[u8],
+        sealed: &[u8],
+    ) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(unsealed());
+        }
+        let (nonce, msg) = sealed.split_at(NONCE_LEN);
+        self.0
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload { msg, aad },
+            )
+            .map_err(|_| unsealed())
+    }
+}
+
+pub fn salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+fn unsealed() -> Error {
+    Error::App(
+        "seal: wrong passphrase or corrupted data".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal() -> Result<()> {
+        let salt = salt();
+        let seal = Seal::new(b"passphrase", &salt)?;
+        let sealed = seal.seal(b"db", b"secret");
+        assert_eq!(sealed.len(), NONCE_LEN + 6 + 16);
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(seal.open(b"db", &sealed)?, b"secret");
+        // a fresh nonce every time
+        assert_ne!(seal.seal(b"db", b"secret"), sealed);
+
+        assert!(seal.open(b"wal", &sealed).is_err());
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(seal.open(b"db", &tampered).is_err());
+        assert!(seal.open(b"db", &sealed[..NONCE_LEN]).is_err());
+
+        let other = Seal::new(b"passphrase!", &salt)?;
+        assert!(other.open(b"db", &sealed).is_err());
+        let other = Seal::new(b"passphrase", &[0; SALT_LEN])?;
+        assert!(other.open(b"db", &sealed).is_err());
+        Ok(())
+    }
+}
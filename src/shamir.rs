@@ -0,0 +1,131 @@
+// k-of-n secret sharing (Shamir) over GF(2^32): the secret is the
+// constant term of a random polynomial of degree k-1, a share is a
+// point (x, y) on it, any k shares recover the polynomial (and the
+// secret), fewer tell nothing. Addition in the field is XOR, so
+// adding a random polynomial with zero constant term to all shares
+// (`eval` at each share's x) refreshes them, keeping the secret.
+
+// x^32 + x^22 + x^2 + x + 1 (irreducible), without the x^32 term
+const POLY: u32 = 0x0040_0007;
+
+pub fn mul(mut a: u32, mut b: u32) -> u32 {
+    let mut ret = 0;
+    while b != 0 {
+        if b & 1 == 1 {
+            ret ^= a;
+        }
+        let carry = a & 0x8000_0000 != 0;
+        a <<= 1;
+        if carry {
+            a ^= POLY;
+        }
+        b >>= 1;
+    }
+    ret
+}
+
+// a^(2^32 - 2), zero for zero
+pub fn inv(a: u32) -> u32 {
+    let mut ret = 1;
+    let mut base = a;
+    let mut exp = u32::MAX - 1;
+    while exp != 0 {
+        if exp & 1 == 1 {
+            ret = mul(ret, base);
+        }
+        base = mul(base, base);
+        exp >>= 1;
+    }
+    ret
+}
+
+// Value of the polynomial at `x`, `coeffs[0]` is the constant term
+pub fn eval(coeffs: &[u32], x: u32) -> u32 {
+    coeffs.iter().rev().fold(0, |acc, c| mul(acc, x) ^ c)
+}
+
+// `n` shares, `k` of which recover the secret; share `i` is
+// at x = i + 1 (x = 0 is the secret itself)
+pub fn split(
+    s: u32,
+    k: usize,
+    n: usize,
+    f: impl Fn() -> u32,
+) -> Vec<(u32, u32)> {
+    assert!(k >= 1 && k <= n, "invalid threshold: {k} of {n}");
+    let coeffs = std::iter::once(s)
+        .chain((1..k).map(|_| f()))
+        .collect::<Vec<_>>();
+    (1..=n as u32).map(|x| (x, eval(&coeffs, x))).collect()
+}
+
+// Lagrange interpolation at x = 0: any `k` shares (distinct x) of the
+// same split give the secret, fewer give garbage
+pub fn merge(shares: &[(u32, u32)]) -> u32 {
+    let mut ret = 0;
+    for (i, (xi, yi)) in shares.iter().enumerate() {
+        let mut num = 1;
+        let mut den = 1;
+        for (j, (xj, _)) in shares.iter().enumerate() {
+            if i != j {
+                num = mul(num, *xj);
+                den = mul(den, xj ^ xi);
+            }
+        }
+        ret ^= mul(*yi, mul(num, inv(den)));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::random;
+
+    use super::*;
+
+    #[test]
+    fn test_field() {
+        assert_eq!(mul(0xCAFEBABE, 1), 0xCAFEBABE);
+        assert_eq!(mul(0xCAFEBABE, 0), 0);
+        assert_eq!(mul(0x8000_0000, 2), POLY);
+        assert_eq!(inv(0), 0);
+        for _ in 0..100 {
+            let (a, b) = (random() | 1, random());
+            assert_eq!(mul(a, inv(a)), 1);
+            assert_eq!(mul(a, b), mul(b, a));
+        }
+    }
+
+    #[test]
+    fn test_split_merge() {
+        let secret = 0xCAFEBABE;
+        let shares = split(secret, 3, 5, random);
+        assert_eq!(shares.len(), 5);
+        assert_eq!(merge(&shares), secret);
+        for i in 0..5 {
+            let some = [
+                shares[i],
+                shares[(i + 2) % 5],
+                shares[(i + 4) % 5],
+            ];
+            assert_eq!(merge(&some), secret);
+        }
+        assert_ne!(merge(&shares[..2]), secret);
+
+        // 1-of-n: every share is the secret
+        let shares = split(secret, 1, 3, random);
+        assert!(shares.iter().all(|(_, y)| *y == secret));
+    }
+
+    #[test]
+    fn test_refresh() {
+        let secret = 0xCAFEBABE;
+        let mut shares = split(secret, 2, 3, random);
+        let delta = [0, random()];
+        shares
+            .iter_mut()
+            .for_each(|(x, y)| *y ^= eval(&delta, *x));
+        assert_eq!(merge(&shares[1..]), secret);
+        assert_eq!(merge(&shares[..2]), secret);
+    }
+}
@@ -0,0 +1,1394 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, ErrorKind, Write},
+    ops::BitXor,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    api::{Error, Result},
+    ec::PublicKey,
+    util::{crc32, merge, split},
+};
+
+#[cfg(feature = "encrypt")]
+use crate::seal::Seal;
+
+// Nothing to encrypt the files with without the `encrypt` feature
+#[cfg(not(feature = "encrypt"))]
+enum Seal {}
+
+#[cfg(not(feature = "encrypt"))]
+impl Seal {
+    fn seal(&self, _: &[u8], _: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    fn open(&self, _: &[u8], _: &[u8]) -> Result<Vec<u8>> {
+        match *self {}
+    }
+}
+
+// Shares kept by a server, a key (`K`) per secret: every version of
+// the share (`S`), the latest one patched with a mask (`M`) on each
+// refresh, and what comes along with it.
+pub trait Storage<K, S, M>: Send {
+    fn set(&mut self, key: K, secret: S);
+    // the latest version of the share, as many times as asked for
+    // (see `Reads` for read-once secrets)
+    fn get(&mut self, key: K) -> Option<S>;
+    // an earlier (or the latest) version of the share, by its index:
+    // zero for the one that was set, see `version` and `epochs`
+    fn get_version(
+        &mut self,
+        key: K,
+        version: usize,
+    ) -> Option<S>;
+    fn patch(&mut self, key: K, mask: M);
+    // next version of the share: `f` of the latest one
+    fn update(&mut self, key: K, f: impl FnOnce(S) -> S);
+    // drop the secret, leaving a tombstone with the time (unix
+    // seconds) it was deleted at, so that a share or a refresh that
+    // was on its way does not bring it back (true if it was there)
+    fn delete(&mut self, key: K, at: u32) -> bool;
+    // time the key was deleted at, unless it was set again since
+    fn deleted(&mut self, key: K) -> Option<u32>;
+    // drop the tombstones left before `before`, how many there were
+    fn forget(&mut self, before: u32) -> usize;
+    fn keys(&mut self) -> Vec<K>;
+    fn owner(&mut self, key: K) -> Option<PublicKey>;
+    fn register(&mut self, key: K, owner: PublicKey);
+    // how the secret is shared: 0 for XOR, threshold (high 16 bits)
+    // and x (low 16 bits) of a Shamir share, plus the `VERIFIABLE`
+    // bit for a Feldman one, `BYTES` for a XOR share of a byte secret
+    fn set_scheme(&mut self, key: K, scheme: u32);
+    fn scheme(&mut self, key: K) -> u32;
+    // bytes kept along with the share (the commitments of a Feldman
+    // share, the share itself for a byte secret), a version per
+    // version of the share: the next one is `f` of the latest one
+    // (empty if there is none)
+    fn attach(
+        &mut self,
+        key: K,
+        f: impl FnOnce(&[u8]) -> Vec<u8>,
+    );
+    fn attachment(&mut self, key: K, version: usize) -> Vec<u8>;
+    // version of the share `get` returns (the latest one)
+    fn version(&mut self, key: K) -> usize;
+    // epoch of each version of the share: zero when set, then the
+    // refresh round the version comes from (the previous one plus
+    // one, unless set for the latest version)
+    fn epochs(&mut self, key: K) -> Vec<u32>;
+    fn set_epoch(&mut self, key: K, epoch: u32);
+    // time (unix seconds) the secret expires at, if it ever does:
+    // reads of it fail from then on, until it is purged (deleted)
+    fn set_expiry(&mut self, key: K, at: u32);
+    fn expiry(&mut self, key: K) -> Option<u32>;
+    // everything stored, in a file of its own (a backup, or to move
+    // the shares to another node)
+    fn snapshot(&mut self) -> Vec<u8>;
+    // replace everything stored with a `snapshot`
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+// Key or share as written down by `FileDB`, big-endian
+pub trait Word: Copy + Send {
+    fn put(&self, buf: &mut Vec<u8>);
+    fn read(r: &mut Reader) -> Result<Self>;
+}
+
+impl Word for u32 {
+    fn put(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.to_be_bytes());
+    }
+
+    fn read(r: &mut Reader) -> Result<Self> {
+        r.u32()
+    }
+}
+
+impl Word for u64 {
+    fn put(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.to_be_bytes());
+    }
+
+    fn read(r: &mut Reader) -> Result<Self> {
+        r.u64()
+    }
+}
+
+// What a read of a secret does to it: nothing (`Latest`, the
+// default), or it is deleted right after the read (`Once`, burned),
+// so that it can be read only once
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Reads {
+    #[default]
+    Latest,
+    Once,
+}
+
+// In memory only
+pub struct DB<K, S> {
+    data: HashMap<K, Vec<S>>,
+    keys: HashMap<K, PublicKey>,
+    schemes: HashMap<K, u32>,
+    attachments: HashMap<K, Vec<Vec<u8>>>,
+    epochs: HashMap<K, Vec<u32>>,
+    expiry: HashMap<K, u32>,
+    tombstones: HashMap<K, u32>,
+}
+
+impl<K, S> DB<K, S> {
+    pub fn new() -> Self {
+        Self {
+            data: HashMap::new(),
+            keys: HashMap::new(),
+            schemes: HashMap::new(),
+            attachments: HashMap::new(),
+            epochs: HashMap::new(),
+            expiry: HashMap::new(),
+            tombstones: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, S> DB<K, S> {
+    // every version of the share, the first one as it was set
+    pub fn versions(&self, key: K) -> &[S] {
+        self.data.get(&key).map_or(&[], |vec| vec)
+    }
+}
+
+impl<K, S> Default for DB<K, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, S> Storage<K, S, S> for DB<K, S>
+where
+    K: Word + Eq + Hash + Ord,
+    S: Word + BitXor<Output = S>,
+{
+    fn set(&mut self, key: K, secret: S) {
+        self.data.insert(key, vec![secret]);
+        self.attachments.remove(&key);
+        self.epochs.insert(key, vec![0]);
+        self.expiry.remove(&key);
+        self.tombstones.remove(&key);
+    }
+
+    fn get(&mut self, key: K) -> Option<S> {
+        self.data.get(&key).and_then(|vec| vec.last()).cloned()
+    }
+
+    fn get_version(
+        &mut self,
+        key: K,
+        version: usize,
+    ) -> Option<S> {
+        self.data
+            .get(&key)
+            .and_then(|vec| vec.get(version))
+            .cloned()
+    }
+
+    fn patch(&mut self, key: K, mask: S) {
+        self.update(key, |last| last ^ mask);
+    }
+
+    fn update(&mut self, key: K, f: impl FnOnce(S) -> S) {
+        if let Some(vec) = self.data.get_mut(&key) {
+            if let Some(last) = vec.last().cloned() {
+                vec.push(f(last));
+                let epochs = self.epochs.entry(key).or_default();
+                let epoch = epochs.last().map_or(0, |e| e + 1);
+                epochs.push(epoch);
+            }
+        }
+    }
+
+    fn delete(&mut self, key: K, at: u32) -> bool {
+        self.keys.remove(&key);
+        self.schemes.remove(&key);
+        self.attachments.remove(&key);
+        self.epochs.remove(&key);
+        self.expiry.remove(&key);
+        self.tombstones.insert(key, at);
+        self.data.remove(&key).is_some()
+    }
+
+    fn deleted(&mut self, key: K) -> Option<u32> {
+        self.tombstones.get(&key).cloned()
+    }
+
+    fn forget(&mut self, before: u32) -> usize {
+        let n = self.tombstones.len();
+        self.tombstones.retain(|_, at| *at >= before);
+        n - self.tombstones.len()
+    }
+
+    fn keys(&mut self) -> Vec<K> {
+        let mut keys =
+            self.data.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn owner(&mut self, key: K) -> Option<PublicKey> {
+        self.keys.get(&key).cloned()
+    }
+
+    fn register(&mut self, key: K, owner: PublicKey) {
+        self.keys.entry(key).or_insert(owner);
+    }
+
+    fn set_scheme(&mut self, key: K, scheme: u32) {
+        self.schemes.insert(key, scheme);
+    }
+
+    fn attach(
+        &mut self,
+        key: K,
+        f: impl FnOnce(&[u8]) -> Vec<u8>,
+    ) {
+        let versions = self.attachments.entry(key).or_default();
+        let next =
+            f(versions.last().map_or(&[], |a| a.as_slice()));
+        versions.push(next);
+    }
+
+    fn attachment(&mut self, key: K, version: usize) -> Vec<u8> {
+        self.attachments
+            .get(&key)
+            .and_then(|versions| versions.get(version))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn version(&mut self, key: K) -> usize {
+        self.data.get(&key).map_or(0, |vec| vec.len() - 1)
+    }
+
+    fn epochs(&mut self, key: K) -> Vec<u32> {
+        self.epochs.get(&key).cloned().unwrap_or_default()
+    }
+
+    fn set_epoch(&mut self, key: K, epoch: u32) {
+        if let Some(last) =
+            self.epochs.get_mut(&key).and_then(|e| e.last_mut())
+        {
+            *last = epoch;
+        }
+    }
+
+    fn scheme(&mut self, key: K) -> u32 {
+        self.schemes.get(&key).cloned().unwrap_or_default()
+    }
+
+    fn set_expiry(&mut self, key: K, at: u32) {
+        if self.data.contains_key(&key) {
+            self.expiry.insert(key, at);
+        }
+    }
+
+    fn expiry(&mut self, key: K) -> Option<u32> {
+        self.expiry.get(&key).cloned()
+    }
+
+    fn snapshot(&mut self) -> Vec<u8> {
+        self.to_snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        *self = DB::from_snapshot(snapshot)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(()) // in memory only
+    }
+}
+
+const DB_MAGIC: &[u8; 4] = b"DSB5";
+// followed by crc32 of the rest: the DB, as in the checkpoint (but
+// never encrypted)
+const SNAPSHOT_MAGIC: &[u8; 4] = b"DSS3";
+
+fn put(buf: &mut Vec<u8>, word: u32) {
+    buf.extend(word.to_be_bytes());
+}
+
+fn put_words<W: Word>(buf: &mut Vec<u8>, words: &[W]) {
+    put(buf, words.len() as u32);
+    words.iter().for_each(|w| w.put(buf));
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put(buf, bytes.len() as u32);
+    buf.extend(bytes);
+}
+
+fn put_u64(buf: &mut Vec<u8>, x: u64) {
+    let (hi, lo) = split(x);
+    put(buf, hi);
+    put(buf, lo);
+}
+
+impl<K, S> DB<K, S>
+where
+    K: Word + Eq + Hash + Ord,
+    S: Word,
+{
+    // Tombstones (count, then key and time for each), then
+    // everything about each key, key by key: key, owner (flag, then
+    // the public key as two words), scheme, versions of the share,
+    // their epochs and their attachments (length, bytes), expiry
+    // (zero if none)
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mut tombstones =
+            self.tombstones.iter().collect::<Vec<_>>();
+        tombstones.sort();
+        put(buf, tombstones.len() as u32);
+        for (key, at) in tombstones {
+            key.put(buf);
+            put(buf, *at);
+        }
+        let mut keys = self.data.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            key.put(buf);
+            let owner = self.keys.get(key).map(u64::from);
+            put(buf, owner.is_some() as u32);
+            put_u64(buf, owner.unwrap_or_default());
+            put(
+                buf,
+                self.schemes.get(key).cloned().unwrap_or(0),
+            );
+            put_words(buf, &self.data[key]);
+            let empty = vec![];
+            put_words(
+                buf,
+                self.epochs.get(key).unwrap_or(&empty),
+            );
+            let attachments =
+                self.attachments.get(key).map_or(&[][..], |a| a);
+            put(buf, attachments.len() as u32);
+            for attachment in attachments {
+                put_bytes(buf, attachment);
+            }
+            put(buf, self.expiry.get(key).cloned().unwrap_or(0));
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Result<Self> {
+        let mut db = DB::new();
+        for _ in 0..r.u32()? {
+            let key = K::read(r)?;
+            db.tombstones.insert(key, r.u32()?);
+        }
+        while !r.0.is_empty() {
+            let key = K::read(r)?;
+            let flag = r.u32()?;
+            let owner = r.u64()?;
+            if flag != 0 {
+                db.keys.insert(key, PublicKey::from(owner));
+            }
+            db.schemes.insert(key, r.u32()?);
+            db.data.insert(key, r.words()?);
+            db.epochs.insert(key, r.words()?);
+            let attachments = (0..r.u32()?)
+                .map(|_| Ok(r.bytes_prefixed()?.to_vec()))
+                .collect::<Result<Vec<_>>>()?;
+            db.attachments.insert(key, attachments);
+            let expiry = r.u32()?;
+            if expiry != 0 {
+                db.expiry.insert(key, expiry);
+            }
+        }
+        Ok(db)
+    }
+
+    fn to_snapshot(&self) -> Vec<u8> {
+        let mut body = vec![];
+        self.encode(&mut body);
+        let mut buf = SNAPSHOT_MAGIC.to_vec();
+        put(&mut buf, crc32(&body));
+        buf.extend(body);
+        buf
+    }
+
+    fn from_snapshot(bytes: &[u8]) -> Result<Self> {
+        let mut r = Reader(bytes);
+        if r.bytes(4)? != SNAPSHOT_MAGIC {
+            return Err(Error::App(
+                "not a snapshot".to_string(),
+            ));
+        }
+        if r.u32()? != crc32(r.0) {
+            return Err(Error::App(
+                "corrupted snapshot".to_string(),
+            ));
+        }
+        DB::decode(&mut r)
+    }
+}
+
+impl<K: Eq + Hash, S> DB<K, S> {
+    // everything of the keys `other` has (none of which are here)
+    fn merge(&mut self, other: DB<K, S>) {
+        self.data.extend(other.data);
+        self.keys.extend(other.keys);
+        self.schemes.extend(other.schemes);
+        self.attachments.extend(other.attachments);
+        self.epochs.extend(other.epochs);
+        self.expiry.extend(other.expiry);
+        self.tombstones.extend(other.tombstones);
+    }
+
+    // into `n` parts, each key with everything about it goes to the
+    // part `part` tells
+    fn split(
+        self,
+        n: usize,
+        part: impl Fn(&K) -> usize,
+    ) -> Vec<Self> {
+        let mut parts =
+            (0..n).map(|_| DB::new()).collect::<Vec<_>>();
+        for (key, value) in self.data {
+            parts[part(&key)].data.insert(key, value);
+        }
+        for (key, value) in self.keys {
+            parts[part(&key)].keys.insert(key, value);
+        }
+        for (key, value) in self.schemes {
+            parts[part(&key)].schemes.insert(key, value);
+        }
+        for (key, value) in self.attachments {
+            parts[part(&key)].attachments.insert(key, value);
+        }
+        for (key, value) in self.epochs {
+            parts[part(&key)].epochs.insert(key, value);
+        }
+        for (key, value) in self.expiry {
+            parts[part(&key)].expiry.insert(key, value);
+        }
+        for (key, value) in self.tombstones {
+            parts[part(&key)].tombstones.insert(key, value);
+        }
+        parts
+    }
+}
+
+// Cursor over an encoded DB
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::App(
+                "truncated storage file".to_string(),
+            ));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn bytes_prefixed(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(
+            self.bytes(4)?.try_into().unwrap(),
+        ))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(merge(self.u32()?, self.u32()?))
+    }
+
+    fn words<W: Word>(&mut self) -> Result<Vec<W>> {
+        (0..self.u32()?).map(|_| W::read(self)).collect()
+    }
+}
+
+// A change to the DB, as recorded in the write-ahead log. Changes
+// made by a closure (`update`, `attach`) are recorded with the value
+// the closure returned, so that replaying them gives the same DB.
+#[derive(Debug, PartialEq)]
+enum Op<K, S> {
+    Set(K, S),
+    Patch(K, S),
+    Update(K, S),
+    Delete(K, u32),
+    Register(K, PublicKey),
+    Scheme(K, u32),
+    Attach(K, Vec<u8>),
+    Epoch(K, u32),
+    Expire(K, u32),
+    Forget(K), // the tombstone
+}
+
+impl<K, S> Op<K, S>
+where
+    K: Word + Eq + Hash + Ord,
+    S: Word + BitXor<Output = S>,
+{
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (tag, key) = match self {
+            Op::Set(key, _) => (1, key),
+            Op::Patch(key, _) => (3, key),
+            Op::Update(key, _) => (4, key),
+            Op::Delete(key, _) => (5, key),
+            Op::Register(key, _) => (6, key),
+            Op::Scheme(key, _) => (7, key),
+            Op::Attach(key, _) => (8, key),
+            Op::Epoch(key, _) => (9, key),
+            Op::Expire(key, _) => (10, key),
+            Op::Forget(key) => (11, key),
+        };
+        put(buf, tag);
+        key.put(buf);
+        match self {
+            Op::Set(_, s)
+            | Op::Patch(_, s)
+            | Op::Update(_, s) => s.put(buf),
+            Op::Forget(_) => (),
+            Op::Register(_, owner) => put_u64(buf, owner.into()),
+            Op::Scheme(_, x)
+            | Op::Epoch(_, x)
+            | Op::Expire(_, x)
+            | Op::Delete(_, x) => put(buf, *x),
+            Op::Attach(_, bytes) => put_bytes(buf, bytes),
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Result<Self> {
+        let (tag, key) = (r.u32()?, K::read(r)?);
+        let op = match tag {
+            1 => Op::Set(key, S::read(r)?),
+            3 => Op::Patch(key, S::read(r)?),
+            4 => Op::Update(key, S::read(r)?),
+            5 => Op::Delete(key, r.u32()?),
+            6 => Op::Register(key, PublicKey::from(r.u64()?)),
+            7 => Op::Scheme(key, r.u32()?),
+            8 => Op::Attach(key, r.bytes_prefixed()?.to_vec()),
+            9 => Op::Epoch(key, r.u32()?),
+            10 => Op::Expire(key, r.u32()?),
+            11 => Op::Forget(key),
+            _ => {
+                return Err(Error::App(format!(
+                    "unknown log record: {tag}"
+                )))
+            }
+        };
+        Ok(op)
+    }
+
+    fn apply(self, db: &mut DB<K, S>) {
+        match self {
+            Op::Set(key, secret) => db.set(key, secret),
+            Op::Patch(key, mask) => db.patch(key, mask),
+            Op::Update(key, value) => db.update(key, |_| value),
+            Op::Delete(key, at) => {
+                db.delete(key, at);
+            }
+            Op::Register(key, owner) => db.register(key, owner),
+            Op::Scheme(key, scheme) => {
+                db.set_scheme(key, scheme)
+            }
+            Op::Attach(key, bytes) => db.attach(key, |_| bytes),
+            Op::Epoch(key, epoch) => db.set_epoch(key, epoch),
+            Op::Expire(key, at) => db.set_expiry(key, at),
+            Op::Forget(key) => {
+                db.tombstones.remove(&key);
+            }
+        }
+    }
+}
+
+// Records in the log between checkpoints (the log is replayed on
+// startup, so it should not grow too long)
+const CHECKPOINT_RECORDS: usize = 1000;
+
+// DB kept in `<dir>`: each change is appended to the write-ahead log
+// `<dir>/wal` before it is applied in memory, and the log is synced
+// on `flush` (i.e. before the response to the request that made the
+// change is sent). Every `checkpoint` records the whole DB is written
+// to `<dir>/db` (to a temporary file first, then renamed) and the log
+// is truncated. On startup the last checkpoint is loaded and the log
+// is replayed over it.
+//
+// A log record is: length, crc32 and the body (sequence number, op).
+// The checkpoint has the sequence number of the last record in it,
+// so that records that made it into the checkpoint are not applied
+// twice if the log was not truncated after it (a crash in between),
+// and a torn record at the end of the log (a crash while appending)
+// is dropped.
+//
+// Opened with a passphrase (`open_sealed`, `encrypt` feature), the
+// checkpoint and the body of each log record are encrypted (`Seal`),
+// and `<dir>/seal` keeps the salt the key is derived with.
+pub struct FileDB<K, S> {
+    db: DB<K, S>,
+    dir: PathBuf,
+    wal: File,
+    seq: u64,          // of the last record
+    records: usize,    // since the last checkpoint
+    checkpoint: usize, // records between checkpoints
+    synced: bool,
+    failed: Option<io::Error>, // failed to append to the log
+    seal: Option<Seal>,
+}
+
+impl<K, S> FileDB<K, S>
+where
+    K: Word + Eq + Hash + Ord,
+    S: Word + BitXor<Output = S>,
+{
+    pub fn open(dir: &Path) -> Result<Self> {
+        if dir.join("seal").exists() {
+            return Err(Error::App(
+                "storage is encrypted".to_string(),
+            ));
+        }
+        Self::load(dir, None)
+    }
+
+    // `<dir>/seal` is the salt and an empty sealed message, which
+    // opens only with the right passphrase
+    #[cfg(feature = "encrypt")]
+    pub fn open_sealed(
+        dir: &Path,
+        passphrase: &[u8],
+    ) -> Result<Self> {
+        use crate::seal::{salt, SALT_LEN};
+
+        fs::create_dir_all(dir)?;
+        let path = dir.join("seal");
+        let seal = match fs::read(&path) {
+            Ok(bytes) => {
+                let mut r = Reader(&bytes);
+                let seal =
+                    Seal::new(passphrase, r.bytes(SALT_LEN)?)?;
+                seal.open(b"seal", r.0)?;
+                seal
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let wal = fs::metadata(dir.join("wal"));
+                if dir.join("db").exists()
+                    || wal.is_ok_and(|wal| wal.len() > 0)
+                {
+                    return Err(Error::App(
+                        "storage is not encrypted".to_string(),
+                    ));
+                }
+                let salt = salt();
+                let seal = Seal::new(passphrase, &salt)?;
+                let mut buf = salt.to_vec();
+                buf.extend(seal.seal(b"seal", &[]));
+                replace(&path, &buf)?;
+                seal
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Self::load(dir, Some(seal))
+    }
+
+    fn load(dir: &Path, seal: Option<Seal>) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let (mut db, mut seq) = match fs::read(dir.join("db")) {
+            Ok(bytes) => {
+                let mut r = Reader(&bytes);
+                if r.bytes(4)? != DB_MAGIC {
+                    return Err(Error::App(
+                        "not a storage file".to_string(),
+                    ));
+                }
+                let body = unseal(&seal, b"db", r.0)?;
+                let mut r = Reader(&body);
+                let seq = r.u64()?;
+                (DB::decode(&mut r)?, seq)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                (DB::new(), 0)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let path = dir.join("wal");
+        let log = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        let mut r = Reader(&log);
+        let mut records = 0;
+        while let Some(body) = next_record(&mut r) {
+            let body = unseal(&seal, b"wal", body)?;
+            let mut body = Reader(&body);
+            let record = body.u64()?;
+            let op = Op::decode(&mut body)?;
+            if record > seq {
+                op.apply(&mut db);
+                seq = record;
+                records += 1;
+            }
+        }
+        let valid = log.len() - r.0.len();
+        if valid < log.len() {
+            tracing::warn!(
+                bytes = log.len() - valid,
+                "torn log record"
+            );
+        }
+
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        wal.set_len(valid as u64)?;
+        Ok(Self {
+            db,
+            dir: dir.to_path_buf(),
+            wal,
+            seq,
+            records,
+            checkpoint: CHECKPOINT_RECORDS,
+            synced: true,
+            failed: None,
+            seal,
+        })
+    }
+
+    fn log(&mut self, op: Op<K, S>) -> Op<K, S> {
+        self.seq += 1;
+        let mut body = vec![];
+        put_u64(&mut body, self.seq);
+        op.encode(&mut body);
+        let body = seal(&self.seal, b"wal", body);
+        let mut record = vec![];
+        put(&mut record, body.len() as u32);
+        put(&mut record, crc32(&body));
+        record.extend(body);
+        if let Err(e) = self.wal.write_all(&record) {
+            self.failed.get_or_insert(e);
+        }
+        self.records += 1;
+        self.synced = false;
+        op
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let mut body = vec![];
+        put_u64(&mut body, self.seq);
+        self.db.encode(&mut body);
+        let mut buf = DB_MAGIC.to_vec();
+        buf.extend(seal(&self.seal, b"db", body));
+        replace(&self.dir.join("db"), &buf)?;
+        // a crash here leaves the log as it is: the records are
+        // already in the checkpoint and are skipped on replay
+        self.wal.set_len(0)?;
+        self.wal.sync_all()?;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+// Write to a temporary file first, so that a crash leaves either
+// the old file or the new one
+fn replace(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn seal(
+    seal: &Option<Seal>,
+    aad: &[u8],
+    bytes: Vec<u8>,
+) -> Vec<u8> {
+    match seal {
+        Some(seal) => seal.seal(aad, &bytes),
+        None => bytes,
+    }
+}
+
+fn unseal(
+    seal: &Option<Seal>,
+    aad: &[u8],
+    bytes: &[u8],
+) -> Result<Vec<u8>> {
+    match seal {
+        Some(seal) => seal.open(aad, bytes),
+        None => Ok(bytes.to_vec()),
+    }
+}
+
+// Body of the next complete and intact log record, if any
+fn next_record<'a>(r: &mut Reader<'a>) -> Option<&'a [u8]> {
+    let mut next = Reader(r.0);
+    let len = next.u32().ok()? as usize;
+    let sum = next.u32().ok()?;
+    let body = next.bytes(len).ok()?;
+    if crc32(body) != sum {
+        return None;
+    }
+    r.0 = next.0;
+    Some(body)
+}
+
+impl<K, S> Storage<K, S, S> for FileDB<K, S>
+where
+    K: Word + Eq + Hash + Ord,
+    S: Word + BitXor<Output = S>,
+{
+    fn set(&mut self, key: K, secret: S) {
+        self.log(Op::Set(key, secret)).apply(&mut self.db);
+    }
+
+    fn get(&mut self, key: K) -> Option<S> {
+        self.db.get(key)
+    }
+
+    fn get_version(
+        &mut self,
+        key: K,
+        version: usize,
+    ) -> Option<S> {
+        self.db.get_version(key, version)
+    }
+
+    fn patch(&mut self, key: K, mask: S) {
+        self.log(Op::Patch(key, mask)).apply(&mut self.db);
+    }
+
+    fn update(&mut self, key: K, f: impl FnOnce(S) -> S) {
+        let last = self.db.data.get(&key).and_then(|v| v.last());
+        if let Some(last) = last.cloned() {
+            self.log(Op::Update(key, f(last)))
+                .apply(&mut self.db);
+        }
+    }
+
+    fn delete(&mut self, key: K, at: u32) -> bool {
+        self.log(Op::Delete(key, at));
+        self.db.delete(key, at)
+    }
+
+    fn deleted(&mut self, key: K) -> Option<u32> {
+        self.db.deleted(key)
+    }
+
+    fn forget(&mut self, before: u32) -> usize {
+        let forgotten = self
+            .db
+            .tombstones
+            .iter()
+            .filter(|(_, at)| **at < before)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in &forgotten {
+            self.log(Op::Forget(*key)).apply(&mut self.db);
+        }
+        forgotten.len()
+    }
+
+    fn keys(&mut self) -> Vec<K> {
+        self.db.keys()
+    }
+
+    fn owner(&mut self, key: K) -> Option<PublicKey> {
+        self.db.owner(key)
+    }
+
+    fn register(&mut self, key: K, owner: PublicKey) {
+        self.log(Op::Register(key, owner)).apply(&mut self.db);
+    }
+
+    fn set_scheme(&mut self, key: K, scheme: u32) {
+        self.log(Op::Scheme(key, scheme)).apply(&mut self.db);
+    }
+
+    fn scheme(&mut self, key: K) -> u32 {
+        self.db.scheme(key)
+    }
+
+    fn attach(
+        &mut self,
+        key: K,
+        f: impl FnOnce(&[u8]) -> Vec<u8>,
+    ) {
+        let versions = self.db.attachments.get(&key);
+        let last = versions.and_then(|v| v.last());
+        let next = f(last.map_or(&[], |a| a.as_slice()));
+        self.log(Op::Attach(key, next)).apply(&mut self.db);
+    }
+
+    fn attachment(&mut self, key: K, version: usize) -> Vec<u8> {
+        self.db.attachment(key, version)
+    }
+
+    fn version(&mut self, key: K) -> usize {
+        self.db.version(key)
+    }
+
+    fn epochs(&mut self, key: K) -> Vec<u32> {
+        self.db.epochs(key)
+    }
+
+    fn set_epoch(&mut self, key: K, epoch: u32) {
+        self.log(Op::Epoch(key, epoch)).apply(&mut self.db);
+    }
+
+    fn set_expiry(&mut self, key: K, at: u32) {
+        self.log(Op::Expire(key, at)).apply(&mut self.db);
+    }
+
+    fn expiry(&mut self, key: K) -> Option<u32> {
+        self.db.expiry(key)
+    }
+
+    fn snapshot(&mut self) -> Vec<u8> {
+        self.db.snapshot()
+    }
+
+    // not a log record: a checkpoint right away, the log of what was
+    // there before is of no use
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        self.db = DB::from_snapshot(snapshot)?;
+        self.save()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(e) = self.failed.take() {
+            return Err(e.into());
+        }
+        if !self.synced {
+            self.wal.sync_data()?;
+            self.synced = true;
+        }
+        if self.records >= self.checkpoint {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+// The storage split into shards, each behind a lock of its own, so
+// that requests for the keys of different shards do not wait for
+// each other. A key always goes to the same shard (by its hash),
+// whatever is done to it is done under the shard's lock; the
+// keys, a snapshot and a restore span all of them.
+pub struct Shards<T> {
+    shards: Vec<Mutex<T>>,
+}
+
+impl<T> Shards<T> {
+    pub fn new(shards: Vec<T>) -> Self {
+        assert!(!shards.is_empty(), "no shards");
+        Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    fn shard<K: Hash>(&self, key: K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    // the shard of the key, locked
+    pub fn lock<K: Hash>(&self, key: K) -> MutexGuard<'_, T> {
+        self.shards[self.shard(key)].lock().unwrap()
+    }
+
+    // every shard, locked one at a time
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = MutexGuard<'_, T>> {
+        self.shards.iter().map(|shard| shard.lock().unwrap())
+    }
+
+    // of all the shards, sorted
+    pub fn keys<K, S>(&self) -> Vec<K>
+    where
+        K: Word + Eq + Hash + Ord,
+        S: Word,
+        T: Storage<K, S, S>,
+    {
+        let mut keys = self
+            .iter()
+            .flat_map(|mut shard| shard.keys())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    // of all the shards at once (all of them locked), the same as
+    // a snapshot of a single storage with all the keys
+    pub fn snapshot<K, S>(&self) -> Result<Vec<u8>>
+    where
+        K: Word + Eq + Hash + Ord,
+        S: Word,
+        T: Storage<K, S, S>,
+    {
+        let mut shards = self.iter().collect::<Vec<_>>();
+        if let [shard] = shards.as_mut_slice() {
+            return Ok(shard.snapshot());
+        }
+        let mut db = DB::<K, S>::new();
+        for shard in &mut shards {
+            db.merge(DB::from_snapshot(&shard.snapshot())?);
+        }
+        Ok(db.to_snapshot())
+    }
+
+    // replace everything stored with a `snapshot` (of any number of
+    // shards), each key going to its shard
+    pub fn restore<K, S>(&self, snapshot: &[u8]) -> Result<()>
+    where
+        K: Word + Eq + Hash + Ord,
+        S: Word,
+        T: Storage<K, S, S>,
+    {
+        let mut shards = self.iter().collect::<Vec<_>>();
+        if let [shard] = shards.as_mut_slice() {
+            return shard.restore(snapshot);
+        }
+        let db = DB::<K, S>::from_snapshot(snapshot)?;
+        let parts =
+            db.split(shards.len(), |key| self.shard(*key));
+        for (shard, part) in shards.iter_mut().zip(parts) {
+            shard.restore(&part.to_snapshot())?;
+        }
+        Ok(())
+    }
+
+    pub fn flush<K, S, M>(&self) -> Result<()>
+    where
+        T: Storage<K, S, M>,
+    {
+        self.iter().try_for_each(|mut shard| shard.flush())
+    }
+}
+
+impl<T> From<T> for Shards<T> {
+    fn from(storage: T) -> Self {
+        Self::new(vec![storage])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ec::SecretKey, util::random, xor::BYTES};
+
+    use super::*;
+
+    #[test]
+    fn test_db() {
+        let mut db = DB::<u64, u64>::new();
+        db.set(1 << 40, 1 << 50);
+        db.patch(1 << 40, 1);
+        db.update(1 << 40, |last| last + 1);
+        db.set_epoch(1 << 40, 7);
+        assert_eq!(db.epochs(1 << 40), vec![0, 1, 7]);
+        db.set_expiry(1 << 40, 1000);
+        db.set_expiry(2, 1000); // nothing to expire
+        assert_eq!(db.expiry(1 << 40), Some(1000));
+        assert_eq!(db.expiry(2), None);
+        assert_eq!(db.version(1 << 40), 2);
+        assert_eq!(
+            db.versions(1 << 40),
+            [1 << 50, (1 << 50) | 1, (1 << 50) + 2]
+        );
+        assert_eq!(db.get(1 << 40), Some((1 << 50) + 2));
+        assert_eq!(db.get(1 << 40), Some((1 << 50) + 2));
+        assert_eq!(db.get(2), None);
+        assert_eq!(db.get_version(1 << 40, 0), Some(1 << 50));
+        assert_eq!(
+            db.get_version(1 << 40, 2),
+            Some((1 << 50) + 2)
+        );
+        assert_eq!(db.get_version(1 << 40, 3), None);
+        assert_eq!(db.get_version(2, 0), None);
+        db.patch(2, 1); // nothing to patch
+        assert_eq!(db.keys(), vec![1 << 40]);
+        assert!(db.delete(1 << 40, 100));
+        assert!(!db.delete(1 << 40, 100));
+        assert!(db.keys().is_empty());
+        assert_eq!(db.deleted(1 << 40), Some(100));
+        assert_eq!(db.forget(100), 0);
+        assert_eq!(db.forget(101), 1);
+        assert_eq!(db.deleted(1 << 40), None);
+
+        // set again: no longer deleted
+        db.delete(2, 100);
+        db.set(2, 1);
+        assert_eq!(db.deleted(2), None);
+
+        db.delete(3, 200);
+        let snapshot = db.snapshot();
+        let mut other = DB::<u64, u64>::new();
+        other.set(4, 4);
+        other.restore(&snapshot).unwrap();
+        assert_eq!(other.keys(), vec![2]);
+        assert_eq!(other.deleted(3), Some(200));
+        assert_eq!(other.get(2), Some(1));
+        assert!(other.restore(&snapshot[..8]).is_err());
+        let mut corrupted = snapshot.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(other.restore(&corrupted).is_err());
+        assert!(other.restore(b"DSB5").is_err());
+    }
+
+    #[test]
+    fn test_shards() -> Result<()> {
+        let shards = Shards::new(
+            (0..4).map(|_| DB::<u64, u64>::new()).collect(),
+        );
+        assert_eq!(shards.len(), 4);
+        for key in 1..=100 {
+            shards.lock(key).set(key, key * 10);
+        }
+        shards.lock(7u64).delete(7, 100);
+        // each key is in its shard, and the shards are all used
+        assert!(shards
+            .iter()
+            .all(|mut db| !db.keys().is_empty()));
+        assert_eq!(shards.lock(42u64).get(42), Some(420));
+        let keys = shards.keys();
+        assert_eq!(keys.len(), 99);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // the snapshot is the one of all the keys in one place
+        let snapshot = shards.snapshot()?;
+        let mut db = DB::<u64, u64>::new();
+        db.restore(&snapshot)?;
+        assert_eq!(db.keys(), keys);
+        assert_eq!(db.deleted(7), Some(100));
+        assert_eq!(snapshot, db.snapshot());
+
+        // and restores into any number of shards
+        let other = Shards::from(DB::<u64, u64>::new());
+        other.lock(1000u64).set(1000, 1);
+        other.restore(&snapshot)?;
+        assert_eq!(other.keys(), keys);
+        let other = Shards::new(
+            (0..3).map(|_| DB::<u64, u64>::new()).collect(),
+        );
+        other.restore(&snapshot)?;
+        assert_eq!(other.keys(), keys);
+        assert_eq!(other.lock(7u64).deleted(7), Some(100));
+        assert_eq!(other.lock(42u64).get(42), Some(420));
+        assert_eq!(other.snapshot()?, snapshot);
+        assert!(other.restore(&snapshot[..8]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_db() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}",
+            random()
+        ));
+        let owner = SecretKey::new(1).public_key();
+        {
+            let mut db = FileDB::<u32, u32>::open(&dir)?;
+            db.set(1, 42);
+            db.register(1, owner.clone());
+            db.set_scheme(1, BYTES);
+            db.attach(1, |_| b"share".to_vec());
+            db.patch(1, 0);
+            db.attach(1, |_| b"refreshed".to_vec());
+            db.set_epoch(1, 5);
+            db.set_expiry(1, 1000);
+            db.set(2, 0xCAFEBABE);
+            assert_eq!(db.get(2), Some(0xCAFEBABE));
+            db.set(3, 0);
+            assert!(db.delete(3, 100));
+            db.flush()?;
+        }
+
+        let mut db = FileDB::<u32, u32>::open(&dir)?;
+        assert_eq!(db.keys(), vec![1, 2]);
+        assert_eq!(db.owner(1), Some(owner));
+        assert_eq!(db.owner(2), None);
+        assert_eq!(db.scheme(1), BYTES);
+        assert_eq!(db.epochs(1), vec![0, 5]);
+        assert_eq!(db.expiry(1), Some(1000));
+        assert_eq!(db.expiry(2), None);
+        assert_eq!(db.attachment(1, 1), b"refreshed");
+        assert_eq!(db.db.data[&1], vec![42, 42]);
+        assert_eq!(db.version(2), 0);
+        assert_eq!(db.get(2), Some(0xCAFEBABE));
+        assert_eq!(db.deleted(3), Some(100));
+        assert_eq!(db.forget(101), 1);
+        db.flush()?;
+        assert_eq!(
+            FileDB::<u32, u32>::open(&dir)?.deleted(3),
+            None
+        );
+
+        // a restored snapshot replaces everything, and is there
+        // after a restart
+        let snapshot = db.snapshot();
+        db.set(4, 4);
+        db.delete(1, 200);
+        db.restore(&snapshot)?;
+        assert_eq!(fs::metadata(dir.join("wal"))?.len(), 0);
+        let mut db = FileDB::<u32, u32>::open(&dir)?;
+        assert_eq!(db.keys(), vec![1, 2]);
+        assert_eq!(db.snapshot(), snapshot);
+
+        fs::write(dir.join("db"), b"DSB5\0\0")?;
+        assert!(FileDB::<u32, u32>::open(&dir).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}",
+            random()
+        ));
+        let dump = |db: &FileDB<u32, u32>| {
+            let mut buf = vec![];
+            db.db.encode(&mut buf);
+            buf
+        };
+
+        let mut db = FileDB::<u32, u32>::open(&dir)?;
+        db.checkpoint = 5;
+        db.set(1, 42);
+        db.patch(1, 0xFF);
+        db.update(1, |last| last + 1);
+        db.delete(3, 100);
+        db.flush()?;
+        assert!(!dir.join("db").exists());
+        let log = fs::read(dir.join("wal"))?;
+        assert_eq!(
+            dump(&FileDB::<u32, u32>::open(&dir)?),
+            dump(&db)
+        );
+
+        // records already in the checkpoint are not applied again
+        db.patch(1, 0xFF00);
+        db.flush()?;
+        assert_eq!(fs::metadata(dir.join("wal"))?.len(), 0);
+        assert_eq!(
+            dump(&FileDB::<u32, u32>::open(&dir)?),
+            dump(&db)
+        );
+        fs::write(dir.join("wal"), &log)?;
+        assert_eq!(
+            dump(&FileDB::<u32, u32>::open(&dir)?),
+            dump(&db)
+        );
+        fs::write(dir.join("wal"), [])?;
+
+        // a torn record at the end of the log is dropped
+        db.set(2, 7);
+        db.flush()?;
+        let expected = dump(&db);
+        db.patch(2, 1);
+        db.flush()?;
+        let mut log = fs::read(dir.join("wal"))?;
+        log.truncate(log.len() - 1);
+        fs::write(dir.join("wal"), &log)?;
+        let mut db = FileDB::<u32, u32>::open(&dir)?;
+        assert_eq!(dump(&db), expected);
+        assert_eq!(db.db.data[&1], vec![42, 0xD5, 0xD6, 0xFFD6]);
+
+        // and the log is appended to after the last intact one
+        db.patch(2, 2);
+        db.flush()?;
+        assert_eq!(
+            FileDB::<u32, u32>::open(&dir)?.db.data[&2],
+            vec![7, 5]
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn test_sealed() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}",
+            random()
+        ));
+        let plain = |name: &str| -> Result<bool> {
+            let bytes = fs::read(dir.join(name))?;
+            Ok(bytes
+                .windows(4)
+                .any(|w| w == 0xCAFEBABEu32.to_be_bytes()))
+        };
+        {
+            let mut db = FileDB::<u32, u32>::open_sealed(
+                &dir, b"secret",
+            )?;
+            db.checkpoint = 2;
+            db.set(1, 0xCAFEBABE);
+            db.flush()?;
+            assert!(!plain("wal")?);
+            db.set(2, 0xCAFEBABE);
+            db.flush()?; // checkpoint
+            assert!(!plain("db")?);
+            db.set(3, 0xCAFEBABE);
+            db.flush()?;
+            assert!(!plain("wal")?);
+        }
+
+        let mut db =
+            FileDB::<u32, u32>::open_sealed(&dir, b"secret")?;
+        assert_eq!(db.keys(), vec![1, 2, 3]);
+        assert_eq!(db.db.data[&3], vec![0xCAFEBABE]);
+        assert!(FileDB::<u32, u32>::open_sealed(&dir, b"wrong")
+            .is_err());
+        assert!(FileDB::<u32, u32>::open(&dir).is_err());
+
+        // a record (the only one since the checkpoint) that is
+        // intact but does not open is not dropped as a torn one
+        let mut log = fs::read(dir.join("wal"))?;
+        let n = log.len();
+        log[n - 1] ^= 1;
+        let sum = crc32(&log[8..]);
+        log[4..8].copy_from_slice(&sum.to_be_bytes());
+        fs::write(dir.join("wal"), &log)?;
+        assert!(FileDB::<u32, u32>::open_sealed(
+            &dir, b"secret"
+        )
+        .is_err());
+        assert_eq!(fs::read(dir.join("wal"))?, log);
+
+        // and plain storage is not opened as an encrypted one
+        let plain = dir.join("plain");
+        FileDB::<u32, u32>::open(&plain)?.set(1, 2);
+        assert!(FileDB::<u32, u32>::open_sealed(
+            &plain, b"secret"
+        )
+        .is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
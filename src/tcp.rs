@@ -1,33 +1,284 @@
 use std::{
-    io::{Read, Write},
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
     net::TcpStream,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::api::{Error, Frame, Receiver, Result, Sender};
+use crate::{
+    api::{
+        closed, timeout, Error, Frame, Receiver, Result, Sender,
+        MAX_FRAME_LEN, TAG_REKEY,
+    },
+    chacha::{self, TAG_LEN},
+    codec::{Codec, Raw},
+    dhke::Ephemeral,
+};
+
+// Receive with the socket read timeout set for the duration of the
+// call. A message cut by the timeout leaves the stream out of sync,
+// so the connection is to be dropped after a timeout.
+pub(crate) fn recv_within<T>(
+    socket: &TcpStream,
+    within: Duration,
+    recv: impl FnOnce() -> Result<Option<T>>,
+) -> Result<T> {
+    // zero means "no timeout" for the socket
+    let within = within.max(Duration::from_millis(1));
+    let idle = socket.read_timeout()?;
+    socket.set_read_timeout(Some(within))?;
+    let received = recv();
+    socket.set_read_timeout(idle)?;
+    match received {
+        Ok(Some(received)) => Ok(received),
+        Ok(None) => Err(closed()),
+        Err(Error::IO(e))
+            if e.kind() == ErrorKind::WouldBlock
+                || e.kind() == ErrorKind::TimedOut =>
+        {
+            Err(timeout())
+        }
+        Err(e) => Err(e),
+    }
+}
 
-const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+// Heartbeat on the wire: a frame of zero length (an empty line for
+// line-delimited codecs), skipped by the receiving side.
+const HEARTBEAT: u32 = 0;
+
+// Waiting for the peer's TAG_REKEY, see `Rekey`
+const REKEY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// New keys on the same connection after so many frames sent or so
+// much time with the current ones, whichever comes first: before
+// the next frame, the side sends TAG_REKEY with a fresh X25519
+// public value (`dhke::Ephemeral`) and waits for the peer's, the
+// peer answers TAG_REKEY with its own as soon as it reads it. Each
+// side switches to the new keys right after its TAG_REKEY, frames
+// received in the meantime are kept for `recv`. Either side can
+// start it, if both do at once each takes the other's as the answer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rekey {
+    pub frames: u64,
+    pub every: Duration,
+}
+
+// The current keys (ChaCha20-Poly1305, see `dhke::Keys`) and the
+// frames sealed and opened with them so far, the nonce of the next
+// one each way
+#[derive(Default)]
+struct Keys {
+    send: [u8; 32],
+    recv: [u8; 32],
+    sent: u64,
+    received: u64,
+    since: Option<Instant>, // none until the handshake: plain frames
+}
 
 pub struct Tcp {
     socket: Arc<TcpStream>,
-    timeout: Duration,
-    key: Option<u32>,
+    keys: Mutex<Keys>,
+    rekey: Option<Rekey>,
+    queue: Mutex<VecDeque<Frame>>, // received while rekeying
+    codec: Box<dyn Codec>,
+    lock: Arc<Mutex<()>>, // writes are shared with heartbeats
+    dead: Arc<AtomicBool>, // a write failed, the peer is gone
 }
 
 impl Tcp {
-    pub fn set_key(&mut self, key: u32) {
-        self.key = Some(key);
+    pub fn with_codec(
+        socket: TcpStream,
+        codec: Box<dyn Codec>,
+    ) -> Self {
+        Tcp {
+            socket: Arc::new(socket),
+            keys: Mutex::default(),
+            rekey: None,
+            queue: Mutex::default(),
+            codec,
+            lock: Arc::new(Mutex::new(())),
+            dead: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // a key per direction (`dhke::Keys::seal` and `open`)
+    pub fn set_keys(&mut self, send: [u8; 32], recv: [u8; 32]) {
+        self.switch(send, recv);
+    }
+
+    fn switch(&self, send: [u8; 32], recv: [u8; 32]) {
+        *self.keys.lock().unwrap() = Keys {
+            send,
+            recv,
+            sent: 0,
+            received: 0,
+            since: Some(Instant::now()),
+        };
+    }
+
+    // Rekey automatically (see `Rekey`), none by default
+    pub fn set_rekey(&mut self, rekey: Option<Rekey>) {
+        self.rekey = rekey;
+    }
+
+    pub fn needs_rekey(&self) -> bool {
+        let Some(rekey) = self.rekey else {
+            return false;
+        };
+        let keys = self.keys.lock().unwrap();
+        keys.since.is_some_and(|since| {
+            keys.sent >= rekey.frames
+                || since.elapsed() >= rekey.every
+        })
+    }
+
+    // Start a rekey: no other writes (heartbeats included) until
+    // the peer's TAG_REKEY is in and the keys are switched
+    pub fn rekey(&self) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let ours = Ephemeral::generate();
+        self.write_unlocked(&self.rekey_frame(&ours)?)?;
+        let theirs = loop {
+            let frame = recv_within(
+                &self.socket,
+                REKEY_TIMEOUT,
+                || self.read_frame(),
+            )?;
+            if frame.tag == TAG_REKEY {
+                break frame;
+            }
+            self.queue.lock().unwrap().push_back(frame);
+        };
+        let keys = ours.shared(&theirs.data)?.keys();
+        self.switch(keys.seal, keys.open);
+        Ok(())
+    }
+
+    // The peer started a rekey: answer, switch to the new keys
+    fn rekeyed(&self, theirs: &Frame) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        let ours = Ephemeral::generate();
+        let keys = ours.shared(&theirs.data)?.keys();
+        self.write_unlocked(&self.rekey_frame(&ours)?)?;
+        self.switch(keys.seal, keys.open);
+        Ok(())
+    }
+
+    fn rekey_frame(&self, ours: &Ephemeral) -> Result<Vec<u8>> {
+        let mut frame = Frame {
+            tag: TAG_REKEY,
+            data: ours.public.to_vec(),
+            ..Frame::default()
+        };
+        frame.sum = frame.checksum();
+        self.encode(&frame)
+    }
+
+    // Fail `recv` with a timeout if nothing (heartbeats included)
+    // arrives within `idle`, so that a silent peer does not keep
+    // the connection (and the thread reading it) forever.
+    pub fn set_idle_timeout(
+        &self,
+        idle: Option<Duration>,
+    ) -> Result<()> {
+        let idle = idle.map(|d| d.max(Duration::from_millis(1)));
+        self.socket.set_read_timeout(idle)?;
+        Ok(())
+    }
+
+    // Send a heartbeat every `interval` (never sealed: there is
+    // nothing in it to keep or to forge) until the connection is dropped or the
+    // peer is gone. Then sending fails right away instead of
+    // waiting for a response that never comes.
+    pub fn keep_alive(&self, interval: Duration) {
+        let socket = Arc::downgrade(&self.socket);
+        let lock = self.lock.clone();
+        let dead = self.dead.clone();
+        let heartbeat = if self.codec.is_line_delimited() {
+            b"\n".to_vec()
+        } else {
+            HEARTBEAT.to_be_bytes().to_vec()
+        };
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let Some(socket) = socket.upgrade() else {
+                return;
+            };
+            let _lock = lock.lock().unwrap();
+            if socket.as_ref().write_all(&heartbeat).is_err() {
+                dead.store(true, Ordering::SeqCst);
+                return;
+            }
+        });
+    }
+
+    pub fn is_alive(&self) -> bool {
+        !self.dead.load(Ordering::SeqCst)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        self.write_unlocked(buf)
+    }
+
+    // with `lock` held
+    fn write_unlocked(&self, buf: &[u8]) -> Result<()> {
+        if !self.is_alive() {
+            return Err(closed());
+        }
+        let mut socket = self.socket.as_ref();
+        let written =
+            socket.write_all(buf).and_then(|_| socket.flush());
+        if let Err(e) = written {
+            self.dead.store(true, Ordering::SeqCst);
+            return Err(Error::IO(e));
+        }
+        Ok(())
+    }
+
+    fn read_line(&self) -> Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            match self.socket.as_ref().read_exact(&mut byte) {
+                Ok(_) if byte[0] == b'\n' => {
+                    return Ok(Some(line))
+                }
+                Ok(_) => line.push(byte[0]),
+                Err(e)
+                    if e.kind()
+                        == std::io::ErrorKind::UnexpectedEof
+                        && line.is_empty() =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(Error::IO(e)),
+            }
+            if line.len() > 8 * MAX_FRAME_LEN {
+                return Err(Error::App(
+                    "line too long".to_string(),
+                ));
+            }
+        }
     }
 }
 
+// 32 bits fixed (zero), then the frame counter (RFC 8439, 2.8)
+pub(crate) fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+// Plain words, for the handshake
 impl Sender<u32> for Tcp {
     fn send(&self, msg: &u32) -> Result<()> {
-        let mask = self.key.unwrap_or_default();
-        let send = mask ^ *msg;
-        self.socket.as_ref().write_all(&send.to_be_bytes())?;
-        self.socket.as_ref().flush()?;
-        Ok(())
+        self.write(&msg.to_be_bytes())
     }
 }
 
@@ -35,47 +286,418 @@ impl Receiver<u32> for Tcp {
     fn recv(&self) -> Result<Option<u32>> {
         let mut buf = [0u8; 4];
         match self.socket.as_ref().read_exact(&mut buf) {
-            Ok(_) => {
-                let read: u32 = u32::from_be_bytes(buf);
-                let mask = self.key.unwrap_or_default();
-                Ok(Some(read ^ mask))
-            }
+            Ok(_) => Ok(Some(u32::from_be_bytes(buf))),
             Err(e)
                 if e.kind()
                     == std::io::ErrorKind::UnexpectedEof =>
             {
                 Ok(None)
             }
+            // idle timeout, see `set_idle_timeout`
+            Err(e)
+                if e.kind() == ErrorKind::WouldBlock
+                    || e.kind() == ErrorKind::TimedOut =>
+            {
+                Err(timeout())
+            }
             Err(e) => Err(Error::IO(e)),
         }
     }
+
+    fn recv_timeout(&self, within: Duration) -> Result<u32> {
+        recv_within(&self.socket, within, || self.recv())
+    }
 }
 
 impl From<TcpStream> for Tcp {
     fn from(socket: TcpStream) -> Self {
-        Tcp {
-            socket: Arc::new(socket),
-            timeout: DEFAULT_TIMEOUT,
-            key: None,
+        Tcp::with_codec(socket, Box::new(Raw))
+    }
+}
+
+// Frame on the wire: byte length of the sealed frame, then the
+// encoded frame (see `Codec`) sealed with ChaCha20-Poly1305 (the
+// length is the associated data), the frame counter is the nonce.
+// Before the handshake the frame goes as it is. Line-delimited
+// codecs send plain-text lines instead.
+impl Tcp {
+    // takes the next nonce: with `lock` held, in the order written
+    fn encode(&self, msg: &Frame) -> Result<Vec<u8>> {
+        let frame = self.codec.encode(msg)?;
+        if self.codec.is_line_delimited() {
+            let mut line = frame;
+            line.push(b'\n');
+            return Ok(line);
         }
+
+        let mut keys = self.keys.lock().unwrap();
+        let frame = if keys.since.is_some() {
+            let len = (frame.len() + TAG_LEN) as u32;
+            let nonce = nonce(keys.sent);
+            keys.sent += 1;
+            chacha::seal(
+                &keys.send,
+                &nonce,
+                &len.to_be_bytes(),
+                &frame,
+            )
+        } else {
+            frame
+        };
+        let len = frame.len() as u32;
+        let mut buf = Vec::with_capacity(4 + frame.len());
+        buf.extend(len.to_be_bytes());
+        buf.extend(frame);
+        Ok(buf)
+    }
+
+    fn read_frame(&self) -> Result<Option<Frame>> {
+        if self.codec.is_line_delimited() {
+            return loop {
+                match self.read_line()? {
+                    Some(line) if line.is_empty() => continue,
+                    Some(line) => {
+                        break self.codec.decode(&line).map(Some)
+                    }
+                    None => break Ok(None),
+                }
+            };
+        }
+        let len = loop {
+            match self.recv()? {
+                Some(HEARTBEAT) => continue,
+                Some(len) => break len as usize,
+                None => return Ok(None),
+            }
+        };
+        if len > MAX_FRAME_LEN + TAG_LEN {
+            return Err(Error::App(format!(
+                "invalid frame length: {len} bytes"
+            )));
+        }
+
+        // `read_exact` keeps reading until the whole frame arrives
+        let mut buf = vec![0u8; len];
+        self.socket.as_ref().read_exact(&mut buf)?;
+        let mut keys = self.keys.lock().unwrap();
+        if keys.since.is_some() {
+            let nonce = nonce(keys.received);
+            let aad = (len as u32).to_be_bytes();
+            // tampered with, replayed, reordered or cut: the stream
+            // is of no use any more
+            buf = chacha::open(&keys.recv, &nonce, &aad, &buf)
+                .ok_or_else(|| {
+                Error::App(
+                    "invalid frame: authentication failed"
+                        .to_string(),
+                )
+            })?;
+            keys.received += 1;
+        }
+        drop(keys);
+        self.codec.decode(&buf).map(Some)
     }
 }
 
 impl Sender<Frame> for Tcp {
     fn send(&self, msg: &Frame) -> Result<()> {
-        for w in msg.words() {
-            self.send(&w)?;
+        if self.needs_rekey() {
+            self.rekey()?;
         }
-        Ok(())
+        let _lock = self.lock.lock().unwrap();
+        self.write_unlocked(&self.encode(msg)?)
     }
 }
 
+// TAG_REKEY of the peer is answered here, never returned
 impl Receiver<Frame> for Tcp {
     fn recv(&self) -> Result<Option<Frame>> {
-        let mut words = [0u32; 8];
-        for w in words.iter_mut() {
-            *w = self.recv_timeout(self.timeout)?;
+        loop {
+            if let Some(frame) =
+                self.queue.lock().unwrap().pop_front()
+            {
+                return Ok(Some(frame));
+            }
+            match self.read_frame()? {
+                Some(frame) if frame.tag == TAG_REKEY => {
+                    self.rekeyed(&frame)?
+                }
+                frame => return Ok(frame),
+            }
+        }
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        recv_within(&self.socket, within, || self.recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    const A: [u8; 32] = [0xA; 32];
+    const B: [u8; 32] = [0xB; 32];
+
+    #[test]
+    fn test_partial_reads() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let frame = Frame {
+            idx: 1,
+            tag: 2,
+            msg: 3,
+            key: 4,
+            sig: 5,
+            ext: 6,
+            ns: 0,
+            sum: 7,
+            data: b"payload".to_vec(),
+        };
+        let words = frame.encode();
+        let buf = std::iter::once(words.len() as u32 * 4)
+            .chain(words)
+            .flat_map(|w| w.to_be_bytes())
+            .collect::<Vec<_>>();
+
+        let h = thread::spawn(move || -> Result<()> {
+            let mut socket = TcpStream::connect(addr)?;
+            for chunk in buf.chunks(5) {
+                socket.write_all(chunk)?;
+                socket.flush()?;
+                thread::sleep(Duration::from_millis(5));
+            }
+            Ok(())
+        });
+
+        let rx = Tcp::from(listener.accept()?.0);
+        let rcvd: Option<Frame> = rx.recv()?;
+        h.join()??;
+
+        assert_eq!(rcvd, Some(frame));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_timeout() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let _socket = TcpStream::connect(addr)?; // never writes
+
+        let rx = Tcp::from(listener.accept()?.0);
+        let now = Instant::now();
+        let rcvd: Result<Frame> =
+            rx.recv_timeout(Duration::from_millis(50));
+        let elapsed = now.elapsed();
+
+        assert!(matches!(
+            rcvd,
+            Err(Error::IO(e)) if e.kind() == ErrorKind::TimedOut
+        ));
+        assert!(elapsed >= Duration::from_millis(50));
+        assert!(elapsed < Duration::from_millis(500));
+        Ok(())
+    }
+
+    #[test]
+    fn test_heartbeat() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let mut tx = Tcp::from(TcpStream::connect(addr)?);
+        tx.set_keys(A, B);
+        tx.keep_alive(Duration::from_millis(10));
+
+        let mut rx = Tcp::from(listener.accept()?.0);
+        rx.set_keys(B, A);
+        rx.set_idle_timeout(Some(Duration::from_millis(50)))?;
+
+        // heartbeats keep the idle connection open
+        thread::sleep(Duration::from_millis(150));
+        let frame = Frame {
+            idx: 1,
+            data: b"payload".to_vec(),
+            ..Frame::default()
+        };
+        tx.send(&frame)?;
+        let rcvd: Option<Frame> = rx.recv()?;
+        assert_eq!(rcvd, Some(frame));
+
+        // `recv_timeout` keeps the idle timeout in place
+        let rcvd: Result<Frame> =
+            rx.recv_timeout(Duration::from_millis(5));
+        assert!(rcvd.is_err());
+        assert!(rx.socket.read_timeout()?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_timeout() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let _socket = TcpStream::connect(addr)?; // never writes
+
+        let rx = Tcp::from(listener.accept()?.0);
+        rx.set_idle_timeout(Some(Duration::from_millis(50)))?;
+        let rcvd: Result<Option<Frame>> = rx.recv();
+        assert!(matches!(
+            rcvd,
+            Err(Error::IO(e)) if e.kind() == ErrorKind::TimedOut
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rekey() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let mut tx = Tcp::from(TcpStream::connect(addr)?);
+        tx.set_keys(A, B);
+        tx.set_rekey(Some(Rekey {
+            frames: 2,
+            every: Duration::from_secs(60),
+        }));
+        let mut rx = Tcp::from(listener.accept()?.0);
+        rx.set_keys(B, A);
+        // the other side rekeys on its own too, by time
+        rx.set_rekey(Some(Rekey {
+            frames: u64::MAX,
+            every: Duration::from_millis(20),
+        }));
+
+        let h = thread::spawn(move || -> Result<[u8; 32]> {
+            while let Some(frame) = Receiver::<Frame>::recv(&rx)?
+            {
+                rx.send(&frame)?;
+            }
+            let send = rx.keys.lock().unwrap().send;
+            Ok(send)
+        });
+
+        let frame = |idx| Frame {
+            idx,
+            data: b"payload".to_vec(),
+            ..Frame::default()
+        };
+        // a few sent ahead of the responses, then one at a time
+        for idx in 0..3 {
+            tx.send(&frame(idx))?;
+        }
+        for idx in 0..3 {
+            let rcvd: Frame = tx.recv_timeout(REKEY_TIMEOUT)?;
+            assert_eq!(rcvd, frame(idx));
+        }
+        for idx in 3..10 {
+            tx.send(&frame(idx))?;
+            let rcvd: Frame = tx.recv_timeout(REKEY_TIMEOUT)?;
+            assert_eq!(rcvd, frame(idx));
+            thread::sleep(Duration::from_millis(5));
         }
-        Ok(Some(Frame::from(words)))
+        let (send, recv) = {
+            let keys = tx.keys.lock().unwrap();
+            (keys.send, keys.recv)
+        };
+        assert_ne!(send, A);
+        drop(tx);
+        assert_eq!(h.join()??, recv);
+        assert_ne!(send, recv);
+        Ok(())
+    }
+
+    // a socket to write raw bytes to, and the other end of it
+    fn pair() -> Result<(TcpStream, TcpStream)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let socket = TcpStream::connect(listener.local_addr()?)?;
+        Ok((socket, listener.accept()?.0))
+    }
+
+    #[test]
+    fn test_sealed() -> Result<()> {
+        let (socket, mut raw) = pair()?;
+        let mut tx = Tcp::from(socket);
+        tx.set_keys(A, B);
+        let frame = Frame {
+            idx: 1,
+            data: b"payload".to_vec(),
+            ..Frame::default()
+        };
+        tx.send(&frame)?;
+        tx.send(&frame)?;
+
+        let mut read = || -> Result<Vec<u8>> {
+            let mut len = [0u8; 4];
+            raw.read_exact(&mut len)?;
+            let mut sealed =
+                vec![0u8; u32::from_be_bytes(len) as usize];
+            raw.read_exact(&mut sealed)?;
+            Ok([len.to_vec(), sealed].concat())
+        };
+        let (first, second) = (read()?, read()?);
+        // nothing of the frame in the clear, and the same frame
+        // again is another ciphertext (the nonce is the counter)
+        assert!(!first.windows(7).any(|w| w == b"payload"));
+        assert_ne!(first, second);
+
+        let open = |wire: Vec<&[u8]>| -> Result<Vec<Frame>> {
+            let (mut socket, other) = pair()?;
+            for bytes in wire {
+                socket.write_all(bytes)?;
+            }
+            drop(socket);
+            let mut rx = Tcp::from(other);
+            rx.set_keys(B, A);
+            let mut frames = vec![];
+            while let Some(frame) = rx.recv()? {
+                frames.push(frame);
+            }
+            Ok(frames)
+        };
+        assert_eq!(
+            open(vec![&first, &second])?,
+            vec![frame.clone(), frame]
+        );
+        // reordered, replayed, or a bit flipped on the way
+        let mut flipped = first.clone();
+        flipped[10] ^= 1;
+        for wire in [
+            vec![&second[..], &first[..]],
+            vec![&first[..], &first[..]],
+            vec![&flipped[..]],
+        ] {
+            assert!(matches!(
+                open(wire),
+                Err(Error::App(e)) if e.contains("authentication")
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dead_peer() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let tx = Tcp::from(TcpStream::connect(addr)?);
+        drop(listener.accept()?); // the peer is gone
+
+        tx.keep_alive(Duration::from_millis(5));
+        let now = Instant::now();
+        while tx.is_alive() {
+            assert!(now.elapsed() < Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(5));
+        }
+        let sent = tx.send(&Frame::default());
+        assert!(matches!(
+            sent,
+            Err(Error::IO(e)) if e.kind() == ErrorKind::UnexpectedEof
+        ));
+        Ok(())
     }
 }
@@ -1,43 +1,281 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::api::{Error, Receiver, Result, Sender};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-type Network = Arc<Mutex<HashMap<String, Vec<u32>>>>;
+use crate::{
+    api::{
+        closed, timeout, Error, Frame, Receiver, Result, Sender,
+        MAX_FRAME_LEN,
+    },
+    chacha::{self, TAG_LEN},
+    codec::{Codec, Raw},
+    tcp::nonce,
+};
+
+// How the messages from one end to another get there. The faults
+// are the chances (0 to 1) of each message being dropped, sent
+// twice, or having a bit of it flipped, drawn from the network's
+// seeded generator: the same seed, the same faults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Link {
+    // visible to the receiving end only so long after being sent
+    pub latency: Duration,
+    // up to so much more, drawn for each message (the words of a
+    // frame go together): a message can then get there before one
+    // sent earlier. None by default, the messages come in the order
+    // sent, as over TCP.
+    pub jitter: Duration,
+    pub drop: f64,
+    pub duplicate: f64,
+    pub corrupt: f64,
+}
+
+#[derive(Debug)]
+struct State {
+    // by receiving end: the messages and when they are visible
+    queues: HashMap<String, VecDeque<(Duration, u32)>>,
+    // by sending and receiving end, no latency if not there
+    links: HashMap<(String, String), Link>,
+    // both ways: whatever is sent over is lost, see `Network::cut`
+    cut: HashSet<(String, String)>,
+    // the ends gone (a probe dropped): nothing more to wait for
+    // from them
+    closed: HashSet<String>,
+    // by name listened on: the ends of the connections not taken
+    // yet, see `Network::connect`
+    listening: HashMap<String, VecDeque<(String, String)>>,
+    connections: usize, // made so far, to name their ends
+    // simulated: moves on only as far as the receiving ends wait
+    // for, see `Probe::recv_timeout`
+    clock: Duration,
+    rng: StdRng, // of the faults
+}
+
+// The messages between the ends (named) of an in-memory network.
+// The ends of a connection (see `Network::connect`) are named after
+// the two sides with `#<n>` added: the links and the cuts are those
+// of the sides.
+#[derive(Clone, Debug)]
+pub struct Network(Arc<Mutex<State>>);
+
+fn side(end: &str) -> &str {
+    end.split('#').next().unwrap_or(end)
+}
 
 pub fn network() -> Network {
-    Arc::new(Mutex::new(HashMap::with_capacity(32)))
+    Network::seeded(0)
+}
+
+impl Network {
+    pub fn seeded(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(State {
+            queues: HashMap::new(),
+            links: HashMap::new(),
+            cut: HashSet::new(),
+            closed: HashSet::new(),
+            listening: HashMap::new(),
+            connections: 0,
+            clock: Duration::ZERO,
+            rng: StdRng::seed_from_u64(seed),
+        })))
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, State>> {
+        self.0.lock().map_err(|e| Error::Other(format!("{e}")))
+    }
+
+    pub fn link(&self, from: &str, to: &str, link: Link) {
+        let mut state = self.lock().unwrap();
+        state
+            .links
+            .insert((from.to_owned(), to.to_owned()), link);
+    }
+
+    // Nothing gets from one end to the other, either way, until
+    // healed; what is on its way already still gets there
+    pub fn cut(&self, a: &str, b: &str) {
+        let mut state = self.lock().unwrap();
+        state.cut.insert((a.to_owned(), b.to_owned()));
+        state.cut.insert((b.to_owned(), a.to_owned()));
+    }
+
+    pub fn heal(&self, a: &str, b: &str) {
+        let mut state = self.lock().unwrap();
+        state.cut.remove(&(a.to_owned(), b.to_owned()));
+        state.cut.remove(&(b.to_owned(), a.to_owned()));
+    }
+
+    // Every end of one side cut from every end of the other
+    pub fn partition(&self, one: &[&str], other: &[&str]) {
+        for a in one {
+            for b in other {
+                self.cut(a, b);
+            }
+        }
+    }
+
+    // No cuts left
+    pub fn heal_all(&self) {
+        self.lock().unwrap().cut.clear();
+    }
+
+    // the simulated time since the network was made
+    pub fn now(&self) -> Duration {
+        self.lock().unwrap().clock
+    }
+
+    // Connections to `name` from now on, until the listener is
+    // dropped, as for a `TcpListener`
+    pub fn listen(&self, name: &str) -> Listener {
+        let mut state = self.lock().unwrap();
+        state.listening.insert(name.to_owned(), VecDeque::new());
+        Listener {
+            name: name.to_owned(),
+            net: self.clone(),
+        }
+    }
+
+    // A connection from `from` to `to` (listened on, and not cut
+    // from it): this side's probe, the other one is for `to` to
+    // accept
+    pub fn connect(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<Probe> {
+        let mut state = self.lock()?;
+        let refused = state
+            .cut
+            .contains(&(from.to_owned(), to.to_owned()));
+        let n = state.connections;
+        let Some(backlog) =
+            state.listening.get_mut(to).filter(|_| !refused)
+        else {
+            let kind = std::io::ErrorKind::ConnectionRefused;
+            return Err(Error::IO(kind.into()));
+        };
+        let (src, dst) =
+            (format!("{from}#{n}"), format!("{to}#{n}"));
+        backlog.push_back((dst.clone(), src.clone()));
+        state.connections += 1;
+        drop(state);
+        Probe::open(&(src, dst, self.clone()))
+    }
+}
+
+pub struct Listener {
+    name: String,
+    net: Network,
+}
+
+impl Listener {
+    // The next connection made, waited for (in real time) at most
+    // `within`
+    pub fn accept(&self, within: Duration) -> Result<Probe> {
+        let until = Instant::now() + within;
+        loop {
+            let ends = self
+                .net
+                .lock()?
+                .listening
+                .get_mut(&self.name)
+                .and_then(VecDeque::pop_front);
+            if let Some((src, dst)) = ends {
+                return Probe::open(&(
+                    src,
+                    dst,
+                    self.net.clone(),
+                ));
+            }
+            if Instant::now() >= until {
+                return Err(timeout());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.net.lock() {
+            state.listening.remove(&self.name);
+        }
+    }
+}
+
+// The session keys (`dhke::Keys::seal` and `open`, as for
+// `tcp::Tcp`) and the frames sealed and opened with them so far
+#[derive(Debug)]
+struct Keys {
+    send: [u8; 32],
+    recv: [u8; 32],
+    sent: u64,
+    received: u64,
 }
 
 pub struct Probe {
     src: String,
     dst: String,
     net: Network,
+    keys: Mutex<Option<Keys>>, // none until the handshake
 }
 
 impl Sender<u32> for Probe {
     fn send(&self, msg: &u32) -> Result<()> {
-        let mut guard = self
-            .net
-            .lock()
-            .map_err(|e| Error::Other(format!("{e}")))?;
-        guard.entry(self.dst.clone()).or_default().push(*msg);
-        Ok(())
+        self.push(&[*msg])
     }
 }
 
 impl Receiver<u32> for Probe {
     fn recv(&self) -> Result<Option<u32>> {
-        let mut guard = self
-            .net
-            .lock()
-            .map_err(|e| Error::Other(format!("{e}")))?;
-        let msg = guard
-            .get_mut(&self.src)
-            .and_then(|queue| queue.pop());
-        Ok(msg)
+        let now = self.net.lock()?.clock;
+        self.take(now)
+    }
+
+    // Waits as long as the simulated clock allows: a message sent
+    // already is taken at once, the clock moved on to when it is
+    // visible, or the clock moved on to the deadline if it is not
+    // visible by then. A message not sent yet (by another thread)
+    // is waited for polling, for as long in real time, unless the
+    // other end is gone (closed, as for a socket).
+    fn recv_timeout(&self, within: Duration) -> Result<u32> {
+        let deadline = self.net.lock()?.clock + within;
+        let until = Instant::now() + within;
+        loop {
+            if let Some(msg) = self.take(deadline)? {
+                return Ok(msg);
+            }
+            let (sent, gone) = {
+                let state = self.net.lock()?;
+                let sent = state
+                    .queues
+                    .get(&self.src)
+                    .is_some_and(|queue| !queue.is_empty());
+                (sent, state.closed.contains(&self.dst))
+            };
+            if !sent && gone {
+                return Err(closed());
+            }
+            if sent || Instant::now() >= until {
+                let mut state = self.net.lock()?;
+                state.clock = state.clock.max(deadline);
+                return Err(timeout());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.net.lock() {
+            state.closed.insert(self.src.clone());
+        }
     }
 }
 
@@ -46,10 +284,514 @@ impl Probe {
         addr: &(String, String, Network),
     ) -> Result<Self> {
         let (src, dst, net) = addr;
+        net.lock()?.closed.remove(src); // open again
         Ok(Self {
             src: src.to_owned(),
             dst: dst.to_owned(),
             net: net.clone(),
+            keys: Mutex::new(None),
         })
     }
+
+    // a key per direction, frames are sealed from now on
+    pub fn set_keys(&mut self, send: [u8; 32], recv: [u8; 32]) {
+        *self.keys.get_mut().unwrap() = Some(Keys {
+            send,
+            recv,
+            sent: 0,
+            received: 0,
+        });
+    }
+
+    // The words sent together (the faults are still word by word):
+    // the words of a frame get there at once, not mixed up with
+    // another one's
+    fn push(&self, words: &[u32]) -> Result<()> {
+        let mut state = self.net.lock()?;
+        let ends = (
+            side(&self.src).to_owned(),
+            side(&self.dst).to_owned(),
+        );
+        if state.cut.contains(&ends) {
+            return Ok(()); // as good as sent
+        }
+        let link =
+            state.links.get(&ends).copied().unwrap_or_default();
+        let mut at = state.clock + link.latency;
+        if !link.jitter.is_zero() {
+            at += state
+                .rng
+                .gen_range(Duration::ZERO..=link.jitter);
+        }
+        for &word in words {
+            if state.rng.gen_bool(link.drop) {
+                continue; // as good as sent
+            }
+            let mut word = word;
+            if state.rng.gen_bool(link.corrupt) {
+                word ^= 1 << state.rng.gen_range(0..32);
+            }
+            let copies =
+                1 + state.rng.gen_bool(link.duplicate) as usize;
+            let queue = state
+                .queues
+                .entry(self.dst.clone())
+                .or_default();
+            for _ in 0..copies {
+                queue.push_back((at, word));
+            }
+        }
+        Ok(())
+    }
+
+    // The first message visible by `deadline` (the clock moved on
+    // to when it is), or none
+    fn take(&self, deadline: Duration) -> Result<Option<u32>> {
+        let mut state = self.net.lock()?;
+        let Some(queue) = state.queues.get_mut(&self.src) else {
+            return Ok(None);
+        };
+        // in order sent, unless a faster link (or less jitter) got
+        // it there first
+        let Some((i, &(at, msg))) = queue
+            .iter()
+            .enumerate()
+            .filter(|(_, (at, _))| *at <= deadline)
+            .min_by_key(|(_, (at, _))| *at)
+        else {
+            return Ok(None);
+        };
+        queue.remove(i);
+        state.clock = state.clock.max(at);
+        Ok(Some(msg))
+    }
+}
+
+// Frames over the words the way `tcp::Tcp` sends them with
+// `codec::Raw`: the byte length, then the encoded frame, sealed with
+// ChaCha20-Poly1305 once the keys are set (the length is the
+// associated data, the frame counter the nonce). No heartbeats, no
+// rekeying.
+impl Sender<Frame> for Probe {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        let frame = Raw.encode(msg)?;
+        let frame = match self.keys.lock().unwrap().as_mut() {
+            Some(keys) => {
+                let len = (frame.len() + TAG_LEN) as u32;
+                let nonce = nonce(keys.sent);
+                keys.sent += 1;
+                chacha::seal(
+                    &keys.send,
+                    &nonce,
+                    &len.to_be_bytes(),
+                    &frame,
+                )
+            }
+            None => frame,
+        };
+        let words = std::iter::once(frame.len() as u32)
+            .chain(frame.chunks(4).map(|w| {
+                u32::from_be_bytes(w.try_into().unwrap())
+            }))
+            .collect::<Vec<_>>();
+        self.push(&words)
+    }
+}
+
+impl Receiver<Frame> for Probe {
+    fn recv(&self) -> Result<Option<Frame>> {
+        match Receiver::<u32>::recv(self)? {
+            Some(len) => self.read_frame(len).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        let len = Receiver::<u32>::recv_timeout(self, within)?;
+        self.read_frame(len)
+    }
+}
+
+impl Probe {
+    // The rest of a frame of `len` bytes, there with its length
+    fn read_frame(&self, len: u32) -> Result<Frame> {
+        let len = len as usize;
+        if len > MAX_FRAME_LEN + TAG_LEN
+            || !len.is_multiple_of(4)
+        {
+            return Err(Error::App(format!(
+                "invalid frame length: {len} bytes"
+            )));
+        }
+        let mut buf = Vec::with_capacity(len);
+        for _ in 0..len / 4 {
+            let word = Receiver::<u32>::recv(self)?.ok_or_else(
+                || Error::App("frame cut short".to_string()),
+            )?;
+            buf.extend(word.to_be_bytes());
+        }
+        if let Some(keys) = self.keys.lock().unwrap().as_mut() {
+            let nonce = nonce(keys.received);
+            let aad = (len as u32).to_be_bytes();
+            buf = chacha::open(&keys.recv, &nonce, &aad, &buf)
+                .ok_or_else(|| {
+                Error::App(
+                    "invalid frame: authentication failed"
+                        .to_string(),
+                )
+            })?;
+            keys.received += 1;
+        }
+        Raw.decode(&buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dhke::{self, Group};
+
+    fn open(from: &str, to: &str, net: &Network) -> Probe {
+        Probe::open(&(
+            from.to_string(),
+            to.to_string(),
+            net.clone(),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_latency() -> Result<()> {
+        let net = network();
+        let ms = Duration::from_millis;
+        net.link(
+            "1",
+            "2",
+            Link {
+                latency: ms(500),
+                ..Link::default()
+            },
+        );
+        let (one, two) =
+            (open("1", "2", &net), open("2", "1", &net));
+
+        one.send(&1)?;
+        one.send(&2)?;
+        // not there yet
+        assert_eq!(two.recv()?, None::<u32>);
+        let started = Instant::now();
+        assert!(Receiver::<u32>::recv_timeout(&two, ms(100))
+            .is_err());
+        assert_eq!(net.now(), ms(100));
+        let rcvd: u32 = two.recv_timeout(ms(1000))?;
+        assert_eq!(rcvd, 1);
+        assert_eq!(net.now(), ms(500));
+        assert_eq!(two.recv()?, Some(2));
+        // the other way, no latency
+        two.send(&3)?;
+        assert_eq!(one.recv()?, Some(3));
+        assert_eq!(net.now(), ms(500));
+        // no real wait for what the clock did
+        assert!(started.elapsed() < ms(100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_faults() -> Result<()> {
+        // the same number in both halves, so that a flipped bit
+        // shows
+        let words =
+            (0..1000).map(|i| i << 16 | i).collect::<Vec<_>>();
+        let run = |seed: u64, link: Link| -> Result<Vec<u32>> {
+            let net = Network::seeded(seed);
+            net.link("1", "2", link);
+            let (one, two) =
+                (open("1", "2", &net), open("2", "1", &net));
+            for word in &words {
+                one.send(word)?;
+            }
+            Ok(std::iter::from_fn(|| two.recv().unwrap())
+                .collect())
+        };
+        assert_eq!(run(1, Link::default())?, words);
+        assert!(run(
+            1,
+            Link {
+                drop: 1.0,
+                ..Link::default()
+            }
+        )?
+        .is_empty());
+
+        let link = Link {
+            drop: 0.1,
+            duplicate: 0.1,
+            corrupt: 0.1,
+            ..Link::default()
+        };
+        let rcvd = run(1, link)?;
+        // the same seed, the same faults
+        assert_eq!(rcvd, run(1, link)?);
+        assert_ne!(rcvd, run(2, link)?);
+        let corrupt = rcvd
+            .iter()
+            .filter(|w| *w >> 16 != *w & 0xffff)
+            .count();
+        let seen = |word: &u32| {
+            rcvd.iter().filter(|w| *w == word).count()
+        };
+        let lost = words.iter().filter(|w| seen(w) == 0).count();
+        let twice =
+            words.iter().filter(|w| seen(w) == 2).count();
+        // about 10% of each (the corrupt ones are lost too)
+        assert!(
+            (50..150).contains(&corrupt),
+            "corrupt {corrupt}"
+        );
+        assert!((150..250).contains(&lost), "lost {lost}");
+        assert!((50..150).contains(&twice), "twice {twice}");
+        Ok(())
+    }
+
+    fn frame(idx: u32, data: &[u8]) -> Frame {
+        let mut frame = Frame {
+            idx,
+            tag: 6,
+            key: 0xCAFEBABE,
+            data: data.to_vec(),
+            ..Frame::default()
+        };
+        frame.sum = frame.checksum();
+        frame
+    }
+
+    #[test]
+    fn test_frames() -> Result<()> {
+        let net = network();
+        let (mut one, mut two) =
+            (open("1", "2", &net), open("2", "1", &net));
+        let (a, b) = (frame(1, b"hello"), frame(2, &[7; 100]));
+
+        // plain before the keys
+        one.send(&a)?;
+        assert_eq!(two.recv()?, Some(a.clone()));
+        assert_eq!(Receiver::<Frame>::recv(&two)?, None);
+
+        one.set_keys([1; 32], [2; 32]);
+        two.set_keys([2; 32], [1; 32]);
+        one.send(&a)?;
+        two.send(&b)?;
+        one.send(&b)?;
+        let within = Duration::from_millis(10);
+        let rcvd: Frame = two.recv_timeout(within)?;
+        assert_eq!(rcvd, a);
+        let rcvd: Frame = two.recv_timeout(within)?;
+        assert_eq!(rcvd, b);
+        let rcvd: Frame = one.recv_timeout(within)?;
+        assert_eq!(rcvd, b);
+        assert!(Receiver::<Frame>::recv_timeout(&one, within)
+            .is_err());
+
+        // sealed: not a frame without the keys
+        let plain = open("2", "1", &net);
+        one.send(&a)?;
+        assert!(Receiver::<Frame>::recv(&plain).is_err());
+
+        // nor with a bit flipped on the way
+        net.link(
+            "1",
+            "2",
+            Link {
+                corrupt: 1.0,
+                ..Link::default()
+            },
+        );
+        one.send(&a)?;
+        assert!(Receiver::<Frame>::recv(&two).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fifo() -> Result<()> {
+        let net = network();
+        let (mut one, mut two) =
+            (open("1", "2", &net), open("2", "1", &net));
+        let within = Duration::from_millis(100);
+
+        // words come out in the order they went in
+        for word in 0..10 {
+            one.send(&word)?;
+        }
+        let rcvd = (0..10)
+            .map(|_| two.recv_timeout(within))
+            .collect::<Result<Vec<u32>>>()?;
+        assert_eq!(rcvd, (0..10).collect::<Vec<_>>());
+
+        // the handshake (many words each way), then the frames
+        // sealed with its keys, in order
+        let h = thread::spawn(move || -> Result<Probe> {
+            let keys =
+                dhke::handshake(&two, within, &Group::ALL)?
+                    .keys();
+            two.set_keys(keys.seal, keys.open);
+            Ok(two)
+        });
+        let keys =
+            dhke::handshake(&one, within, &Group::ALL)?.keys();
+        one.set_keys(keys.seal, keys.open);
+        let two = h.join().unwrap()?;
+
+        let frames = (0..5)
+            .map(|i| frame(i, &[i as u8; 10]))
+            .collect::<Vec<_>>();
+        for frame in &frames {
+            one.send(frame)?;
+        }
+        let rcvd = (0..5)
+            .map(|_| two.recv_timeout(within))
+            .collect::<Result<Vec<Frame>>>()?;
+        assert_eq!(rcvd, frames);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reorder() -> Result<()> {
+        let ms = Duration::from_millis;
+        let run =
+            |seed: u64, jitter: Duration| -> Result<Vec<u32>> {
+                let net = Network::seeded(seed);
+                net.link(
+                    "1",
+                    "2",
+                    Link {
+                        latency: ms(10),
+                        jitter,
+                        ..Link::default()
+                    },
+                );
+                let (one, two) =
+                    (open("1", "2", &net), open("2", "1", &net));
+                for word in 0..100 {
+                    one.send(&word)?;
+                }
+                (0..100)
+                    .map(|_| two.recv_timeout(ms(1000)))
+                    .collect()
+            };
+        let words = (0..100).collect::<Vec<_>>();
+        // first in, first out
+        assert_eq!(run(1, Duration::ZERO)?, words);
+
+        let mut rcvd = run(1, ms(50))?;
+        assert_ne!(rcvd, words);
+        assert_eq!(rcvd, run(1, ms(50))?);
+        rcvd.sort();
+        assert_eq!(rcvd, words);
+
+        // a frame still gets there whole
+        let net = network();
+        net.link(
+            "1",
+            "2",
+            Link {
+                jitter: ms(50),
+                ..Link::default()
+            },
+        );
+        let (one, two) =
+            (open("1", "2", &net), open("2", "1", &net));
+        let frames = (0..20)
+            .map(|i| frame(i, &[i as u8; 33]))
+            .collect::<Vec<_>>();
+        for frame in &frames {
+            one.send(frame)?;
+        }
+        let mut rcvd = (0..20)
+            .map(|_| two.recv_timeout(ms(1000)))
+            .collect::<Result<Vec<Frame>>>()?;
+        assert_ne!(rcvd, frames);
+        rcvd.sort_by_key(|frame| frame.idx);
+        assert_eq!(rcvd, frames);
+        Ok(())
+    }
+
+    #[test]
+    fn test_connect() -> Result<()> {
+        let net = network();
+        let ms = Duration::from_millis;
+        net.link(
+            "c",
+            "s",
+            Link {
+                latency: ms(100),
+                ..Link::default()
+            },
+        );
+        let listener = net.listen("s");
+        let client = net.connect("c", "s")?;
+        let server = listener.accept(ms(10))?;
+        assert!(listener.accept(ms(10)).is_err());
+
+        // the link of the sides
+        client.send(&1)?;
+        assert_eq!(server.recv()?, None::<u32>);
+        let rcvd: u32 = server.recv_timeout(ms(1000))?;
+        assert_eq!(rcvd, 1);
+        assert_eq!(net.now(), ms(100));
+        server.send(&2)?;
+        assert_eq!(client.recv()?, Some(2));
+
+        // what was sent still gets there, then it is closed
+        client.send(&3)?;
+        drop(client);
+        let started = Instant::now();
+        let rcvd: u32 = server.recv_timeout(ms(1000))?;
+        assert_eq!(rcvd, 3);
+        assert!(Receiver::<u32>::recv_timeout(
+            &server,
+            ms(1000)
+        )
+        .is_err());
+        assert!(started.elapsed() < ms(500));
+
+        assert!(net.connect("c", "x").is_err());
+        net.cut("c", "s");
+        assert!(net.connect("c", "s").is_err());
+        net.heal_all();
+        drop(listener);
+        assert!(net.connect("c", "s").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition() -> Result<()> {
+        let net = network();
+        let ends = ["a", "b", "c"];
+        let probe = |from: &str, to: &str| open(from, to, &net);
+        let (ab, ba) = (probe("a", "b"), probe("b", "a"));
+        let (ac, ca) = (probe("a", "c"), probe("c", "a"));
+        let (bc, cb) = (probe("b", "c"), probe("c", "b"));
+
+        ab.send(&1)?; // on its way before the cut
+        net.partition(&ends[..1], &ends[1..]);
+        ab.send(&2)?;
+        ba.send(&3)?;
+        ca.send(&4)?;
+        bc.send(&5)?; // on the same side
+        assert_eq!(cb.recv()?, Some(5));
+        assert_eq!(ba.recv()?, Some(1));
+        assert_eq!(ba.recv()?, None::<u32>);
+        assert_eq!(ab.recv()?, None::<u32>);
+        assert_eq!(ac.recv()?, None::<u32>);
+
+        net.heal("a", "b");
+        ab.send(&6)?;
+        ca.send(&7)?; // still cut
+        assert_eq!(ba.recv()?, Some(6));
+        assert_eq!(ac.recv()?, None::<u32>);
+        net.heal_all();
+        ca.send(&8)?;
+        assert_eq!(ac.recv()?, Some(8));
+        Ok(())
+    }
 }
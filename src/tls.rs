@@ -0,0 +1,303 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rustls::{
+    pki_types::ServerName, ClientConfig, ClientConnection,
+    Connection, RootCertStore, ServerConfig, ServerConnection,
+};
+
+use crate::{
+    api::{
+        Error, Frame, Receiver, Result, Sender, MAX_FRAME_LEN,
+    },
+    codec::{Codec, Raw},
+    tcp::recv_within,
+};
+
+// Frames over TLS: same length-prefixed raw frames as `Tcp`, but
+// without the XOR mask (and without the DHKE handshake).
+//
+// The connection state is locked only to push bytes in and out,
+// so that reading and writing can happen concurrently (`Mux`).
+pub struct Tls {
+    socket: TcpStream,
+    conn: Mutex<Connection>,
+    plain: Mutex<Vec<u8>>, // decrypted, not yet consumed
+}
+
+impl Tls {
+    pub fn client(
+        socket: TcpStream,
+        config: Arc<ClientConfig>,
+        peer: IpAddr,
+    ) -> Result<Self> {
+        let name = ServerName::from(peer);
+        let conn =
+            ClientConnection::new(config, name).map_err(tls)?;
+        Self::new(socket, conn.into())
+    }
+
+    pub fn server(
+        socket: TcpStream,
+        config: Arc<ServerConfig>,
+    ) -> Result<Self> {
+        let conn = ServerConnection::new(config).map_err(tls)?;
+        Self::new(socket, conn.into())
+    }
+
+    fn new(
+        socket: TcpStream,
+        mut conn: Connection,
+    ) -> Result<Self> {
+        while conn.is_handshaking() {
+            conn.complete_io(&mut &socket)?;
+        }
+        Ok(Tls {
+            socket,
+            conn: Mutex::new(conn),
+            plain: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let mut rest = bytes;
+        // rustls buffers only so much, flush as it goes
+        while !rest.is_empty() {
+            let n = conn.writer().write(rest)?;
+            rest = &rest[n..];
+            while conn.wants_write() {
+                conn.write_tls(&mut &self.socket)?;
+            }
+        }
+        Ok(())
+    }
+
+    // None on EOF before the first byte
+    fn read(&self, len: usize) -> Result<Option<Vec<u8>>> {
+        let mut plain = self.plain.lock().unwrap();
+        let mut buf = vec![0u8; 16 * 1024];
+        while plain.len() < len {
+            let n = (&self.socket).read(&mut buf)?;
+            if n == 0 && plain.is_empty() {
+                return Ok(None);
+            }
+            if n == 0 {
+                let e = std::io::Error::from(
+                    ErrorKind::UnexpectedEof,
+                );
+                return Err(Error::IO(e));
+            }
+
+            let mut conn = self.conn.lock().unwrap();
+            let mut tail = &buf[..n];
+            let mut out = [0u8; 4096];
+            while !tail.is_empty() {
+                // drain the plaintext as it comes, rustls limits
+                // how much of it can be buffered
+                conn.read_tls(&mut tail)?;
+                conn.process_new_packets().map_err(tls)?;
+                loop {
+                    match conn.reader().read(&mut out) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            plain.extend_from_slice(&out[..n])
+                        }
+                        Err(e)
+                            if e.kind()
+                                == ErrorKind::WouldBlock =>
+                        {
+                            break
+                        }
+                        Err(e) => return Err(Error::IO(e)),
+                    }
+                }
+            }
+            while conn.wants_write() {
+                conn.write_tls(&mut &self.socket)?;
+            }
+        }
+        Ok(Some(plain.drain(..len).collect()))
+    }
+}
+
+fn tls(e: rustls::Error) -> Error {
+    Error::App(format!("tls: {e}"))
+}
+
+fn certs(
+    pem: &[u8],
+) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(Error::App(
+            "no certificates found".to_string(),
+        ));
+    }
+    Ok(certs)
+}
+
+// Trusted CA certificate(s) in PEM to verify the server with
+pub fn client_config(ca: &[u8]) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(ca)? {
+        roots.add(cert).map_err(tls)?;
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+// Certificate chain and private key in PEM
+pub fn server_config(
+    cert: &[u8],
+    key: &[u8],
+) -> Result<Arc<ServerConfig>> {
+    let key = rustls_pemfile::private_key(&mut &key[..])?
+        .ok_or_else(|| {
+            Error::App("no private key found".to_string())
+        })?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs(cert)?, key)
+        .map_err(tls)?;
+    Ok(Arc::new(config))
+}
+
+impl Sender<u32> for Tls {
+    fn send(&self, msg: &u32) -> Result<()> {
+        self.write(&msg.to_be_bytes())
+    }
+}
+
+impl Receiver<u32> for Tls {
+    fn recv(&self) -> Result<Option<u32>> {
+        Ok(self.read(4)?.map(|buf| {
+            u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+        }))
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<u32> {
+        recv_within(&self.socket, within, || self.recv())
+    }
+}
+
+impl Sender<Frame> for Tls {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        let frame = Raw.encode(msg)?;
+        let mut buf = Vec::with_capacity(4 + frame.len());
+        buf.extend((frame.len() as u32).to_be_bytes());
+        buf.extend(frame);
+        self.write(&buf)
+    }
+}
+
+impl Receiver<Frame> for Tls {
+    fn recv(&self) -> Result<Option<Frame>> {
+        let len: u32 = match self.recv()? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let len = len as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(Error::App(format!(
+                "invalid frame length: {len} bytes"
+            )));
+        }
+        match self.read(len)? {
+            Some(buf) => Raw.decode(&buf).map(Some),
+            None => {
+                let e = std::io::Error::from(
+                    ErrorKind::UnexpectedEof,
+                );
+                Err(Error::IO(e))
+            }
+        }
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        recv_within(&self.socket, within, || self.recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+    use crate::api::MAX_PAYLOAD_LEN;
+
+    fn configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![
+                "127.0.0.1".to_string(),
+            ])
+            .unwrap();
+        let cert = cert.pem().into_bytes();
+        let key = key_pair.serialize_pem().into_bytes();
+        let server = server_config(&cert, &key).unwrap();
+        let client = client_config(&cert).unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn test_frame_roundtrip() -> Result<()> {
+        let (server, client) = configs();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let h = thread::spawn(move || -> Result<()> {
+            let socket = listener.accept()?.0;
+            let tx = Tls::server(socket, server)?;
+            let frame: Frame =
+                tx.recv_timeout(Duration::from_secs(1))?;
+            tx.send(&frame)?;
+            Ok(())
+        });
+
+        let socket = TcpStream::connect(addr)?;
+        let tx = Tls::client(socket, client, addr.ip())?;
+        let frame = Frame {
+            idx: 1,
+            tag: 2,
+            msg: 3,
+            key: 4,
+            sig: 5,
+            ext: 6,
+            ns: 0,
+            sum: 7,
+            data: vec![42; MAX_PAYLOAD_LEN], // many TLS records
+        };
+        tx.send(&frame)?;
+        let echo: Frame =
+            tx.recv_timeout(Duration::from_secs(1))?;
+        h.join()??;
+
+        assert_eq!(echo, frame);
+        Ok(())
+    }
+
+    #[test]
+    fn test_untrusted_server() -> Result<()> {
+        let (server, _) = configs();
+        let (_, client) = configs(); // trusts another cert
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let h = thread::spawn(move || {
+            let socket = listener.accept().unwrap().0;
+            Tls::server(socket, server).is_err()
+        });
+
+        let socket = TcpStream::connect(addr)?;
+        assert!(Tls::client(socket, client, addr.ip()).is_err());
+        assert!(h.join().unwrap());
+        Ok(())
+    }
+}
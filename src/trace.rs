@@ -0,0 +1,345 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api::{timeout, Error, Frame, Receiver, Result, Sender},
+    util::{from_hex, to_hex},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dir {
+    Send,
+    Recv,
+}
+
+// A frame that went over a transport, and when (since the recording
+// started)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub at: Duration,
+    pub dir: Dir,
+    pub frame: Frame,
+}
+
+// A line of a trace: `<millis> <send|recv> <words, hex>`
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words = self
+            .frame
+            .encode()
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect::<Vec<_>>();
+        let dir = match self.dir {
+            Dir::Send => "send",
+            Dir::Recv => "recv",
+        };
+        write!(
+            f,
+            "{} {dir} {}",
+            self.at.as_millis(),
+            to_hex(&words)
+        )
+    }
+}
+
+impl FromStr for Event {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let invalid = || {
+            Error::App(format!("invalid trace event: {line}"))
+        };
+        let mut fields = line.split(' ');
+        let mut next = || fields.next().ok_or_else(invalid);
+        let at = next()?.parse().map_err(|_| invalid())?;
+        let dir = match next()? {
+            "send" => Dir::Send,
+            "recv" => Dir::Recv,
+            _ => return Err(invalid()),
+        };
+        let bytes = from_hex(next()?)
+            .filter(|bytes| bytes.len().is_multiple_of(4))
+            .ok_or_else(invalid)?;
+        let words = bytes
+            .chunks(4)
+            .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        Ok(Self {
+            at: Duration::from_millis(at),
+            dir,
+            frame: Frame::decode(&words)?,
+        })
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Event>> {
+    text.lines().map(str::parse).collect()
+}
+
+// A transport whose frames, sent and received, are written down to
+// a file as they go, a line each (see `Event`): a capture of the
+// traffic of a session (after its handshake) to be replayed later,
+// see `Replay`. The frames go to the file as they are, it is no
+// place for the frames of a session with shares in them.
+pub struct Recorder<T> {
+    inner: T,
+    file: Mutex<File>,
+    started: Instant,
+}
+
+impl<T> Recorder<T> {
+    pub fn new(inner: T, path: &Path) -> Result<Self> {
+        Ok(Self {
+            inner,
+            file: Mutex::new(File::create(path)?),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&self, dir: Dir, frame: &Frame) -> Result<()> {
+        let event = Event {
+            at: self.started.elapsed(),
+            dir,
+            frame: frame.clone(),
+        };
+        let mut file = self.file.lock().unwrap();
+        Ok(file.write_all(format!("{event}\n").as_bytes())?)
+    }
+}
+
+impl<T: Sender<Frame>> Sender<Frame> for Recorder<T> {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        self.inner.send(msg)?;
+        self.record(Dir::Send, msg)
+    }
+}
+
+impl<T: Receiver<Frame>> Receiver<Frame> for Recorder<T> {
+    fn recv(&self) -> Result<Option<Frame>> {
+        let frame = self.inner.recv()?;
+        if let Some(frame) = &frame {
+            self.record(Dir::Recv, frame)?;
+        }
+        Ok(frame)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Frame> {
+        let frame = self.inner.recv_timeout(timeout)?;
+        self.record(Dir::Recv, &frame)?;
+        Ok(frame)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    events: VecDeque<Event>,
+    at: Duration, // of the last event played
+    sent: Vec<Frame>,
+}
+
+// A recorded trace played back as the other side: the frames
+// received then are received again, in order, whatever is sent
+// (the frames sent are kept, see `sent`, for the test to check). A
+// frame that came in later than the timeout after the event before
+// it times out again (and is left for the next call).
+#[derive(Debug, Default)]
+pub struct Replay {
+    state: Mutex<State>,
+}
+
+impl Replay {
+    pub fn new(events: Vec<Event>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                events: events.into(),
+                ..State::default()
+            }),
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self::new(parse(&fs::read_to_string(path)?)?))
+    }
+
+    // what was sent to the replay so far
+    pub fn sent(&self) -> Vec<Frame> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    // no frames left to receive
+    pub fn is_done(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        !state.events.iter().any(|e| e.dir == Dir::Recv)
+    }
+
+    fn next(
+        &self,
+        within: Option<Duration>,
+    ) -> Result<Option<Frame>> {
+        let mut state = self.state.lock().unwrap();
+        let Some(i) =
+            state.events.iter().position(|e| e.dir == Dir::Recv)
+        else {
+            return Ok(None);
+        };
+        let at = state.events[i].at;
+        if let Some(within) = within {
+            if at > state.at + within {
+                state.at += within;
+                return Err(timeout());
+            }
+        }
+        // the ones sent before it are done with
+        let event =
+            state.events.drain(..=i).next_back().unwrap();
+        state.at = at;
+        Ok(Some(event.frame))
+    }
+}
+
+impl Sender<Frame> for Replay {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        self.state.lock().unwrap().sent.push(msg.clone());
+        Ok(())
+    }
+}
+
+impl Receiver<Frame> for Replay {
+    fn recv(&self) -> Result<Option<Frame>> {
+        self.next(None)
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        self.next(Some(within))?.ok_or_else(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::random;
+
+    use super::*;
+
+    // Answers each frame with itself, tagged OK
+    #[derive(Default)]
+    struct Echo(Mutex<VecDeque<Frame>>);
+
+    impl Sender<Frame> for Echo {
+        fn send(&self, msg: &Frame) -> Result<()> {
+            let mut frame = msg.clone();
+            frame.tag = 200;
+            frame.sum = frame.checksum();
+            self.0.lock().unwrap().push_back(frame);
+            Ok(())
+        }
+    }
+
+    impl Receiver<Frame> for Echo {
+        fn recv(&self) -> Result<Option<Frame>> {
+            Ok(self.0.lock().unwrap().pop_front())
+        }
+
+        fn recv_timeout(&self, _: Duration) -> Result<Frame> {
+            self.recv()?.ok_or_else(timeout)
+        }
+    }
+
+    fn frame(idx: u32, data: &[u8]) -> Frame {
+        let mut frame = Frame {
+            idx,
+            tag: 6,
+            msg: 0,
+            key: 0xCAFEBABE,
+            sig: 0,
+            ext: 0,
+            ns: 0,
+            sum: 0,
+            data: data.to_vec(),
+        };
+        frame.sum = frame.checksum();
+        frame
+    }
+
+    #[test]
+    fn test_event() -> Result<()> {
+        let event = Event {
+            at: Duration::from_millis(1500),
+            dir: Dir::Recv,
+            frame: frame(1, b"hi"),
+        };
+        let line = event.to_string();
+        assert!(line.starts_with("1500 recv 00000001"));
+        assert_eq!(line.parse::<Event>()?, event);
+        assert!("1 sent 00".parse::<Event>().is_err());
+        assert!("1 send 000".parse::<Event>().is_err());
+        assert!("1 send 00000001".parse::<Event>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_replay() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "doing-some-blockchain-{:0x}.trace",
+            random()
+        ));
+        let tx = Recorder::new(Echo::default(), &path)?;
+        for i in 1..=3 {
+            tx.send(&frame(i, &[i as u8; 5]))?;
+            let _: Frame = tx.recv_timeout(Duration::ZERO)?;
+        }
+        let events = parse(&fs::read_to_string(&path)?)?;
+        assert_eq!(events.len(), 6);
+        assert!(events.windows(2).all(|e| e[0].at <= e[1].at));
+
+        let replay = Replay::open(&path)?;
+        for i in 1..=3 {
+            replay.send(&frame(10 + i, &[]))?;
+            let rcvd: Frame =
+                replay.recv_timeout(Duration::from_secs(1))?;
+            assert_eq!((rcvd.idx, rcvd.tag), (i, 200));
+            assert_eq!(rcvd.data, vec![i as u8; 5]);
+        }
+        assert!(replay.is_done());
+        assert_eq!(replay.sent().len(), 3);
+        assert!(Receiver::<Frame>::recv(&replay)?.is_none());
+        fs::remove_file(&path)?;
+
+        // a response recorded late times out again
+        let late = |ms: u64, idx: u32| Event {
+            at: Duration::from_millis(ms),
+            dir: Dir::Recv,
+            frame: frame(idx, &[]),
+        };
+        let replay =
+            Replay::new(vec![late(100, 1), late(900, 2)]);
+        let within = Duration::from_millis(500);
+        assert_eq!(
+            Receiver::<Frame>::recv_timeout(&replay, within)?
+                .idx,
+            1
+        );
+        assert!(Receiver::<Frame>::recv_timeout(
+            &replay, within
+        )
+        .is_err());
+        assert_eq!(
+            Receiver::<Frame>::recv_timeout(&replay, within)?
+                .idx,
+            2
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::VecDeque,
+    net::UdpSocket,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api::{timeout, Error, Frame, Receiver, Result, Sender},
+    codec::{Codec, Raw},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+const DEFAULT_RETRIES: usize = 5;
+
+// Largest payload of a single UDP datagram (IPv4)
+const MAX_DATAGRAM_LEN: usize = 65507;
+
+const KIND_DATA: u32 = 0;
+const KIND_ACK: u32 = 1;
+
+// Stop-and-wait over a connected UDP socket: each datagram is
+// `seq || kind || body` (masked with the key), DATA datagrams
+// are retransmitted until ACKed with the same `seq`, duplicates
+// are ACKed again and dropped.
+pub struct Udp {
+    socket: UdpSocket,
+    key: Option<u32>,
+    timeout: Duration, // per attempt
+    retries: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_seq: u32,
+    last_seq: Option<u32>,
+    // DATA received while waiting for an ACK
+    inbox: VecDeque<Vec<u8>>,
+}
+
+enum Datagram {
+    Data(u32, Vec<u8>),
+    Ack(u32),
+}
+
+impl Udp {
+    pub fn set_key(&mut self, key: u32) {
+        self.key = Some(key);
+    }
+
+    pub fn set_retries(
+        &mut self,
+        retries: usize,
+        timeout: Duration,
+    ) {
+        self.retries = retries;
+        self.timeout = timeout;
+    }
+
+    fn mask(&self, bytes: &mut [u8]) {
+        let mask = self.key.unwrap_or_default().to_be_bytes();
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+
+    fn write(
+        &self,
+        seq: u32,
+        kind: u32,
+        body: &[u8],
+    ) -> Result<()> {
+        let mut buf = Vec::with_capacity(8 + body.len());
+        buf.extend(seq.to_be_bytes());
+        buf.extend(kind.to_be_bytes());
+        buf.extend(body);
+        if buf.len() > MAX_DATAGRAM_LEN {
+            return Err(Error::App(format!(
+                "datagram too large: {} bytes",
+                buf.len()
+            )));
+        }
+        self.mask(&mut buf);
+        self.socket.send(&buf)?;
+        Ok(())
+    }
+
+    // None if nothing arrived within the timeout: a datagram that
+    // is not ours (too short, of no known kind) is skipped, it is
+    // waited for another one until then
+    fn read(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<Datagram>> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+        while let Some(left) =
+            deadline.checked_duration_since(Instant::now())
+        {
+            // zero means "no timeout" for the socket
+            let left = left.max(Duration::from_millis(1));
+            self.socket.set_read_timeout(Some(left))?;
+            let n = match self.socket.recv(&mut buf) {
+                Ok(n) => n,
+                Err(e)
+                    if e.kind()
+                        == std::io::ErrorKind::WouldBlock
+                        || e.kind()
+                            == std::io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(Error::IO(e)),
+            };
+            if n < 8 {
+                continue; // not ours, ignore
+            }
+            let mut datagram = buf[..n].to_vec();
+            self.mask(&mut datagram);
+            let seq = u32::from_be_bytes(
+                datagram[0..4].try_into().unwrap(),
+            );
+            let kind = u32::from_be_bytes(
+                datagram[4..8].try_into().unwrap(),
+            );
+            match kind {
+                KIND_DATA => {
+                    let body = datagram.split_off(8);
+                    return Ok(Some(Datagram::Data(seq, body)));
+                }
+                KIND_ACK => return Ok(Some(Datagram::Ack(seq))),
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    // ACK the DATA datagram, false if it is a duplicate
+    fn accept(
+        &self,
+        state: &mut State,
+        seq: u32,
+    ) -> Result<bool> {
+        self.write(seq, KIND_ACK, &[])?;
+        if state.last_seq == Some(seq) {
+            return Ok(false);
+        }
+        state.last_seq = Some(seq);
+        Ok(true)
+    }
+
+    fn send_bytes(&self, body: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq = seq.wrapping_add(1);
+
+        for _ in 0..=self.retries {
+            self.write(seq, KIND_DATA, body)?;
+            let deadline = Instant::now() + self.timeout;
+            while let Some(left) =
+                deadline.checked_duration_since(Instant::now())
+            {
+                match self.read(left)? {
+                    Some(Datagram::Ack(acked))
+                        if acked == seq =>
+                    {
+                        return Ok(())
+                    }
+                    Some(Datagram::Data(seq, body)) => {
+                        if self.accept(&mut state, seq)? {
+                            state.inbox.push_back(body);
+                        }
+                    }
+                    Some(Datagram::Ack(_)) => (),
+                    None => break,
+                }
+            }
+        }
+
+        Err(timeout())
+    }
+
+    fn recv_bytes(
+        &self,
+        within: Duration,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(body) = state.inbox.pop_front() {
+            return Ok(Some(body));
+        }
+        let deadline = Instant::now() + within;
+        while let Some(left) =
+            deadline.checked_duration_since(Instant::now())
+        {
+            match self.read(left)? {
+                Some(Datagram::Data(seq, body)) => {
+                    if self.accept(&mut state, seq)? {
+                        return Ok(Some(body));
+                    }
+                }
+                Some(Datagram::Ack(_)) => (),
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl From<UdpSocket> for Udp {
+    // the socket must be connected to the peer
+    fn from(socket: UdpSocket) -> Self {
+        Udp {
+            socket,
+            key: None,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl Sender<u32> for Udp {
+    fn send(&self, msg: &u32) -> Result<()> {
+        self.send_bytes(&msg.to_be_bytes())
+    }
+}
+
+fn decode_u32(body: &[u8]) -> Result<u32> {
+    let bytes: [u8; 4] = body.try_into().map_err(|_| {
+        Error::App(format!(
+            "expected 4 bytes, got {}",
+            body.len()
+        ))
+    })?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+impl Receiver<u32> for Udp {
+    fn recv(&self) -> Result<Option<u32>> {
+        self.recv_bytes(self.timeout)?
+            .map(|body| decode_u32(&body))
+            .transpose()
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<u32> {
+        let body =
+            self.recv_bytes(within)?.ok_or_else(timeout)?;
+        decode_u32(&body)
+    }
+}
+
+impl Sender<Frame> for Udp {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        self.send_bytes(&Raw.encode(msg)?)
+    }
+}
+
+impl Receiver<Frame> for Udp {
+    fn recv(&self) -> Result<Option<Frame>> {
+        match self.recv_bytes(self.timeout)? {
+            Some(body) => Raw.decode(&body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        let body =
+            self.recv_bytes(within)?.ok_or_else(timeout)?;
+        Raw.decode(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{dhke::dhke_handshake, util::random};
+
+    fn sockets() -> Result<(UdpSocket, UdpSocket)> {
+        let a = UdpSocket::bind("127.0.0.1:0")?;
+        let b = UdpSocket::bind("127.0.0.1:0")?;
+        a.connect(b.local_addr()?)?;
+        b.connect(a.local_addr()?)?;
+        Ok((a, b))
+    }
+
+    fn pair() -> Result<(Udp, Udp)> {
+        let (a, b) = sockets()?;
+        Ok((Udp::from(a), Udp::from(b)))
+    }
+
+    #[test]
+    fn test_handshake_and_frame() -> Result<()> {
+        let (mut a, mut b) = pair()?;
+        let timeout = Duration::from_secs(1);
+
+        let h = thread::spawn(move || -> Result<Udp> {
+            let key = dhke_handshake(&b, timeout, random())?;
+            b.set_key(key);
+            let frame: Frame = b.recv_timeout(timeout)?;
+            b.send(&frame)?;
+            Ok(b)
+        });
+        let key = dhke_handshake(&a, timeout, random())?;
+        a.set_key(key);
+
+        let frame = Frame {
+            idx: 1,
+            tag: 2,
+            msg: 3,
+            key: 4,
+            sig: 5,
+            ext: 6,
+            ns: 0,
+            sum: 7,
+            data: b"payload".to_vec(),
+        };
+        a.send(&frame)?;
+        let echo: Frame = a.recv_timeout(timeout)?;
+        h.join()??;
+
+        assert_eq!(echo, frame);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_ack() -> Result<()> {
+        // the peer never reads, so never ACKs
+        let (mut a, _b) = pair()?;
+        a.set_retries(2, Duration::from_millis(10));
+        assert!(Sender::<u32>::send(&a, &42).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_junk_skipped() -> Result<()> {
+        let (a, b) = sockets()?;
+        let junk = a.try_clone()?;
+        let (a, b) = (Udp::from(a), Udp::from(b));
+
+        // too short, then of no known kind: neither ends the wait
+        junk.send(&[1, 2, 3])?;
+        junk.send(&[0, 0, 0, 0, 0, 0, 0, 9])?;
+        let h =
+            thread::spawn(move || Sender::<u32>::send(&a, &42));
+        let rcvd: u32 =
+            b.recv_timeout(Duration::from_secs(1))?;
+        assert_eq!(rcvd, 42);
+        h.join().unwrap()?;
+        Ok(())
+    }
+}
@@ -1,3 +1,7 @@
+use std::{fmt::Debug, fs, path::Path, sync::Mutex};
+
+use crate::api::{Error, Result};
+
 pub fn crc32(xs: &[u8]) -> u32 {
     use crc32fast::Hasher;
     let mut hasher = Hasher::new();
@@ -5,6 +9,20 @@ pub fn crc32(xs: &[u8]) -> u32 {
     hasher.finalize()
 }
 
+// For what must not collide, unlike `crc32` (e.g. the blocks of
+// `block::Chain`): SHA-256
+pub fn hash(bytes: &[u8]) -> [u8; 32] {
+    crate::sha256::sha256(bytes)
+}
+
+// The leading 32 bits of `hash`: what is signed (`ec` signs a word),
+// so that a forged message has to be searched for, where one with
+// the same crc32 is found in a step (crc32 is linear)
+pub fn hash32(bytes: &[u8]) -> u32 {
+    let hash = hash(bytes);
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
 pub fn time() -> u32 {
     use std::time::SystemTime;
     SystemTime::now()
@@ -19,6 +37,58 @@ pub fn random() -> u32 {
     rng.gen()
 }
 
+// Where random words come from: the thread's generator (`Os`, the
+// same as `random`), or a seeded one (`Seeded`) for what has to be
+// reproducible in tests: the masks of a refresh, the exponents of a
+// handshake. Never seeded outside of tests, what it draws is then
+// known to anyone who knows the seed.
+pub trait Rng: Debug + Send + Sync {
+    fn next_u32(&self) -> u32;
+
+    // 4 bytes per word
+    fn fill(&self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(4) {
+            let word = self.next_u32().to_be_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Os;
+
+impl Rng for Os {
+    fn next_u32(&self) -> u32 {
+        random()
+    }
+
+    fn fill(&self, bytes: &mut [u8]) {
+        use rand::RngCore;
+        rand::rngs::OsRng.fill_bytes(bytes);
+    }
+}
+
+// SplitMix64: the same words for the same seed, nothing more
+#[derive(Debug)]
+pub struct Seeded(Mutex<u64>);
+
+impl Seeded {
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(seed))
+    }
+}
+
+impl Rng for Seeded {
+    fn next_u32(&self) -> u32 {
+        let mut state = self.0.lock().unwrap();
+        *state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+}
+
 pub fn split(x: u64) -> (u32, u32) {
     let lo = (x & u32::MAX as u64) as u32;
     let hi = (x >> 32) as u32;
@@ -32,9 +102,99 @@ pub fn merge(hi: u32, lo: u32) -> u64 {
     x
 }
 
+pub fn pack(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut buf = [0u8; 4];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(buf)
+        })
+        .collect()
+}
+
+pub fn unpack(words: &[u32], len: usize) -> Vec<u8> {
+    let mut ret = words
+        .iter()
+        .flat_map(|w| w.to_be_bytes())
+        .collect::<Vec<_>>();
+    ret.truncate(len);
+    ret
+}
+
+// Big-endian u64 words, a trailing partial word is dropped
+pub fn pack64(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| {
+            u64::from_be_bytes(chunk.try_into().unwrap())
+        })
+        .collect()
+}
+
+pub fn unpack64(words: &[u64]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// `NAME=value` lines of a config file (`--config` of the binaries),
+// blank lines and `#` comments skipped: the same settings as the env
+// variables of the same names
+pub fn parse_env(text: &str) -> Result<Vec<(String, String)>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| {
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(n, line)| {
+            let (name, value) = line
+                .split_once('=')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .ok_or_else(|| {
+                    Error::App(format!(
+                        "line {n}: NAME=value expected"
+                    ))
+                })?;
+            Ok((
+                name.trim().to_string(),
+                value.trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+// The settings of a config file, but for those set in the
+// environment already (which win)
+pub fn load_env(path: &Path) -> Result<()> {
+    let text = fs::read_to_string(path)?;
+    for (name, value) in parse_env(&text)? {
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{merge, split};
+    use super::{
+        crc32, from_hex, hash32, merge, pack, pack64, parse_env,
+        split, to_hex, unpack, unpack64, Rng, Seeded,
+    };
 
     #[test]
     fn test_split() {
@@ -51,4 +211,78 @@ mod tests {
             0xCAFEBABEBEEFFACE
         );
     }
+
+    #[test]
+    fn test_hash32() {
+        // SHA-256("abc") = ba7816bf 8f01cfea...
+        assert_eq!(hash32(b"abc"), 0xba7816bf);
+        // a flipped bit flips the crc32 the same way whatever the
+        // message (it is linear), not the hash
+        let flip = |f: fn(&[u8]) -> u32, msg: &[u8]| {
+            let mut other = msg.to_vec();
+            other[0] ^= 1;
+            f(msg) ^ f(&other)
+        };
+        assert_eq!(flip(crc32, b"abcd"), flip(crc32, b"wxyz"));
+        assert_ne!(flip(hash32, b"abcd"), flip(hash32, b"wxyz"));
+    }
+
+    #[test]
+    fn test_seeded() {
+        // SplitMix64's first output for seed 0: e220a8397b1dcdaf
+        assert_eq!(Seeded::new(0).next_u32(), 0xe220a839);
+        let words = |seed| {
+            let rng = Seeded::new(seed);
+            (0..4).map(|_| rng.next_u32()).collect::<Vec<_>>()
+        };
+        assert_eq!(words(7), words(7));
+        assert_ne!(words(7), words(8));
+        let mut bytes = [0u8; 6];
+        Seeded::new(7).fill(&mut bytes);
+        let w = words(7);
+        assert_eq!(bytes[..4], w[0].to_be_bytes());
+        assert_eq!(bytes[4..], w[1].to_be_bytes()[..2]);
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let bytes = b"Hello, World!";
+        let words = pack(bytes);
+        assert_eq!(words.len(), 4);
+        assert_eq!(words[3], 0x21000000);
+        assert_eq!(unpack(&words, bytes.len()), bytes);
+
+        let words = pack64(bytes);
+        assert_eq!(words, vec![0x48656C6C6F2C2057]);
+        assert_eq!(unpack64(&words), &bytes[..8]);
+    }
+
+    #[test]
+    fn test_hex() {
+        let bytes = vec![0x00, 0x0f, 0xca, 0xfe, 0xff];
+        assert_eq!(to_hex(&bytes), "000fcafeff");
+        assert_eq!(from_hex("000fcafeff"), Some(bytes));
+        assert_eq!(
+            from_hex("000FCAFEFF").map(|b| b.len()),
+            Some(5)
+        );
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_parse_env() {
+        let text =
+            "# comment\n\nRETRIES=3\n  PSK = c0ffee \nEMPTY=\n";
+        assert_eq!(
+            parse_env(text).unwrap(),
+            vec![
+                ("RETRIES".to_string(), "3".to_string()),
+                ("PSK".to_string(), "c0ffee".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+            ]
+        );
+        assert!(parse_env("RETRIES=3\nRETRIES 3").is_err());
+        assert!(parse_env("=3").is_err());
+    }
 }
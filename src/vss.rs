@@ -0,0 +1,167 @@
+// Verifiable k-of-n secret sharing (Feldman): Shamir shares over
+// Z_q, plus commitments g^a mod p to the polynomial's coefficients
+// a, published with the shares. A share (x, y) is consistent with
+// them if g^y = Π C_j^(x^j) (mod p), which anyone holding the share
+// and the commitments can check, without learning the secret.
+//
+// p = 2q + 1 is a safe prime and g generates the subgroup of order
+// q, so the shares (mod q) still fit in u32, but the secret must be
+// less than q (all u32 values but the 5 largest ones).
+
+pub const Q: u64 = 4_294_967_291; // 2^32 - 5, prime
+pub const P: u64 = 2 * Q + 1; // prime
+pub const G: u64 = 4; // a square mod p, so of order q
+
+// `ext` bit of a share frame: verifiable (Feldman) Shamir share,
+// the rest of `ext` is the same as for a Shamir share
+pub const VERIFIABLE: u32 = 1 << 31;
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut ret = 1;
+    base %= m;
+    while exp != 0 {
+        if exp & 1 == 1 {
+            ret = mul_mod(ret, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    ret
+}
+
+// (a + b) mod q
+pub fn add(a: u32, b: u32) -> u32 {
+    ((a as u64 + b as u64) % Q) as u32
+}
+
+// Value of the polynomial at `x` (mod q), `coeffs[0]` is the
+// constant term
+pub fn eval(coeffs: &[u32], x: u32) -> u32 {
+    coeffs.iter().rev().fold(0, |acc, c| {
+        let acc = mul_mod(acc as u64, x as u64, Q);
+        ((acc + *c as u64) % Q) as u32
+    })
+}
+
+// g^a (mod p) for each coefficient a
+pub fn commit(coeffs: &[u32]) -> Vec<u64> {
+    coeffs.iter().map(|a| pow_mod(G, *a as u64, P)).collect()
+}
+
+// `n` shares (share `i` is at x = i + 1), `k` of which recover the
+// secret, and the commitments to check them against
+pub fn split(
+    s: u32,
+    k: usize,
+    n: usize,
+    f: impl Fn() -> u32,
+) -> (Vec<(u32, u32)>, Vec<u64>) {
+    assert!(k >= 1 && k <= n, "invalid threshold: {k} of {n}");
+    assert!((s as u64) < Q, "secret out of range: {s}");
+    let coeffs = std::iter::once(s)
+        .chain((1..k).map(|_| (f() as u64 % Q) as u32))
+        .collect::<Vec<_>>();
+    let shares =
+        (1..=n as u32).map(|x| (x, eval(&coeffs, x))).collect();
+    (shares, commit(&coeffs))
+}
+
+pub fn verify(share: (u32, u32), commitments: &[u64]) -> bool {
+    let (x, y) = share;
+    if commitments.is_empty() || y as u64 >= Q {
+        return false;
+    }
+    let mut rhs = 1;
+    let mut power = 1; // x^j mod q
+    for c in commitments {
+        rhs = mul_mod(rhs, pow_mod(*c, power, P), P);
+        power = mul_mod(power, x as u64, Q);
+    }
+    pow_mod(G, y as u64, P) == rhs
+}
+
+// Lagrange interpolation at x = 0 (mod q), same as `shamir::merge`
+pub fn merge(shares: &[(u32, u32)]) -> u32 {
+    let mut ret = 0;
+    for (i, (xi, yi)) in shares.iter().enumerate() {
+        let mut num = 1;
+        let mut den = 1;
+        for (j, (xj, _)) in shares.iter().enumerate() {
+            if i != j {
+                num = mul_mod(num, *xj as u64, Q);
+                den = mul_mod(
+                    den,
+                    (*xj as u64 + Q - *xi as u64) % Q,
+                    Q,
+                );
+            }
+        }
+        let lambda = mul_mod(num, pow_mod(den, Q - 2, Q), Q);
+        ret = (ret + mul_mod(*yi as u64, lambda, Q)) % Q;
+    }
+    ret as u32
+}
+
+// Commitments after `delta` (zero constant term) is added to the
+// polynomial, i.e. `eval(delta, x)` to each share
+pub fn refresh(commitments: &[u64], delta: &[u32]) -> Vec<u64> {
+    commitments
+        .iter()
+        .zip(commit(delta))
+        .map(|(c, d)| mul_mod(*c, d, P))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::random;
+
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        assert_eq!(pow_mod(G, Q, P), 1);
+        assert_ne!(pow_mod(G, 2, P), 1);
+        assert_eq!(add(Q as u32 - 1, 3), 2);
+    }
+
+    #[test]
+    fn test_split_verify_merge() {
+        let secret = 0xCAFEBABE;
+        let (shares, commitments) = split(secret, 3, 5, random);
+        assert_eq!(commitments.len(), 3);
+        assert!(shares.iter().all(|s| verify(*s, &commitments)));
+        assert_eq!(merge(&shares[..3]), secret);
+        assert_eq!(merge(&shares[2..]), secret);
+        assert_eq!(
+            merge(&[shares[0], shares[2], shares[4]]),
+            secret
+        );
+
+        // a tampered share or commitment is caught
+        let (x, y) = shares[1];
+        assert!(!verify((x, add(y, 1)), &commitments));
+        assert!(!verify((x + 1, y), &commitments));
+        let mut forged = commitments.clone();
+        forged[0] = mul_mod(forged[0], G, P);
+        assert!(!verify(shares[1], &forged));
+    }
+
+    #[test]
+    fn test_refresh() {
+        let secret = 0xCAFEBABE;
+        let (mut shares, commitments) =
+            split(secret, 2, 3, random);
+        let delta = [0, random() % Q as u32];
+        shares
+            .iter_mut()
+            .for_each(|(x, y)| *y = add(*y, eval(&delta, *x)));
+        let commitments = refresh(&commitments, &delta);
+        assert!(shares.iter().all(|s| verify(*s, &commitments)));
+        assert_eq!(merge(&shares[1..]), secret);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::api::{Error, Result};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// Fixed number of threads running jobs from a bounded queue, so the
+// number of threads (and their memory) does not grow with the load.
+// The threads exit once `Workers` is dropped (or joined) and the
+// queue is empty.
+pub struct Workers {
+    queue: Mutex<Option<SyncSender<Job>>>, // None: joined
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Workers {
+    // `size` threads, up to `queue` jobs waiting for a free one
+    // (zero: a job is handed over to an idle thread directly)
+    pub fn new(size: usize, queue: usize) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Job>(queue);
+        let rx = Arc::new(Mutex::new(rx));
+        let threads = (0..size)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || work(&rx))
+            })
+            .collect();
+        Self {
+            queue: Mutex::new(Some(tx)),
+            threads: Mutex::new(threads),
+        }
+    }
+
+    fn queue(&self) -> Result<SyncSender<Job>> {
+        self.queue.lock().unwrap().clone().ok_or_else(stopped)
+    }
+
+    // Blocks while all the threads are busy and the queue is full
+    pub fn run(
+        &self,
+        job: impl FnOnce() + Send + 'static,
+    ) -> Result<()> {
+        self.queue()?.send(Box::new(job)).map_err(|_| stopped())
+    }
+
+    // false (and the job is dropped) if it would have to wait
+    pub fn try_run(
+        &self,
+        job: impl FnOnce() + Send + 'static,
+    ) -> bool {
+        self.queue().is_ok_and(|queue| {
+            queue.try_send(Box::new(job)).is_ok()
+        })
+    }
+
+    // Take no more jobs, wait for the queued and running ones
+    pub fn join(&self) {
+        self.queue.lock().unwrap().take();
+        let threads =
+            std::mem::take(&mut *self.threads.lock().unwrap());
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work(rx: &Mutex<Receiver<Job>>) {
+    loop {
+        // the lock is held only while waiting for the next job
+        let job = match rx.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // a panicking job does not take the thread down with it
+        let _ = catch_unwind(AssertUnwindSafe(job));
+    }
+}
+
+fn stopped() -> Error {
+    Error::Other("workers stopped".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_fixed_size() -> Result<()> {
+        let workers = Workers::new(2, 0);
+        let (done, rx) = mpsc::channel();
+        let (release, wait) = mpsc::channel::<()>();
+        let wait = Arc::new(Mutex::new(wait));
+        for _ in 0..2 {
+            let done = done.clone();
+            let wait = wait.clone();
+            workers.run(move || {
+                let _ = wait.lock().unwrap().recv();
+                done.send(()).unwrap();
+            })?;
+        }
+
+        // both threads are busy
+        assert!(!workers.try_run(|| ()));
+        release.send(()).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        release.send(()).unwrap();
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_panic() -> Result<()> {
+        let workers = Workers::new(1, 4);
+        let count = Arc::new(AtomicUsize::new(0));
+        workers.run(|| panic!("oops"))?;
+        for _ in 0..3 {
+            let count = count.clone();
+            workers.run(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            })?;
+        }
+        drop(workers);
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            while count.load(Ordering::SeqCst) < 3 {
+                thread::yield_now();
+            }
+            tx.send(()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_join() {
+        let workers = Workers::new(2, 4);
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..6 {
+            let count = count.clone();
+            workers
+                .run(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    count.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap();
+        }
+        workers.join();
+        assert_eq!(count.load(Ordering::SeqCst), 6);
+        assert!(workers.run(|| ()).is_err());
+        assert!(!workers.try_run(|| ()));
+    }
+}
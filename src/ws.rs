@@ -0,0 +1,354 @@
+use std::{
+    io::ErrorKind,
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tungstenite::{error::Error as WsError, Message, WebSocket};
+
+use crate::{
+    api::{
+        closed, timeout, Error, Frame, Receiver, Result, Sender,
+    },
+    codec::{Codec, Raw},
+    sha256::hmac_sha256,
+};
+
+// How long a pending read holds the socket before letting a write
+// through (reads and writes share the same `WebSocket`)
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Frames over WebSocket: the same protocol as `Tcp` (DHKE handshake
+// included, masked with the 32-bit keys rather than sealed and
+// authenticated with an HMAC, see `set_mac`), but each u32 or frame
+// is a single binary message, so a browser gets message boundaries
+// for free.
+pub struct Ws {
+    ws: Mutex<WebSocket<TcpStream>>,
+    keys: Option<(u32, u32)>, // to send with, to receive with
+    mac: Option<[u8; 32]>,    // see `set_mac`
+    sent: AtomicU64,          // frames, each way
+    received: AtomicU64,
+}
+
+// HMAC-SHA256 of a frame, truncated
+const MAC_LEN: usize = 16;
+
+impl Ws {
+    // Server side of the upgrade
+    pub fn accept(socket: TcpStream) -> Result<Self> {
+        let ws = tungstenite::accept(socket)
+            .map_err(|e| Error::App(format!("ws: {e}")))?;
+        Self::new(ws)
+    }
+
+    // Client side of the upgrade, e.g. `ws://127.0.0.1:10001/`
+    pub fn connect(
+        socket: TcpStream,
+        url: &str,
+    ) -> Result<Self> {
+        let (ws, _) = tungstenite::client(url, socket)
+            .map_err(|e| Error::App(format!("ws: {e}")))?;
+        Self::new(ws)
+    }
+
+    fn new(ws: WebSocket<TcpStream>) -> Result<Self> {
+        ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Ws {
+            ws: Mutex::new(ws),
+            keys: None,
+            mac: None,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+        })
+    }
+
+    pub fn set_key(&mut self, key: u32) {
+        self.set_keys(key, key);
+    }
+
+    // a key per direction (`dhke::Keys::send` and `recv`)
+    pub fn set_keys(&mut self, send: u32, recv: u32) {
+        self.keys = Some((send, recv));
+    }
+
+    // Masking hides little and protects nothing (`Tcp` seals its
+    // frames instead): from then on each frame carries an HMAC of
+    // it (`dhke::Keys::mac`), of the sender's masking key and of the
+    // number of frames before it, checked on receive, so a frame
+    // changed, replayed, reordered or reflected is rejected
+    pub fn set_mac(&mut self, key: [u8; 32]) {
+        self.mac = Some(key);
+        self.sent.store(0, Ordering::SeqCst);
+        self.received.store(0, Ordering::SeqCst);
+    }
+
+    fn tag(
+        &self,
+        key: u32,
+        n: u64,
+        frame: &[u8],
+    ) -> Option<Vec<u8>> {
+        let mac = self.mac.as_ref()?;
+        let h = hmac_sha256(
+            mac,
+            &[&key.to_be_bytes(), &n.to_be_bytes(), frame],
+        );
+        Some(h[..MAC_LEN].to_vec())
+    }
+
+    fn with_mac(&self, mut frame: Vec<u8>) -> Vec<u8> {
+        let (send, _) = self.keys.unwrap_or_default();
+        let n = self.sent.fetch_add(1, Ordering::SeqCst);
+        if let Some(tag) = self.tag(send, n, &frame) {
+            frame.extend(tag);
+        }
+        frame
+    }
+
+    fn checked(&self, mut frame: Vec<u8>) -> Result<Frame> {
+        let (_, recv) = self.keys.unwrap_or_default();
+        if self.mac.is_some() {
+            let len = frame
+                .len()
+                .checked_sub(MAC_LEN)
+                .ok_or_else(|| {
+                    Error::App(
+                        "invalid frame: no MAC".to_string(),
+                    )
+                })?;
+            let tag = frame.split_off(len);
+            let n = self.received.fetch_add(1, Ordering::SeqCst);
+            let expected = self.tag(recv, n, &frame).unwrap();
+            // compared in full, not up to the first difference
+            let diff = expected
+                .iter()
+                .zip(&tag)
+                .fold(0, |acc, (a, b)| acc | (a ^ b));
+            if diff != 0 {
+                return Err(Error::App(
+                    "invalid frame: bad MAC".to_string(),
+                ));
+            }
+        }
+        Raw.decode(&frame)
+    }
+
+    fn write(&self, mut bytes: Vec<u8>) -> Result<()> {
+        let (send, _) = self.keys.unwrap_or_default();
+        mask(&mut bytes, send);
+        let mut ws = self.ws.lock().unwrap();
+        ws.send(Message::Binary(bytes)).map_err(ws_error)
+    }
+
+    // None when the connection is closed
+    fn read(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Vec<u8>>> {
+        loop {
+            let mut ws = self.ws.lock().unwrap();
+            match ws.read() {
+                Ok(Message::Binary(mut bytes)) => {
+                    let (_, recv) =
+                        self.keys.unwrap_or_default();
+                    mask(&mut bytes, recv);
+                    return Ok(Some(bytes));
+                }
+                Ok(Message::Close(_)) => return Ok(None),
+                Ok(Message::Text(_)) => {
+                    return Err(Error::App(
+                        "ws: binary messages expected"
+                            .to_string(),
+                    ))
+                }
+                Ok(_) => (), // ping/pong are handled by tungstenite
+                Err(WsError::Io(e))
+                    if e.kind() == ErrorKind::WouldBlock
+                        || e.kind() == ErrorKind::TimedOut =>
+                {
+                    drop(ws);
+                    if deadline
+                        .is_some_and(|d| Instant::now() >= d)
+                    {
+                        return Err(timeout());
+                    }
+                    thread::yield_now();
+                }
+                Err(
+                    WsError::ConnectionClosed
+                    | WsError::AlreadyClosed,
+                ) => return Ok(None),
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+    }
+}
+
+fn ws_error(e: WsError) -> Error {
+    match e {
+        WsError::Io(e) => Error::IO(e),
+        e => Error::App(format!("ws: {e}")),
+    }
+}
+
+impl Sender<u32> for Ws {
+    fn send(&self, msg: &u32) -> Result<()> {
+        self.write(msg.to_be_bytes().to_vec())
+    }
+}
+
+fn decode_u32(buf: &[u8]) -> Result<u32> {
+    let bytes: [u8; 4] = buf.try_into().map_err(|_| {
+        Error::App(format!(
+            "expected 4 bytes, got {}",
+            buf.len()
+        ))
+    })?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+impl Receiver<u32> for Ws {
+    fn recv(&self) -> Result<Option<u32>> {
+        self.read(None)?.map(|buf| decode_u32(&buf)).transpose()
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<u32> {
+        let deadline = Instant::now() + within;
+        let buf =
+            self.read(Some(deadline))?.ok_or_else(closed)?;
+        decode_u32(&buf)
+    }
+}
+
+impl Sender<Frame> for Ws {
+    fn send(&self, msg: &Frame) -> Result<()> {
+        self.write(self.with_mac(Raw.encode(msg)?))
+    }
+}
+
+impl Receiver<Frame> for Ws {
+    fn recv(&self) -> Result<Option<Frame>> {
+        match self.read(None)? {
+            Some(buf) => self.checked(buf).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn recv_timeout(&self, within: Duration) -> Result<Frame> {
+        let deadline = Instant::now() + within;
+        let buf =
+            self.read(Some(deadline))?.ok_or_else(closed)?;
+        self.checked(buf)
+    }
+}
+
+// XOR bytes with the key bytes (same as masking whole words)
+fn mask(bytes: &mut [u8], key: u32) {
+    let mask = key.to_be_bytes();
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::{dhke::dhke_handshake, util::random};
+
+    #[test]
+    fn test_handshake_and_frame() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let timeout = Duration::from_secs(1);
+
+        let h = thread::spawn(move || -> Result<()> {
+            let mut tx = Ws::accept(listener.accept()?.0)?;
+            let key = dhke_handshake(&tx, timeout, random())?;
+            tx.set_key(key);
+            let frame: Frame = tx.recv_timeout(timeout)?;
+            tx.send(&frame)?;
+            Ok(())
+        });
+
+        let socket = TcpStream::connect(addr)?;
+        let mut tx =
+            Ws::connect(socket, &format!("ws://{addr}/"))?;
+        let key = dhke_handshake(&tx, timeout, random())?;
+        tx.set_key(key);
+
+        let frame = Frame {
+            idx: 1,
+            tag: 2,
+            msg: 3,
+            key: 4,
+            sig: 5,
+            ext: 6,
+            ns: 0,
+            sum: 7,
+            data: b"payload".to_vec(),
+        };
+        tx.send(&frame)?;
+        let echo: Frame = tx.recv_timeout(timeout)?;
+        h.join()??;
+
+        assert_eq!(echo, frame);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mac() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let timeout = Duration::from_secs(1);
+        let connect =
+            |theirs: [u8; 32]| -> Result<Result<Frame>> {
+                let h = thread::spawn(move || -> Result<()> {
+                    let mut tx = Ws::connect(
+                        TcpStream::connect(addr)?,
+                        &format!("ws://{addr}/"),
+                    )?;
+                    tx.set_keys(1, 2);
+                    tx.set_mac(theirs);
+                    let frame = Frame {
+                        idx: 1,
+                        data: b"payload".to_vec(),
+                        ..Frame::default()
+                    };
+                    tx.send(&frame)?;
+                    tx.send(&frame)?;
+                    Ok(())
+                });
+                let mut rx = Ws::accept(listener.accept()?.0)?;
+                rx.set_keys(2, 1);
+                rx.set_mac([7; 32]);
+                let rcvd =
+                    rx.recv_timeout(timeout).and_then(|first| {
+                        let second: Frame =
+                            rx.recv_timeout(timeout)?;
+                        assert_eq!(first, second);
+                        Ok(first)
+                    });
+                h.join()??;
+                Ok(rcvd)
+            };
+
+        // the same frame twice: the counter makes the MACs differ
+        let rcvd = connect([7; 32])?;
+        assert_eq!(rcvd?.data, b"payload");
+        let rcvd = connect([8; 32])?;
+        assert!(matches!(
+            rcvd,
+            Err(Error::App(e)) if e.contains("MAC")
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+// X25519 (RFC 7748): Diffie-Hellman over Curve25519, hand-rolled as
+// the rest of the crypto here. Field elements mod p = 2^255 - 19 are
+// five 51-bit limbs (least significant first), so that a product of
+// two limbs (and a sum of five of them) fits `u128`.
+
+type Fe = [u64; 5];
+
+const MASK: u64 = (1 << 51) - 1;
+
+// (A - 2) / 4 for A = 486662 of Curve25519
+const A24: u64 = 121665;
+
+// u = 9
+pub const BASEPOINT: [u8; 32] = {
+    let mut u = [0u8; 32];
+    u[0] = 9;
+    u
+};
+
+// X25519(k, u): the u-coordinate of k * (u, ...), all little-endian
+pub fn x25519(k: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+    let mut k = *k;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+
+    // Montgomery ladder, the same operations for every bit of k
+    let x1 = from_bytes(u);
+    let (mut x2, mut z2) = ([1, 0, 0, 0, 0], [0; 5]);
+    let (mut x3, mut z3) = (x1, [1, 0, 0, 0, 0]);
+    let mut swap = 0;
+    for t in (0..255).rev() {
+        let bit = ((k[t / 8] >> (t % 8)) & 1) as u64;
+        swap ^= bit;
+        cswap(swap, &mut x2, &mut x3);
+        cswap(swap, &mut z2, &mut z3);
+        swap = bit;
+
+        let a = add(&x2, &z2);
+        let aa = mul(&a, &a);
+        let b = sub(&x2, &z2);
+        let bb = mul(&b, &b);
+        let e = sub(&aa, &bb);
+        let c = add(&x3, &z3);
+        let d = sub(&x3, &z3);
+        let da = mul(&d, &a);
+        let cb = mul(&c, &b);
+        let x = add(&da, &cb);
+        x3 = mul(&x, &x);
+        let z = sub(&da, &cb);
+        z3 = mul(&x1, &mul(&z, &z));
+        x2 = mul(&aa, &bb);
+        z2 = mul(&e, &add(&aa, &mul_small(&e, A24)));
+    }
+    cswap(swap, &mut x2, &mut x3);
+    cswap(swap, &mut z2, &mut z3);
+    to_bytes(&mul(&x2, &invert(&z2)))
+}
+
+fn from_bytes(bytes: &[u8; 32]) -> Fe {
+    let word = |i: usize| {
+        u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap())
+    };
+    // the top bit is ignored (RFC 7748, 5)
+    [
+        word(0) & MASK,
+        (word(6) >> 3) & MASK,
+        (word(12) >> 6) & MASK,
+        (word(19) >> 1) & MASK,
+        (word(24) >> 12) & MASK,
+    ]
+}
+
+fn to_bytes(f: &Fe) -> [u8; 32] {
+    // fully reduced: below 2^255 after carrying, then minus p if
+    // that is at least p (q is 1 if adding 19 carries out of 2^255)
+    let mut t = carry(f);
+    let mut q = (t[0] + 19) >> 51;
+    for limb in &t[1..] {
+        q = (limb + q) >> 51;
+    }
+    t[0] += 19 * q;
+    for i in 0..4 {
+        t[i + 1] += t[i] >> 51;
+        t[i] &= MASK;
+    }
+    t[4] &= MASK;
+
+    let mut bytes = [0u8; 32];
+    let mut acc: u128 = 0;
+    let mut bits = 0;
+    let mut i = 0;
+    for limb in t {
+        acc |= (limb as u128) << bits;
+        bits += 51;
+        while bits >= 8 && i < 32 {
+            bytes[i] = acc as u8;
+            acc >>= 8;
+            bits -= 8;
+            i += 1;
+        }
+    }
+    bytes[31] = acc as u8;
+    bytes
+}
+
+// limbs back to 51 bits (the top one but for a bit)
+fn carry(f: &Fe) -> Fe {
+    let mut t = *f;
+    for i in 0..4 {
+        t[i + 1] += t[i] >> 51;
+        t[i] &= MASK;
+    }
+    t[0] += 19 * (t[4] >> 51);
+    t[4] &= MASK;
+    t[1] += t[0] >> 51;
+    t[0] &= MASK;
+    t
+}
+
+fn add(a: &Fe, b: &Fe) -> Fe {
+    carry(&[
+        a[0] + b[0],
+        a[1] + b[1],
+        a[2] + b[2],
+        a[3] + b[3],
+        a[4] + b[4],
+    ])
+}
+
+// a + 2p - b, for `b` of limbs below 2^52
+fn sub(a: &Fe, b: &Fe) -> Fe {
+    const P2: Fe = [
+        2 * ((1 << 51) - 19),
+        2 * MASK,
+        2 * MASK,
+        2 * MASK,
+        2 * MASK,
+    ];
+    carry(&[
+        a[0] + P2[0] - b[0],
+        a[1] + P2[1] - b[1],
+        a[2] + P2[2] - b[2],
+        a[3] + P2[3] - b[3],
+        a[4] + P2[4] - b[4],
+    ])
+}
+
+// 2^255 = 19 (mod p): what goes over the top limb comes back at the
+// bottom times 19
+fn mul(a: &Fe, b: &Fe) -> Fe {
+    let m = |x: u64, y: u64| x as u128 * y as u128;
+    let [a0, a1, a2, a3, a4] = *a;
+    let [b0, b1, b2, b3, b4] = *b;
+    let [b1_19, b2_19, b3_19, b4_19] =
+        [b1, b2, b3, b4].map(|x| 19 * x);
+
+    let mut c = [
+        m(a0, b0)
+            + m(a1, b4_19)
+            + m(a2, b3_19)
+            + m(a3, b2_19)
+            + m(a4, b1_19),
+        m(a0, b1)
+            + m(a1, b0)
+            + m(a2, b4_19)
+            + m(a3, b3_19)
+            + m(a4, b2_19),
+        m(a0, b2)
+            + m(a1, b1)
+            + m(a2, b0)
+            + m(a3, b4_19)
+            + m(a4, b3_19),
+        m(a0, b3)
+            + m(a1, b2)
+            + m(a2, b1)
+            + m(a3, b0)
+            + m(a4, b4_19),
+        m(a0, b4)
+            + m(a1, b3)
+            + m(a2, b2)
+            + m(a3, b1)
+            + m(a4, b0),
+    ];
+    let mask = MASK as u128;
+    for i in 0..4 {
+        c[i + 1] += c[i] >> 51;
+        c[i] &= mask;
+    }
+    c[0] += 19 * (c[4] >> 51);
+    c[4] &= mask;
+    c[1] += c[0] >> 51;
+    c[0] &= mask;
+    c.map(|limb| limb as u64)
+}
+
+fn mul_small(a: &Fe, k: u64) -> Fe {
+    mul(a, &[k, 0, 0, 0, 0])
+}
+
+// a^(p - 2), p - 2 = 2^255 - 21
+fn invert(a: &Fe) -> Fe {
+    let mut r = [1, 0, 0, 0, 0];
+    for i in (0..255).rev() {
+        r = mul(&r, &r);
+        // every bit of 2^255 - 21 is set but bits 2 and 4
+        if i != 2 && i != 4 {
+            r = mul(&r, a);
+        }
+    }
+    r
+}
+
+// swapped if `swap` is 1, the same operations either way
+fn cswap(swap: u64, a: &mut Fe, b: &mut Fe) {
+    let mask = 0u64.wrapping_sub(swap);
+    for (x, y) in a.iter_mut().zip(b.iter_mut()) {
+        let t = mask & (*x ^ *y);
+        *x ^= t;
+        *y ^= t;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{from_hex, to_hex};
+
+    fn bytes(hex: &str) -> [u8; 32] {
+        from_hex(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_x25519() {
+        // RFC 7748, 5.2
+        assert_eq!(
+            to_hex(&x25519(
+                &bytes("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4"),
+                &bytes("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c"),
+            )),
+            "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"
+        );
+    }
+
+    #[test]
+    fn test_dh() {
+        // RFC 7748, 6.1
+        let a = bytes("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let b = bytes("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let (pa, pb) =
+            (x25519(&a, &BASEPOINT), x25519(&b, &BASEPOINT));
+        assert_eq!(
+            to_hex(&pa),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+        assert_eq!(
+            to_hex(&pb),
+            "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"
+        );
+        let shared = x25519(&a, &pb);
+        assert_eq!(shared, x25519(&b, &pa));
+        assert_eq!(
+            to_hex(&shared),
+            "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742"
+        );
+    }
+
+    #[test]
+    fn test_field() {
+        let x = from_bytes(&bytes(
+            "0900000000000000000000000000000000000000000000000000000000000000",
+        ));
+        assert_eq!(to_bytes(&x), BASEPOINT);
+        assert_eq!(to_bytes(&mul(&x, &invert(&x))), {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            one
+        });
+        // p itself is zero
+        let mut p = [0xff; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        assert_eq!(to_bytes(&from_bytes(&p)), [0; 32]);
+        assert_eq!(to_bytes(&sub(&x, &x)), [0; 32]);
+    }
+}
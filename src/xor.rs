@@ -1,3 +1,7 @@
+// `ext` bit of a share frame: the share is the bytes following the
+// owner's public key in `data` (`msg` is not used)
+pub const BYTES: u32 = 1 << 30;
+
 pub fn split(s: u32, n: usize, f: impl Fn() -> u32) -> Vec<u32> {
     let mut ret: Vec<u32> = (0..n).map(|_| f()).collect();
     let acc = ret
@@ -18,9 +22,66 @@ pub fn merge(shares: &[u32]) -> u32 {
     ret
 }
 
+// Proactive refresh: a random mask per pair of shares, XOR-ed into
+// both of them, so every mask cancels out in `merge` whatever the
+// number of shares (a single mask XOR-ed into all the shares only
+// does for an even number of them). No share stays the same, as
+// long as there are at least two.
+pub fn refresh(shares: &mut [u32], f: impl Fn() -> u32) {
+    for i in 0..shares.len() {
+        for j in i + 1..shares.len() {
+            let mask = f();
+            shares[i] ^= mask;
+            shares[j] ^= mask;
+        }
+    }
+}
+
+// `len` random bytes, 4 per call of `f`
+pub fn pad(len: usize, f: impl Fn() -> u32) -> Vec<u8> {
+    (0..len.div_ceil(4))
+        .flat_map(|_| f().to_be_bytes())
+        .take(len)
+        .collect()
+}
+
+// XOR `pad` into `bytes` (up to the shorter of the two)
+pub fn mask(bytes: &mut [u8], pad: &[u8]) {
+    bytes.iter_mut().zip(pad).for_each(|(b, p)| *b ^= p);
+}
+
+// Same as `split`, byte-wise: every share is as long as the secret
+pub fn split_bytes(
+    s: &[u8],
+    n: usize,
+    f: impl Fn() -> u32,
+) -> Vec<Vec<u8>> {
+    let mut ret: Vec<Vec<u8>> =
+        (0..n).map(|_| pad(s.len(), &f)).collect();
+    let mut first = s.to_vec();
+    ret.iter().skip(1).for_each(|share| mask(&mut first, share));
+    ret[0] = first;
+    ret
+}
+
+pub fn merge_bytes(shares: &[Vec<u8>]) -> Vec<u8> {
+    let len =
+        shares.iter().map(Vec::len).max().unwrap_or_default();
+    let mut ret = vec![0u8; len];
+    for share in shares {
+        mask(&mut ret, share);
+    }
+    ret
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::random;
+    use proptest::prelude::*;
+
+    use crate::{
+        arbitrary,
+        util::{random, Rng, Seeded},
+    };
 
     use super::*;
 
@@ -28,20 +89,77 @@ mod tests {
     fn test_split_merge() {
         let secret = 0xCAFEBABE;
         let n = 10;
-        let shares = split(secret, n, || random());
+        let shares = split(secret, n, random);
         assert_eq!(merge(&shares), secret);
+
+        // the same shares for the same seed
+        let seeded = |seed| {
+            let rng = Seeded::new(seed);
+            split(secret, n, || rng.next_u32())
+        };
+        assert_eq!(seeded(1), seeded(1));
+        assert_ne!(seeded(1), seeded(2));
+        assert_eq!(merge(&seeded(1)), secret);
     }
 
     #[test]
     fn test_refresh() {
         let secret = 0xCAFEBABE;
-        let k = random() as usize % 10;
-        let n = k * 2; // works only with even number of shares
-        let mut shares = split(secret, n, || random());
+        for n in 1..=10 {
+            let mut shares = split(secret, n, random);
+            let before = shares.clone();
+            refresh(&mut shares, random);
+            assert_eq!(merge(&shares), secret, "n={n}");
+            if n > 1 {
+                assert_ne!(shares, before);
+            }
+        }
+
+        // a common mask only works for an even number of shares
+        let k = 1 + random() as usize % 9;
+        let masked = |n| {
+            let mut shares = split(secret, n, random);
+            shares.iter_mut().for_each(|s| *s ^= 0x0F0F0F0F);
+            merge(&shares)
+        };
+        assert_eq!(masked(k * 2), secret);
+        assert_ne!(masked(k * 2 + 1), secret);
+    }
 
-        let r = random();
-        shares.iter_mut().for_each(|s| *s ^= r);
+    #[test]
+    fn test_split_merge_bytes() {
+        for len in [0, 1, 4, 7, 1000] {
+            let secret = pad(len, random);
+            let shares = split_bytes(&secret, 3, random);
+            assert!(shares.iter().all(|s| s.len() == len));
+            assert_eq!(merge_bytes(&shares), secret);
+        }
 
-        assert_eq!(merge(&shares), secret);
+        let secret = b"correct horse battery staple".to_vec();
+        let mut shares = split_bytes(&secret, 1, random);
+        assert_eq!(shares, vec![secret.clone()]);
+        shares.push(pad(secret.len(), random));
+        assert_ne!(merge_bytes(&shares), secret);
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_shares(
+            (secret, shares) in arbitrary::shares(16)
+        ) {
+            prop_assert_eq!(merge(&shares), secret);
+            let mut refreshed = shares.clone();
+            refresh(&mut refreshed, random);
+            prop_assert_eq!(merge(&refreshed), secret);
+        }
+
+        #[test]
+        fn test_any_byte_shares(
+            (secret, shares) in arbitrary::byte_shares(16)
+        ) {
+            let len = secret.len();
+            prop_assert!(shares.iter().all(|s| s.len() == len));
+            prop_assert_eq!(merge_bytes(&shares), secret);
+        }
     }
 }